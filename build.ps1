@@ -30,7 +30,20 @@ if (Test-Path $privateKeyPassPath) {
     Write-Host "[OK] Key password loaded." -ForegroundColor Green
 }
 
-# 3. Запускаем сборку
+# 3. Подписываем каталог серверов. Приложение скачивает src/data/servers.json из ветки main
+#    вместе с servers.json.sig и без действительной подписи берёт встроенную копию, поэтому
+#    подпись коммитится рядом с каталогом каждый раз, когда он меняется
+$catalogPath = "src\data\servers.json"
+$catalogSigPath = "$catalogPath.sig"
+bun tauri signer sign $catalogPath
+
+if ($LASTEXITCODE -ne 0 -or -not (Test-Path $catalogSigPath)) {
+    Write-Host "[ERROR] Failed to sign $catalogPath" -ForegroundColor Red
+    exit 1
+}
+Write-Host "[OK] Catalog signed: $catalogSigPath" -ForegroundColor Green
+
+# 4. Запускаем сборку
 Write-Host "[INFO] Building Tauri app..." -ForegroundColor Green
 bun tauri build
 
@@ -41,7 +54,7 @@ if ($LASTEXITCODE -ne 0) {
 
 Write-Host "[OK] Build successful! Preparing latest.json..." -ForegroundColor Green
 
-# 4. Находим .sig файлы
+# 5. Находим .sig файлы
 $bundleDir = "src-tauri\target\release\bundle"
 $sigFiles = Get-ChildItem -Path $bundleDir -Filter *.sig -Recurse
 
@@ -50,7 +63,7 @@ if ($sigFiles.Count -eq 0) {
     exit 0
 }
 
-# 5. Определяем GitHub репозиторий из endpoints
+# 6. Определяем GitHub репозиторий из endpoints
 $githubRepo = ""
 if ($tauriConfig.plugins.updater.endpoints.Count -gt 0) {
     $endpoint = $tauriConfig.plugins.updater.endpoints[0]
@@ -60,7 +73,7 @@ if ($tauriConfig.plugins.updater.endpoints.Count -gt 0) {
     }
 }
 
-# 6. Создаём структуру latest.json
+# 7. Создаём структуру latest.json
 $latestJson = @{
     version = "v$version"
     notes = "Auto-generated update for version $version"
@@ -68,7 +81,7 @@ $latestJson = @{
     platforms = @{}
 }
 
-# 7. Обрабатываем каждый .sig файл
+# 8. Обрабатываем каждый .sig файл
 foreach ($sigFile in $sigFiles) {
     $platformKey = ""
     $sigContent = (Get-Content $sigFile.FullName -Raw).Trim()
@@ -105,7 +118,7 @@ foreach ($sigFile in $sigFiles) {
     }
 }
 
-# 8. Сохраняем latest.json
+# 9. Сохраняем latest.json
 $jsonOutput = $latestJson | ConvertTo-Json -Depth 10
 $outputPath = "latest.json"
 $jsonOutput | Out-File -FilePath $outputPath -Encoding UTF8
@@ -115,22 +128,23 @@ Write-Host "[OK] latest.json created!" -ForegroundColor Green
 Write-Host "File: $outputPath" -ForegroundColor Green
 Write-Host ""
 
-# 9. Показываем содержимое для проверки
+# 10. Показываем содержимое для проверки
 Write-Host "latest.json CONTENT:" -ForegroundColor Yellow
 Write-Host "====================" -ForegroundColor Yellow
 Get-Content $outputPath
 Write-Host "====================" -ForegroundColor Yellow
 Write-Host ""
 
-# 10. Инструкции для релиза
+# 11. Инструкции для релиза
 Write-Host "NEXT STEPS:" -ForegroundColor Yellow
 Write-Host "1. Check URLs in latest.json" -ForegroundColor Yellow
 if ($githubRepo -eq "") {
     Write-Host "   WARNING: GitHub repo not detected" -ForegroundColor Red
     Write-Host "   Manually update URLs in latest.json" -ForegroundColor Red
 }
-Write-Host "2. Create GitHub release with tag: v$version" -ForegroundColor Yellow
-Write-Host "3. Upload ALL files from:" -ForegroundColor Yellow
+Write-Host "2. Commit $catalogSigPath (with servers.json, if it changed) and push it to main" -ForegroundColor Yellow
+Write-Host "3. Create GitHub release with tag: v$version" -ForegroundColor Yellow
+Write-Host "4. Upload ALL files from:" -ForegroundColor Yellow
 Write-Host "   $bundleDir" -ForegroundColor Cyan
-Write-Host "4. Upload latest.json to the release" -ForegroundColor Yellow
-Write-Host "5. Verify filenames match URLs in latest.json" -ForegroundColor Yellow
\ No newline at end of file
+Write-Host "5. Upload latest.json to the release" -ForegroundColor Yellow
+Write-Host "6. Verify filenames match URLs in latest.json" -ForegroundColor Yellow
\ No newline at end of file
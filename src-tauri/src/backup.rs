@@ -0,0 +1,236 @@
+// Hosts backups: "<hosts>.clusterbanned.bak.<unix seconds>" files next to the hosts file.
+
+use crate::error::{Error, Result};
+use crate::hosts::HostsDocument;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+const BACKUP_INFIX: &str = ".clusterbanned.bak.";
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupInfo {
+    // File name, used as the id for restore/diff
+    pub id: String,
+    pub path: String,
+    pub timestamp: u64,
+    pub size: u64,
+    pub managed_blocks: usize,
+}
+
+fn backup_prefix(hosts_path: &Path) -> String {
+    let name = hosts_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "hosts".into());
+    format!("{}{}", name, BACKUP_INFIX)
+}
+
+fn backup_dir(hosts_path: &Path) -> PathBuf {
+    hosts_path
+        .parent()
+        .map(|p| p.to_path_buf())
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+// Save `original` as a new backup of `hosts_path`
+pub fn create(hosts_path: &Path, original: &str) -> Result<PathBuf> {
+    let mut ts = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| Error::Other(format!("failed to compute timestamp for backup: {}", e)))?
+        .as_secs();
+    let dir = backup_dir(hosts_path);
+    let prefix = backup_prefix(hosts_path);
+
+    // Two writes within the same second must not overwrite each other's backup
+    let mut backup_path = dir.join(format!("{}{}", prefix, ts));
+    while backup_path.exists() {
+        ts += 1;
+        backup_path = dir.join(format!("{}{}", prefix, ts));
+    }
+
+    std::fs::write(&backup_path, original.as_bytes()).map_err(|e| {
+        Error::io(
+            format!("failed to write backup {}", backup_path.display()),
+            e,
+        )
+    })?;
+    Ok(backup_path)
+}
+
+// All backups of `hosts_path`, newest first
+pub fn list(hosts_path: &Path) -> Result<Vec<BackupInfo>> {
+    let dir = backup_dir(hosts_path);
+    let prefix = backup_prefix(hosts_path);
+    let entries = std::fs::read_dir(&dir)
+        .map_err(|e| Error::io(format!("failed to list {}", dir.display()), e))?;
+
+    let mut out = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let timestamp = match name
+            .strip_prefix(&prefix)
+            .and_then(|ts| ts.parse::<u64>().ok())
+        {
+            Some(ts) => ts,
+            None => continue,
+        };
+        let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+        let managed_blocks = std::fs::read_to_string(entry.path())
+            .map(|text| HostsDocument::parse(&text).blocks().count())
+            .unwrap_or(0);
+        out.push(BackupInfo {
+            id: name,
+            path: entry.path().to_string_lossy().to_string(),
+            timestamp,
+            size,
+            managed_blocks,
+        });
+    }
+    out.sort_by_key(|b| std::cmp::Reverse(b.timestamp));
+    Ok(out)
+}
+
+// Resolve a backup id to its path; only names from `list` are accepted, never arbitrary paths
+fn find(hosts_path: &Path, id: &str) -> Result<BackupInfo> {
+    list(hosts_path)?
+        .into_iter()
+        .find(|b| b.id == id)
+        .ok_or_else(|| Error::NotFound(format!("backup not found: {}", id)))
+}
+
+// Delete the oldest backups so that at most `keep` remain (at least one is always kept)
+pub fn prune(hosts_path: &Path, keep: u32) -> Result<Vec<String>> {
+    let keep = keep.max(1) as usize;
+    let mut removed = Vec::new();
+    for b in list(hosts_path)?.into_iter().skip(keep) {
+        match std::fs::remove_file(&b.path) {
+            Ok(_) => removed.push(b.id),
+            Err(e) => eprintln!("[TAURI] Failed to remove old backup {}: {}", b.path, e),
+        }
+    }
+    Ok(removed)
+}
+
+pub fn read(hosts_path: &Path, id: &str) -> Result<String> {
+    let b = find(hosts_path, id)?;
+    std::fs::read_to_string(&b.path)
+        .map_err(|e| Error::io(format!("failed to read backup {}", b.path), e))
+}
+
+// Unified diff from the backup to the current hosts file
+pub fn diff(hosts_path: &Path, id: &str, current: &str) -> Result<String> {
+    let old = read(hosts_path, id)?;
+    Ok(crate::hosts::unified_diff(
+        &old,
+        current,
+        id,
+        &hosts_path.to_string_lossy(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAIN: &str = "127.0.0.1 localhost\n";
+    const BLOCKED: &str =
+        "127.0.0.1 localhost\n\n# clusterbanned start region:eu separated\n0.0.0.0 a.example\n# clusterbanned end\n";
+
+    // A hosts file of its own in a fresh directory
+    fn hosts_file(name: &str, text: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "clusterbanned-backup-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("hosts");
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn ids(path: &Path) -> Vec<String> {
+        list(path).unwrap().into_iter().map(|b| b.id).collect()
+    }
+
+    #[test]
+    fn backups_in_the_same_second_get_their_own_files() {
+        let hosts = hosts_file("create", PLAIN);
+        let dir = hosts.parent().unwrap();
+        // Not ours: another file's backups and names that only look like backups
+        std::fs::write(dir.join("hosts.old.clusterbanned.bak.1"), "").unwrap();
+        std::fs::write(dir.join("hosts.clusterbanned.bak.latest"), "").unwrap();
+
+        let first = create(&hosts, PLAIN).unwrap();
+        let second = create(&hosts, BLOCKED).unwrap();
+        assert_ne!(first, second);
+
+        let backups = list(&hosts).unwrap();
+        assert_eq!(backups.len(), 2);
+        // Newest first
+        assert_eq!(Path::new(&backups[0].path), second);
+        assert!(backups[0].timestamp > backups[1].timestamp);
+        assert_eq!(backups[0].managed_blocks, 1);
+        assert_eq!(backups[1].managed_blocks, 0);
+        assert_eq!(backups[1].size, PLAIN.len() as u64);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn prune_keeps_the_newest() {
+        let hosts = hosts_file("prune", PLAIN);
+        for _ in 0..4 {
+            create(&hosts, PLAIN).unwrap();
+        }
+        let all = ids(&hosts);
+
+        assert_eq!(prune(&hosts, 2).unwrap(), all[2..].to_vec());
+        assert_eq!(ids(&hosts), all[..2].to_vec());
+        // Nothing to do
+        assert!(prune(&hosts, 2).unwrap().is_empty());
+        // At least one is always kept
+        assert_eq!(prune(&hosts, 0).unwrap(), vec![all[1].clone()]);
+        assert_eq!(ids(&hosts), all[..1].to_vec());
+        std::fs::remove_dir_all(hosts.parent().unwrap()).unwrap();
+    }
+
+    // What restore_backup does with the active hosts file, on a hosts file of our own
+    #[test]
+    fn restore_round_trip() {
+        let hosts = hosts_file("restore", PLAIN);
+        create(&hosts, PLAIN).unwrap();
+        let id = ids(&hosts).remove(0);
+        std::fs::write(&hosts, BLOCKED).unwrap();
+
+        let diff = diff(&hosts, &id, BLOCKED).unwrap();
+        assert!(diff.contains("\n+0.0.0.0 a.example\n"), "{}", diff);
+
+        let restored = read(&hosts, &id).unwrap();
+        create(&hosts, BLOCKED).unwrap();
+        crate::hosts::commit(&hosts, BLOCKED, &HostsDocument::parse(&restored)).unwrap();
+        assert_eq!(std::fs::read_to_string(&hosts).unwrap(), PLAIN);
+
+        // The state before the restore was saved and restores the block
+        let undo = ids(&hosts).remove(0);
+        assert_ne!(undo, id);
+        assert_eq!(read(&hosts, &undo).unwrap(), BLOCKED);
+        std::fs::remove_dir_all(hosts.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn only_listed_backups_can_be_read() {
+        let hosts = hosts_file("find", PLAIN);
+        create(&hosts, PLAIN).unwrap();
+        let id = ids(&hosts).remove(0);
+        for bad in ["hosts", "../hosts", &format!("./{}", id), ""] {
+            assert!(
+                matches!(read(&hosts, bad), Err(Error::NotFound(_))),
+                "{}",
+                bad
+            );
+        }
+        std::fs::remove_dir_all(hosts.parent().unwrap()).unwrap();
+    }
+}
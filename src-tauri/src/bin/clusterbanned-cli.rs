@@ -0,0 +1,421 @@
+// Headless front end to the same backend as the app, for shells, scheduled tasks and SSH
+// sessions. Writing hosts and firewall rules goes through clusterbanned-helper when it runs and
+// needs the same privileges as the app otherwise.
+
+use clusterbanned_lib::hosts::{self, HostsDocument};
+use clusterbanned_lib::{
+    backup, blocking, capabilities, catalog, firewall, helper, probe, settings, Error, Result,
+};
+use serde_json::{json, Value};
+
+const USAGE: &str = "\
+Usage: clusterbanned-cli [--json] <command> [args]
+
+Commands:
+  block <region> <cluster>...     Block clusters (by id or domain) on top of what is blocked
+  unblock <region> [<cluster>...] Unblock clusters; all of the region's when none are given
+  status [<region>]               Blocked clusters in hosts and the app's firewall rules
+  clear                           Remove every hosts block and firewall rule of the app
+  ping <host>                     One latency probe, recorded in the history for catalog clusters
+  backups [list]                  Hosts backups
+  backups restore <id>            Put a hosts backup back in place
+  backups diff <id>               Diff between a backup and the current hosts file
+  catalog [status]                Regions and clusters, or where servers.json came from
+  capabilities                    Whether hosts and firewall blocking can be used here, and why not
+
+Options:
+  --json                 Machine-readable output on stdout
+  --no-hosts             block/unblock: leave the hosts file alone
+  --no-firewall          block/unblock: leave the firewall alone (default: the useFirewall setting)
+  --method <m>           ping: icmp, tcp or udp (default: the cluster's probe, else ICMP then TCP)
+  --port <n>             ping: TCP/UDP port
+  --timeout <ms>         ping: probe timeout (default 600)";
+
+#[derive(Default)]
+struct Args {
+    json: bool,
+    no_hosts: bool,
+    no_firewall: bool,
+    method: Option<String>,
+    port: Option<u16>,
+    timeout_ms: Option<u64>,
+    positional: Vec<String>,
+}
+
+fn parse_args(mut raw: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut args = Args::default();
+    while let Some(arg) = raw.next() {
+        let mut value = |name: &str| raw.next().ok_or_else(|| format!("{} needs a value", name));
+        match arg.as_str() {
+            "--json" => args.json = true,
+            "--no-hosts" => args.no_hosts = true,
+            "--no-firewall" => args.no_firewall = true,
+            "--method" => args.method = Some(value("--method")?),
+            "--port" => {
+                let v = value("--port")?;
+                args.port = Some(v.parse().map_err(|_| format!("invalid port: {}", v))?);
+            }
+            "--timeout" => {
+                let v = value("--timeout")?;
+                args.timeout_ms = Some(v.parse().map_err(|_| format!("invalid timeout: {}", v))?);
+            }
+            "-h" | "--help" => args.positional.insert(0, "help".into()),
+            s if s.starts_with("--") => return Err(format!("unknown option: {}", s)),
+            _ => args.positional.push(arg),
+        }
+    }
+    Ok(args)
+}
+
+// What a command prints: the JSON document and its human-readable form
+struct Output {
+    json: Value,
+    text: String,
+    // Exit with 1 even though the command itself ran
+    failed: bool,
+}
+
+impl Output {
+    fn new(json: Value, text: String) -> Output {
+        Output {
+            json,
+            text,
+            failed: false,
+        }
+    }
+}
+
+// Catalog domains of `clusters` (ids or domains) in `region_id`
+fn resolve_clusters(
+    game: &catalog::Game,
+    region_id: &str,
+    clusters: &[String],
+) -> Result<Vec<String>> {
+    let region = game
+        .region(region_id)
+        .ok_or_else(|| Error::NotFound(format!("region {} not found in catalog", region_id)))?;
+    clusters
+        .iter()
+        .map(|name| {
+            region
+                .clusters
+                .iter()
+                .find(|c| c.id == *name || c.domain.eq_ignore_ascii_case(name))
+                .map(|c| c.domain.clone())
+                .ok_or_else(|| {
+                    Error::NotFound(format!(
+                        "cluster {} not found in region {}",
+                        name, region_id
+                    ))
+                })
+        })
+        .collect()
+}
+
+fn region_blocked(region_id: &str) -> Result<Vec<String>> {
+    Ok(HostsDocument::parse(&hosts::read_text()?)
+        .block(Some(region_id))
+        .map(|b| b.domains())
+        .unwrap_or_default())
+}
+
+fn update_output(result: Value) -> Output {
+    let text = format!(
+        "hosts: {}\nfirewall: {}",
+        result["hosts"].as_str().unwrap_or(""),
+        result["firewall"].as_str().unwrap_or("")
+    );
+    Output {
+        failed: result["success"] != Value::Bool(true),
+        json: result,
+        text,
+    }
+}
+
+async fn block(args: &Args, enable: bool) -> Result<Output> {
+    let (region_id, clusters) = match args.positional.split_first() {
+        Some((region_id, clusters)) if enable && clusters.is_empty() => {
+            return Err(Error::InvalidInput(format!(
+                "block {}: no clusters given",
+                region_id
+            )))
+        }
+        Some(split) => split,
+        None => return Err(Error::InvalidInput("no region given".into())),
+    };
+    let game = catalog::load().await?;
+    let mut domains = resolve_clusters(&game, region_id, clusters)?;
+    let (use_firewall, _, _) = settings::blocking();
+    let use_firewall = use_firewall && !args.no_firewall;
+
+    if enable {
+        // The app replaces the region's block with the full selection: keep what is blocked already
+        let mut all = region_blocked(region_id).unwrap_or_default();
+        for d in domains {
+            if !all.contains(&d) {
+                all.push(d);
+            }
+        }
+        domains = all;
+    } else if clusters.is_empty() {
+        domains = region_blocked(region_id)?;
+        if domains.is_empty() {
+            return Ok(Output::new(
+                json!({ "success": true, "hosts": "Skipped", "firewall": "Skipped" }),
+                format!("nothing is blocked in region {}", region_id),
+            ));
+        }
+    }
+
+    let result = helper::update(region_id, &domains, enable, !args.no_hosts, use_firewall).await?;
+    Ok(update_output(result))
+}
+
+async fn status(args: &Args) -> Result<Output> {
+    let only = args.positional.first();
+    let doc = HostsDocument::parse(&hosts::read_text()?);
+    let game = catalog::load().await.ok();
+
+    let mut regions = Vec::new();
+    let mut text = format!("hosts: {}\n", hosts::active_path());
+    for (region_id, domains) in doc.block_summary() {
+        if only.is_some() && region_id.as_ref() != only {
+            continue;
+        }
+        let clusters: Vec<Value> = domains
+            .iter()
+            .map(|d| {
+                let cluster = game.as_ref().and_then(|g| {
+                    g.clusters()
+                        .find(|(r, c)| {
+                            Some(&r.id) == region_id.as_ref() && c.domain.eq_ignore_ascii_case(d)
+                        })
+                        .map(|(_, c)| c.id.clone())
+                });
+                json!({ "clusterId": cluster, "domain": d })
+            })
+            .collect();
+        text.push_str(&format!(
+            "{}: {}\n",
+            region_id.as_deref().unwrap_or("(no region)"),
+            domains.join(", ")
+        ));
+        regions.push(json!({ "regionId": region_id, "clusters": clusters }));
+    }
+    if regions.is_empty() {
+        text.push_str("nothing is blocked in hosts\n");
+    }
+
+    let firewall = match firewall::backend().and_then(|b| Ok((b.name(), b.rules()?))) {
+        Ok((name, rules)) => {
+            text.push_str(&format!("firewall ({}): {} rule(s)\n", name, rules.len()));
+            for r in &rules {
+                text.push_str(&format!("  {}\n", r));
+            }
+            json!({ "backend": name, "rules": rules })
+        }
+        Err(e) => {
+            text.push_str(&format!("firewall: {}\n", e));
+            json!({ "error": e })
+        }
+    };
+
+    Ok(Output::new(
+        json!({ "hostsPath": hosts::active_path(), "regions": regions, "firewall": firewall }),
+        text.trim_end().to_string(),
+    ))
+}
+
+async fn ping(args: &Args) -> Result<Output> {
+    let host = args
+        .positional
+        .first()
+        .ok_or_else(|| Error::InvalidInput("no host given".into()))?;
+    let timeout = std::time::Duration::from_millis(args.timeout_ms.unwrap_or(600));
+    let result = probe::ping(host, timeout, args.port, args.method.clone()).await?;
+    let text = match result["rtt_ms"].as_f64() {
+        Some(ms) => format!(
+            "{} ({} {}): {:.1} ms",
+            host,
+            result["method"].as_str().unwrap_or(""),
+            result["ip"].as_str().unwrap_or(""),
+            ms
+        ),
+        None => format!(
+            "{}: {} {}",
+            host,
+            result["status"].as_str().unwrap_or(""),
+            result["error"].as_str().unwrap_or("")
+        ),
+    };
+    Ok(Output {
+        failed: result["rtt_ms"].is_null(),
+        json: result,
+        text,
+    })
+}
+
+fn backups(args: &Args) -> Result<Output> {
+    let sub = args
+        .positional
+        .first()
+        .map(|s| s.as_str())
+        .unwrap_or("list");
+    let id = || {
+        args.positional
+            .get(1)
+            .ok_or_else(|| Error::InvalidInput(format!("backups {}: no id given", sub)))
+    };
+    match sub {
+        "list" => {
+            let list = backup::list(std::path::Path::new(hosts::active_path()))?;
+            let text = if list.is_empty() {
+                "no backups".to_string()
+            } else {
+                list.iter()
+                    .map(|b| format!("{}  {} bytes, {} block(s)", b.id, b.size, b.managed_blocks))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            };
+            Ok(Output::new(json!(list), text))
+        }
+        "restore" => {
+            let msg = blocking::restore_backup(id()?)?;
+            Ok(Output::new(json!({ "message": msg }), msg))
+        }
+        "diff" => {
+            let diff = blocking::diff_backup(id()?)?;
+            Ok(Output::new(json!({ "diff": diff }), diff))
+        }
+        other => Err(Error::InvalidInput(format!(
+            "unknown backups command: {}",
+            other
+        ))),
+    }
+}
+
+async fn catalog_cmd(args: &Args) -> Result<Output> {
+    match args.positional.first().map(|s| s.as_str()) {
+        None | Some("list") => {
+            let game = catalog::load().await?;
+            let text = game
+                .clusters()
+                .map(|(r, c)| format!("{}  {}  {}  {}", r.id, c.id, c.domain, c.ips.join(",")))
+                .collect::<Vec<_>>()
+                .join("\n");
+            let json = serde_json::to_value(&game)?;
+            Ok(Output::new(json, text))
+        }
+        Some("status") => {
+            let status = catalog::status().await?;
+            let json = serde_json::to_value(&status)?;
+            let text = serde_json::to_string_pretty(&json)?;
+            Ok(Output::new(json, text))
+        }
+        Some(other) => Err(Error::InvalidInput(format!(
+            "unknown catalog command: {}",
+            other
+        ))),
+    }
+}
+
+async fn capabilities_cmd() -> Result<Output> {
+    let caps = capabilities::check().await;
+    let line = |name: &str, check: &capabilities::Check| match &check.reason {
+        _ if check.ok => format!("{}: yes", name),
+        Some(reason) => format!("{}: no ({})", name, reason),
+        None => format!("{}: no", name),
+    };
+    let mut text = vec![
+        line("hosts", &caps.hosts),
+        line("firewall", &caps.firewall),
+        format!(
+            "helper: {}",
+            if caps.helper {
+                "running"
+            } else {
+                "not running"
+            }
+        ),
+    ];
+    for p in &caps.firewall_profiles {
+        text.push(format!(
+            "profile {}: {}{}{}",
+            p.name,
+            if p.enabled { "on" } else { "off" },
+            if p.local_rules_allowed {
+                ""
+            } else {
+                ", local rules ignored"
+            },
+            if p.policy_managed {
+                ", managed by Group Policy"
+            } else {
+                ""
+            }
+        ));
+    }
+    Ok(Output::new(serde_json::to_value(&caps)?, text.join("\n")))
+}
+
+async fn run(args: &mut Args) -> Result<Output> {
+    let command = args.positional.remove(0);
+    match command.as_str() {
+        "block" => block(args, true).await,
+        "unblock" => block(args, false).await,
+        "status" => status(args).await,
+        "clear" => {
+            let msg = helper::clear().await?;
+            Ok(Output::new(json!({ "message": msg }), msg))
+        }
+        "ping" => ping(args).await,
+        "backups" => backups(args),
+        "catalog" => catalog_cmd(args).await,
+        "capabilities" => capabilities_cmd().await,
+        other => Err(Error::InvalidInput(format!("unknown command: {}", other))),
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let mut args = match parse_args(std::env::args().skip(1)) {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    match args.positional.first().map(|s| s.as_str()) {
+        Some("help") => {
+            println!("{}", USAGE);
+            return;
+        }
+        None => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+        _ => {}
+    }
+
+    let json = args.json;
+    match run(&mut args).await {
+        Ok(out) => {
+            if json {
+                println!("{}", out.json);
+            } else {
+                println!("{}", out.text);
+            }
+            if out.failed {
+                std::process::exit(1);
+            }
+        }
+        Err(e) => {
+            if json {
+                println!("{}", json!({ "error": e }));
+            } else {
+                eprintln!("error: {}", e);
+            }
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,175 @@
+// The privileged half of the app: runs as root (a systemd service, see
+// packaging/linux/clusterbanned-helper.{socket,service}) or as SYSTEM on Windows and serves
+// clusterbanned_lib::helper requests, so the GUI and the CLI can run unprivileged.
+
+use clusterbanned_lib::helper;
+
+const USAGE: &str = "\
+Usage: clusterbanned-helper [--socket <path>]
+
+Serves hosts and firewall changes for the unprivileged app. Needs root (Administrator/SYSTEM on
+Windows). Under systemd socket activation the socket is taken from systemd.
+
+Options:
+  --socket <path>        Unix socket to listen on (default: $CLUSTERBANNED_HELPER_SOCKET or
+                         /run/clusterbanned/helper.sock)";
+
+#[cfg(unix)]
+fn listener(socket: Option<std::path::PathBuf>) -> std::io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    // systemd socket activation: the listening socket is fd 3
+    let activated = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|p| p.parse::<u32>().ok())
+        == Some(std::process::id())
+        && std::env::var("LISTEN_FDS")
+            .ok()
+            .and_then(|n| n.parse::<u32>().ok())
+            .unwrap_or(0)
+            >= 1;
+    if activated {
+        use std::os::unix::io::FromRawFd;
+        eprintln!("[HELPER] listening on the socket passed by systemd");
+        let listener = unsafe { std::os::unix::net::UnixListener::from_raw_fd(3) };
+        listener.set_nonblocking(true)?;
+        return tokio::net::UnixListener::from_std(listener);
+    }
+
+    let path = socket.unwrap_or_else(helper::socket_path);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // A socket left behind by a previous run; never anything else at that path
+    if let Ok(meta) = std::fs::symlink_metadata(&path) {
+        if !meta.file_type().is_socket() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+        std::fs::remove_file(&path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(&path)?;
+    // Any local user may send requests; they are validated and limited to catalog clusters
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o666))?;
+    eprintln!("[HELPER] listening on {}", path.display());
+    Ok(listener)
+}
+
+#[cfg(unix)]
+async fn run(socket: Option<std::path::PathBuf>) -> std::io::Result<()> {
+    let listener = listener(socket)?;
+    // One connection at a time: writes to hosts and the firewall must not interleave
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => helper::serve(stream).await,
+            Err(e) => eprintln!("[HELPER] accept failed: {}", e),
+        }
+    }
+}
+
+#[cfg(windows)]
+mod pipe_security {
+    use std::ffi::c_void;
+
+    // SYSTEM and Administrators: full control; interactive users: read/write, i.e. send requests
+    const SDDL: &str = "D:P(A;;GA;;;SY)(A;;GA;;;BA)(A;;GRGW;;;IU)";
+    const SDDL_REVISION_1: u32 = 1;
+
+    #[repr(C)]
+    pub struct SecurityAttributes {
+        length: u32,
+        descriptor: *mut c_void,
+        inherit_handle: i32,
+    }
+
+    #[link(name = "advapi32")]
+    extern "system" {
+        fn ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            sddl: *const u16,
+            revision: u32,
+            descriptor: *mut *mut c_void,
+            size: *mut u32,
+        ) -> i32;
+    }
+
+    // Used for every pipe instance while the helper runs, so the descriptor is never freed
+    pub fn attributes() -> std::io::Result<SecurityAttributes> {
+        let sddl: Vec<u16> = SDDL.encode_utf16().chain(std::iter::once(0)).collect();
+        let mut descriptor = std::ptr::null_mut();
+        let ok = unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                sddl.as_ptr(),
+                SDDL_REVISION_1,
+                &mut descriptor,
+                std::ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(SecurityAttributes {
+            length: std::mem::size_of::<SecurityAttributes>() as u32,
+            descriptor,
+            inherit_handle: 0,
+        })
+    }
+}
+
+#[cfg(windows)]
+async fn run(_socket: Option<std::path::PathBuf>) -> std::io::Result<()> {
+    use tokio::net::windows::named_pipe::{NamedPipeServer, ServerOptions};
+
+    let mut attributes = pipe_security::attributes()?;
+    // The first instance fails if someone else already owns the pipe name
+    let mut create = |first: bool| -> std::io::Result<NamedPipeServer> {
+        unsafe {
+            ServerOptions::new()
+                .first_pipe_instance(first)
+                .create_with_security_attributes_raw(
+                    helper::PIPE_NAME,
+                    &mut attributes as *mut _ as *mut std::ffi::c_void,
+                )
+        }
+    };
+
+    let mut server = create(true)?;
+    eprintln!("[HELPER] listening on {}", helper::PIPE_NAME);
+    // One connection at a time: writes to hosts and the firewall must not interleave
+    loop {
+        server.connect().await?;
+        let connected = std::mem::replace(&mut server, create(false)?);
+        helper::serve(connected).await;
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let mut socket = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--socket" => match args.next() {
+                Some(path) => socket = Some(std::path::PathBuf::from(path)),
+                None => {
+                    eprintln!("--socket needs a value\n\n{}", USAGE);
+                    std::process::exit(2);
+                }
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            other => {
+                eprintln!("unknown argument: {}\n\n{}", other, USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    if let Err(e) = run(socket).await {
+        eprintln!("[HELPER] {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,301 @@
+// Applying blocks: the region's managed hosts block (with backups) and the firewall rules for its
+// clusters. The Tauri commands and the CLI both go through here.
+
+use crate::catalog::Game;
+use crate::error::{Error, Result};
+use crate::hosts::{self, HostsDocument};
+use crate::{backup, firewall, settings};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// Whether hosts writes make a backup first, and how many backups to keep (useBackup,
+// backupCount)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Backups {
+    pub enabled: bool,
+    pub count: u32,
+}
+
+impl Backups {
+    // From this process's own settings
+    pub fn configured() -> Backups {
+        let (_use_firewall, enabled, count) = settings::blocking();
+        Backups { enabled, count }
+    }
+}
+
+// Keep at most `backupCount` backups around after a hosts write
+fn prune_backups(path: &str, backup_count: u32) {
+    match backup::prune(Path::new(path), backup_count) {
+        Ok(removed) if !removed.is_empty() => {
+            eprintln!("[TAURI] Pruned {} old backup(s)", removed.len())
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("[TAURI] Failed to prune backups: {}", e),
+    }
+}
+
+fn write_failed(path: &str, e: Error) -> Error {
+    match e {
+        Error::PermissionDenied(m) => Error::PermissionDenied(format!(
+            "failed to write hosts file ({}): {}. Start clusterbanned-helper or run the app with elevated privileges",
+            path, m
+        )),
+        other => other.context(format!("failed to write hosts file ({})", path)),
+    }
+}
+
+// Replace the region's hosts block with `domains`, or remove `domains` from it
+pub fn update_hosts(
+    region: Option<&str>,
+    domains: &[String],
+    remove: bool,
+    backups: Backups,
+) -> Result<String> {
+    let path = hosts::active_path();
+
+    let original = hosts::read(path)?;
+    let mut doc = HostsDocument::parse(&original);
+
+    let message = match hosts::update_region(&mut doc, domains, region, remove) {
+        hosts::RegionUpdate::Unchanged(msg) => return Ok(msg),
+        hosts::RegionUpdate::Changed(msg) => format!("{} (wrote to {})", msg, path),
+    };
+
+    if backups.enabled {
+        backup::create(Path::new(path), &original)?;
+    }
+    // Temp file + rename, rolled back if the result doesn't verify
+    hosts::commit(Path::new(path), &original, &doc).map_err(|e| write_failed(path, e))?;
+    prune_backups(path, backups.count);
+
+    Ok(message)
+}
+
+// (domain, ips) of every selected cluster of the region, in catalog order. The plan, the
+// firewall update and the helper's Change all take their IPs from here, so only addresses that
+// firewall::rule_targets lets through.
+pub fn firewall_targets(
+    catalog: &Game,
+    region_id: &str,
+    blocked_domains: &[String],
+) -> Result<Vec<(String, Vec<String>)>> {
+    let region = catalog.region(region_id).ok_or_else(|| {
+        Error::NotFound(format!("region {} not found in servers.json", region_id))
+    })?;
+    Ok(region
+        .clusters
+        .iter()
+        .filter(|c| blocked_domains.contains(&c.domain))
+        .map(|c| (c.domain.clone(), firewall::rule_targets(&c.ips)))
+        .collect())
+}
+
+// Block (`enable`) or unblock the catalog IPs of the selected clusters of a region. `ips` are
+// the IPs per domain when the caller's catalog is not this process's (see helper::Change).
+pub async fn update_firewall(
+    region_id: &str,
+    blocked_domains: &[String],
+    enable: bool,
+    ips: Option<&BTreeMap<String, Vec<String>>>,
+) -> Result<String> {
+    if region_id.is_empty() {
+        return Err(Error::InvalidInput("region_id is empty".into()));
+    }
+    let backend = firewall::backend()?;
+    let targets = match ips {
+        Some(ips) => blocked_domains
+            .iter()
+            .filter_map(|d| ips.get(d).map(|ips| (d.clone(), ips.clone())))
+            .collect(),
+        None => firewall_targets(&crate::catalog::load().await?, region_id, blocked_domains)?,
+    };
+
+    let mut results = Vec::new();
+    let mut total_ips_blocked = 0;
+
+    for (domain, ips) in targets {
+        if ips.is_empty() {
+            results.push(format!("No IPs found for {}", domain));
+            continue;
+        }
+
+        match backend.set_blocked(region_id, &domain, &ips, enable) {
+            Ok(msg) => {
+                results.push(format!("{}: {}", domain, msg));
+                total_ips_blocked += ips.len();
+            }
+            Err(e) => results.push(format!(
+                "Failed to {} {}: {}",
+                if enable { "block" } else { "unblock" },
+                domain,
+                e
+            )),
+        }
+    }
+
+    if results.is_empty() {
+        Ok(format!(
+            "No firewall rules {} for region {}",
+            if enable { "added" } else { "removed" },
+            region_id
+        ))
+    } else {
+        let summary = format!(
+            "{} {} IPs across {} domains",
+            if enable { "Blocked" } else { "Unblocked" },
+            total_ips_blocked,
+            results.len()
+        );
+        results.insert(0, summary);
+        Ok(results.join("\n"))
+    }
+}
+
+// Hosts and firewall together. `enable` replaces the region's hosts block with
+// `blocked_domains`; otherwise those domains are unblocked. Returns {success, hosts, firewall},
+// plus errorCode (the first failure's error code) when a part failed.
+pub async fn update(
+    region_id: &str,
+    blocked_domains: &[String],
+    enable: bool,
+    use_hosts: bool,
+    use_firewall: bool,
+    backups: Backups,
+    ips: Option<&BTreeMap<String, Vec<String>>>,
+) -> serde_json::Value {
+    let mut results = serde_json::json!({
+        "success": true,
+        "hosts": "",
+        "firewall": ""
+    });
+
+    if use_hosts {
+        match update_hosts(Some(region_id), blocked_domains, !enable, backups) {
+            Ok(msg) => results["hosts"] = serde_json::Value::String(msg),
+            Err(e) => {
+                results["success"] = serde_json::Value::Bool(false);
+                results["errorCode"] = e.code().into();
+                results["hosts"] = serde_json::Value::String(format!("Error: {}", e));
+            }
+        }
+    } else {
+        results["hosts"] = serde_json::Value::String("Skipped".into());
+    }
+
+    if use_firewall {
+        match update_firewall(region_id, blocked_domains, enable, ips).await {
+            Ok(msg) => results["firewall"] = serde_json::Value::String(msg),
+            Err(e) => {
+                if results["success"] == true {
+                    results["errorCode"] = e.code().into();
+                }
+                results["success"] = serde_json::Value::Bool(false);
+                results["firewall"] = serde_json::Value::String(format!("Error: {}", e));
+            }
+        }
+    } else {
+        results["firewall"] = serde_json::Value::String("Skipped".into());
+    }
+
+    results
+}
+
+// Remove every firewall rule this app has created
+pub async fn clear_firewall() -> Result<String> {
+    let backend = firewall::backend()?;
+    // The catalog only helps netsh find rules its listing missed; clearing must work without it
+    let catalog = match crate::catalog::load().await {
+        Ok(c) => Some(c),
+        Err(e) => {
+            eprintln!(
+                "[TAURI] clear_firewall_rules: no catalog ({}), relying on rule listing",
+                e
+            );
+            None
+        }
+    };
+    backend.clear(catalog.as_ref())
+}
+
+// Remove every managed hosts block (other lines stay untouched) and every firewall rule
+pub async fn clear(backups: Backups) -> Result<String> {
+    let mut messages = Vec::new();
+
+    let path = hosts::active_path();
+    let original = hosts::read(path)?;
+    let mut doc = HostsDocument::parse(&original);
+
+    // Blocks with and without a region tag alike
+    let removed = doc.remove_all_blocks();
+    eprintln!("[TAURI] Removed {} blocks total", removed);
+
+    if removed > 0 {
+        if backups.enabled {
+            match backup::create(Path::new(path), &original) {
+                Ok(backup_path) => {
+                    messages.push(format!("Backup created: {}", backup_path.display()))
+                }
+                Err(e) => messages.push(format!("Backup failed: {}", e)),
+            }
+        }
+
+        match hosts::commit(Path::new(path), &original, &doc) {
+            Ok(_) => {
+                prune_backups(path, backups.count);
+                messages.push(format!(
+                    "Successfully removed {} block(s) from hosts",
+                    removed
+                ))
+            }
+            Err(e) => {
+                let error_msg = write_failed(path, e).to_string();
+                eprintln!("[TAURI] {}", error_msg);
+                messages.push(error_msg);
+            }
+        }
+    } else {
+        messages.push("No clusterbanned blocks found in hosts".into());
+    }
+
+    match clear_firewall().await {
+        Ok(fw_msg) => messages.push(fw_msg),
+        Err(e) => {
+            let error_msg = format!("Firewall cleanup error: {}", e);
+            eprintln!("[TAURI] {}", error_msg);
+            messages.push(error_msg);
+        }
+    }
+
+    Ok(messages.join("\n"))
+}
+
+// Put hosts backup `id` back in place
+pub fn restore_backup(id: &str) -> Result<String> {
+    let path = hosts::active_path();
+    let hosts_path = Path::new(path);
+    let (_use_firewall, backup_saved, backup_count) = settings::blocking();
+
+    let restored = backup::read(hosts_path, id)?;
+    let current = hosts::read(path)?;
+
+    // The current state gets a backup too, so a restore can itself be undone
+    if backup_saved {
+        backup::create(hosts_path, &current)?;
+    }
+
+    hosts::commit(hosts_path, &current, &HostsDocument::parse(&restored))
+        .map_err(|e| write_failed(path, e))?;
+    prune_backups(path, backup_count);
+
+    Ok(format!("Restored {} (wrote to {})", id, path))
+}
+
+// Unified diff between backup `id` and the current hosts file
+pub fn diff_backup(id: &str) -> Result<String> {
+    let path = hosts::active_path();
+    let current = hosts::read(path)?;
+    backup::diff(Path::new(path), id, &current)
+}
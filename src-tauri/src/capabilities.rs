@@ -0,0 +1,251 @@
+// What this process can actually do, checked per action rather than as a single "is admin":
+// replacing the hosts file, and managing firewall rules (backend present, privileges, and on
+// Windows the firewall service, per-profile state and Group Policy). A running
+// clusterbanned-helper does the writes for us, so it stands in for our own privileges.
+
+use crate::error::{Error, Result};
+use crate::firewall::{self, FirewallProfile};
+use crate::{helper, hosts};
+use serde::Serialize;
+
+// Whether one action can be carried out, and if not, why
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Check {
+    pub ok: bool,
+    // Error code (see error.rs) when it can't
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl Check {
+    fn passed() -> Check {
+        Check {
+            ok: true,
+            code: None,
+            reason: None,
+        }
+    }
+
+    fn failed(e: Error) -> Check {
+        Check {
+            ok: false,
+            code: Some(e.code()),
+            reason: Some(e.to_string()),
+        }
+    }
+
+    fn from_result(result: Result<()>) -> Check {
+        match result {
+            Ok(()) => Check::passed(),
+            Err(e) => Check::failed(e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Capabilities {
+    pub hosts_path: String,
+    // A clusterbanned-helper answered; hosts and firewall writes go through it
+    pub helper: bool,
+    // This process can replace the hosts file
+    pub hosts_writable: Check,
+    pub firewall_backend: Option<String>,
+    // nft/iptables/netsh is there to be used
+    pub firewall_backend_available: Check,
+    // This process may change firewall rules (CAP_NET_ADMIN, administrator)
+    pub firewall_privileges: Check,
+    // Windows Firewall profiles; empty elsewhere
+    pub firewall_profiles: Vec<FirewallProfile>,
+    // The firewall is running and filters, and our rules take effect
+    pub firewall_enabled: Check,
+    // Group Policy controls at least one profile
+    pub policy_managed: bool,
+    // The two blocking modes, all of the above considered
+    pub hosts: Check,
+    pub firewall: Check,
+}
+
+// The process token is elevated (UAC)
+#[cfg(windows)]
+fn token_elevated() -> bool {
+    use std::ffi::c_void;
+    const TOKEN_QUERY: u32 = 0x0008;
+    // TOKEN_INFORMATION_CLASS::TokenElevation
+    const TOKEN_ELEVATION: i32 = 20;
+
+    #[link(name = "advapi32")]
+    extern "system" {
+        fn OpenProcessToken(process: *mut c_void, access: u32, token: *mut *mut c_void) -> i32;
+        fn GetTokenInformation(
+            token: *mut c_void,
+            class: i32,
+            info: *mut c_void,
+            length: u32,
+            returned: *mut u32,
+        ) -> i32;
+    }
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetCurrentProcess() -> *mut c_void;
+        fn CloseHandle(handle: *mut c_void) -> i32;
+    }
+
+    let mut token = std::ptr::null_mut();
+    let mut elevated = 0u32;
+    let mut returned = 0u32;
+    unsafe {
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) == 0 {
+            return false;
+        }
+        let ok = GetTokenInformation(
+            token,
+            TOKEN_ELEVATION,
+            &mut elevated as *mut u32 as *mut c_void,
+            std::mem::size_of::<u32>() as u32,
+            &mut returned,
+        );
+        CloseHandle(token);
+        ok != 0 && elevated != 0
+    }
+}
+
+// Effective capabilities contain CAP_NET_ADMIN (bit 12), which nftables and iptables need
+#[cfg(target_os = "linux")]
+fn has_net_admin() -> bool {
+    const CAP_NET_ADMIN: u32 = 12;
+    std::fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find_map(|l| l.strip_prefix("CapEff:"))
+                .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+        })
+        .map(|caps| caps & (1 << CAP_NET_ADMIN) != 0)
+        .unwrap_or(false)
+}
+
+// Whether this process has the privileges firewall tools need: an elevated token on Windows,
+// CAP_NET_ADMIN on Linux. None where that can't be told.
+pub fn privileged() -> Option<bool> {
+    #[cfg(windows)]
+    {
+        Some(token_elevated())
+    }
+    #[cfg(target_os = "linux")]
+    {
+        Some(has_net_admin())
+    }
+    #[cfg(not(any(windows, target_os = "linux")))]
+    {
+        None
+    }
+}
+
+// Whether a helper is running; it answers Status without touching anything
+async fn helper_running() -> bool {
+    match helper::Client::connect().await {
+        Ok(client) => client.call(&helper::Request::Status).await.is_ok(),
+        Err(_) => false,
+    }
+}
+
+pub async fn check() -> Capabilities {
+    let hosts_path = hosts::active_path().to_string();
+    let helper = helper_running().await;
+    let hosts_writable = Check::from_result(hosts::check_writable(&hosts_path));
+
+    let backend = firewall::backend();
+    let firewall_backend = backend.as_ref().ok().map(|b| b.name().to_string());
+    let firewall_backend_available = Check::from_result(backend.map(|_| ()));
+
+    let elevated = privileged().unwrap_or(false);
+    #[cfg(windows)]
+    let (firewall_enabled, firewall_profiles) = match firewall::netsh::status() {
+        Ok(status) => (
+            Check::from_result(windows_firewall_enabled(&status)),
+            status.profiles,
+        ),
+        Err(e) => (Check::failed(e), Vec::new()),
+    };
+    // Our own nftables table / iptables chain filters no matter what else is configured
+    #[cfg(not(windows))]
+    let (firewall_enabled, firewall_profiles) = (Check::passed(), Vec::<FirewallProfile>::new());
+
+    let firewall_privileges = if elevated {
+        Check::passed()
+    } else if cfg!(windows) {
+        Check::failed(Error::PermissionDenied(
+            "changing Windows Firewall rules requires administrator rights".into(),
+        ))
+    } else if cfg!(target_os = "linux") {
+        Check::failed(Error::PermissionDenied(
+            "changing nftables/iptables rules requires root (CAP_NET_ADMIN)".into(),
+        ))
+    } else {
+        Check::failed(Error::Unsupported(
+            "Firewall rules are not supported on this platform".into(),
+        ))
+    };
+    let policy_managed = firewall_profiles.iter().any(|p| p.policy_managed);
+
+    // The helper has its own privileges; what the machine's firewall allows still applies
+    let hosts = if helper {
+        Check::passed()
+    } else {
+        hosts_writable.clone()
+    };
+    let mut firewall_checks = vec![&firewall_backend_available, &firewall_enabled];
+    if !helper {
+        firewall_checks.push(&firewall_privileges);
+    }
+    let firewall = firewall_checks
+        .into_iter()
+        .find(|c| !c.ok)
+        .cloned()
+        .unwrap_or_else(Check::passed);
+
+    Capabilities {
+        hosts_path,
+        helper,
+        hosts_writable,
+        firewall_backend,
+        firewall_backend_available,
+        firewall_privileges,
+        firewall_profiles,
+        firewall_enabled,
+        policy_managed,
+        hosts,
+        firewall,
+    }
+}
+
+// Our rules are outbound blocks added to the local store: they need the service running and at
+// least one profile that filters and honours local rules
+#[cfg(windows)]
+fn windows_firewall_enabled(status: &firewall::netsh::Status) -> Result<()> {
+    if !status.service_running {
+        return Err(Error::Unsupported(
+            "the Windows Firewall service (MpsSvc) is not running".into(),
+        ));
+    }
+    if !status.profiles.iter().any(|p| p.enabled) {
+        return Err(Error::Unsupported(
+            "Windows Firewall is turned off in every profile".into(),
+        ));
+    }
+    let enabled: Vec<&FirewallProfile> = status.profiles.iter().filter(|p| p.enabled).collect();
+    // Administrator rights don't help here, hence not PermissionDenied
+    if enabled.iter().all(|p| !p.local_rules_allowed) {
+        let names: Vec<&str> = enabled.iter().map(|p| p.name.as_str()).collect();
+        return Err(Error::Unsupported(format!(
+            "Group Policy ignores locally added firewall rules ({})",
+            names.join(", ")
+        )));
+    }
+    Ok(())
+}
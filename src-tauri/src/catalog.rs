@@ -0,0 +1,440 @@
+// Typed model of servers.json (game -> regions -> clusters).
+//
+// The JSON calls both levels "clusters": the game's `clusters` are regions, and each
+// region's `clusters` are the actual login clusters.
+
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::net::IpAddr;
+
+pub mod cache;
+pub mod overlay;
+pub mod signature;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Game {
+    pub id: String,
+    #[serde(default)]
+    pub alias_name: Option<String>,
+    pub name: String,
+    #[serde(default)]
+    pub icon: Option<String>,
+    #[serde(default)]
+    pub posters: Vec<String>,
+    #[serde(rename = "clusters")]
+    pub regions: Vec<Region>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Region {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub alias_name: Option<String>,
+    #[serde(default)]
+    pub icon: Option<String>,
+    pub clusters: Vec<Cluster>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cluster {
+    pub id: String,
+    pub domain: String,
+    #[serde(default)]
+    pub location: Option<String>,
+    pub ips: Vec<String>,
+    // How to measure latency to this cluster; ICMP (falling back to TCP) when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub probe: Option<crate::probe::ProbeSpec>,
+}
+
+impl Game {
+    pub fn region(&self, id: &str) -> Option<&Region> {
+        self.regions.iter().find(|r| r.id == id)
+    }
+
+    // Every (region, cluster) pair in catalog order
+    pub fn clusters(&self) -> impl Iterator<Item = (&Region, &Cluster)> {
+        self.regions
+            .iter()
+            .flat_map(|r| r.clusters.iter().map(move |c| (r, c)))
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.regions.is_empty() {
+            return Err(Error::Parse(
+                "catalog: field `clusters` has no regions".into(),
+            ));
+        }
+        let mut region_ids = std::collections::HashSet::new();
+        for (ri, region) in self.regions.iter().enumerate() {
+            if region.id.trim().is_empty() {
+                return Err(Error::Parse(format!(
+                    "catalog region #{}: field `id` is empty",
+                    ri
+                )));
+            }
+            if !region_ids.insert(region.id.as_str()) {
+                return Err(Error::Parse(format!(
+                    "catalog region '{}': duplicate region id",
+                    region.id
+                )));
+            }
+            region.validate()?;
+        }
+        Ok(())
+    }
+}
+
+impl Region {
+    fn validate(&self) -> Result<()> {
+        if self.clusters.is_empty() {
+            return Err(Error::Parse(format!(
+                "catalog region '{}': field `clusters` is empty",
+                self.id
+            )));
+        }
+        let mut ids = std::collections::HashSet::new();
+        let mut domains = std::collections::HashSet::new();
+        for (ci, cluster) in self.clusters.iter().enumerate() {
+            let ctx = format!(
+                "catalog region '{}', cluster #{} ('{}')",
+                self.id, ci, cluster.id
+            );
+            if cluster.id.trim().is_empty() {
+                return Err(Error::Parse(format!("{}: field `id` is empty", ctx)));
+            }
+            if !ids.insert(cluster.id.as_str()) {
+                return Err(Error::Parse(format!("{}: duplicate cluster id", ctx)));
+            }
+            if !is_valid_domain(&cluster.domain) {
+                return Err(Error::Parse(format!(
+                    "{}: field `domain` is not a valid host name: {:?}",
+                    ctx, cluster.domain
+                )));
+            }
+            if !domains.insert(cluster.domain.to_lowercase()) {
+                return Err(Error::Parse(format!(
+                    "{}: field `domain` duplicates another cluster: {}",
+                    ctx, cluster.domain
+                )));
+            }
+            if let Some(probe) = &cluster.probe {
+                probe
+                    .validate()
+                    .map_err(|e| Error::Parse(format!("{}: field `probe`: {}", ctx, e)))?;
+            }
+            // `ips` may be empty (or [""] in the shipped file): such clusters are hosts-only
+            for (i, ip) in cluster.ips.iter().enumerate() {
+                if ip.trim().parse::<IpAddr>().is_err() {
+                    return Err(Error::Parse(format!(
+                        "{}: field `ips[{}]` is not an IP address: {:?}",
+                        ctx, i, ip
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Cluster {
+    pub fn ip_addrs(&self) -> Vec<IpAddr> {
+        self.ips
+            .iter()
+            .filter_map(|s| s.trim().parse().ok())
+            .collect()
+    }
+}
+
+fn is_valid_domain(domain: &str) -> bool {
+    !domain.is_empty()
+        && domain.len() <= 253
+        && domain.contains('.')
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+// serde only says *what* went wrong; re-run it per region and cluster to say *where*
+fn locate_error(root: &Value, err: serde_json::Error) -> String {
+    if let Some(Value::Array(regions)) = root.get("clusters") {
+        for (ri, region) in regions.iter().enumerate() {
+            let region_name = region
+                .get("id")
+                .and_then(|v| v.as_str())
+                .map(|s| format!("'{}'", s))
+                .unwrap_or_else(|| format!("#{}", ri));
+            if let Some(Value::Array(clusters)) = region.get("clusters") {
+                for (ci, cluster) in clusters.iter().enumerate() {
+                    if let Err(e) = serde_json::from_value::<Cluster>(cluster.clone()) {
+                        let cluster_name = cluster
+                            .get("id")
+                            .and_then(|v| v.as_str())
+                            .map(|s| format!(" ('{}')", s))
+                            .unwrap_or_default();
+                        return format!(
+                            "catalog region {}, cluster #{}{}: {}",
+                            region_name, ci, cluster_name, e
+                        );
+                    }
+                }
+            }
+            if let Err(e) = serde_json::from_value::<Region>(region.clone()) {
+                return format!("catalog region {}: {}", region_name, e);
+            }
+        }
+    }
+    format!("catalog: {}", err)
+}
+
+// Parse and validate a servers.json document
+pub fn parse(text: &str) -> Result<Game> {
+    let root: Value = serde_json::from_str(text)
+        .map_err(|e| Error::Parse(format!("catalog is not valid JSON: {}", e)))?;
+    from_value(root)
+}
+
+pub fn from_value(root: Value) -> Result<Game> {
+    let mut game: Game = match serde_json::from_value(root.clone()) {
+        Ok(g) => g,
+        Err(e) => return Err(Error::Parse(locate_error(&root, e))),
+    };
+    for region in &mut game.regions {
+        for cluster in &mut region.clusters {
+            cluster.ips.retain(|ip| !ip.trim().is_empty());
+        }
+    }
+    game.validate()?;
+    Ok(game)
+}
+
+// The copy of servers.json compiled into the binary
+pub fn embedded() -> Result<Game> {
+    parse(include_str!("../../src/data/servers.json"))
+}
+
+// Last loaded catalog (overlay applied), so frequent callers don't reload it
+static LAST: std::sync::Mutex<Option<Game>> = std::sync::Mutex::new(None);
+
+// Where servers.json came from last time
+static LAST_STATUS: std::sync::Mutex<Option<cache::Status>> = std::sync::Mutex::new(None);
+
+// servers.json without the user overlay: cache, remote or the embedded copy
+pub async fn load_base() -> Result<(Game, cache::Status)> {
+    let (offline, ttl_secs) = crate::settings::catalog();
+    let dir = crate::settings::config_dir()?;
+
+    let (game, status) = cache::load(&cache::Options {
+        dir: &dir,
+        url: cache::CATALOG_URL,
+        public_key: signature::PUBLIC_KEY,
+        ttl_secs,
+        offline,
+    })
+    .await
+    .inspect_err(|_| eprintln!("⛔ Все источники недоступны!"))?;
+
+    match &status.error {
+        Some(e) => eprintln!("⚠️ servers.json: {:?} ({})", status.source, e),
+        None => eprintln!("✅ servers.json: {:?}", status.source),
+    }
+    Ok((game, status))
+}
+
+// The catalog as the app uses it: servers.json with the user overlay on top
+pub async fn load() -> Result<Game> {
+    let (base, mut status) = load_base().await?;
+
+    // A broken overlay must not break blocking: fall back to the catalog without it
+    let game = match crate::settings::config_dir()
+        .and_then(|dir| overlay::load(&dir))
+        .and_then(|overlay| overlay.apply(&base).map(|game| (overlay.is_empty(), game)))
+    {
+        Ok((empty, game)) => {
+            status.overlay_applied = !empty;
+            game
+        }
+        Err(e) => {
+            eprintln!("⚠️ Пользовательский overlay не применён: {}", e);
+            status.overlay_error = Some(e.to_string());
+            base
+        }
+    };
+
+    *LAST_STATUS.lock().unwrap() = Some(status);
+    *LAST.lock().unwrap() = Some(game.clone());
+    Ok(game)
+}
+
+// The last loaded catalog, if any has been loaded yet
+pub fn last() -> Option<Game> {
+    LAST.lock().unwrap().clone()
+}
+
+// The last loaded catalog, loading it if there is none yet
+pub async fn cached() -> Result<Game> {
+    match last() {
+        Some(game) => Ok(game),
+        None => load().await,
+    }
+}
+
+// Source and age of servers.json, loading the catalog if it hasn't been yet
+pub async fn status() -> Result<cache::Status> {
+    let last = LAST_STATUS.lock().unwrap().clone();
+    if let Some(status) = last {
+        return Ok(status.refreshed());
+    }
+    load().await?;
+    LAST_STATUS
+        .lock()
+        .unwrap()
+        .clone()
+        .ok_or_else(|| Error::Other("catalog status unavailable".into()))
+}
+
+// The catalog cluster with this domain: (region_id, cluster_id, probe)
+pub async fn cluster_by_domain(
+    hostname: &str,
+) -> Option<(String, String, Option<crate::probe::ProbeSpec>)> {
+    let catalog = cached().await.ok()?;
+    for (region, cluster) in catalog.clusters() {
+        if cluster.domain.eq_ignore_ascii_case(hostname) {
+            return Some((region.id.clone(), cluster.id.clone(), cluster.probe));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // A one-region catalog around `clusters`
+    fn region(clusters: Value) -> Value {
+        json!({"id": "game", "name": "Game", "clusters": [
+            {"id": "eu", "name": "EU", "clusters": clusters}
+        ]})
+    }
+
+    fn error(root: Value) -> String {
+        match from_value(root) {
+            Err(Error::Parse(m)) => m,
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn the_embedded_catalog_is_valid() {
+        let game = embedded().unwrap();
+        assert!(!game.regions.is_empty());
+        assert!(game.regions.iter().all(|r| !r.clusters.is_empty()));
+        // Blank entries are dropped, so every IP left parses
+        for (_, cluster) in game.clusters() {
+            assert_eq!(
+                cluster.ip_addrs().len(),
+                cluster.ips.len(),
+                "{}",
+                cluster.id
+            );
+        }
+        assert!(game.clusters().any(|(_, c)| !c.ips.is_empty()));
+    }
+
+    #[test]
+    fn blank_ips_make_a_hosts_only_cluster() {
+        let game = from_value(region(json!([
+            {"id": "c1", "domain": "login.eu.example", "ips": [""]},
+            {"id": "c2", "domain": "login2.eu.example", "ips": [" 10.0.0.1", " "]}
+        ])))
+        .unwrap();
+        assert!(game.regions[0].clusters[0].ips.is_empty());
+        assert_eq!(game.regions[0].clusters[1].ips, vec![" 10.0.0.1"]);
+        assert_eq!(
+            game.regions[0].clusters[1].ip_addrs(),
+            vec![IpAddr::from([10, 0, 0, 1])]
+        );
+    }
+
+    #[test]
+    fn validation_says_what_and_where() {
+        let c1 = json!({"id": "c1", "domain": "login.eu.example", "ips": []});
+        let cases = [
+            (
+                json!({"id": "game", "name": "Game", "clusters": []}),
+                "catalog: field `clusters` has no regions",
+            ),
+            (
+                json!({"id": "game", "name": "Game", "clusters": [
+                    {"id": " ", "name": "EU", "clusters": [c1]}
+                ]}),
+                "catalog region #0: field `id` is empty",
+            ),
+            (
+                json!({"id": "game", "name": "Game", "clusters": [
+                    {"id": "eu", "name": "EU", "clusters": [c1]},
+                    {"id": "eu", "name": "EU again", "clusters": [c1]}
+                ]}),
+                "catalog region 'eu': duplicate region id",
+            ),
+            (region(json!([])), "catalog region 'eu': field `clusters` is empty"),
+            (
+                region(json!([{"id": "", "domain": "login.eu.example", "ips": []}])),
+                "catalog region 'eu', cluster #0 (''): field `id` is empty",
+            ),
+            (
+                region(json!([c1, {"id": "c1", "domain": "login2.eu.example", "ips": []}])),
+                "catalog region 'eu', cluster #1 ('c1'): duplicate cluster id",
+            ),
+            (
+                region(json!([{"id": "c1", "domain": "login eu", "ips": []}])),
+                "catalog region 'eu', cluster #0 ('c1'): field `domain` is not a valid host name: \"login eu\"",
+            ),
+            (
+                region(json!([c1, {"id": "c2", "domain": "LOGIN.eu.example", "ips": []}])),
+                "catalog region 'eu', cluster #1 ('c2'): field `domain` duplicates another cluster: LOGIN.eu.example",
+            ),
+            (
+                region(json!([{"id": "c1", "domain": "login.eu.example", "ips": ["10.0.0.1", "10.0.0.256"]}])),
+                "catalog region 'eu', cluster #0 ('c1'): field `ips[1]` is not an IP address: \"10.0.0.256\"",
+            ),
+            (
+                region(json!([{"id": "c1", "domain": "login.eu.example", "ips": [],
+                    "probe": {"method": "udp"}}])),
+                "catalog region 'eu', cluster #0 ('c1'): field `probe`: udp probe needs a `port`",
+            ),
+        ];
+        for (root, expected) in cases {
+            assert_eq!(error(root), expected);
+        }
+    }
+
+    #[test]
+    fn type_errors_point_at_the_cluster() {
+        let message = error(region(json!([
+            {"id": "c1", "domain": "login.eu.example", "ips": []},
+            {"id": "c2", "domain": "login2.eu.example", "ips": "10.0.0.1"}
+        ])));
+        assert!(
+            message.starts_with("catalog region 'eu', cluster #1 ('c2'): invalid type"),
+            "{}",
+            message
+        );
+        let message = error(json!({"id": "game", "name": "Game", "clusters": [{"id": "eu"}]}));
+        assert!(
+            message.starts_with("catalog region 'eu': missing field"),
+            "{}",
+            message
+        );
+        assert!(
+            matches!(parse("{"), Err(Error::Parse(m)) if m.starts_with("catalog is not valid JSON"))
+        );
+    }
+}
@@ -0,0 +1,521 @@
+// servers.json cache in the app config dir. The last good download is kept as servers.json with
+// its signature in servers.json.sig, and its validators (ETag, Last-Modified) and download time in
+// servers.meta.json. Within the TTL the cache is used as is; after that it is revalidated with a
+// conditional GET, and a failed download falls back to the stale cache, then to the copy compiled
+// into the binary. A download without a valid signature goes straight to the compiled-in copy.
+
+use super::{signature, Game};
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const CATALOG_URL: &str =
+    "https://raw.githubusercontent.com/SWIRCH/cluster-banned-manager/main/src/data/servers.json";
+pub const DEFAULT_TTL_SECS: u64 = 6 * 60 * 60;
+
+const BODY_FILE: &str = "servers.json";
+const SIG_FILE: &str = "servers.json.sig";
+const META_FILE: &str = "servers.meta.json";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CacheMeta {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    // Unix seconds of the last download or successful revalidation
+    fetched_at: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Source {
+    // Downloaded just now
+    Network,
+    // The server answered 304 Not Modified
+    Revalidated,
+    // Cache within its TTL, or offline mode
+    Cache,
+    // Cache past its TTL because the download failed
+    StaleCache,
+    Embedded,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Status {
+    pub source: Source,
+    pub url: String,
+    pub fetched_at: Option<u64>,
+    pub age_secs: Option<u64>,
+    pub ttl_secs: u64,
+    pub offline: bool,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    // Why a fresher source couldn't be used
+    pub error: Option<String>,
+    // Set when the downloaded catalog was thrown away because of its signature
+    pub signature_error: Option<String>,
+    // Whether the user overlay was merged in, or why it couldn't be
+    pub overlay_applied: bool,
+    pub overlay_error: Option<String>,
+}
+
+impl Status {
+    // Recompute the age for reporting at a later time
+    pub fn refreshed(&self) -> Status {
+        let mut status = self.clone();
+        status.age_secs = status.fetched_at.map(|t| now_secs().saturating_sub(t));
+        status
+    }
+}
+
+pub struct Options<'a> {
+    pub dir: &'a Path,
+    pub url: &'a str,
+    // What the download and the cache must be signed with: signature::PUBLIC_KEY
+    pub public_key: &'a str,
+    pub ttl_secs: u64,
+    pub offline: bool,
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn body_path(dir: &Path) -> PathBuf {
+    dir.join(BODY_FILE)
+}
+
+fn sig_path(dir: &Path) -> PathBuf {
+    dir.join(SIG_FILE)
+}
+
+fn meta_path(dir: &Path) -> PathBuf {
+    dir.join(META_FILE)
+}
+
+// The cached catalog for `url`, if there is a valid one
+fn read_cache(dir: &Path, url: &str, public_key: &str) -> Result<(Game, CacheMeta)> {
+    let meta_text =
+        std::fs::read_to_string(meta_path(dir)).map_err(|e| Error::io("no cached catalog", e))?;
+    let meta: CacheMeta = serde_json::from_str(&meta_text)
+        .map_err(|e| Error::Parse(format!("cache metadata is corrupt: {}", e)))?;
+    if meta.url != url {
+        return Err(Error::NotFound(format!(
+            "cached catalog is for {}",
+            meta.url
+        )));
+    }
+    let body =
+        std::fs::read_to_string(body_path(dir)).map_err(|e| Error::io("no cached catalog", e))?;
+    // The cache is as untrusted as the network: check it every time it is used
+    let sig = std::fs::read_to_string(sig_path(dir))
+        .map_err(|e| Error::io("cached catalog has no signature", e))?;
+    signature::verify(public_key, body.as_bytes(), &sig)
+        .map_err(|e| e.context("cached catalog"))?;
+    let game = super::parse(&body).map_err(|e| e.context("cached catalog is invalid"))?;
+    Ok((game, meta))
+}
+
+// Body and signature first, so metadata never describes a body that isn't there
+fn write_cache(dir: &Path, signed_body: Option<(&str, &str)>, meta: &CacheMeta) -> Result<()> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| Error::io(format!("failed to create {}", dir.display()), e))?;
+    if let Some((body, sig)) = signed_body {
+        crate::hosts::write_atomic(&body_path(dir), body)?;
+        crate::hosts::write_atomic(&sig_path(dir), sig)?;
+    }
+    let meta_text = serde_json::to_string_pretty(meta)?;
+    crate::hosts::write_atomic(&meta_path(dir), &meta_text)
+}
+
+enum Fetched {
+    NotModified,
+    // Body, its signature (or why there is none) and the new metadata
+    Body(String, Result<String>, CacheMeta),
+}
+
+async fn fetch_signature(client: &reqwest::Client, url: &str) -> Result<String> {
+    let sig_url = signature::signature_url(url);
+    let response = client
+        .get(&sig_url)
+        .send()
+        .await
+        .map_err(|e| Error::Network(format!("signature download failed: {}", e)))?;
+    if !response.status().is_success() {
+        return Err(Error::Network(format!(
+            "signature missing: {} returned HTTP {}",
+            sig_url,
+            response.status()
+        )));
+    }
+    response
+        .text()
+        .await
+        .map_err(|e| Error::Network(format!("signature download failed: {}", e)))
+}
+
+async fn fetch(url: &str, cached: Option<&CacheMeta>) -> Result<Fetched> {
+    use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .map_err(|e| Error::Network(format!("failed to create HTTP client: {}", e)))?;
+    let mut request = client.get(url);
+    if let Some(meta) = cached {
+        if let Some(etag) = &meta.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(lm) = &meta.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, lm);
+        }
+    }
+
+    let response = request
+        .send()
+        .await
+        .map_err(|e| Error::Network(format!("download failed: {}", e)))?;
+    if response.status() == reqwest::StatusCode::NOT_MODIFIED && cached.is_some() {
+        return Ok(Fetched::NotModified);
+    }
+    if !response.status().is_success() {
+        return Err(Error::Network(format!(
+            "download failed: HTTP {}",
+            response.status()
+        )));
+    }
+
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v: &reqwest::header::HeaderValue| v.to_str().ok())
+            .map(|s| s.to_string())
+    };
+    let meta = CacheMeta {
+        url: url.to_string(),
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+        fetched_at: now_secs(),
+    };
+    let body = response
+        .text()
+        .await
+        .map_err(|e| Error::Network(format!("download failed: {}", e)))?;
+    let sig = fetch_signature(&client, url).await;
+    Ok(Fetched::Body(body, sig, meta))
+}
+
+fn status_for(
+    source: Source,
+    opts: &Options,
+    meta: Option<&CacheMeta>,
+    error: Option<String>,
+) -> Status {
+    let fetched_at = meta.map(|m| m.fetched_at);
+    Status {
+        source,
+        url: opts.url.to_string(),
+        fetched_at,
+        age_secs: fetched_at.map(|t| now_secs().saturating_sub(t)),
+        ttl_secs: opts.ttl_secs,
+        offline: opts.offline,
+        etag: meta.and_then(|m| m.etag.clone()),
+        last_modified: meta.and_then(|m| m.last_modified.clone()),
+        error,
+        signature_error: None,
+        overlay_applied: false,
+        overlay_error: None,
+    }
+}
+
+fn embedded(opts: &Options, error: Option<String>) -> Result<(Game, Status)> {
+    let game = super::embedded().map_err(|e| e.context("embedded servers.json is invalid"))?;
+    Ok((game, status_for(Source::Embedded, opts, None, error)))
+}
+
+// The freshest usable catalog and where it came from
+pub async fn load(opts: &Options<'_>) -> Result<(Game, Status)> {
+    let cached = read_cache(opts.dir, opts.url, opts.public_key);
+
+    if opts.offline {
+        return match cached {
+            Ok((game, meta)) => Ok((game, status_for(Source::Cache, opts, Some(&meta), None))),
+            Err(e) => embedded(opts, Some(format!("offline mode, {}", e))),
+        };
+    }
+
+    if let Ok((game, meta)) = &cached {
+        if now_secs().saturating_sub(meta.fetched_at) < opts.ttl_secs {
+            return Ok((
+                game.clone(),
+                status_for(Source::Cache, opts, Some(meta), None),
+            ));
+        }
+    }
+
+    let cached_meta = cached.as_ref().ok().map(|(_, m)| m);
+    let error = match fetch(opts.url, cached_meta).await {
+        Ok(Fetched::NotModified) => match cached {
+            Ok((game, mut meta)) => {
+                meta.fetched_at = now_secs();
+                let error = write_cache(opts.dir, None, &meta)
+                    .err()
+                    .map(|e| e.to_string());
+                return Ok((
+                    game,
+                    status_for(Source::Revalidated, opts, Some(&meta), error),
+                ));
+            }
+            Err(e) => return embedded(opts, Some(e.to_string())),
+        },
+        Ok(Fetched::Body(body, sig, meta)) => {
+            let verified = sig.and_then(|sig| {
+                signature::verify(opts.public_key, body.as_bytes(), &sig)?;
+                Ok(sig)
+            });
+            let sig = match verified {
+                Ok(sig) => sig,
+                Err(e) => {
+                    let (game, mut status) = embedded(
+                        opts,
+                        Some(format!("downloaded catalog is not trusted: {}", e)),
+                    )?;
+                    status.signature_error = Some(e.to_string());
+                    return Ok((game, status));
+                }
+            };
+            match super::parse(&body) {
+                Ok(game) => {
+                    // A cache write failure only costs us the next download
+                    let error = write_cache(opts.dir, Some((&body, &sig)), &meta)
+                        .err()
+                        .map(|e| e.to_string());
+                    return Ok((game, status_for(Source::Network, opts, Some(&meta), error)));
+                }
+                Err(e) => format!("downloaded catalog is invalid: {}", e),
+            }
+        }
+        Err(e) => e.to_string(),
+    };
+
+    match cached {
+        Ok((game, meta)) => Ok((
+            game,
+            status_for(Source::StaleCache, opts, Some(&meta), Some(error)),
+        )),
+        Err(cache_error) => embedded(opts, Some(format!("{}; {}", error, cache_error))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    // The throwaway key of signature.rs's tests, and two catalogs signed with it
+    const TEST_KEY: &str = "dW50cnVzdGVkIGNvbW1lbnQ6IG1pbmlzaWduIHB1YmxpYyBrZXkgMDgwNzA2MDUwNDAzMDIwMQpSV1FCQWdNRUJRWUhDS3lGWllma0Q0a3haRHdEbTRHMU04WXEzT2FUZFBIZU0vaXZUZHZVK01XMQo=";
+    const V1: &str = "{\"id\": \"game\", \"name\": \"Game v1\", \"clusters\": [{\"id\": \"eu\", \"name\": \"EU\", \"clusters\": [{\"id\": \"c1\", \"domain\": \"login.eu.example\", \"ips\": [\"10.0.0.1\"]}]}]}\n";
+    const V1_SIG: &str = "dW50cnVzdGVkIGNvbW1lbnQ6IHNpZ25hdHVyZSBmcm9tIHRhdXJpIHNlY3JldCBrZXkKUlVRQkFnTUVCUVlIQ0Q2TVdtSGZ0SkNCejYrTEtxL1FIQmFuc0p5bzBIekliUkgyM2pwc3o1dkxNQjVCaU9EL2xJYnoranNxTzVTbStxOWgyR1hwOEdrZ1RwMmpMWndIcVFzPQp0cnVzdGVkIGNvbW1lbnQ6IHRpbWVzdGFtcDoxNzYwNzQ1NjAwCWZpbGU6c2VydmVycy5qc29uCnRwb0R5dHZiRkZucGMrOW80SFN4TkF3OTh6bCtEVTFzcjF1eWpRSzhOSjZ1R0plUUdMcEl6MUpnZUdGQ3NGcVl6UlNRbCtLaGsxUTJKaE1nY2JyS0JRPT0K";
+    const V2: &str = "{\"id\": \"game\", \"name\": \"Game v2\", \"clusters\": [{\"id\": \"eu\", \"name\": \"EU\", \"clusters\": [{\"id\": \"c1\", \"domain\": \"login.eu.example\", \"ips\": [\"10.0.0.1\"]}]}]}\n";
+    const V2_SIG: &str = "dW50cnVzdGVkIGNvbW1lbnQ6IHNpZ25hdHVyZSBmcm9tIHRhdXJpIHNlY3JldCBrZXkKUlVRQkFnTUVCUVlIQ01UMlR5NzdXbnFDLzRFUmx5ekR3UDM3YjZsZ2szemZQS1ZpMGpPZWdsTmRUZzI0Z1dYNFk3ZFJCajU5bVpkZnpFcVRoN0pzS3FJWi9OT2NUOWVydndJPQp0cnVzdGVkIGNvbW1lbnQ6IHRpbWVzdGFtcDoxNzYwNzQ1NjAwCWZpbGU6c2VydmVycy5qc29uCk5xaGlQNEJ4N3RJc3hQU3VPSHZyQnhEbXI4WXBuVG5IdUNEbEEyU2dFZG1oUEY1QklOK0pVZzhtZlBtc0s5QUNuT2JralZZdEgyYXdPOEZsMnh2REJRPT0K";
+    // Nothing listens here
+    const DEAD_URL: &str = "http://127.0.0.1:1/servers.json";
+
+    // What the test server publishes: ETag, catalog and signature
+    type Published = Arc<Mutex<(String, &'static str, &'static str)>>;
+
+    // A server for servers.json and its .sig; returns the catalog URL and every If-None-Match it
+    // was sent ("" for none)
+    fn serve(published: Published) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/servers.json", listener.local_addr().unwrap());
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let mut reader = BufReader::new(&stream);
+                let mut lines = Vec::new();
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 2 {
+                    lines.push(line.trim().to_string());
+                    line.clear();
+                }
+                let (etag, body, sig) = published.lock().unwrap().clone();
+                let if_none_match = lines
+                    .iter()
+                    .find_map(|l| {
+                        l.to_lowercase()
+                            .strip_prefix("if-none-match: ")
+                            .map(String::from)
+                    })
+                    .unwrap_or_default();
+                let (status, body) = if lines[0].starts_with("GET /servers.json.sig ") {
+                    ("200 OK", sig.to_string())
+                } else {
+                    log.lock().unwrap().push(if_none_match.clone());
+                    if if_none_match == etag.to_lowercase() {
+                        ("304 Not Modified", String::new())
+                    } else {
+                        ("200 OK", body.to_string())
+                    }
+                };
+                let _ = write!(
+                    &stream,
+                    "HTTP/1.1 {}\r\nETag: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    etag,
+                    body.len(),
+                    body
+                );
+            }
+        });
+        (url, seen)
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "clusterbanned-cache-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn options<'a>(dir: &'a Path, url: &'a str, ttl_secs: u64, offline: bool) -> Options<'a> {
+        Options {
+            dir,
+            url,
+            public_key: TEST_KEY,
+            ttl_secs,
+            offline,
+        }
+    }
+
+    // A cached V1 for `url`, downloaded `age_secs` ago
+    fn cache_v1(dir: &Path, url: &str, age_secs: u64) {
+        let meta = CacheMeta {
+            url: url.into(),
+            etag: Some("\"v1\"".into()),
+            last_modified: None,
+            fetched_at: now_secs() - age_secs,
+        };
+        write_cache(dir, Some((V1, V1_SIG)), &meta).unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_cache_is_used_within_its_ttl_and_kept_when_stale() {
+        let dir = temp_dir("ttl");
+        cache_v1(&dir, DEAD_URL, 60);
+
+        let (game, status) = load(&options(&dir, DEAD_URL, 3600, false)).await.unwrap();
+        assert_eq!(game.name, "Game v1");
+        assert_eq!(status.source, Source::Cache);
+        assert_eq!(status.etag.as_deref(), Some("\"v1\""));
+        assert!(status.age_secs.unwrap() >= 60 && status.error.is_none());
+
+        // Past the TTL the download is tried; when it fails the stale cache still beats the
+        // compiled-in copy
+        let (game, status) = load(&options(&dir, DEAD_URL, 30, false)).await.unwrap();
+        assert_eq!(game.name, "Game v1");
+        assert_eq!(status.source, Source::StaleCache);
+        assert!(status.error.unwrap().starts_with("download failed"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn revalidates_with_the_etag() {
+        let dir = temp_dir("etag");
+        let published: Published = Arc::new(Mutex::new(("\"v1\"".into(), V1, V1_SIG)));
+        let (url, seen) = serve(published.clone());
+
+        let (game, status) = load(&options(&dir, &url, 0, false)).await.unwrap();
+        assert_eq!(
+            (game.name.as_str(), status.source),
+            ("Game v1", Source::Network)
+        );
+        assert_eq!(std::fs::read_to_string(sig_path(&dir)).unwrap(), V1_SIG);
+
+        // Unchanged: 304 and the cached copy, with a new download time
+        let meta = CacheMeta {
+            fetched_at: 1,
+            ..read_cache(&dir, &url, TEST_KEY).unwrap().1
+        };
+        assert_eq!(meta.etag.as_deref(), Some("\"v1\""));
+        write_cache(&dir, None, &meta).unwrap();
+        let (game, status) = load(&options(&dir, &url, 0, false)).await.unwrap();
+        assert_eq!(
+            (game.name.as_str(), status.source),
+            ("Game v1", Source::Revalidated)
+        );
+        assert!(status.fetched_at.unwrap() > 1);
+        assert!(read_cache(&dir, &url, TEST_KEY).unwrap().1.fetched_at > 1);
+
+        // Changed: the new catalog replaces the cache
+        *published.lock().unwrap() = ("\"v2\"".into(), V2, V2_SIG);
+        let (game, status) = load(&options(&dir, &url, 0, false)).await.unwrap();
+        assert_eq!(
+            (game.name.as_str(), status.source),
+            ("Game v2", Source::Network)
+        );
+        assert_eq!(status.etag.as_deref(), Some("\"v2\""));
+        assert_eq!(read_cache(&dir, &url, TEST_KEY).unwrap().0.name, "Game v2");
+
+        assert_eq!(*seen.lock().unwrap(), vec!["", "\"v1\"", "\"v1\""]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unsigned_downloads_and_caches_are_not_trusted() {
+        let dir = temp_dir("untrusted");
+        // Signed, but not this catalog
+        let published: Published = Arc::new(Mutex::new(("\"v2\"".into(), V2, V1_SIG)));
+        let (url, _) = serve(published);
+        cache_v1(&dir, &url, 3600);
+        std::fs::write(body_path(&dir), V2).unwrap();
+
+        let (game, status) = load(&options(&dir, &url, 60, false)).await.unwrap();
+        assert_eq!(status.source, Source::Embedded);
+        assert_ne!(game.name, "Game v2");
+        assert!(status
+            .signature_error
+            .unwrap()
+            .starts_with("signature rejected"));
+        // The cache was not overwritten with the untrusted download
+        assert_eq!(std::fs::read_to_string(sig_path(&dir)).unwrap(), V1_SIG);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn offline_mode_never_downloads() {
+        let dir = temp_dir("offline");
+        let (_, status) = load(&options(&dir, DEAD_URL, 0, true)).await.unwrap();
+        assert_eq!(status.source, Source::Embedded);
+        assert!(status
+            .error
+            .unwrap()
+            .starts_with("offline mode, no cached catalog"));
+
+        // However old the cache, but only the cache of this URL
+        cache_v1(&dir, DEAD_URL, 365 * 24 * 3600);
+        let (game, status) = load(&options(&dir, DEAD_URL, 0, true)).await.unwrap();
+        assert_eq!(
+            (game.name.as_str(), status.source),
+            ("Game v1", Source::Cache)
+        );
+        let other = "http://127.0.0.1:1/other.json";
+        let (_, status) = load(&options(&dir, other, 0, true)).await.unwrap();
+        assert_eq!(status.source, Source::Embedded);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,605 @@
+// User overlay over the shipped catalog, kept in catalog.overlay.json in the app config dir.
+//
+// Precedence, applied on top of whatever catalog was loaded (network, cache or embedded):
+// - Regions are matched by id. An unknown id adds a region (it then needs a name and at least
+//   one cluster); `removed` hides a shipped region.
+// - A region's name, alias_name and icon replace the shipped ones when set.
+// - Clusters are matched by id within their region, then by domain. An unknown cluster is added
+//   (it then needs a domain); `removed` hides a shipped cluster.
+// - A cluster's domain, location and probe replace the shipped ones when set. Its ips are added to the
+//   shipped ones, or replace them with `replaceIps`.
+// The merged catalog goes through the same validation as servers.json.
+
+use super::{is_valid_domain, Cluster, Game, Region};
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+const OVERLAY_FILE: &str = "catalog.overlay.json";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Overlay {
+    #[serde(default)]
+    pub regions: Vec<RegionOverride>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegionOverride {
+    pub id: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub alias_name: Option<String>,
+    #[serde(default)]
+    pub icon: Option<String>,
+    #[serde(default)]
+    pub removed: bool,
+    #[serde(default)]
+    pub clusters: Vec<ClusterOverride>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterOverride {
+    pub id: String,
+    #[serde(default)]
+    pub domain: Option<String>,
+    #[serde(default)]
+    pub location: Option<String>,
+    #[serde(default)]
+    pub ips: Vec<String>,
+    #[serde(default)]
+    pub replace_ips: bool,
+    #[serde(default)]
+    pub probe: Option<crate::probe::ProbeSpec>,
+    #[serde(default)]
+    pub removed: bool,
+}
+
+pub fn overlay_path(dir: &Path) -> PathBuf {
+    dir.join(OVERLAY_FILE)
+}
+
+// The saved overlay; no file means an empty one
+pub fn load(dir: &Path) -> Result<Overlay> {
+    let path = overlay_path(dir);
+    let text = match std::fs::read_to_string(&path) {
+        Ok(t) => t,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Overlay::default()),
+        Err(e) => return Err(Error::io(format!("failed to read {}", path.display()), e)),
+    };
+    let overlay: Overlay = serde_json::from_str(&text)
+        .map_err(|e| Error::Parse(format!("{} is invalid: {}", path.display(), e)))?;
+    overlay
+        .validate()
+        .map_err(|e| Error::Parse(format!("{} is invalid: {}", path.display(), e)))?;
+    Ok(overlay)
+}
+
+pub fn save(dir: &Path, overlay: &Overlay) -> Result<()> {
+    overlay.validate()?;
+    std::fs::create_dir_all(dir)
+        .map_err(|e| Error::io(format!("failed to create {}", dir.display()), e))?;
+    let text = serde_json::to_string_pretty(overlay)?;
+    crate::hosts::write_atomic(&overlay_path(dir), &text)
+}
+
+impl Overlay {
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    // Checks that don't need the base catalog
+    pub fn validate(&self) -> Result<()> {
+        let mut region_ids = std::collections::HashSet::new();
+        for (ri, region) in self.regions.iter().enumerate() {
+            if region.id.trim().is_empty() {
+                return Err(Error::InvalidInput(format!(
+                    "overlay region #{}: field `id` is empty",
+                    ri
+                )));
+            }
+            if !region_ids.insert(region.id.as_str()) {
+                return Err(Error::InvalidInput(format!(
+                    "overlay region '{}': duplicate region id",
+                    region.id
+                )));
+            }
+            let mut cluster_ids = std::collections::HashSet::new();
+            for (ci, cluster) in region.clusters.iter().enumerate() {
+                let ctx = format!(
+                    "overlay region '{}', cluster #{} ('{}')",
+                    region.id, ci, cluster.id
+                );
+                if cluster.id.trim().is_empty() {
+                    return Err(Error::InvalidInput(format!("{}: field `id` is empty", ctx)));
+                }
+                if !cluster_ids.insert(cluster.id.as_str()) {
+                    return Err(Error::InvalidInput(format!(
+                        "{}: duplicate cluster id",
+                        ctx
+                    )));
+                }
+                if let Some(domain) = &cluster.domain {
+                    if !is_valid_domain(domain) {
+                        return Err(Error::InvalidInput(format!(
+                            "{}: field `domain` is not a valid host name: {:?}",
+                            ctx, domain
+                        )));
+                    }
+                }
+                for (i, ip) in cluster.ips.iter().enumerate() {
+                    if ip.trim().parse::<IpAddr>().is_err() {
+                        return Err(Error::InvalidInput(format!(
+                            "{}: field `ips[{}]` is not an IP address: {:?}",
+                            ctx, i, ip
+                        )));
+                    }
+                }
+                if let Some(probe) = &cluster.probe {
+                    probe.validate().map_err(|e| {
+                        Error::InvalidInput(format!("{}: field `probe`: {}", ctx, e))
+                    })?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn region_mut(&mut self, id: &str) -> Option<&mut RegionOverride> {
+        self.regions.iter_mut().find(|r| r.id == id)
+    }
+
+    // Add or update a region override; its cluster overrides are kept
+    pub fn upsert_region(&mut self, region: RegionOverride) {
+        match self.region_mut(&region.id) {
+            Some(existing) => {
+                existing.name = region.name;
+                existing.alias_name = region.alias_name;
+                existing.icon = region.icon;
+                existing.removed = region.removed;
+                if !region.clusters.is_empty() {
+                    existing.clusters = region.clusters;
+                }
+            }
+            None => self.regions.push(region),
+        }
+    }
+
+    // Drop a region override, so the shipped region (if any) shows through again
+    pub fn remove_region(&mut self, id: &str) -> bool {
+        let before = self.regions.len();
+        self.regions.retain(|r| r.id != id);
+        self.regions.len() != before
+    }
+
+    pub fn upsert_cluster(&mut self, region_id: &str, cluster: ClusterOverride) {
+        if self.region_mut(region_id).is_none() {
+            self.regions.push(RegionOverride {
+                id: region_id.to_string(),
+                ..Default::default()
+            });
+        }
+        let region = self.region_mut(region_id).expect("region inserted above");
+        match region.clusters.iter_mut().find(|c| c.id == cluster.id) {
+            Some(existing) => *existing = cluster,
+            None => region.clusters.push(cluster),
+        }
+    }
+
+    // Add `ips` to a cluster's override, creating it if needed, without touching the rest of it
+    pub fn add_ips(&mut self, region_id: &str, cluster_id: &str, ips: &[String]) {
+        let mut cluster = self
+            .regions
+            .iter()
+            .find(|r| r.id == region_id)
+            .and_then(|r| r.clusters.iter().find(|c| c.id == cluster_id))
+            .cloned()
+            .unwrap_or_else(|| ClusterOverride {
+                id: cluster_id.to_string(),
+                ..Default::default()
+            });
+        for ip in ips {
+            let ip = ip.trim().to_string();
+            if !cluster.ips.contains(&ip) {
+                cluster.ips.push(ip);
+            }
+        }
+        self.upsert_cluster(region_id, cluster);
+    }
+
+    // Drop a cluster override; a region override left with nothing in it goes too
+    pub fn remove_cluster(&mut self, region_id: &str, cluster_id: &str) -> bool {
+        let removed = match self.region_mut(region_id) {
+            Some(region) => {
+                let before = region.clusters.len();
+                region.clusters.retain(|c| c.id != cluster_id);
+                region.clusters.len() != before
+            }
+            None => false,
+        };
+        self.regions.retain(|r| {
+            !r.clusters.is_empty()
+                || r.removed
+                || r.name.is_some()
+                || r.alias_name.is_some()
+                || r.icon.is_some()
+        });
+        removed
+    }
+
+    // `base` with this overlay applied, validated like servers.json
+    pub fn apply(&self, base: &Game) -> Result<Game> {
+        let mut game = base.clone();
+        for ov in &self.regions {
+            if ov.removed {
+                game.regions.retain(|r| r.id != ov.id);
+                continue;
+            }
+            let idx = match game.regions.iter().position(|r| r.id == ov.id) {
+                Some(i) => i,
+                None => {
+                    let name = ov.name.clone().ok_or_else(|| {
+                        Error::InvalidInput(format!(
+                            "overlay region '{}': new region needs a `name`",
+                            ov.id
+                        ))
+                    })?;
+                    game.regions.push(Region {
+                        id: ov.id.clone(),
+                        name,
+                        alias_name: None,
+                        icon: None,
+                        clusters: Vec::new(),
+                    });
+                    game.regions.len() - 1
+                }
+            };
+            let region = &mut game.regions[idx];
+            if let Some(name) = &ov.name {
+                region.name = name.clone();
+            }
+            if ov.alias_name.is_some() {
+                region.alias_name = ov.alias_name.clone();
+            }
+            if ov.icon.is_some() {
+                region.icon = ov.icon.clone();
+            }
+            for co in &ov.clusters {
+                apply_cluster(region, co)?;
+            }
+        }
+        // The base catalog is valid, so whatever fails here comes from the overlay
+        game.validate()
+            .map_err(|e| Error::InvalidInput(format!("overlay: {}", e)))?;
+        Ok(game)
+    }
+}
+
+fn apply_cluster(region: &mut Region, co: &ClusterOverride) -> Result<()> {
+    let existing = region
+        .clusters
+        .iter()
+        .position(|c| c.id == co.id)
+        .or_else(|| {
+            co.domain.as_ref().and_then(|d| {
+                region
+                    .clusters
+                    .iter()
+                    .position(|c| c.domain.eq_ignore_ascii_case(d))
+            })
+        });
+    let ips: Vec<String> = co.ips.iter().map(|ip| ip.trim().to_string()).collect();
+
+    match (existing, co.removed) {
+        (Some(i), true) => {
+            region.clusters.remove(i);
+        }
+        (None, true) => {}
+        (Some(i), false) => {
+            let cluster = &mut region.clusters[i];
+            if let Some(domain) = &co.domain {
+                cluster.domain = domain.clone();
+            }
+            if co.location.is_some() {
+                cluster.location = co.location.clone();
+            }
+            if co.probe.is_some() {
+                cluster.probe = co.probe;
+            }
+            if co.replace_ips {
+                cluster.ips = ips;
+            } else {
+                for ip in ips {
+                    if !cluster.ips.contains(&ip) {
+                        cluster.ips.push(ip);
+                    }
+                }
+            }
+        }
+        (None, false) => {
+            let domain = co.domain.clone().ok_or_else(|| {
+                Error::InvalidInput(format!(
+                    "overlay region '{}', cluster '{}': new cluster needs a `domain`",
+                    region.id, co.id
+                ))
+            })?;
+            region.clusters.push(Cluster {
+                id: co.id.clone(),
+                domain,
+                location: co.location.clone(),
+                ips,
+                probe: co.probe,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::{Method, ProbeSpec};
+
+    fn base() -> Game {
+        super::super::parse(
+            r#"{"id": "game", "name": "Game", "clusters": [
+                {"id": "eu", "name": "EU", "icon": "eu.png", "clusters": [
+                    {"id": "c1", "domain": "login.eu.example", "ips": ["10.0.0.1"]},
+                    {"id": "c2", "domain": "login2.eu.example", "location": "Amsterdam",
+                     "ips": ["10.0.0.2"]}
+                ]},
+                {"id": "na", "name": "NA", "clusters": [
+                    {"id": "c1", "domain": "login.na.example", "ips": []}
+                ]}
+            ]}"#,
+        )
+        .unwrap()
+    }
+
+    fn cluster(id: &str) -> ClusterOverride {
+        ClusterOverride {
+            id: id.into(),
+            ..Default::default()
+        }
+    }
+
+    fn region(id: &str, clusters: Vec<ClusterOverride>) -> RegionOverride {
+        RegionOverride {
+            id: id.into(),
+            clusters,
+            ..Default::default()
+        }
+    }
+
+    fn error(overlay: Overlay) -> String {
+        match overlay.apply(&base()) {
+            Err(Error::InvalidInput(m)) => m,
+            other => panic!("expected InvalidInput, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn overrides_take_precedence_over_the_shipped_catalog() {
+        let overlay = Overlay {
+            regions: vec![
+                RegionOverride {
+                    name: Some("Europe".into()),
+                    alias_name: Some("EU".into()),
+                    ..region(
+                        "eu",
+                        vec![
+                            // By id: ips are added, set fields replace shipped ones
+                            ClusterOverride {
+                                ips: vec![" 10.0.0.9".into(), "10.0.0.1".into()],
+                                probe: Some(ProbeSpec {
+                                    method: Method::Tcp,
+                                    port: Some(443),
+                                }),
+                                ..cluster("c1")
+                            },
+                            // By domain, whatever the id and case
+                            ClusterOverride {
+                                domain: Some("LOGIN2.eu.example".into()),
+                                ips: vec!["10.0.0.3".into()],
+                                replace_ips: true,
+                                ..cluster("mine")
+                            },
+                            ClusterOverride {
+                                domain: Some("login3.eu.example".into()),
+                                ..cluster("c3")
+                            },
+                        ],
+                    )
+                },
+                RegionOverride {
+                    removed: true,
+                    ..region("na", vec![])
+                },
+                RegionOverride {
+                    name: Some("Asia".into()),
+                    ..region(
+                        "asia",
+                        vec![ClusterOverride {
+                            domain: Some("login.asia.example".into()),
+                            ips: vec!["10.1.0.1".into()],
+                            ..cluster("c1")
+                        }],
+                    )
+                },
+            ],
+        };
+        let game = overlay.apply(&base()).unwrap();
+
+        let ids: Vec<&str> = game.regions.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec!["eu", "asia"]);
+        let eu = &game.regions[0];
+        assert_eq!(eu.name, "Europe");
+        assert_eq!(eu.alias_name.as_deref(), Some("EU"));
+        // Unset fields keep the shipped value
+        assert_eq!(eu.icon.as_deref(), Some("eu.png"));
+
+        let c1 = &eu.clusters[0];
+        assert_eq!(c1.ips, vec!["10.0.0.1", "10.0.0.9"]);
+        assert_eq!(c1.probe.map(|p| p.port), Some(Some(443)));
+        let c2 = &eu.clusters[1];
+        assert_eq!(
+            (c2.id.as_str(), c2.domain.as_str()),
+            ("c2", "LOGIN2.eu.example")
+        );
+        assert_eq!(c2.ips, vec!["10.0.0.3"]);
+        assert_eq!(c2.location.as_deref(), Some("Amsterdam"));
+        assert_eq!(eu.clusters[2].domain, "login3.eu.example");
+        assert!(eu.clusters[2].ips.is_empty());
+
+        assert_eq!(game.regions[1].name, "Asia");
+        assert_eq!(game.regions[1].clusters[0].ips, vec!["10.1.0.1"]);
+    }
+
+    #[test]
+    fn removed_clusters_are_hidden() {
+        let overlay = Overlay {
+            regions: vec![region(
+                "eu",
+                vec![
+                    ClusterOverride {
+                        removed: true,
+                        ..cluster("c1")
+                    },
+                    // Nothing to remove
+                    ClusterOverride {
+                        removed: true,
+                        ..cluster("c9")
+                    },
+                ],
+            )],
+        };
+        let game = overlay.apply(&base()).unwrap();
+        let ids: Vec<&str> = game.regions[0]
+            .clusters
+            .iter()
+            .map(|c| c.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["c2"]);
+    }
+
+    #[test]
+    fn what_the_overlay_gets_wrong_is_its_error() {
+        assert_eq!(
+            error(Overlay {
+                regions: vec![region("asia", vec![cluster("c1")])]
+            }),
+            "overlay region 'asia': new region needs a `name`"
+        );
+        assert_eq!(
+            error(Overlay {
+                regions: vec![region("eu", vec![cluster("c3")])]
+            }),
+            "overlay region 'eu', cluster 'c3': new cluster needs a `domain`"
+        );
+        // A new region without clusters fails the catalog's own validation
+        assert_eq!(
+            error(Overlay {
+                regions: vec![RegionOverride {
+                    name: Some("Asia".into()),
+                    ..region("asia", vec![])
+                }]
+            }),
+            "overlay: catalog region 'asia': field `clusters` is empty"
+        );
+        // Moving c2 onto c1's domain makes two clusters with one domain
+        assert_eq!(
+            error(Overlay {
+                regions: vec![region(
+                    "eu",
+                    vec![ClusterOverride {
+                        domain: Some("login.eu.example".into()),
+                        ..cluster("c2")
+                    }]
+                )]
+            }),
+            "overlay: catalog region 'eu', cluster #1 ('c2'): field `domain` duplicates another cluster: login.eu.example"
+        );
+    }
+
+    #[test]
+    fn remove_cluster_drops_emptied_region_overrides() {
+        let mut overlay = Overlay::default();
+        overlay.add_ips("eu", "c1", &["10.0.0.9".into()]);
+        overlay.upsert_cluster("eu", cluster("c2"));
+        overlay.upsert_region(RegionOverride {
+            name: Some("Renamed".into()),
+            ..region("na", vec![])
+        });
+        overlay.upsert_cluster("na", cluster("c1"));
+
+        assert!(overlay.remove_cluster("eu", "c1"));
+        assert!(!overlay.remove_cluster("eu", "c1"));
+        assert!(!overlay.remove_cluster("asia", "c1"));
+        assert_eq!(overlay.regions[0].clusters, vec![cluster("c2")]);
+
+        // Left with nothing: the region override goes
+        assert!(overlay.remove_cluster("eu", "c2"));
+        // Still renames the region: it stays
+        assert!(overlay.remove_cluster("na", "c1"));
+        let ids: Vec<&str> = overlay.regions.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec!["na"]);
+        assert!(overlay.remove_region("na"));
+        assert!(overlay.is_empty());
+    }
+
+    #[test]
+    fn add_ips_keeps_the_rest_of_the_override() {
+        let mut overlay = Overlay::default();
+        overlay.upsert_cluster(
+            "eu",
+            ClusterOverride {
+                location: Some("Frankfurt".into()),
+                ips: vec!["10.0.0.8".into()],
+                ..cluster("c1")
+            },
+        );
+        overlay.add_ips("eu", "c1", &[" 10.0.0.9 ".into(), "10.0.0.8".into()]);
+        assert_eq!(
+            overlay.regions[0].clusters,
+            vec![ClusterOverride {
+                location: Some("Frankfurt".into()),
+                ips: vec!["10.0.0.8".into(), "10.0.0.9".into()],
+                ..cluster("c1")
+            }]
+        );
+        // A region update without clusters leaves them alone
+        overlay.upsert_region(RegionOverride {
+            icon: Some("eu.svg".into()),
+            ..region("eu", vec![])
+        });
+        assert_eq!(overlay.regions[0].clusters.len(), 1);
+    }
+
+    #[test]
+    fn saves_and_loads() {
+        let dir =
+            std::env::temp_dir().join(format!("clusterbanned-overlay-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(load(&dir).unwrap().is_empty());
+
+        let mut overlay = Overlay::default();
+        overlay.add_ips("eu", "c1", &["10.0.0.9".into()]);
+        save(&dir, &overlay).unwrap();
+        assert_eq!(load(&dir).unwrap(), overlay);
+
+        // Invalid overlays are neither saved nor loaded
+        overlay.add_ips("eu", "c1", &["not an ip".into()]);
+        assert!(matches!(save(&dir, &overlay), Err(Error::InvalidInput(_))));
+        std::fs::write(
+            overlay_path(&dir),
+            r#"{"regions": [{"id": "eu"}, {"id": "eu"}]}"#,
+        )
+        .unwrap();
+        assert!(matches!(load(&dir), Err(Error::Parse(m)) if m.ends_with("duplicate region id")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,120 @@
+// Detached minisign signatures for the remote servers.json.
+//
+// The catalog is signed with the release signing key (`tauri signer sign servers.json`), so the
+// public key and the `.sig` format are the same as for the updater: base64 of the minisign
+// public key / signature files. Plain minisign text is accepted as well.
+
+use crate::error::{Error, Result};
+use base64::Engine;
+use minisign_verify::{PublicKey, Signature};
+
+// Same key as plugins.updater.pubkey in tauri.conf.json
+pub const PUBLIC_KEY: &str = "dW50cnVzdGVkIGNvbW1lbnQ6IG1pbmlzaWduIHB1YmxpYyBrZXk6IEM2MDREOUNFMjgyNDM0NDMKUldSRE5DUW96dGtFeG4vQUhkMmxCZ3ZWSWNNL3MrbmgyS2dxaVBMcUN5WHE0c1h2QlJaYkp6ZzYK";
+
+// Where the signature of a catalog published at `url` lives
+pub fn signature_url(url: &str) -> String {
+    format!("{}.sig", url)
+}
+
+// minisign text, unwrapping the base64 layer if there is one
+fn decode_text(data: &str) -> Result<String> {
+    let data = data.trim();
+    if data.starts_with("untrusted comment:") {
+        return Ok(data.to_string());
+    }
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .map_err(|e| Error::Parse(format!("not base64: {}", e)))?;
+    String::from_utf8(bytes).map_err(|_| Error::Parse("not UTF-8".into()))
+}
+
+fn public_key(key: &str) -> Result<PublicKey> {
+    let text = decode_text(key).map_err(|e| e.context("public key"))?;
+    PublicKey::decode(&text).map_err(|e| Error::Parse(format!("public key: {}", e)))
+}
+
+// Check `signature` (the contents of the .sig file) against `body` under `key`, PUBLIC_KEY
+// outside tests. Legacy (not prehashed) minisign signatures are refused.
+// (a bad signature makes the catalog as unusable as unreadable JSON, hence Parse)
+pub fn verify(key: &str, body: &[u8], signature: &str) -> Result<()> {
+    if signature.trim().is_empty() {
+        return Err(Error::Parse("signature is empty".into()));
+    }
+    let text = decode_text(signature).map_err(|e| e.context("malformed signature"))?;
+    let signature = Signature::decode(&text)
+        .map_err(|e| Error::Parse(format!("malformed signature: {}", e)))?;
+    public_key(key)?
+        .verify(body, &signature, false)
+        .map_err(|e| Error::Parse(format!("signature rejected: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A throwaway minisign key pair (key id 0807060504030201) made for these tests, in the
+    // base64-wrapped form `tauri signer` writes, and signatures of BODY
+    const TEST_KEY: &str = "dW50cnVzdGVkIGNvbW1lbnQ6IG1pbmlzaWduIHB1YmxpYyBrZXkgMDgwNzA2MDUwNDAzMDIwMQpSV1FCQWdNRUJRWUhDS3lGWllma0Q0a3haRHdEbTRHMU04WXEzT2FUZFBIZU0vaXZUZHZVK01XMQo=";
+    const BODY: &[u8] = b"{\"id\":\"game\"}\n";
+    const SIGNATURE: &str = "dW50cnVzdGVkIGNvbW1lbnQ6IHNpZ25hdHVyZSBmcm9tIHRhdXJpIHNlY3JldCBrZXkKUlVRQkFnTUVCUVlIQ0QxRU8xZXg5YXdleTlNVExiWnZHTjEzajMwdWlSWWlGdFNJeWpmdXFwZkhsSGt6Tkw2SS9ValBjWXpZZmFIRUxUS3dCNXhkUkVUUVFiakFYMS9LT3d3PQp0cnVzdGVkIGNvbW1lbnQ6IHRpbWVzdGFtcDoxNzYwNzQ1NjAwCWZpbGU6c2VydmVycy5qc29uCmFzTDJvck5KMWUzQyt0dXlzUnp6MkJoajVHQ1F4U0xCRUNLV0dHUVZNUW1YMDBkWEZuT01ZdzRhalBJdUw0aW5PVThnUG9kZCtQREt4WW5HZzdIWkNRPT0K";
+    // The same signature as plain minisign text
+    const SIGNATURE_TEXT: &str = "untrusted comment: signature from tauri secret key
+RUQBAgMEBQYHCD1EO1ex9awey9MTLbZvGN13j30uiRYiFtSIyjfuqpfHlHkzNL6I/UjPcYzYfaHELTKwB5xdRETQQbjAX1/KOww=
+trusted comment: timestamp:1760745600\tfile:servers.json
+asL2orNJ1e3C+tuysRzz2Bhj5GCQxSLBECKWGGQVMQmX00dXFnOMYw4ajPIuL4inOU8gPodd+PDKxYnGg7HZCQ==
+";
+    // BODY signed with another key
+    const OTHER_KEY_SIGNATURE: &str = "dW50cnVzdGVkIGNvbW1lbnQ6IHNpZ25hdHVyZSBmcm9tIHRhdXJpIHNlY3JldCBrZXkKUlVRUkVoTVVGUllYR01QZjVDTlVHMEExSE1VMlNIcjZyTFFQZFBkL3ppeGpyMGljVHF1M2taMVdCOEE4KzlONmViOEIrQTFTZ3dvNlM5YThXTkRvT3FHWUZTTUdsRFJUb1FVPQp0cnVzdGVkIGNvbW1lbnQ6IHRpbWVzdGFtcDoxNzYwNzQ1NjAwCWZpbGU6c2VydmVycy5qc29uCjBrZDAweFB1TkVUZ1JrcklNRU82SFRWWUtWd3ZPT2M4bU9vVmlnaktBaGx6c0N5dUREVHppeXg5OTQrakx1YzVGb1VkYkgyRmtyb1FybUNIQXRFTkJ3PT0K";
+    // BODY signed with the test key by the legacy algorithm (Ed25519 over the body itself)
+    const LEGACY_SIGNATURE: &str = "dW50cnVzdGVkIGNvbW1lbnQ6IHNpZ25hdHVyZSBmcm9tIHRhdXJpIHNlY3JldCBrZXkKUldRQkFnTUVCUVlIQ0RNRWd6K1Q4clEwU3V1dzJJQWJVZ2VKWHNUV01FVkR0UXNaZ0grS3NSSGdrU3NBQk5mSEdVcXNpeHZmSkJvcTFmeVB5cHZhSDIwRTZkYktzbHNDdVFnPQp0cnVzdGVkIGNvbW1lbnQ6IHRpbWVzdGFtcDoxNzYwNzQ1NjAwCWZpbGU6c2VydmVycy5qc29uCjJOT0VaVHAvd2Q4a3ZPWHp2TktrSktOSWY4TkdYMHdONk9iTzd1Z3U1dVdaWVUzMkhXaGNvSUhkdkpSektkaSt4RjY3bVBJeTVwVVB2MFgvbERGMkFRPT0K";
+
+    fn rejection(body: &[u8], signature: &str) -> String {
+        match verify(TEST_KEY, body, signature) {
+            Err(Error::Parse(m)) => m,
+            other => panic!("expected a Parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn accepts_a_valid_signature_in_either_form() {
+        verify(TEST_KEY, BODY, SIGNATURE).unwrap();
+        verify(TEST_KEY, BODY, SIGNATURE_TEXT).unwrap();
+        // Surrounding whitespace from the download doesn't matter
+        verify(TEST_KEY, BODY, &format!("\n{}\n", SIGNATURE)).unwrap();
+    }
+
+    #[test]
+    fn rejects_tampered_data() {
+        let tampered = b"{\"id\":\"gamf\"}\n";
+        assert!(rejection(tampered, SIGNATURE).starts_with("signature rejected"));
+    }
+
+    #[test]
+    fn rejects_a_signature_by_another_key() {
+        assert!(rejection(BODY, OTHER_KEY_SIGNATURE).starts_with("signature rejected"));
+        // ...and the test signature under the built-in key
+        assert!(verify(PUBLIC_KEY, BODY, SIGNATURE).is_err());
+    }
+
+    #[test]
+    fn rejects_a_legacy_signature() {
+        assert!(rejection(BODY, LEGACY_SIGNATURE).starts_with("signature rejected"));
+    }
+
+    #[test]
+    fn rejects_empty_and_malformed_signatures() {
+        assert_eq!(rejection(BODY, "  \n"), "signature is empty");
+        assert!(rejection(BODY, "not a signature!").starts_with("malformed signature"));
+        let truncated = SIGNATURE_TEXT
+            .lines()
+            .take(2)
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert!(rejection(BODY, &truncated).starts_with("malformed signature"));
+    }
+
+    #[test]
+    fn the_built_in_key_decodes() {
+        public_key(PUBLIC_KEY).unwrap();
+    }
+}
@@ -0,0 +1,554 @@
+// DNS discovery of cluster IPs. Every cluster domain is resolved (A and AAAA) against several
+// resolvers over several rounds, since login domains rotate through their addresses, and the
+// answers are compared with the catalog.
+//
+// Queries are plain RFC 1035 UDP messages built and parsed here, so any resolver address works,
+// including a stub server on 127.0.0.1 with a non-standard port.
+
+use crate::catalog::Game;
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::Duration;
+
+pub const DEFAULT_RESOLVERS: [&str; 4] = ["1.1.1.1:53", "8.8.8.8:53", "9.9.9.9:53", "77.88.8.8:53"];
+pub const DEFAULT_ROUNDS: u32 = 3;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+const ROUND_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub resolvers: Vec<SocketAddr>,
+    pub rounds: u32,
+    pub timeout: Duration,
+    pub interval: Duration,
+}
+
+impl Options {
+    // "1.1.1.1", "1.1.1.1:53", "[2606:4700::1111]:53" or "127.0.0.1:5353"
+    pub fn new(resolvers: &[String], rounds: u32) -> Result<Options> {
+        let resolvers = resolvers
+            .iter()
+            .map(|r| parse_resolver(r))
+            .collect::<Result<Vec<_>>>()?;
+        if resolvers.is_empty() {
+            return Err(Error::InvalidInput("no DNS resolvers configured".into()));
+        }
+        Ok(Options {
+            resolvers,
+            rounds: rounds.max(1),
+            timeout: QUERY_TIMEOUT,
+            interval: ROUND_INTERVAL,
+        })
+    }
+}
+
+fn parse_resolver(s: &str) -> Result<SocketAddr> {
+    let s = s.trim();
+    if let Ok(addr) = s.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    s.parse::<IpAddr>()
+        .map(|ip| SocketAddr::new(ip, 53))
+        .map_err(|_| Error::InvalidInput(format!("invalid DNS resolver address: {:?}", s)))
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiscoveredIp {
+    pub ip: String,
+    // Resolvers that returned it
+    pub resolvers: Vec<String>,
+    // Number of answers (across rounds and resolvers) that contained it
+    pub seen: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterDiscovery {
+    pub region_id: String,
+    pub cluster_id: String,
+    pub domain: String,
+    pub catalog_ips: Vec<String>,
+    pub discovered: Vec<DiscoveredIp>,
+    // Resolved but not in the catalog
+    pub new_ips: Vec<String>,
+    // In the catalog but never resolved (may be retired, or just not handed out right now)
+    pub missing_ips: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiscoveryReport {
+    pub resolvers: Vec<String>,
+    pub rounds: u32,
+    pub clusters: Vec<ClusterDiscovery>,
+}
+
+// IPs to add to one cluster in the user overlay
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IpAddition {
+    pub region_id: String,
+    pub cluster_id: String,
+    pub ips: Vec<String>,
+}
+
+fn next_query_id() -> u16 {
+    static COUNTER: AtomicU16 = AtomicU16::new(0);
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    (nanos as u16) ^ COUNTER.fetch_add(0x9e37, Ordering::Relaxed)
+}
+
+// A recursive query for `name`
+pub fn build_query(id: u16, name: &str, qtype: u16) -> Result<Vec<u8>> {
+    let mut msg = Vec::with_capacity(32 + name.len());
+    msg.extend_from_slice(&id.to_be_bytes());
+    msg.extend_from_slice(&0x0100u16.to_be_bytes()); // RD
+    msg.extend_from_slice(&1u16.to_be_bytes()); // QDCOUNT
+    msg.extend_from_slice(&[0; 6]); // AN, NS, AR
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(Error::InvalidInput(format!(
+                "invalid domain name: {:?}",
+                name
+            )));
+        }
+        msg.push(label.len() as u8);
+        msg.extend_from_slice(label.as_bytes());
+    }
+    msg.push(0);
+    msg.extend_from_slice(&qtype.to_be_bytes());
+    msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(msg)
+}
+
+fn truncated() -> Error {
+    Error::Parse("truncated DNS response".into())
+}
+
+fn read_u16(buf: &[u8], pos: usize) -> Result<u16> {
+    buf.get(pos..pos + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or_else(truncated)
+}
+
+// Position after the (possibly compressed) name starting at `pos`
+fn skip_name(buf: &[u8], mut pos: usize) -> Result<usize> {
+    loop {
+        let len = *buf.get(pos).ok_or_else(truncated)? as usize;
+        if len == 0 {
+            return Ok(pos + 1);
+        }
+        if len & 0xC0 == 0xC0 {
+            // Compression pointer: the name ends here as far as this record is concerned
+            return Ok(pos + 2);
+        }
+        pos += 1 + len;
+    }
+}
+
+// Addresses in the answer section of a response to query `id`; NXDOMAIN is an empty answer
+pub fn parse_response(id: u16, buf: &[u8]) -> Result<Vec<IpAddr>> {
+    if read_u16(buf, 0)? != id {
+        return Err(Error::Parse("DNS response id mismatch".into()));
+    }
+    let flags = read_u16(buf, 2)?;
+    if flags & 0x8000 == 0 {
+        return Err(Error::Parse("DNS message is not a response".into()));
+    }
+    match flags & 0x000F {
+        0 => {}
+        3 => return Ok(Vec::new()),
+        rcode => return Err(Error::Network(format!("DNS error (rcode {})", rcode))),
+    }
+    if flags & 0x0200 != 0 {
+        return Err(Error::Parse("DNS response truncated".into()));
+    }
+    let qdcount = read_u16(buf, 4)?;
+    let ancount = read_u16(buf, 6)?;
+
+    let mut pos = 12;
+    for _ in 0..qdcount {
+        pos = skip_name(buf, pos)? + 4;
+    }
+    let mut ips = Vec::new();
+    for _ in 0..ancount {
+        pos = skip_name(buf, pos)?;
+        let rtype = read_u16(buf, pos)?;
+        let class = read_u16(buf, pos + 2)?;
+        let rdlen = read_u16(buf, pos + 8)? as usize;
+        pos += 10;
+        let rdata = buf.get(pos..pos + rdlen).ok_or_else(truncated)?;
+        pos += rdlen;
+        if class != CLASS_IN {
+            continue;
+        }
+        // CNAMEs are followed by the resolver; their targets' records are in the same section
+        match (rtype, rdlen) {
+            (TYPE_A, 4) => ips.push(IpAddr::V4(Ipv4Addr::new(
+                rdata[0], rdata[1], rdata[2], rdata[3],
+            ))),
+            (TYPE_AAAA, 16) => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(rdata);
+                ips.push(IpAddr::V6(Ipv6Addr::from(octets)));
+            }
+            _ => {}
+        }
+    }
+    Ok(ips)
+}
+
+// One query to one resolver
+pub async fn query(
+    server: SocketAddr,
+    name: &str,
+    qtype: u16,
+    timeout: Duration,
+) -> Result<Vec<IpAddr>> {
+    let id = next_query_id();
+    let msg = build_query(id, name, qtype)?;
+    let bind: SocketAddr = if server.is_ipv6() {
+        "[::]:0".parse().unwrap()
+    } else {
+        "0.0.0.0:0".parse().unwrap()
+    };
+    let socket = tokio::net::UdpSocket::bind(bind)
+        .await
+        .map_err(|e| Error::Network(format!("failed to open UDP socket: {}", e)))?;
+    socket
+        .connect(server)
+        .await
+        .map_err(|e| Error::Network(format!("{}: {}", server, e)))?;
+    socket
+        .send(&msg)
+        .await
+        .map_err(|e| Error::Network(format!("{}: {}", server, e)))?;
+
+    let mut buf = [0u8; 1232];
+    // Skip stray datagrams (e.g. a late answer to an earlier query) until ours arrives
+    let wait = async {
+        loop {
+            let n = socket
+                .recv(&mut buf)
+                .await
+                .map_err(|e| Error::Network(format!("{}: {}", server, e)))?;
+            if buf[..n].get(..2) != Some(&id.to_be_bytes()[..]) {
+                continue;
+            }
+            return parse_response(id, &buf[..n]);
+        }
+    };
+    tokio::time::timeout(timeout, wait)
+        .await
+        .map_err(|_| Error::Network(format!("{}: no answer within {:?}", server, timeout)))?
+}
+
+// All addresses of `domain` one resolver knows (A and AAAA)
+async fn resolve(server: SocketAddr, domain: String, timeout: Duration) -> Result<Vec<IpAddr>> {
+    let (v4, v6) = tokio::join!(
+        query(server, &domain, TYPE_A, timeout),
+        query(server, &domain, TYPE_AAAA, timeout)
+    );
+    match (v4, v6) {
+        (Err(e), Err(_)) => Err(e),
+        (v4, v6) => Ok(v4
+            .unwrap_or_default()
+            .into_iter()
+            .chain(v6.unwrap_or_default())
+            .collect()),
+    }
+}
+
+// Resolve every cluster of `catalog` (or of one region) and compare with the catalog
+pub async fn discover(
+    catalog: &Game,
+    region_id: Option<&str>,
+    opts: &Options,
+) -> Result<DiscoveryReport> {
+    let clusters: Vec<_> = catalog
+        .clusters()
+        .filter(|(r, _)| region_id.map(|id| r.id == id).unwrap_or(true))
+        .collect();
+    if clusters.is_empty() {
+        return Err(Error::NotFound(match region_id {
+            Some(id) => format!("region {} not found in catalog", id),
+            None => "catalog has no clusters".into(),
+        }));
+    }
+
+    let domains: BTreeSet<String> = clusters
+        .iter()
+        .map(|(_, c)| c.domain.to_lowercase())
+        .collect();
+    // domain -> ip -> (resolvers, seen)
+    let mut found: BTreeMap<String, BTreeMap<IpAddr, (BTreeSet<String>, u32)>> = BTreeMap::new();
+    let mut errors: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+
+    for round in 0..opts.rounds {
+        if round > 0 {
+            tokio::time::sleep(opts.interval).await;
+        }
+        let mut tasks = tokio::task::JoinSet::new();
+        for domain in &domains {
+            for server in &opts.resolvers {
+                let (domain, server, timeout) = (domain.clone(), *server, opts.timeout);
+                tasks.spawn(async move {
+                    let result = resolve(server, domain.clone(), timeout).await;
+                    (domain, server, result)
+                });
+            }
+        }
+        while let Some(joined) = tasks.join_next().await {
+            let (domain, server, result) = match joined {
+                Ok(r) => r,
+                Err(e) => return Err(Error::Other(format!("DNS task failed: {}", e))),
+            };
+            match result {
+                Ok(ips) => {
+                    let per_ip = found.entry(domain).or_default();
+                    for ip in ips {
+                        let entry = per_ip.entry(ip).or_default();
+                        entry.0.insert(server.to_string());
+                        entry.1 += 1;
+                    }
+                }
+                Err(e) => {
+                    errors.entry(domain).or_default().insert(e.to_string());
+                }
+            }
+        }
+    }
+
+    let report = clusters
+        .iter()
+        .map(|(region, cluster)| {
+            let domain = cluster.domain.to_lowercase();
+            let catalog_ips: BTreeSet<IpAddr> = cluster.ip_addrs().into_iter().collect();
+            let resolved = found.get(&domain).cloned().unwrap_or_default();
+            let new_ips = resolved
+                .keys()
+                .filter(|ip| !catalog_ips.contains(ip))
+                .map(|ip| ip.to_string())
+                .collect();
+            let missing_ips = catalog_ips
+                .iter()
+                .filter(|ip| !resolved.contains_key(ip))
+                .map(|ip| ip.to_string())
+                .collect();
+            ClusterDiscovery {
+                region_id: region.id.clone(),
+                cluster_id: cluster.id.clone(),
+                domain: cluster.domain.clone(),
+                catalog_ips: cluster.ips.clone(),
+                discovered: resolved
+                    .into_iter()
+                    .map(|(ip, (resolvers, seen))| DiscoveredIp {
+                        ip: ip.to_string(),
+                        resolvers: resolvers.into_iter().collect(),
+                        seen,
+                    })
+                    .collect(),
+                new_ips,
+                missing_ips,
+                errors: errors
+                    .get(&domain)
+                    .map(|e| e.iter().cloned().collect())
+                    .unwrap_or_default(),
+            }
+        })
+        .collect();
+
+    Ok(DiscoveryReport {
+        resolvers: opts.resolvers.iter().map(|r| r.to_string()).collect(),
+        rounds: opts.rounds,
+        clusters: report,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TYPE_CNAME: u16 = 5;
+
+    // A response to `query` with the question copied and every record named by a pointer to it
+    fn response(query: &[u8], id: u16, rcode: u16, records: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut msg = Vec::new();
+        msg.extend_from_slice(&id.to_be_bytes());
+        msg.extend_from_slice(&(0x8180 | rcode).to_be_bytes());
+        msg.extend_from_slice(&1u16.to_be_bytes());
+        msg.extend_from_slice(&(records.len() as u16).to_be_bytes());
+        msg.extend_from_slice(&[0; 4]);
+        msg.extend_from_slice(&query[12..]);
+        for (rtype, rdata) in records {
+            msg.extend_from_slice(&0xC00Cu16.to_be_bytes());
+            msg.extend_from_slice(&rtype.to_be_bytes());
+            msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+            msg.extend_from_slice(&60u32.to_be_bytes());
+            msg.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            msg.extend_from_slice(rdata);
+        }
+        msg
+    }
+
+    // Stub resolver on 127.0.0.1: a.example has two A records (behind a CNAME) and one AAAA,
+    // every other name is NXDOMAIN. Each answer is preceded by a stray one with the wrong id.
+    async fn stub_resolver() -> SocketAddr {
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            loop {
+                let (n, from) = match socket.recv_from(&mut buf).await {
+                    Ok(r) => r,
+                    Err(_) => return,
+                };
+                let query = &buf[..n];
+                let id = read_u16(query, 0).unwrap();
+                let end = skip_name(query, 12).unwrap();
+                let qtype = read_u16(query, end).unwrap();
+                let name = &query[12..end];
+                let records = if name == b"\x01a\x07example\x00" {
+                    match qtype {
+                        TYPE_A => vec![
+                            (TYPE_CNAME, b"\x01b\xC0\x0E".to_vec()),
+                            (TYPE_A, vec![10, 0, 0, 1]),
+                            (TYPE_A, vec![10, 0, 0, 2]),
+                        ],
+                        _ => vec![(
+                            TYPE_AAAA,
+                            "2001:db8::1".parse::<Ipv6Addr>().unwrap().octets().to_vec(),
+                        )],
+                    }
+                } else {
+                    Vec::new()
+                };
+                let rcode = if records.is_empty() { 3 } else { 0 };
+                let _ = socket.send_to(&response(query, id ^ 1, 0, &[]), from).await;
+                let _ = socket
+                    .send_to(&response(query, id, rcode, &records), from)
+                    .await;
+            }
+        });
+        addr
+    }
+
+    fn catalog() -> Game {
+        serde_json::from_value(serde_json::json!({
+            "id": "test",
+            "name": "Test",
+            "clusters": [{
+                "id": "eu",
+                "name": "Europe",
+                "clusters": [
+                    { "id": "a", "domain": "A.example", "ips": ["10.0.0.1", "10.0.0.9"] },
+                    { "id": "b", "domain": "b.example", "ips": [] }
+                ]
+            }]
+        }))
+        .unwrap()
+    }
+
+    fn options(resolver: SocketAddr, timeout: Duration) -> Options {
+        Options {
+            resolvers: vec![resolver],
+            rounds: 2,
+            timeout,
+            interval: Duration::ZERO,
+        }
+    }
+
+    #[tokio::test]
+    async fn query_skips_stray_answers() {
+        let server = stub_resolver().await;
+        let ips = query(server, "a.example", TYPE_A, Duration::from_secs(2))
+            .await
+            .unwrap();
+        assert_eq!(
+            ips,
+            vec![
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            ]
+        );
+        let none = query(server, "missing.example", TYPE_A, Duration::from_secs(2))
+            .await
+            .unwrap();
+        assert!(none.is_empty());
+    }
+
+    #[tokio::test]
+    async fn discover_compares_with_catalog() {
+        let server = stub_resolver().await;
+        let report = discover(&catalog(), None, &options(server, Duration::from_secs(2)))
+            .await
+            .unwrap();
+        assert_eq!(report.rounds, 2);
+
+        let a = &report.clusters[0];
+        let found: Vec<(&str, u32)> = a
+            .discovered
+            .iter()
+            .map(|d| (d.ip.as_str(), d.seen))
+            .collect();
+        assert_eq!(
+            found,
+            vec![("10.0.0.1", 2), ("10.0.0.2", 2), ("2001:db8::1", 2)]
+        );
+        assert_eq!(a.discovered[0].resolvers, vec![server.to_string()]);
+        assert_eq!(a.new_ips, vec!["10.0.0.2", "2001:db8::1"]);
+        assert_eq!(a.missing_ips, vec!["10.0.0.9"]);
+        assert!(a.errors.is_empty());
+
+        let b = &report.clusters[1];
+        assert!(b.discovered.is_empty());
+        assert!(b.errors.is_empty());
+    }
+
+    #[tokio::test]
+    async fn silent_resolver_is_an_error() {
+        // Bound but never answering
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = silent.local_addr().unwrap();
+        let report = discover(
+            &catalog(),
+            Some("eu"),
+            &options(server, Duration::from_millis(200)),
+        )
+        .await
+        .unwrap();
+        let a = &report.clusters[0];
+        assert!(a.discovered.is_empty());
+        assert_eq!(a.missing_ips, vec!["10.0.0.1", "10.0.0.9"]);
+        assert!(a.errors[0].contains("no answer"), "{:?}", a.errors);
+
+        let missing = discover(&catalog(), Some("na"), &options(server, Duration::ZERO)).await;
+        assert!(matches!(missing, Err(Error::NotFound(_))));
+    }
+
+    #[test]
+    fn parse_response_rejects_bad_messages() {
+        let query = build_query(7, "a.example", TYPE_A).unwrap();
+        // The query itself is not a response
+        assert!(parse_response(7, &query).is_err());
+        assert!(parse_response(7, &response(&query, 7, 2, &[]))
+            .unwrap_err()
+            .to_string()
+            .contains("rcode 2"));
+        let mut truncated = response(&query, 7, 0, &[(TYPE_A, vec![1, 2, 3, 4])]);
+        truncated.pop();
+        assert!(parse_response(7, &truncated).is_err());
+    }
+}
@@ -0,0 +1,313 @@
+// Elevation on demand: relaunch the app elevated, or carry out one helper::Request in an elevated
+// copy of the app and bring its result back to this (unelevated) instance. UAC `runas` on
+// Windows, pkexec on Linux.
+//
+// The elevated copy is started with OPERATION_ARG <request as base64 JSON> and handles it before
+// Tauri starts (see main.rs). It answers with a helper response line: on stdout under pkexec, in
+// the file passed with RESULT_ARG on Windows, where the output of an elevated process can't be
+// captured.
+
+use crate::error::{Error, Result};
+use crate::helper::{self, Request, Response};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde_json::Value;
+use std::path::PathBuf;
+
+pub const OPERATION_ARG: &str = "--elevated-operation";
+#[cfg(windows)]
+pub const RESULT_ARG: &str = "--elevated-result";
+
+#[cfg(windows)]
+mod shell {
+    use std::ffi::{c_void, OsStr};
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::Path;
+
+    pub const SW_HIDE: i32 = 0;
+    pub const SW_SHOWNORMAL: i32 = 1;
+    pub const ERROR_CANCELLED: i32 = 1223;
+    const SEE_MASK_NOCLOSEPROCESS: u32 = 0x40;
+    const SEE_MASK_NOASYNC: u32 = 0x100;
+    const INFINITE: u32 = 0xFFFF_FFFF;
+
+    // SHELLEXECUTEINFOW
+    #[repr(C)]
+    struct ShellExecuteInfo {
+        size: u32,
+        mask: u32,
+        hwnd: *mut c_void,
+        verb: *const u16,
+        file: *const u16,
+        parameters: *const u16,
+        directory: *const u16,
+        show: i32,
+        instance: *mut c_void,
+        id_list: *mut c_void,
+        class: *const u16,
+        class_key: *mut c_void,
+        hot_key: u32,
+        icon_or_monitor: *mut c_void,
+        process: *mut c_void,
+    }
+
+    #[link(name = "shell32")]
+    extern "system" {
+        fn ShellExecuteExW(info: *mut ShellExecuteInfo) -> i32;
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn WaitForSingleObject(handle: *mut c_void, milliseconds: u32) -> u32;
+        fn GetExitCodeProcess(handle: *mut c_void, code: *mut u32) -> i32;
+        fn CloseHandle(handle: *mut c_void) -> i32;
+    }
+
+    fn wide(s: &OsStr) -> Vec<u16> {
+        s.encode_wide().chain(std::iter::once(0)).collect()
+    }
+
+    // Start `file` through the UAC prompt; with `wait`, until it exits, returning its exit code.
+    // A declined prompt is ERROR_CANCELLED.
+    pub fn runas(
+        file: &Path,
+        parameters: &str,
+        show: i32,
+        wait: bool,
+    ) -> std::io::Result<Option<u32>> {
+        let verb = wide(OsStr::new("runas"));
+        let file = wide(file.as_os_str());
+        let parameters = wide(OsStr::new(parameters));
+        let mut info = ShellExecuteInfo {
+            size: std::mem::size_of::<ShellExecuteInfo>() as u32,
+            mask: SEE_MASK_NOASYNC | if wait { SEE_MASK_NOCLOSEPROCESS } else { 0 },
+            hwnd: std::ptr::null_mut(),
+            verb: verb.as_ptr(),
+            file: file.as_ptr(),
+            parameters: parameters.as_ptr(),
+            directory: std::ptr::null(),
+            show,
+            instance: std::ptr::null_mut(),
+            id_list: std::ptr::null_mut(),
+            class: std::ptr::null(),
+            class_key: std::ptr::null_mut(),
+            hot_key: 0,
+            icon_or_monitor: std::ptr::null_mut(),
+            process: std::ptr::null_mut(),
+        };
+        if unsafe { ShellExecuteExW(&mut info) } == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if !wait || info.process.is_null() {
+            return Ok(None);
+        }
+
+        let mut code = 0u32;
+        let ok = unsafe {
+            WaitForSingleObject(info.process, INFINITE);
+            let ok = GetExitCodeProcess(info.process, &mut code);
+            CloseHandle(info.process);
+            ok
+        };
+        if ok == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Some(code))
+    }
+}
+
+fn cancelled() -> Error {
+    Error::PermissionDenied("elevation was cancelled".into())
+}
+
+fn encode(request: &Request) -> Result<String> {
+    Ok(URL_SAFE_NO_PAD.encode(serde_json::to_vec(request)?))
+}
+
+fn decode(text: &str) -> Result<Request> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(text)
+        .map_err(|e| Error::InvalidInput(format!("invalid elevated operation: {}", e)))?;
+    serde_json::from_slice(&bytes)
+        .map_err(|e| Error::InvalidInput(format!("invalid elevated operation: {}", e)))
+}
+
+// The executable to start elevated; for an AppImage the image itself, since root can't see the
+// user's FUSE mount it runs from
+fn executable() -> Result<PathBuf> {
+    #[cfg(target_os = "linux")]
+    if let Some(image) = std::env::var_os("APPIMAGE").filter(|p| !p.is_empty()) {
+        return Ok(image.into());
+    }
+    std::env::current_exe().map_err(|e| Error::io("failed to locate the app executable", e))
+}
+
+// Start a new, elevated instance of the app; the caller exits this one on success
+pub fn relaunch() -> Result<()> {
+    #[cfg(windows)]
+    {
+        let exe = executable()?;
+        match shell::runas(&exe, "", shell::SW_SHOWNORMAL, false) {
+            Ok(_) => Ok(()),
+            Err(e) if e.raw_os_error() == Some(shell::ERROR_CANCELLED) => Err(cancelled()),
+            Err(e) => Err(Error::io("failed to relaunch the app elevated", e)),
+        }
+    }
+
+    #[cfg(not(windows))]
+    {
+        // A whole webview running as root is asking for trouble; pkexec is for single operations
+        Err(Error::Unsupported(
+            "relaunching the app elevated is only supported on Windows; \
+             run single operations elevated or start clusterbanned-helper instead"
+                .into(),
+        ))
+    }
+}
+
+// Carry out `request` in an elevated copy of the app and return its result
+pub async fn run(request: Request) -> Result<Value> {
+    // The elevated copy runs with root's (or the administrator's) settings
+    let request = helper::with_user_settings(request).await;
+    request.validate()?;
+    let exe = executable()?;
+    let operation = encode(&request)?;
+
+    #[cfg(windows)]
+    {
+        // A fresh directory, so the elevated copy creates the result file rather than opening one
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        let dir = std::env::temp_dir().join(format!(
+            "clusterbanned-elevated-{}-{}",
+            std::process::id(),
+            nanos
+        ));
+        std::fs::create_dir(&dir)
+            .map_err(|e| Error::io(format!("failed to create {}", dir.display()), e))?;
+        let result_path = dir.join("result.json");
+        let parameters = format!(
+            "{} {} {} \"{}\"",
+            OPERATION_ARG,
+            operation,
+            RESULT_ARG,
+            result_path.display()
+        );
+
+        let started = tokio::task::spawn_blocking(move || {
+            shell::runas(&exe, &parameters, shell::SW_HIDE, true)
+        })
+        .await
+        .map_err(|e| Error::Other(format!("elevated operation panicked: {}", e)))?;
+        let answer = std::fs::read_to_string(&result_path);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        match started {
+            Err(e) if e.raw_os_error() == Some(shell::ERROR_CANCELLED) => return Err(cancelled()),
+            Err(e) => return Err(Error::io("failed to start the app elevated", e)),
+            Ok(_) => {}
+        }
+        let answer = answer.map_err(|e| Error::io("the elevated app left no result", e))?;
+        Response::parse(answer.trim())
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    {
+        let output = tokio::process::Command::new("pkexec")
+            .arg(&exe)
+            .arg(OPERATION_ARG)
+            .arg(&operation)
+            .output()
+            .await
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => {
+                    Error::Unsupported("pkexec is not installed".into())
+                }
+                _ => Error::io("failed to run pkexec", e),
+            })?;
+        // 126: the authentication dialog was dismissed; 127: not authorized
+        match output.status.code() {
+            Some(126) => return Err(cancelled()),
+            Some(127) => {
+                return Err(Error::PermissionDenied(format!(
+                    "not authorized to run the app elevated: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )))
+            }
+            _ => {}
+        }
+        // Only the last line is the answer; anything before it is the backend's own output
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        match stdout.lines().rev().find(|l| !l.trim().is_empty()) {
+            Some(line) => Response::parse(line),
+            None => Err(Error::Command(format!(
+                "the elevated app failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))),
+        }
+    }
+
+    #[cfg(not(any(windows, all(unix, not(target_os = "macos")))))]
+    {
+        let _ = (exe, operation);
+        Err(Error::Unsupported(
+            "running operations elevated is not supported on this platform".into(),
+        ))
+    }
+}
+
+// In the elevated copy: carry out the operation passed on the command line, if any, report the
+// result and return the exit code for the process
+pub fn serve_from_args() -> Option<i32> {
+    let args: Vec<String> = std::env::args().collect();
+    let value_of = |name: &str| {
+        args.iter()
+            .position(|a| a == name)
+            .and_then(|i| args.get(i + 1))
+            .cloned()
+    };
+    let operation = value_of(OPERATION_ARG)?;
+
+    let outcome = decode(&operation).and_then(|request| {
+        // Validated again: the command line is as much input as the helper's socket. Unlike the
+        // helper, there is no catalog check: whoever passed the UAC / pkexec prompt vouched for
+        // the clusters and IPs, overlay and discovered ones included.
+        request.validate()?;
+        eprintln!("[ELEVATED] {:?}", request);
+        tokio::runtime::Runtime::new()
+            .map_err(|e| Error::io("failed to start the async runtime", e))?
+            .block_on(helper::handle(request))
+    });
+    let code = if outcome.is_ok() { 0 } else { 1 };
+    let line = match serde_json::to_string(&Response::from(outcome)) {
+        Ok(line) => line,
+        Err(e) => {
+            eprintln!("[ELEVATED] {}", e);
+            return Some(1);
+        }
+    };
+
+    #[cfg(windows)]
+    {
+        use std::io::Write;
+        let written = value_of(RESULT_ARG)
+            .ok_or_else(|| std::io::Error::other(format!("{} is missing", RESULT_ARG)))
+            .and_then(|path| {
+                std::fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(path)
+            })
+            .and_then(|mut file| file.write_all(line.as_bytes()));
+        if let Err(e) = written {
+            eprintln!("[ELEVATED] failed to write the result: {}", e);
+            return Some(1);
+        }
+    }
+    #[cfg(not(windows))]
+    println!("{}", line);
+
+    Some(code)
+}
@@ -0,0 +1,139 @@
+// The backend's error type. Commands return it to the frontend as {code, message}: the message is
+// for people, the code is stable so the UI can react to it (e.g. offer to elevate on
+// "permission-denied").
+
+use serde::ser::SerializeStruct;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    // Writing hosts or firewall rules without the rights for it
+    #[error("{0}")]
+    PermissionDenied(String),
+    // The helper won't do it for an unprivileged caller (a cluster or IP outside its own
+    // catalog); an elevated process still can
+    #[error("{0}")]
+    NotAuthorized(String),
+    // A file, region, cluster, backup or process that isn't there
+    #[error("{0}")]
+    NotFound(String),
+    // Unreadable JSON, servers.json or tool output
+    #[error("{0}")]
+    Parse(String),
+    // Arguments the command can't work with
+    #[error("{0}")]
+    InvalidInput(String),
+    // Not available on this platform or without the needed tools
+    #[error("{0}")]
+    Unsupported(String),
+    // Downloading the catalog or reaching a server
+    #[error("{0}")]
+    Network(String),
+    // An external tool (netsh, nft, iptables, ...) that ran and failed
+    #[error("{0}")]
+    Command(String),
+    #[error("{0}")]
+    Io(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::PermissionDenied(_) => "permission-denied",
+            Error::NotAuthorized(_) => "not-authorized",
+            Error::NotFound(_) => "not-found",
+            Error::Parse(_) => "parse",
+            Error::InvalidInput(_) => "invalid-input",
+            Error::Unsupported(_) => "unsupported",
+            Error::Network(_) => "network",
+            Error::Command(_) => "command-failed",
+            Error::Io(_) => "io",
+            Error::Other(_) => "other",
+        }
+    }
+
+    // The error `code()` stands for; unknown codes become Other
+    pub fn from_code(code: &str, message: String) -> Error {
+        match code {
+            "permission-denied" => Error::PermissionDenied(message),
+            "not-authorized" => Error::NotAuthorized(message),
+            "not-found" => Error::NotFound(message),
+            "parse" => Error::Parse(message),
+            "invalid-input" => Error::InvalidInput(message),
+            "unsupported" => Error::Unsupported(message),
+            "network" => Error::Network(message),
+            "command-failed" => Error::Command(message),
+            "io" => Error::Io(message),
+            _ => Error::Other(message),
+        }
+    }
+
+    // `context: e`, classified by the kind of the I/O error
+    pub fn io(context: impl std::fmt::Display, e: std::io::Error) -> Error {
+        let message = format!("{}: {}", context, e);
+        match e.kind() {
+            std::io::ErrorKind::PermissionDenied => Error::PermissionDenied(message),
+            std::io::ErrorKind::NotFound => Error::NotFound(message),
+            _ => Error::Io(message),
+        }
+    }
+
+    // A failed external command. Without the privileges firewall tools need, elevating is what
+    // to try next, so it's a privilege problem; with them it's the tool's own failure. The
+    // message is not looked at: it is localized on Windows.
+    pub fn command(message: impl Into<String>) -> Error {
+        let message = message.into();
+        match crate::capabilities::privileged() {
+            Some(false) => Error::PermissionDenied(message),
+            _ => Error::Command(message),
+        }
+    }
+
+    // The same kind of error with `context: ` in front of the message
+    pub fn context(self, context: impl std::fmt::Display) -> Error {
+        let wrap = |m: String| format!("{}: {}", context, m);
+        match self {
+            Error::PermissionDenied(m) => Error::PermissionDenied(wrap(m)),
+            Error::NotAuthorized(m) => Error::NotAuthorized(wrap(m)),
+            Error::NotFound(m) => Error::NotFound(wrap(m)),
+            Error::Parse(m) => Error::Parse(wrap(m)),
+            Error::InvalidInput(m) => Error::InvalidInput(wrap(m)),
+            Error::Unsupported(m) => Error::Unsupported(wrap(m)),
+            Error::Network(m) => Error::Network(wrap(m)),
+            Error::Command(m) => Error::Command(wrap(m)),
+            Error::Io(m) => Error::Io(wrap(m)),
+            Error::Other(m) => Error::Other(wrap(m)),
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        Error::Parse(e.to_string())
+    }
+}
+
+impl serde::Serialize for Error {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Error", 2)?;
+        s.serialize_field("code", self.code())?;
+        s.serialize_field("message", &self.to_string())?;
+        s.end()
+    }
+}
+
+// Errors coming back from the privileged helper
+impl<'de> serde::Deserialize<'de> for Error {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Error, D::Error> {
+        #[derive(serde::Deserialize)]
+        struct Coded {
+            code: String,
+            message: String,
+        }
+        let e = Coded::deserialize(deserializer)?;
+        Ok(Error::from_code(&e.code, e.message))
+    }
+}
@@ -0,0 +1,554 @@
+// Firewall backends: netsh on Windows, nftables (or plain iptables as a fallback) on Linux.
+
+use crate::catalog::Game;
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+
+#[cfg(target_os = "linux")]
+pub mod iptables;
+#[cfg(windows)]
+pub mod netsh;
+#[cfg(target_os = "linux")]
+pub mod nftables;
+
+// What a backend would do for a change, without doing it
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FirewallPlan {
+    pub backend: String,
+    // Commands in the order they would run
+    pub commands: Vec<String>,
+    // Existing rules or set entries the change would remove
+    pub deletes: Vec<String>,
+}
+
+// One rule (or nftables set share) this app owns, in backend-neutral terms
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FirewallRule {
+    pub name: String,
+    // "out" or "in"
+    pub direction: String,
+    // "block" or "allow"; nftables/iptables DROP counts as block
+    pub action: String,
+    pub remote_ips: Vec<String>,
+    pub enabled: bool,
+    // Windows firewall profiles; other backends have none
+    pub profile: Option<String>,
+    // The cluster domain the rule was created for, when the backend can tell
+    pub domain: Option<String>,
+    pub region_id: Option<String>,
+}
+
+// A Windows Firewall profile (Domain, Private, Public): whether it filters, and whether Group
+// Policy has a say in it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FirewallProfile {
+    pub name: String,
+    pub enabled: bool,
+    // Rules added locally (ours) take effect; Group Policy can switch them off
+    pub local_rules_allowed: bool,
+    // Group Policy sets whether the profile is on or whether local rules apply
+    pub policy_managed: bool,
+}
+
+pub trait FirewallBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Block (`enable`) or unblock the IPs of one cluster domain of `region`
+    fn set_blocked(
+        &self,
+        region: &str,
+        domain: &str,
+        ips: &[String],
+        enable: bool,
+    ) -> Result<String>;
+
+    // The commands `set_blocked` would run for each (domain, ips) target, in order
+    fn plan(
+        &self,
+        region: &str,
+        targets: &[(String, Vec<String>)],
+        enable: bool,
+    ) -> Result<FirewallPlan>;
+
+    // Remove everything this app has created. The catalog, when available, lets backends that
+    // name rules after domains also catch rules the listing missed.
+    fn clear(&self, catalog: Option<&Game>) -> Result<String>;
+
+    // The rules this app owns. The catalog, when available, lets backends that only keep a
+    // mangled domain in the rule name map rules back to their cluster.
+    fn inventory(&self, catalog: Option<&Game>) -> Result<Vec<FirewallRule>>;
+
+    // Human-readable list of the rules this app owns
+    fn rules(&self) -> Result<Vec<String>> {
+        Ok(self
+            .inventory(None)?
+            .iter()
+            .map(|r| {
+                format!(
+                    "{} ({} {}{}): {}",
+                    r.name,
+                    r.direction,
+                    r.action,
+                    if r.enabled { "" } else { ", disabled" },
+                    r.remote_ips.join(", ")
+                )
+            })
+            .collect())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleStatus {
+    // Matches its catalog cluster
+    Ok,
+    // Belongs to a catalog cluster, but the addresses or settings differ
+    Drift,
+    // No catalog cluster owns it
+    Orphaned,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InventoryEntry {
+    #[serde(flatten)]
+    pub rule: FirewallRule,
+    pub cluster_id: Option<String>,
+    pub status: RuleStatus,
+    // Addresses in the rule that the catalog no longer lists for the cluster
+    pub stale_ips: Vec<String>,
+    // Catalog addresses of the cluster that none of its rules block
+    pub missing_ips: Vec<String>,
+    pub problems: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Inventory {
+    pub backend: String,
+    pub rules: Vec<InventoryEntry>,
+    pub orphaned: usize,
+    pub drifted: usize,
+}
+
+// Rule addresses are single IPs, "a-a" ranges or /32 and /128 networks depending on the backend
+fn rule_addr(s: &str) -> Option<IpAddr> {
+    let s = s.trim();
+    let s = match s.split_once('-') {
+        Some((from, to)) if from == to => from,
+        _ => s,
+    };
+    let s = s
+        .strip_suffix("/32")
+        .or_else(|| s.strip_suffix("/128"))
+        .or_else(|| s.strip_suffix("/255.255.255.255"))
+        .unwrap_or(s);
+    s.parse().ok()
+}
+
+// Whether one of `rules` drops outgoing traffic to `ip`
+pub fn blocks(rules: &[FirewallRule], ip: IpAddr) -> bool {
+    rules.iter().any(|r| {
+        r.enabled
+            && r.direction == "out"
+            && r.action == "block"
+            && r.remote_ips.iter().any(|s| rule_addr(s) == Some(ip))
+    })
+}
+
+// Longer address lists of one cluster are cut to this many; the helper refuses them
+pub const MAX_IPS_PER_DOMAIN: usize = 64;
+
+// Firewall rules for anything else (loopback, private networks, multicast) could cut this
+// machine off its own network
+pub fn public_unicast(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            !(v4.is_unspecified()
+                || v4.is_loopback()
+                || v4.is_private()
+                || v4.is_link_local()
+                || v4.is_multicast()
+                || v4.is_broadcast())
+        }
+        IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            !(v6.is_unspecified()
+                || v6.is_loopback()
+                || v6.is_multicast()
+                // Unique local fc00::/7 and link-local fe80::/10
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80)
+        }
+    }
+}
+
+// The addresses of a cluster that firewall rules may block: the public unicast ones among `ips`,
+// normalized, at most MAX_IPS_PER_DOMAIN of them
+pub fn rule_targets(ips: &[String]) -> Vec<String> {
+    ips.iter()
+        .filter_map(|ip| ip.trim().parse::<IpAddr>().ok())
+        .filter(|ip| public_unicast(*ip))
+        .map(|ip| ip.to_string())
+        .take(MAX_IPS_PER_DOMAIN)
+        .collect()
+}
+
+// Map `rules` back to catalog clusters and flag the ones nothing owns or that no longer match
+pub fn audit(backend: &str, rules: Vec<FirewallRule>, catalog: &Game) -> Inventory {
+    let owner = |rule: &FirewallRule| {
+        let domain = rule.domain.as_deref()?;
+        catalog
+            .clusters()
+            .filter(|(_, c)| c.domain.eq_ignore_ascii_case(domain))
+            .find(|(r, _)| {
+                rule.region_id
+                    .as_deref()
+                    .map(|id| r.id == id)
+                    .unwrap_or(true)
+            })
+    };
+
+    // A cluster can be covered by several rules (the netsh per-IP fallback, nftables v4 and v6
+    // sets), so missing addresses are counted against all of them together
+    let mut covered: Vec<(String, BTreeSet<IpAddr>)> = Vec::new();
+    for rule in &rules {
+        if let Some((region, cluster)) = owner(rule) {
+            let key = format!("{}/{}", region.id, cluster.id);
+            let ips = rule.remote_ips.iter().filter_map(|s| rule_addr(s));
+            match covered.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, set)) => set.extend(ips),
+                None => covered.push((key, ips.collect())),
+            }
+        }
+    }
+
+    let mut entries = Vec::new();
+    for rule in rules {
+        let mut problems = Vec::new();
+        let (cluster_id, region_id, stale_ips, missing_ips) = match owner(&rule) {
+            Some((region, cluster)) => {
+                let catalog_ips: BTreeSet<IpAddr> = cluster.ip_addrs().into_iter().collect();
+                let stale: Vec<String> = rule
+                    .remote_ips
+                    .iter()
+                    .filter(|s| {
+                        rule_addr(s)
+                            .map(|ip| !catalog_ips.contains(&ip))
+                            .unwrap_or(true)
+                    })
+                    .cloned()
+                    .collect();
+                let key = format!("{}/{}", region.id, cluster.id);
+                let blocked = covered
+                    .iter()
+                    .find(|(k, _)| *k == key)
+                    .map(|(_, set)| set.clone())
+                    .unwrap_or_default();
+                let missing: Vec<String> = catalog_ips
+                    .difference(&blocked)
+                    .map(|ip| ip.to_string())
+                    .collect();
+                if !stale.is_empty() {
+                    problems.push(format!(
+                        "{} address(es) no longer in the catalog: {}",
+                        stale.len(),
+                        stale.join(", ")
+                    ));
+                }
+                if !missing.is_empty() {
+                    problems.push(format!(
+                        "{} catalog address(es) not blocked: {}",
+                        missing.len(),
+                        missing.join(", ")
+                    ));
+                }
+                (
+                    Some(cluster.id.clone()),
+                    Some(region.id.clone()),
+                    stale,
+                    missing,
+                )
+            }
+            None => {
+                problems.push(match &rule.domain {
+                    Some(d) => format!("{} is not in the catalog", d),
+                    None => "rule does not belong to any catalog cluster".to_string(),
+                });
+                (None, rule.region_id.clone(), Vec::new(), Vec::new())
+            }
+        };
+        if !rule.enabled {
+            problems.push("rule is disabled".into());
+        }
+        if rule.direction != "out" {
+            problems.push(format!(
+                "rule direction is {}, expected out",
+                rule.direction
+            ));
+        }
+        if rule.action != "block" {
+            problems.push(format!("rule action is {}, expected block", rule.action));
+        }
+
+        let status = if cluster_id.is_none() {
+            RuleStatus::Orphaned
+        } else if problems.is_empty() {
+            RuleStatus::Ok
+        } else {
+            RuleStatus::Drift
+        };
+        entries.push(InventoryEntry {
+            rule: FirewallRule { region_id, ..rule },
+            cluster_id,
+            status,
+            stale_ips,
+            missing_ips,
+            problems,
+        });
+    }
+
+    Inventory {
+        backend: backend.to_string(),
+        orphaned: entries
+            .iter()
+            .filter(|e| e.status == RuleStatus::Orphaned)
+            .count(),
+        drifted: entries
+            .iter()
+            .filter(|e| e.status == RuleStatus::Drift)
+            .count(),
+        rules: entries,
+    }
+}
+
+// Whether a helper binary can be started at all
+#[cfg(target_os = "linux")]
+fn command_exists(program: &str) -> bool {
+    std::process::Command::new(program)
+        .arg("--version")
+        .output()
+        .map(|o| o.status.success())
+        .unwrap_or(false)
+}
+
+// The backend for this platform, or why there is none
+pub fn backend() -> Result<Box<dyn FirewallBackend>> {
+    #[cfg(windows)]
+    {
+        Ok(Box::new(netsh::NetshBackend))
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        if command_exists("nft") {
+            Ok(Box::new(nftables::NftablesBackend))
+        } else if command_exists("iptables") {
+            Ok(Box::new(iptables::IptablesBackend))
+        } else {
+            Err(crate::error::Error::Unsupported(
+                "Neither nft nor iptables found; firewall mode is unavailable".into(),
+            ))
+        }
+    }
+
+    #[cfg(not(any(windows, target_os = "linux")))]
+    {
+        Err(crate::error::Error::Unsupported(
+            "Firewall rules are not supported on this platform".into(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn catalog() -> Game {
+        crate::catalog::parse(
+            r#"{"id": "game", "name": "Game", "clusters": [
+                {"id": "eu", "name": "EU", "clusters": [
+                    {"id": "c1", "domain": "login.eu.example", "ips": ["10.0.0.1", "10.0.0.2"]},
+                    {"id": "c2", "domain": "login2.eu.example", "ips": ["10.0.0.3", "2001:db8::1"]}
+                ]}
+            ]}"#,
+        )
+        .unwrap()
+    }
+
+    fn rule(name: &str, domain: Option<&str>, ips: &[&str]) -> FirewallRule {
+        FirewallRule {
+            name: name.into(),
+            direction: "out".into(),
+            action: "block".into(),
+            remote_ips: ips.iter().map(|s| s.to_string()).collect(),
+            enabled: true,
+            profile: None,
+            domain: domain.map(String::from),
+            region_id: None,
+        }
+    }
+
+    #[test]
+    fn rule_addresses_in_every_backend_notation() {
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        for s in [
+            "10.0.0.1",
+            " 10.0.0.1/32",
+            "10.0.0.1-10.0.0.1",
+            "10.0.0.1/255.255.255.255",
+        ] {
+            assert_eq!(rule_addr(s), Some(ip), "{}", s);
+        }
+        assert_eq!(rule_addr("2001:db8::1/128"), "2001:db8::1".parse().ok());
+        assert_eq!(rule_addr("10.0.0.0/24"), None);
+        assert_eq!(rule_addr("10.0.0.1-10.0.0.9"), None);
+    }
+
+    #[test]
+    fn only_enabled_outbound_block_rules_block() {
+        let blocked = |rule: FirewallRule, ip: &str| blocks(&[rule], ip.parse().unwrap());
+        let base = rule("r", None, &["10.0.0.1/32"]);
+        assert!(blocked(base.clone(), "10.0.0.1"));
+        assert!(!blocked(base.clone(), "10.0.0.2"));
+        let disabled = FirewallRule {
+            enabled: false,
+            ..base.clone()
+        };
+        assert!(!blocked(disabled, "10.0.0.1"));
+        let inbound = FirewallRule {
+            direction: "in".into(),
+            ..base.clone()
+        };
+        assert!(!blocked(inbound, "10.0.0.1"));
+        let allow = FirewallRule {
+            action: "allow".into(),
+            ..base
+        };
+        assert!(!blocked(allow, "10.0.0.1"));
+    }
+
+    #[test]
+    fn rule_targets_are_public_unicast_and_capped() {
+        let ips: Vec<String> = [
+            " 1.1.1.1 ",
+            "10.0.0.1",
+            "127.0.0.1",
+            "169.254.0.1",
+            "224.0.0.1",
+            "255.255.255.255",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "ff02::1",
+            "2001:DB8::0:1",
+            "login.example",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        assert_eq!(rule_targets(&ips), vec!["1.1.1.1", "2001:db8::1"]);
+
+        let many: Vec<String> = (0..100).map(|i| format!("8.8.{}.8", i)).collect();
+        let targets = rule_targets(&many);
+        assert_eq!(targets.len(), MAX_IPS_PER_DOMAIN);
+        assert_eq!(targets[..], many[..MAX_IPS_PER_DOMAIN]);
+    }
+
+    #[test]
+    fn per_ip_rules_of_a_cluster_cover_it_together() {
+        let rules = vec![
+            rule("c1_a", Some("login.eu.example"), &["10.0.0.1"]),
+            rule("c1_b", Some("LOGIN.eu.example"), &["10.0.0.2/32"]),
+        ];
+        let inventory = audit("netsh", rules, &catalog());
+        assert_eq!((inventory.orphaned, inventory.drifted), (0, 0));
+        for entry in &inventory.rules {
+            assert_eq!(entry.status, RuleStatus::Ok, "{:?}", entry.problems);
+            assert_eq!(entry.cluster_id.as_deref(), Some("c1"));
+            assert_eq!(entry.rule.region_id.as_deref(), Some("eu"));
+            assert!(entry.missing_ips.is_empty());
+        }
+    }
+
+    #[test]
+    fn stale_and_missing_addresses_are_drift() {
+        let inventory = audit(
+            "nftables",
+            vec![rule(
+                "c2",
+                Some("login2.eu.example"),
+                &["10.0.0.3", "10.0.0.9"],
+            )],
+            &catalog(),
+        );
+        assert_eq!((inventory.orphaned, inventory.drifted), (0, 1));
+        let entry = &inventory.rules[0];
+        assert_eq!(entry.status, RuleStatus::Drift);
+        assert_eq!(entry.stale_ips, vec!["10.0.0.9"]);
+        assert_eq!(entry.missing_ips, vec!["2001:db8::1"]);
+        assert_eq!(
+            entry.problems,
+            vec![
+                "1 address(es) no longer in the catalog: 10.0.0.9",
+                "1 catalog address(es) not blocked: 2001:db8::1",
+            ]
+        );
+    }
+
+    #[test]
+    fn settings_other_than_an_enabled_outbound_block_are_drift() {
+        let rule = FirewallRule {
+            enabled: false,
+            direction: "in".into(),
+            action: "allow".into(),
+            ..rule("c1", Some("login.eu.example"), &["10.0.0.1", "10.0.0.2"])
+        };
+        let entry = &audit("netsh", vec![rule], &catalog()).rules[0];
+        assert_eq!(entry.status, RuleStatus::Drift);
+        assert_eq!(
+            entry.problems,
+            vec![
+                "rule is disabled",
+                "rule direction is in, expected out",
+                "rule action is allow, expected block",
+            ]
+        );
+    }
+
+    #[test]
+    fn rules_no_cluster_owns_are_orphaned() {
+        let rules = vec![
+            rule("gone", Some("gone.example"), &["10.0.0.1"]),
+            rule("unknown", None, &["10.0.0.1"]),
+            // The domain is in the catalog, but under another region
+            FirewallRule {
+                region_id: Some("na".into()),
+                ..rule("moved", Some("login.eu.example"), &["10.0.0.1"])
+            },
+        ];
+        let inventory = audit("iptables", rules, &catalog());
+        assert_eq!((inventory.orphaned, inventory.drifted), (3, 0));
+        let problems: Vec<&str> = inventory
+            .rules
+            .iter()
+            .map(|e| e.problems[0].as_str())
+            .collect();
+        assert_eq!(
+            problems,
+            vec![
+                "gone.example is not in the catalog",
+                "rule does not belong to any catalog cluster",
+                "login.eu.example is not in the catalog",
+            ]
+        );
+        assert_eq!(inventory.rules[2].rule.region_id.as_deref(), Some("na"));
+        assert!(inventory.rules.iter().all(|e| e.missing_ips.is_empty()));
+    }
+}
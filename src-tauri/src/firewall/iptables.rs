@@ -0,0 +1,299 @@
+// Plain iptables/ip6tables backend for systems without the nft tool. Rules live in our own
+// CLUSTERBANNED chain, jumped to from OUTPUT, and carry a "clusterbanned:<region>:<domain>"
+// comment so they can be found again without relying on the catalog.
+
+use super::{FirewallBackend, FirewallPlan, FirewallRule};
+use crate::catalog::Game;
+use crate::error::{Error, Result};
+use std::net::IpAddr;
+use std::process::{Command, Output};
+
+pub const CHAIN: &str = "CLUSTERBANNED";
+const COMMENT_PREFIX: &str = "clusterbanned:";
+
+pub struct IptablesBackend;
+
+fn run(bin: &str, args: &[&str]) -> Result<Output> {
+    Command::new(bin)
+        .args(args)
+        .output()
+        .map_err(|e| Error::io(format!("failed to run {}", bin), e))
+}
+
+fn tool_for(ip: &IpAddr) -> &'static str {
+    if ip.is_ipv6() {
+        "ip6tables"
+    } else {
+        "iptables"
+    }
+}
+
+fn comment(region: &str, domain: &str) -> String {
+    format!("{}{}:{}", COMMENT_PREFIX, region, domain)
+}
+
+fn ensure_chain(bin: &str) -> Result<()> {
+    // Fails harmlessly when the chain already exists
+    let _ = run(bin, &["-N", CHAIN]);
+    if !run(bin, &["-C", "OUTPUT", "-j", CHAIN])?.status.success() {
+        let out = run(bin, &["-I", "OUTPUT", "-j", CHAIN])?;
+        if !out.status.success() {
+            return Err(Error::command(format!(
+                "{} -I OUTPUT failed: {}",
+                bin,
+                String::from_utf8_lossy(&out.stderr).trim()
+            )));
+        }
+    }
+    Ok(())
+}
+
+// (rule number, comment) of every commented rule in our chain
+fn list_numbered(bin: &str) -> Result<Vec<(u32, String)>> {
+    let out = run(bin, &["-L", CHAIN, "-n", "--line-numbers"])?;
+    if !out.status.success() {
+        // Chain doesn't exist: nothing of ours
+        return Ok(Vec::new());
+    }
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let mut rules = Vec::new();
+    for line in stdout.lines() {
+        let num = match line
+            .split_whitespace()
+            .next()
+            .and_then(|n| n.parse::<u32>().ok())
+        {
+            Some(n) => n,
+            None => continue,
+        };
+        if let (Some(start), Some(end)) = (line.find("/* "), line.rfind(" */")) {
+            if start + 3 <= end {
+                rules.push((num, line[start + 3..end].to_string()));
+            }
+        }
+    }
+    Ok(rules)
+}
+
+// `iptables -S CLUSTERBANNED` lines: "-A CLUSTERBANNED -d 1.2.3.4/32 -m comment --comment
+// clusterbanned:<region>:<domain> -j DROP", grouped into one rule per comment
+pub fn parse_rules(bin: &str, text: &str, hooked: bool) -> Vec<FirewallRule> {
+    let mut rules: Vec<FirewallRule> = Vec::new();
+    for line in text.lines().filter(|l| l.starts_with("-A ")) {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        let value = |flag: &str| {
+            args.iter()
+                .position(|a| *a == flag)
+                .and_then(|i| args.get(i + 1))
+                .map(|v| v.trim_matches('"').to_string())
+        };
+        let tag = value("--comment").unwrap_or_default();
+        let target = value("-j").unwrap_or_default();
+        let action = match target.as_str() {
+            "DROP" | "REJECT" => "block".to_string(),
+            "ACCEPT" => "allow".to_string(),
+            other => other.to_lowercase(),
+        };
+        let (region_id, domain) = match tag
+            .strip_prefix(COMMENT_PREFIX)
+            .and_then(|t| t.split_once(':'))
+        {
+            Some((r, d)) => (Some(r.to_string()), Some(d.to_string())),
+            None => (None, None),
+        };
+        let name = format!(
+            "{} {} {}",
+            bin,
+            CHAIN,
+            if tag.is_empty() { "(no comment)" } else { &tag }
+        );
+        let rule = match rules
+            .iter_mut()
+            .position(|r| r.name == name && r.action == action)
+        {
+            Some(i) => &mut rules[i],
+            None => {
+                rules.push(FirewallRule {
+                    name,
+                    direction: "out".into(),
+                    action,
+                    remote_ips: Vec::new(),
+                    enabled: hooked,
+                    profile: None,
+                    domain,
+                    region_id,
+                });
+                rules.last_mut().unwrap()
+            }
+        };
+        if let Some(ip) = value("-d") {
+            // Single hosts are listed as /32 or /128 networks
+            let host = ip
+                .strip_suffix("/32")
+                .or_else(|| ip.strip_suffix("/128"))
+                .unwrap_or(&ip);
+            rule.remote_ips.push(host.to_string());
+        }
+    }
+    rules
+}
+
+// Delete every rule whose comment satisfies `pred`, highest number first so numbers stay valid
+fn delete_where(bin: &str, pred: impl Fn(&str) -> bool) -> Result<usize> {
+    let mut numbers: Vec<u32> = list_numbered(bin)?
+        .into_iter()
+        .filter(|(_, c)| pred(c))
+        .map(|(n, _)| n)
+        .collect();
+    numbers.sort_unstable_by(|a, b| b.cmp(a));
+    for n in &numbers {
+        run(bin, &["-D", CHAIN, &n.to_string()])?;
+    }
+    Ok(numbers.len())
+}
+
+impl FirewallBackend for IptablesBackend {
+    fn name(&self) -> &'static str {
+        "iptables"
+    }
+
+    fn set_blocked(
+        &self,
+        region: &str,
+        domain: &str,
+        ips: &[String],
+        enable: bool,
+    ) -> Result<String> {
+        let tag = comment(region, &domain.to_lowercase());
+
+        // Old rules for this domain go first in both directions, so re-blocking never duplicates
+        let mut removed = 0usize;
+        for bin in ["iptables", "ip6tables"] {
+            removed += delete_where(bin, |c| c == tag).unwrap_or(0);
+        }
+        if !enable {
+            return Ok(if removed > 0 {
+                format!("Removed {} iptables rule(s) for {}", removed, domain)
+            } else {
+                format!("Note: {} was not blocked in iptables", domain)
+            });
+        }
+
+        let addrs: Vec<IpAddr> = ips.iter().filter_map(|s| s.trim().parse().ok()).collect();
+        let mut added = 0usize;
+        for ip in &addrs {
+            let bin = tool_for(ip);
+            ensure_chain(bin)?;
+            let ip_s = ip.to_string();
+            let out = run(
+                bin,
+                &[
+                    "-A",
+                    CHAIN,
+                    "-d",
+                    &ip_s,
+                    "-m",
+                    "comment",
+                    "--comment",
+                    &tag,
+                    "-j",
+                    "DROP",
+                ],
+            )?;
+            if out.status.success() {
+                added += 1;
+            } else {
+                return Err(Error::command(format!(
+                    "{} -A failed for {}: {}",
+                    bin,
+                    ip_s,
+                    String::from_utf8_lossy(&out.stderr).trim()
+                )));
+            }
+        }
+        Ok(format!(
+            "iptables rules created for {} ({} IPs)",
+            domain, added
+        ))
+    }
+
+    fn plan(
+        &self,
+        region: &str,
+        targets: &[(String, Vec<String>)],
+        enable: bool,
+    ) -> Result<FirewallPlan> {
+        let mut plan = FirewallPlan {
+            backend: self.name().into(),
+            ..Default::default()
+        };
+        let tags: Vec<String> = targets
+            .iter()
+            .map(|(domain, _)| comment(region, &domain.to_lowercase()))
+            .collect();
+
+        for bin in ["iptables", "ip6tables"] {
+            let mut existing: Vec<(u32, String)> = list_numbered(bin)?
+                .into_iter()
+                .filter(|(_, c)| tags.contains(c))
+                .collect();
+            existing.sort_unstable_by_key(|r| std::cmp::Reverse(r.0));
+            for (n, c) in existing {
+                plan.commands.push(format!("{} -D {} {}", bin, CHAIN, n));
+                plan.deletes
+                    .push(format!("{} {} #{} ({})", bin, CHAIN, n, c));
+            }
+        }
+        if enable {
+            for ((_, ips), tag) in targets.iter().zip(&tags) {
+                for ip in ips.iter().filter_map(|s| s.trim().parse::<IpAddr>().ok()) {
+                    plan.commands.push(format!(
+                        "{} -A {} -d {} -m comment --comment {} -j DROP",
+                        tool_for(&ip),
+                        CHAIN,
+                        ip,
+                        tag
+                    ));
+                }
+            }
+        }
+        Ok(plan)
+    }
+
+    fn clear(&self, _catalog: Option<&Game>) -> Result<String> {
+        let mut removed = 0usize;
+        for bin in ["iptables", "ip6tables"] {
+            removed += list_numbered(bin).map(|r| r.len()).unwrap_or(0);
+            // Unhook, flush and drop the chain; each step may fail if it is already gone
+            while run(bin, &["-D", "OUTPUT", "-j", CHAIN])
+                .map(|o| o.status.success())
+                .unwrap_or(false)
+            {}
+            let _ = run(bin, &["-F", CHAIN]);
+            let _ = run(bin, &["-X", CHAIN]);
+        }
+        if removed == 0 {
+            Ok("No firewall rules found to delete".into())
+        } else {
+            Ok(format!("Deleted {} firewall rules", removed))
+        }
+    }
+
+    fn inventory(&self, _catalog: Option<&Game>) -> Result<Vec<FirewallRule>> {
+        let mut rules = Vec::new();
+        for bin in ["iptables", "ip6tables"] {
+            let out = run(bin, &["-S", CHAIN])?;
+            if !out.status.success() {
+                continue;
+            }
+            // Rules in a chain OUTPUT doesn't jump to never see any traffic
+            let hooked = run(bin, &["-C", "OUTPUT", "-j", CHAIN])?.status.success();
+            rules.extend(parse_rules(
+                bin,
+                &String::from_utf8_lossy(&out.stdout),
+                hooked,
+            ));
+        }
+        Ok(rules)
+    }
+}
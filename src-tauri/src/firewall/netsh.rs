@@ -0,0 +1,575 @@
+// Windows Firewall through `netsh advfirewall`. Rules are named after the cluster domain:
+// WoT_Blitz_Block_<domain> for the whole cluster, WoT_Blitz_Block_<domain>_<ip> when netsh
+// refused the combined remoteip list and we fell back to one rule per IP.
+//
+// `netsh advfirewall firewall show rule` output is localized, so the rules are listed through the
+// NetSecurity PowerShell module instead, whose enum values and JSON keys are the same everywhere.
+
+use super::{FirewallBackend, FirewallPlan, FirewallProfile, FirewallRule};
+use crate::catalog::Game;
+use crate::error::{Error, Result};
+use serde_json::Value;
+use std::process::{Command, Output};
+
+const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+// Every prefix this app (including older versions) has used for rule names
+pub const RULE_PREFIXES: [&str; 3] = ["WoT_Blitz_Block_", "WoT_Block_", "ClusterBanned_"];
+
+pub struct NetshBackend;
+
+pub fn rule_name(domain: &str) -> String {
+    format!("WoT_Blitz_Block_{}", domain.replace(".", "_"))
+}
+
+pub fn ip_rule_name(domain: &str, ip: &str) -> String {
+    format!("{}_{}", rule_name(domain), ip.replace(".", "_"))
+}
+
+fn netsh<S: AsRef<std::ffi::OsStr>>(args: &[S]) -> std::io::Result<Output> {
+    Command::new("netsh").args(args).output()
+}
+
+fn delete_args(name: &str) -> Vec<String> {
+    vec![
+        "advfirewall".into(),
+        "firewall".into(),
+        "delete".into(),
+        "rule".into(),
+        format!("name={}", name),
+    ]
+}
+
+fn add_block_args(name: &str, remote_ips: &str) -> Vec<String> {
+    vec![
+        "advfirewall".into(),
+        "firewall".into(),
+        "add".into(),
+        "rule".into(),
+        format!("name={}", name),
+        "dir=out".into(),
+        "action=block".into(),
+        format!("remoteip={}", remote_ips),
+        "protocol=any".into(),
+        "enable=yes".into(),
+        "profile=any".into(),
+    ]
+}
+
+fn command_line(args: &[String]) -> String {
+    format!("netsh {}", args.join(" "))
+}
+
+fn delete_rule(name: &str) -> std::io::Result<Output> {
+    netsh(&delete_args(name))
+}
+
+fn add_block_rule(name: &str, remote_ips: &str) -> std::io::Result<Output> {
+    netsh(&add_block_args(name, remote_ips))
+}
+
+// Lists our rules as JSON; netsh's `name` is the rule's DisplayName
+fn list_script() -> String {
+    let patterns: Vec<String> = RULE_PREFIXES.iter().map(|p| format!("'{}*'", p)).collect();
+    format!(
+        "$ErrorActionPreference = 'Stop'; \
+         $rules = @(Get-NetFirewallRule -DisplayName {} -ErrorAction SilentlyContinue | ForEach-Object {{ \
+         $f = $_ | Get-NetFirewallAddressFilter; \
+         [pscustomobject]@{{ name = $_.DisplayName; enabled = [string]$_.Enabled; \
+         direction = [string]$_.Direction; action = [string]$_.Action; profile = [string]$_.Profile; \
+         remote = @($f.RemoteAddress | ForEach-Object {{ [string]$_ }}) }} }}); \
+         ConvertTo-Json -InputObject $rules -Compress",
+        patterns.join(",")
+    )
+}
+
+// The firewall service, whether we run as an administrator, and each profile in the effective
+// (ActiveStore) and Group Policy (RSOP) stores
+const STATUS_SCRIPT: &str = "$ErrorActionPreference = 'Stop'; \
+     $svc = Get-Service -Name MpsSvc -ErrorAction SilentlyContinue; \
+     $principal = [Security.Principal.WindowsPrincipal][Security.Principal.WindowsIdentity]::GetCurrent(); \
+     $admin = $principal.IsInRole([Security.Principal.WindowsBuiltInRole]::Administrator); \
+     $gpo = @{}; \
+     try { Get-NetFirewallProfile -PolicyStore RSOP | ForEach-Object { $gpo[[string]$_.Name] = $_ } } catch {}; \
+     $profiles = @(); \
+     try { $profiles = @(Get-NetFirewallProfile -PolicyStore ActiveStore | ForEach-Object { \
+     $g = $gpo[[string]$_.Name]; \
+     [pscustomobject]@{ name = [string]$_.Name; enabled = [string]$_.Enabled; \
+     allowLocalRules = [string]$_.AllowLocalFirewallRules; policyEnabled = [string]$g.Enabled; \
+     policyAllowLocalRules = [string]$g.AllowLocalFirewallRules } }) } catch {}; \
+     ConvertTo-Json -Compress -Depth 3 -InputObject ([pscustomobject]@{ \
+     service = [string]$svc.Status; admin = $admin; profiles = $profiles })";
+
+// What STATUS_SCRIPT found
+#[derive(Debug, Clone, PartialEq)]
+pub struct Status {
+    pub service_running: bool,
+    pub elevated: bool,
+    pub profiles: Vec<FirewallProfile>,
+}
+
+fn powershell(script: &str) -> std::io::Result<Output> {
+    Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", script])
+        .output()
+}
+
+// The catalog domain a rule name was made from, including per-IP fallback rules. Dots became
+// underscores, so the longest match wins (a_b_c over a_b).
+fn owning_domain(name: &str, catalog: Option<&Game>) -> Option<String> {
+    catalog?
+        .clusters()
+        .map(|(_, c)| &c.domain)
+        .filter(|d| {
+            let base = rule_name(d);
+            name == base
+                || name
+                    .strip_prefix(&base)
+                    .map(|rest| rest.starts_with('_'))
+                    .unwrap_or(false)
+        })
+        .max_by_key(|d| d.len())
+        .cloned()
+}
+
+// LIST_SCRIPT output: an array of rules (a lone object on older PowerShell versions)
+pub fn parse_rules(json: &str, catalog: Option<&Game>) -> Result<Vec<FirewallRule>> {
+    let json = json.trim();
+    if json.is_empty() {
+        return Ok(Vec::new());
+    }
+    let root: Value = serde_json::from_str(json)
+        .map_err(|e| Error::Parse(format!("failed to parse firewall rule list: {}", e)))?;
+    let items = match root {
+        Value::Array(items) => items,
+        Value::Null => Vec::new(),
+        other => vec![other],
+    };
+    let text = |v: &Value| v.as_str().unwrap_or("").to_string();
+
+    Ok(items
+        .iter()
+        .map(|item| {
+            let name = text(&item["name"]);
+            let remote_ips = match &item["remote"] {
+                Value::Array(list) => list.iter().map(text).collect(),
+                Value::String(s) => vec![s.clone()],
+                _ => Vec::new(),
+            };
+            FirewallRule {
+                domain: owning_domain(&name, catalog),
+                name,
+                direction: match text(&item["direction"]).as_str() {
+                    "Inbound" => "in".to_string(),
+                    "Outbound" => "out".to_string(),
+                    other => other.to_lowercase(),
+                },
+                action: text(&item["action"]).to_lowercase(),
+                // "Any" means all of them
+                remote_ips: remote_ips
+                    .into_iter()
+                    .filter(|ip: &String| !ip.eq_ignore_ascii_case("any"))
+                    .collect(),
+                enabled: text(&item["enabled"]) == "True",
+                profile: Some(text(&item["profile"])).filter(|p| !p.is_empty()),
+                region_id: None,
+            }
+        })
+        .collect())
+}
+
+// STATUS_SCRIPT output. Group Policy values are "True", "False" or "NotConfigured"; a profile is
+// policy-managed when the policy sets either of them.
+pub fn parse_status(json: &str) -> Result<Status> {
+    let root: Value = serde_json::from_str(json.trim())
+        .map_err(|e| Error::Parse(format!("failed to parse firewall status: {}", e)))?;
+    let text = |v: &Value| v.as_str().unwrap_or("").to_string();
+    let configured = |v: &Value| matches!(v.as_str(), Some("True") | Some("False"));
+    let profiles = match &root["profiles"] {
+        Value::Array(items) => items.clone(),
+        Value::Null => Vec::new(),
+        other => vec![other.clone()],
+    };
+
+    Ok(Status {
+        service_running: text(&root["service"]) == "Running",
+        elevated: root["admin"].as_bool().unwrap_or(false),
+        profiles: profiles
+            .iter()
+            .map(|p| FirewallProfile {
+                name: text(&p["name"]),
+                enabled: text(&p["enabled"]) == "True",
+                local_rules_allowed: text(&p["allowLocalRules"]) != "False",
+                policy_managed: configured(&p["policyEnabled"])
+                    || configured(&p["policyAllowLocalRules"]),
+            })
+            .collect(),
+    })
+}
+
+pub fn status() -> Result<Status> {
+    let output =
+        powershell(STATUS_SCRIPT).map_err(|e| Error::io("Failed to get firewall status", e))?;
+    if !output.status.success() {
+        return Err(Error::command(format!(
+            "Failed to get firewall status: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    parse_status(&String::from_utf8_lossy(&output.stdout))
+}
+
+impl FirewallBackend for NetshBackend {
+    fn name(&self) -> &'static str {
+        "netsh"
+    }
+
+    fn set_blocked(
+        &self,
+        _region: &str,
+        domain: &str,
+        ips: &[String],
+        enable: bool,
+    ) -> Result<String> {
+        let mut results = Vec::new();
+        let rule_name = rule_name(domain);
+
+        if enable {
+            // Сначала удаляем существующее правило, если есть
+            let _ = delete_rule(&rule_name);
+
+            // Блокируем все IP одним правилом
+            let output = add_block_rule(&rule_name, &ips.join(","))
+                .map_err(|e| Error::io("Failed to create firewall rule", e))?;
+
+            if output.status.success() {
+                results.push(format!(
+                    "Firewall rule created for {} ({} IPs)",
+                    domain,
+                    ips.len()
+                ));
+            } else {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                // Пробуем создать правила для каждого IP отдельно
+                if stderr.contains("remoteip") {
+                    for ip in ips {
+                        if let Ok(out) = add_block_rule(&ip_rule_name(domain, ip), ip) {
+                            if out.status.success() {
+                                results.push(format!("Blocked IP: {}", ip));
+                            }
+                        }
+                    }
+                } else {
+                    // netsh prints most errors (e.g. "requires elevation") to stdout
+                    let message = if stderr.trim().is_empty() {
+                        String::from_utf8_lossy(&output.stdout)
+                    } else {
+                        stderr
+                    };
+                    return Err(Error::command(format!(
+                        "Failed to create firewall rule: {}",
+                        message.trim()
+                    )));
+                }
+            }
+        } else {
+            // Удаляем правило
+            let output = delete_rule(&rule_name);
+
+            // Также удаляем отдельные правила IP
+            for ip in ips {
+                let _ = delete_rule(&ip_rule_name(domain, ip));
+            }
+
+            if let Ok(out) = output {
+                if out.status.success() {
+                    results.push(format!("Firewall rule removed for {}", domain));
+                } else {
+                    results.push(format!(
+                        "Note: Firewall rule for {} may not have existed",
+                        domain
+                    ));
+                }
+            }
+        }
+
+        if results.is_empty() {
+            Ok("No firewall changes made".into())
+        } else {
+            Ok(results.join("\n"))
+        }
+    }
+
+    fn plan(
+        &self,
+        _region: &str,
+        targets: &[(String, Vec<String>)],
+        enable: bool,
+    ) -> Result<FirewallPlan> {
+        let mut plan = FirewallPlan {
+            backend: self.name().into(),
+            ..Default::default()
+        };
+        for (domain, ips) in targets {
+            let name = rule_name(domain);
+            plan.commands.push(command_line(&delete_args(&name)));
+            plan.deletes.push(name.clone());
+            if enable {
+                // The per-IP fallback only kicks in if netsh rejects the list, which can't be known up front
+                plan.commands
+                    .push(command_line(&add_block_args(&name, &ips.join(","))));
+            } else {
+                for ip in ips {
+                    let ip_name = ip_rule_name(domain, ip);
+                    plan.commands.push(command_line(&delete_args(&ip_name)));
+                    plan.deletes.push(ip_name);
+                }
+            }
+        }
+        Ok(plan)
+    }
+
+    fn clear(&self, catalog: Option<&Game>) -> Result<String> {
+        let mut results = Vec::new();
+        let mut deleted_rules = std::collections::HashSet::new();
+
+        // Удаляем все наши правила из инвентаря (не зависит от языка Windows)
+        // Если PowerShell недоступен, остаётся удаление по каталогу ниже
+        let rules = self.inventory(None).unwrap_or_else(|e| {
+            eprintln!("[TAURI] clear: {}", e);
+            Vec::new()
+        });
+        for rule in rules {
+            if deleted_rules.contains(&rule.name) {
+                continue;
+            }
+            match delete_rule(&rule.name) {
+                Ok(del_out) => {
+                    if del_out.status.success() {
+                        results.push(format!("Deleted rule: {}", rule.name));
+                        eprintln!("[TAURI] Successfully deleted firewall rule: {}", rule.name);
+                        deleted_rules.insert(rule.name);
+                    } else {
+                        let stderr = String::from_utf8_lossy(&del_out.stderr);
+                        let stdout_msg = String::from_utf8_lossy(&del_out.stdout);
+                        eprintln!(
+                            "[TAURI] Failed to delete rule {}: stderr={}, stdout={}",
+                            rule.name, stderr, stdout_msg
+                        );
+                    }
+                }
+                Err(e) => {
+                    eprintln!("[TAURI] Error deleting rule {}: {}", rule.name, e);
+                }
+            }
+        }
+
+        // Также пробуем удалить правила напрямую по известным доменам из servers.json
+        // Это нужно на случай, если инвентарь что-то пропустил
+        if let Some(catalog) = catalog {
+            for (_region, cluster) in catalog.clusters() {
+                let name = rule_name(&cluster.domain);
+                if deleted_rules.contains(&name) {
+                    continue;
+                }
+
+                if let Ok(del_out) = delete_rule(&name) {
+                    if del_out.status.success() {
+                        deleted_rules.insert(name.clone());
+                        results.push(format!("Deleted rule: {}", name));
+                        eprintln!(
+                            "[TAURI] Successfully deleted firewall rule (direct): {}",
+                            name
+                        );
+                    }
+                }
+
+                // Также пробуем удалить правила для отдельных IP
+                for ip in &cluster.ips {
+                    let ip_name = ip_rule_name(&cluster.domain, ip);
+                    if !deleted_rules.contains(&ip_name) {
+                        let _ = delete_rule(&ip_name);
+                    }
+                }
+            }
+        }
+
+        if results.is_empty() {
+            Ok("No firewall rules found to delete".into())
+        } else {
+            Ok(format!("Deleted {} firewall rules", results.len()))
+        }
+    }
+
+    fn inventory(&self, catalog: Option<&Game>) -> Result<Vec<FirewallRule>> {
+        let output =
+            powershell(&list_script()).map_err(|e| Error::io("Failed to get firewall rules", e))?;
+        if !output.status.success() {
+            return Err(Error::command(format!(
+                "Failed to get firewall rules: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        parse_rules(&String::from_utf8_lossy(&output.stdout), catalog)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "login.eu" makes a rule name prefix of "login.eu.example"
+    fn catalog() -> Game {
+        crate::catalog::parse(
+            r#"{"id": "game", "name": "Game", "clusters": [
+                {"id": "eu", "name": "EU", "clusters": [
+                    {"id": "c1", "domain": "login.eu.example", "ips": ["10.0.0.1", "10.0.0.2"]},
+                    {"id": "c2", "domain": "login.eu", "ips": ["10.0.0.3"]}
+                ]}
+            ]}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_a_lone_rule_object() {
+        let json = r#"{"name": "WoT_Blitz_Block_login_eu_example", "enabled": "True",
+            "direction": "Outbound", "action": "Block", "profile": "Any",
+            "remote": ["10.0.0.1", "10.0.0.2"]}"#;
+        let rules = parse_rules(json, Some(&catalog())).unwrap();
+        assert_eq!(
+            rules,
+            vec![FirewallRule {
+                name: "WoT_Blitz_Block_login_eu_example".into(),
+                direction: "out".into(),
+                action: "block".into(),
+                remote_ips: vec!["10.0.0.1".into(), "10.0.0.2".into()],
+                enabled: true,
+                profile: Some("Any".into()),
+                domain: Some("login.eu.example".into()),
+                region_id: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn remote_any_means_no_addresses() {
+        let json = r#"[{"name": "WoT_Block_old", "enabled": "False", "direction": "Inbound",
+            "action": "Allow", "profile": "", "remote": "Any"}]"#;
+        let rule = &parse_rules(json, Some(&catalog())).unwrap()[0];
+        assert!(rule.remote_ips.is_empty());
+        assert_eq!(
+            (rule.direction.as_str(), rule.action.as_str()),
+            ("in", "allow")
+        );
+        assert!(!rule.enabled);
+        assert_eq!(rule.profile, None);
+        assert_eq!(rule.domain, None);
+    }
+
+    #[test]
+    fn per_ip_fallback_rules_map_to_the_longest_domain() {
+        let json = format!(
+            r#"[{{"name": "{}", "remote": "10.0.0.1"}}, {{"name": "{}", "remote": ["10.0.0.3"]}}]"#,
+            ip_rule_name("login.eu.example", "10.0.0.1"),
+            ip_rule_name("login.eu", "10.0.0.3"),
+        );
+        let domains: Vec<Option<String>> = parse_rules(&json, Some(&catalog()))
+            .unwrap()
+            .into_iter()
+            .map(|r| r.domain)
+            .collect();
+        assert_eq!(
+            domains,
+            vec![Some("login.eu.example".into()), Some("login.eu".into())]
+        );
+        // Without the catalog the mangled names can't be mapped back
+        assert_eq!(parse_rules(&json, None).unwrap()[0].domain, None);
+    }
+
+    #[test]
+    fn empty_and_broken_listings() {
+        assert!(parse_rules("", None).unwrap().is_empty());
+        assert!(parse_rules(" null\r\n", None).unwrap().is_empty());
+        assert!(parse_rules("[]", None).unwrap().is_empty());
+        assert!(matches!(parse_rules("[{", None), Err(Error::Parse(_))));
+    }
+
+    // The per-IP fallback rules of one cluster, one of them stale, plus a rule the catalog lost
+    #[test]
+    fn listed_rules_audit_against_the_catalog() {
+        let json = format!(
+            r#"[{{"name": "{}", "enabled": "True", "direction": "Outbound", "action": "Block",
+                 "remote": ["10.0.0.1"]}},
+               {{"name": "{}", "enabled": "True", "direction": "Outbound", "action": "Block",
+                 "remote": ["10.0.0.9"]}},
+               {{"name": "WoT_Blitz_Block_gone_example", "enabled": "True",
+                 "direction": "Outbound", "action": "Block", "remote": ["10.0.0.5"]}}]"#,
+            ip_rule_name("login.eu.example", "10.0.0.1"),
+            ip_rule_name("login.eu.example", "10.0.0.9"),
+        );
+        let catalog = catalog();
+        let inventory = crate::firewall::audit(
+            "netsh",
+            parse_rules(&json, Some(&catalog)).unwrap(),
+            &catalog,
+        );
+        assert_eq!((inventory.orphaned, inventory.drifted), (1, 2));
+        let first = &inventory.rules[0];
+        assert_eq!(first.cluster_id.as_deref(), Some("c1"));
+        assert!(first.stale_ips.is_empty());
+        assert_eq!(first.missing_ips, vec!["10.0.0.2"]);
+        assert_eq!(inventory.rules[1].stale_ips, vec!["10.0.0.9"]);
+        assert_eq!(inventory.rules[2].cluster_id, None);
+        assert_eq!(
+            inventory.rules[2].problems,
+            vec!["rule does not belong to any catalog cluster"]
+        );
+    }
+
+    #[test]
+    fn parses_status_with_group_policy() {
+        let json = r#"{"service": "Running", "admin": true, "profiles": [
+            {"name": "Domain", "enabled": "True", "allowLocalRules": "NotConfigured",
+             "policyEnabled": "True", "policyAllowLocalRules": "NotConfigured"},
+            {"name": "Public", "enabled": "False", "allowLocalRules": "False",
+             "policyEnabled": "NotConfigured", "policyAllowLocalRules": "False"},
+            {"name": "Private", "enabled": "True", "allowLocalRules": "True",
+             "policyEnabled": "", "policyAllowLocalRules": "NotConfigured"}
+        ]}"#;
+        let status = parse_status(json).unwrap();
+        assert!(status.service_running && status.elevated);
+        let profile =
+            |name: &str, enabled: bool, local_rules_allowed: bool, policy_managed: bool| {
+                FirewallProfile {
+                    name: name.into(),
+                    enabled,
+                    local_rules_allowed,
+                    policy_managed,
+                }
+            };
+        assert_eq!(
+            status.profiles,
+            vec![
+                profile("Domain", true, true, true),
+                profile("Public", false, false, true),
+                profile("Private", true, true, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_status_with_a_lone_profile_or_none() {
+        let json = r#"{"service": "Stopped", "admin": false,
+            "profiles": {"name": "Public", "enabled": "True", "allowLocalRules": "True"}}"#;
+        let status = parse_status(json).unwrap();
+        assert!(!status.service_running && !status.elevated);
+        assert_eq!(status.profiles.len(), 1);
+        assert!(!status.profiles[0].policy_managed);
+
+        // No firewall service at all: Get-Service found nothing and the profiles threw
+        let status = parse_status(r#"{"service": "", "admin": true, "profiles": []}"#).unwrap();
+        assert!(!status.service_running && status.profiles.is_empty());
+        assert!(matches!(parse_status("oops"), Err(Error::Parse(_))));
+    }
+}
@@ -0,0 +1,634 @@
+// nftables backend. Everything lives in our own `inet clusterbanned` table: one set of
+// blocked addresses per region (and address family) plus an output chain dropping them.
+//
+// The whole table is regenerated from the desired state and loaded with `nft -f` in one
+// transaction, after `nft -c` has checked the generated ruleset. The current state is read
+// back from the kernel: set comments carry the region id, element comments the cluster domains.
+
+use super::{FirewallBackend, FirewallPlan, FirewallRule};
+use crate::catalog::Game;
+use crate::error::{Error, Result};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::net::IpAddr;
+use std::process::{Command, Stdio};
+
+pub const TABLE: &str = "clusterbanned";
+const REGION_TAG: &str = "region:";
+
+// region -> domain -> addresses
+pub type State = BTreeMap<String, BTreeMap<String, BTreeSet<IpAddr>>>;
+
+pub struct NftablesBackend;
+
+// nft identifiers: letters, digits and underscores, starting with a letter. The region id is
+// escaped so that no two ids share a set: `_` becomes `__` and every other byte that isn't a
+// letter or digit `_xx` with its hex value ("wot_eu" -> r_wot__eu_v4, "wot-eu" -> r_wot_2deu_v4).
+fn set_name(region: &str, v6: bool) -> String {
+    let mut escaped = String::new();
+    for b in region.bytes() {
+        match b {
+            b'_' => escaped.push_str("__"),
+            b if b.is_ascii_alphanumeric() => escaped.push(b as char),
+            b => escaped.push_str(&format!("_{:02x}", b)),
+        }
+    }
+    format!("r_{}_{}", escaped, if v6 { "v6" } else { "v4" })
+}
+
+// The region id of a set `set_name` made, or None for any other name
+fn region_of(name: &str) -> Option<String> {
+    let rest = name.strip_prefix("r_")?;
+    let escaped = rest
+        .strip_suffix("_v4")
+        .or_else(|| rest.strip_suffix("_v6"))?;
+    let mut bytes = Vec::new();
+    let mut it = escaped.bytes();
+    while let Some(b) = it.next() {
+        if b != b'_' {
+            bytes.push(b);
+            continue;
+        }
+        match it.next()? {
+            b'_' => bytes.push(b'_'),
+            hi => {
+                let hex = [hi, it.next()?];
+                bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', ""))
+}
+
+// The complete ruleset for `state`; an empty state just removes our table
+pub fn render_ruleset(state: &State) -> String {
+    let mut out = String::new();
+    out.push_str("#!/usr/sbin/nft -f\n");
+    out.push_str("# Generated by Cluster Banned Manager, changes will be overwritten\n\n");
+    // Create-then-delete makes the delete valid whether or not the table exists
+    out.push_str(&format!("table inet {} {{}}\n", TABLE));
+    out.push_str(&format!("delete table inet {}\n", TABLE));
+
+    let mut sets = Vec::new();
+    for (region, domains) in state {
+        for v6 in [false, true] {
+            // address -> every domain that uses it (a set element can only appear once)
+            let mut elements: BTreeMap<IpAddr, Vec<&str>> = BTreeMap::new();
+            for (domain, ips) in domains {
+                for ip in ips.iter().filter(|ip| ip.is_ipv6() == v6) {
+                    elements.entry(*ip).or_default().push(domain);
+                }
+            }
+            if !elements.is_empty() {
+                sets.push((region, v6, elements));
+            }
+        }
+    }
+    if sets.is_empty() {
+        return out;
+    }
+
+    out.push_str(&format!("\ntable inet {} {{\n", TABLE));
+    for (region, v6, elements) in &sets {
+        out.push_str(&format!("\tset {} {{\n", set_name(region, *v6)));
+        out.push_str(&format!(
+            "\t\ttype {}\n",
+            if *v6 { "ipv6_addr" } else { "ipv4_addr" }
+        ));
+        out.push_str(&format!(
+            "\t\tcomment {}\n",
+            quote(&format!("{}{}", REGION_TAG, region))
+        ));
+        let elems: Vec<String> = elements
+            .iter()
+            .map(|(ip, domains)| {
+                let owners: Vec<&str> = domains.iter().copied().filter(|d| !d.is_empty()).collect();
+                if owners.is_empty() {
+                    ip.to_string()
+                } else {
+                    format!("{} comment {}", ip, quote(&owners.join(",")))
+                }
+            })
+            .collect();
+        out.push_str(&format!("\t\telements = {{ {} }}\n", elems.join(", ")));
+        out.push_str("\t}\n\n");
+    }
+    out.push_str("\tchain output {\n");
+    out.push_str("\t\ttype filter hook output priority filter; policy accept;\n");
+    for (region, v6, _) in &sets {
+        out.push_str(&format!(
+            "\t\t{} daddr @{} drop\n",
+            if *v6 { "ip6" } else { "ip" },
+            set_name(region, *v6)
+        ));
+    }
+    out.push_str("\t}\n}\n");
+    out
+}
+
+// One set of our table as nft lists it
+struct ListedSet {
+    name: String,
+    region: String,
+    // domain -> addresses; "" for addresses without a domain comment
+    domains: BTreeMap<String, BTreeSet<IpAddr>>,
+}
+
+// The sets in `nft -j list table inet clusterbanned` output
+fn parse_sets(json: &str) -> Result<Vec<ListedSet>> {
+    let root: Value = serde_json::from_str(json)
+        .map_err(|e| Error::Parse(format!("failed to parse nft output: {}", e)))?;
+    let mut sets = Vec::new();
+    let items = root["nftables"].as_array().cloned().unwrap_or_default();
+
+    for item in items {
+        let set = match item.get("set") {
+            Some(s) => s,
+            None => continue,
+        };
+        let name = set["name"].as_str().unwrap_or("");
+        // The comment is authoritative: sets made before ids were escaped have other names
+        let region = match set["comment"]
+            .as_str()
+            .and_then(|c| c.strip_prefix(REGION_TAG))
+        {
+            Some(r) => r.to_string(),
+            None => region_of(name).unwrap_or_else(|| name.to_string()),
+        };
+        let mut domains: BTreeMap<String, BTreeSet<IpAddr>> = BTreeMap::new();
+
+        for elem in set["elem"].as_array().cloned().unwrap_or_default() {
+            // Plain elements are strings, elements with a comment are {"elem": {"val", "comment"}}
+            let (val, comment) = match &elem {
+                Value::String(s) => (s.clone(), String::new()),
+                Value::Object(_) => (
+                    elem["elem"]["val"].as_str().unwrap_or("").to_string(),
+                    elem["elem"]["comment"].as_str().unwrap_or("").to_string(),
+                ),
+                _ => continue,
+            };
+            let ip: IpAddr = match val.parse() {
+                Ok(ip) => ip,
+                Err(_) => continue,
+            };
+            let owners: Vec<&str> = comment.split(',').filter(|d| !d.is_empty()).collect();
+            if owners.is_empty() {
+                domains.entry(String::new()).or_default().insert(ip);
+            }
+            for d in owners {
+                domains.entry(d.to_string()).or_default().insert(ip);
+            }
+        }
+        sets.push(ListedSet {
+            name: name.to_string(),
+            region,
+            domains,
+        });
+    }
+    Ok(sets)
+}
+
+// Parse `nft -j list table inet clusterbanned` output back into a state
+pub fn parse_state(json: &str) -> Result<State> {
+    let mut state = State::new();
+    for set in parse_sets(json)? {
+        let domains = state.entry(set.region).or_default();
+        for (domain, ips) in set.domains {
+            domains.entry(domain).or_default().extend(ips);
+        }
+    }
+    state.retain(|_, domains| !domains.is_empty());
+    Ok(state)
+}
+
+// `nft -j list table inet clusterbanned`, or None when the table doesn't exist
+fn list_table() -> Result<Option<String>> {
+    let output = Command::new("nft")
+        .args(["-j", "list", "table", "inet", TABLE])
+        .output()
+        .map_err(|e| Error::io("failed to run nft", e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        // No table yet means nothing is blocked
+        if stderr.contains("No such file or directory") {
+            return Ok(None);
+        }
+        return Err(Error::command(format!(
+            "nft list table failed: {}",
+            stderr.trim()
+        )));
+    }
+    Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned()))
+}
+
+pub fn read_state() -> Result<State> {
+    match list_table()? {
+        Some(json) => parse_state(&json),
+        None => Ok(State::new()),
+    }
+}
+
+// Names of the sets an output chain rule drops traffic to
+pub fn dropped_sets(json: &str) -> Result<BTreeSet<String>> {
+    let root: Value = serde_json::from_str(json)
+        .map_err(|e| Error::Parse(format!("failed to parse nft output: {}", e)))?;
+    let mut sets = BTreeSet::new();
+    for item in root["nftables"].as_array().cloned().unwrap_or_default() {
+        let rule = match item.get("rule") {
+            Some(r) => r,
+            None => continue,
+        };
+        let exprs = rule["expr"].as_array().cloned().unwrap_or_default();
+        if !exprs.iter().any(|e| e.get("drop").is_some()) {
+            continue;
+        }
+        for e in &exprs {
+            if let Some(set) = e["match"]["right"]
+                .as_str()
+                .and_then(|r| r.strip_prefix('@'))
+            {
+                sets.insert(set.to_string());
+            }
+        }
+    }
+    Ok(sets)
+}
+
+// Check the ruleset with `nft -c`, then load it. It goes to nft on stdin: a file would have to
+// live somewhere another user could swap it between the check and the load.
+pub fn apply(state: &State) -> Result<()> {
+    let ruleset = render_ruleset(state);
+
+    let run = |check: bool| -> Result<()> {
+        let mut cmd = Command::new("nft");
+        if check {
+            cmd.arg("-c");
+        }
+        let mut child = cmd
+            .args(["-f", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| Error::io("failed to run nft", e))?;
+        // nft reads the whole ruleset before it writes anything, so this can't deadlock
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(ruleset.as_bytes())
+                .map_err(|e| Error::io("failed to pass the ruleset to nft", e))?;
+        }
+        let output = child
+            .wait_with_output()
+            .map_err(|e| Error::io("failed to run nft", e))?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(Error::command(format!(
+                "nft {}failed: {}",
+                if check { "-c " } else { "" },
+                String::from_utf8_lossy(&output.stderr).trim()
+            )))
+        }
+    };
+    run(true).and_then(|_| run(false))
+}
+
+// `state` with the (domain, ips) targets of `region` blocked or unblocked
+fn next_state(
+    mut state: State,
+    region: &str,
+    targets: &[(String, Vec<String>)],
+    enable: bool,
+) -> State {
+    for (domain, ips) in targets {
+        let domain = domain.to_lowercase();
+        if enable {
+            let addrs: BTreeSet<IpAddr> =
+                ips.iter().filter_map(|s| s.trim().parse().ok()).collect();
+            if !addrs.is_empty() {
+                state
+                    .entry(region.to_string())
+                    .or_default()
+                    .insert(domain, addrs);
+            }
+        } else if let Some(domains) = state.get_mut(region) {
+            domains.remove(&domain);
+        }
+    }
+    state.retain(|_, domains| !domains.is_empty());
+    state
+}
+
+// "set element (domains)" for every address in `before` that is gone in `after`
+fn removed_entries(before: &State, after: &State) -> Vec<String> {
+    let mut out = Vec::new();
+    for (region, domains) in before {
+        for (domain, ips) in domains {
+            for ip in ips {
+                let kept = after
+                    .get(region)
+                    .and_then(|d| d.get(domain))
+                    .map(|set| set.contains(ip))
+                    .unwrap_or(false);
+                if !kept {
+                    out.push(format!(
+                        "{} {} ({})",
+                        set_name(region, ip.is_ipv6()),
+                        ip,
+                        domain
+                    ));
+                }
+            }
+        }
+    }
+    out
+}
+
+impl FirewallBackend for NftablesBackend {
+    fn name(&self) -> &'static str {
+        "nftables"
+    }
+
+    fn set_blocked(
+        &self,
+        region: &str,
+        domain: &str,
+        ips: &[String],
+        enable: bool,
+    ) -> Result<String> {
+        let state = read_state()?;
+        let domain = domain.to_lowercase();
+        let next = next_state(
+            state.clone(),
+            region,
+            &[(domain.clone(), ips.to_vec())],
+            enable,
+        );
+        if next == state {
+            let valid = ips.iter().any(|s| s.trim().parse::<IpAddr>().is_ok());
+            return Ok(if enable && valid {
+                // Same addresses as the set already holds
+                format!("{} is already blocked in nftables", domain)
+            } else if enable {
+                format!("No valid IPs for {}", domain)
+            } else {
+                format!("Note: {} was not blocked in nftables", domain)
+            });
+        }
+        apply(&next)?;
+
+        if enable {
+            let count = next[region][&domain].len();
+            Ok(format!(
+                "nftables set updated for {} ({} IPs)",
+                domain, count
+            ))
+        } else {
+            Ok(format!("nftables entries removed for {}", domain))
+        }
+    }
+
+    fn plan(
+        &self,
+        region: &str,
+        targets: &[(String, Vec<String>)],
+        enable: bool,
+    ) -> Result<FirewallPlan> {
+        let state = read_state()?;
+        let next = next_state(state.clone(), region, targets, enable);
+        let mut plan = FirewallPlan {
+            backend: self.name().into(),
+            deletes: removed_entries(&state, &next),
+            ..Default::default()
+        };
+        if next != state {
+            let ruleset = render_ruleset(&next);
+            plan.commands
+                .push(format!("nft -c -f - <<'EOF'\n{}EOF", ruleset));
+            plan.commands
+                .push(format!("nft -f - <<'EOF'\n{}EOF", ruleset));
+        }
+        Ok(plan)
+    }
+
+    fn clear(&self, _catalog: Option<&Game>) -> Result<String> {
+        let state = read_state()?;
+        if state.is_empty() {
+            return Ok("No firewall rules found to delete".into());
+        }
+        apply(&State::new())?;
+        Ok(format!("Deleted nftables table inet {}", TABLE))
+    }
+
+    // One entry per set and domain (a set holds one region and address family): each is a
+    // share of one drop rule's set
+    fn inventory(&self, _catalog: Option<&Game>) -> Result<Vec<FirewallRule>> {
+        let json = match list_table()? {
+            Some(json) => json,
+            None => return Ok(Vec::new()),
+        };
+        let dropped = dropped_sets(&json)?;
+        let mut rules = Vec::new();
+        for set in parse_sets(&json)? {
+            for (domain, ips) in set.domains {
+                if ips.is_empty() {
+                    continue;
+                }
+                rules.push(FirewallRule {
+                    name: format!("inet {} {} ({})", TABLE, set.name, domain),
+                    direction: "out".into(),
+                    action: "block".into(),
+                    remote_ips: ips.iter().map(|ip| ip.to_string()).collect(),
+                    // A set nothing drops traffic to blocks nothing
+                    enabled: dropped.contains(&set.name),
+                    profile: None,
+                    region_id: Some(set.region.clone()),
+                    domain: (!domain.is_empty()).then_some(domain),
+                });
+            }
+        }
+        Ok(rules)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    // The state blocking two clusters of a small catalog, one of them with an IPv6 address
+    fn sample_state() -> State {
+        let catalog = crate::catalog::parse(
+            r#"{"id": "game", "name": "Game", "clusters": [
+                {"id": "wot_eu", "name": "EU", "clusters": [
+                    {"id": "c1", "domain": "login.eu.example", "ips": ["10.0.0.1", "10.0.0.2"]},
+                    {"id": "c2", "domain": "login2.eu.example", "ips": ["10.0.0.2", "2001:db8::1"]}
+                ]}
+            ]}"#,
+        )
+        .unwrap();
+        let targets: Vec<(String, Vec<String>)> = catalog.regions[0]
+            .clusters
+            .iter()
+            .map(|c| (c.domain.clone(), c.ips.clone()))
+            .collect();
+        next_state(State::new(), "wot_eu", &targets, true)
+    }
+
+    // What `nft -j list table` reports once `render_ruleset(state)` is loaded
+    fn listing(state: &State) -> String {
+        let mut items = vec![json!({"table": {"family": "inet", "name": TABLE, "handle": 1}})];
+        let mut rules = Vec::new();
+        for (region, domains) in state {
+            for v6 in [false, true] {
+                let mut elements: BTreeMap<IpAddr, Vec<&str>> = BTreeMap::new();
+                for (domain, ips) in domains {
+                    for ip in ips.iter().filter(|ip| ip.is_ipv6() == v6) {
+                        elements.entry(*ip).or_default().push(domain);
+                    }
+                }
+                if elements.is_empty() {
+                    continue;
+                }
+                let name = set_name(region, v6);
+                let elem: Vec<Value> = elements
+                    .iter()
+                    .map(|(ip, domains)| {
+                        json!({"elem": {"val": ip.to_string(), "comment": domains.join(",")}})
+                    })
+                    .collect();
+                items.push(json!({"set": {
+                    "family": "inet",
+                    "name": name,
+                    "table": TABLE,
+                    "type": if v6 { "ipv6_addr" } else { "ipv4_addr" },
+                    "comment": format!("{}{}", REGION_TAG, region),
+                    "elem": elem,
+                }}));
+                rules.push(json!({"rule": {
+                    "family": "inet",
+                    "table": TABLE,
+                    "chain": "output",
+                    "expr": [
+                        {"match": {
+                            "op": "==",
+                            "left": {"payload": {
+                                "protocol": if v6 { "ip6" } else { "ip" },
+                                "field": "daddr",
+                            }},
+                            "right": format!("@{}", name),
+                        }},
+                        {"drop": null},
+                    ],
+                }}));
+            }
+        }
+        items.extend(rules);
+        json!({ "nftables": items }).to_string()
+    }
+
+    #[test]
+    fn set_names_of_different_regions_never_collide() {
+        let ids = [
+            "wot_eu",
+            "wot-eu",
+            "wot__eu",
+            "wot_5feu",
+            "wot.eu",
+            "wot eu",
+            "wot\u{435}u",
+        ];
+        let names: BTreeSet<String> = ids.iter().map(|id| set_name(id, false)).collect();
+        assert_eq!(names.len(), ids.len(), "{:?}", names);
+        assert_eq!(set_name("wot_eu", false), "r_wot__eu_v4");
+        assert_eq!(set_name("wot-eu", true), "r_wot_2deu_v6");
+        for id in ids {
+            assert_eq!(region_of(&set_name(id, true)).as_deref(), Some(id));
+        }
+        assert_eq!(region_of("filter_v4"), None);
+    }
+
+    #[test]
+    fn renders_the_sets_and_drop_rules_of_a_catalog_region() {
+        let ruleset = render_ruleset(&sample_state());
+        assert_eq!(
+            ruleset,
+            "#!/usr/sbin/nft -f\n\
+             # Generated by Cluster Banned Manager, changes will be overwritten\n\n\
+             table inet clusterbanned {}\n\
+             delete table inet clusterbanned\n\n\
+             table inet clusterbanned {\n\
+             \tset r_wot__eu_v4 {\n\
+             \t\ttype ipv4_addr\n\
+             \t\tcomment \"region:wot_eu\"\n\
+             \t\telements = { 10.0.0.1 comment \"login.eu.example\", \
+             10.0.0.2 comment \"login.eu.example,login2.eu.example\" }\n\
+             \t}\n\n\
+             \tset r_wot__eu_v6 {\n\
+             \t\ttype ipv6_addr\n\
+             \t\tcomment \"region:wot_eu\"\n\
+             \t\telements = { 2001:db8::1 comment \"login2.eu.example\" }\n\
+             \t}\n\n\
+             \tchain output {\n\
+             \t\ttype filter hook output priority filter; policy accept;\n\
+             \t\tip daddr @r_wot__eu_v4 drop\n\
+             \t\tip6 daddr @r_wot__eu_v6 drop\n\
+             \t}\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn an_empty_state_only_removes_the_table() {
+        let ruleset = render_ruleset(&State::new());
+        assert!(ruleset.ends_with("delete table inet clusterbanned\n"));
+        assert!(!ruleset.contains("set "));
+    }
+
+    #[test]
+    fn parse_state_reads_back_what_was_rendered() {
+        let mut state = sample_state();
+        state
+            .entry("wot-eu".into())
+            .or_default()
+            .insert("login.other.example".into(), [ip("10.1.0.1")].into());
+        let json = listing(&state);
+        assert_eq!(parse_state(&json).unwrap(), state);
+        assert_eq!(
+            dropped_sets(&json).unwrap(),
+            ["r_wot__eu_v4", "r_wot__eu_v6", "r_wot_2deu_v4"]
+                .into_iter()
+                .map(String::from)
+                .collect()
+        );
+    }
+
+    #[test]
+    fn parse_state_keeps_uncommented_elements_and_legacy_set_names() {
+        // A set made before region ids were escaped, and one that lost its comment
+        let json = json!({"nftables": [
+            {"set": {"name": "r_wot_eu_v4", "comment": "region:wot_eu", "elem": ["10.0.0.9"]}},
+            {"set": {"name": "r_wot__na_v4", "elem": [{"elem": {"val": "10.2.0.1", "comment": "na"}}]}},
+            {"rule": {"chain": "output", "expr": [
+                {"match": {"right": "@r_wot_eu_v4"}},
+                {"counter": {"packets": 0, "bytes": 0}}
+            ]}},
+        ]})
+        .to_string();
+        let state = parse_state(&json).unwrap();
+        assert_eq!(state["wot_eu"][""], [ip("10.0.0.9")].into());
+        assert_eq!(state["wot_na"]["na"], [ip("10.2.0.1")].into());
+        // The rule counts but doesn't drop
+        assert!(dropped_sets(&json).unwrap().is_empty());
+        assert!(parse_state("not json").is_err());
+    }
+}
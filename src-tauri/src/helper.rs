@@ -0,0 +1,680 @@
+// Privileged helper: a small service (src/bin/clusterbanned-helper.rs) that writes hosts and
+// firewall rules on behalf of the unprivileged app and CLI. It listens on a Unix socket
+// (/run/clusterbanned/helper.sock, systemd socket activation supported) or the named pipe
+// \\.\pipe\clusterbanned-helper on Windows.
+//
+// One request per connection, one JSON line each way. Only the narrow set of operations below is
+// accepted, and every request is validated before anything is written: domains end up as lines
+// of the hosts file, so anything that isn't a plain hostname is rejected, and the helper only
+// touches clusters and IPs of its own catalog (see `authorize`).
+//
+// The helper runs with its own config dir (/var/lib/clusterbanned under systemd, root's or
+// SYSTEM's profile otherwise), so the user's settings and catalog overlay aren't there. The
+// backup settings and the IPs of each cluster travel with the request (see
+// `with_user_settings`). Overlay clusters and discovered IPs are not the helper's to vouch for:
+// it answers those with "not-authorized", and the app does them elevated instead.
+
+use crate::blocking::{self, Backups};
+use crate::catalog::Game;
+use crate::error::{Error, Result};
+use crate::firewall::{public_unicast, MAX_IPS_PER_DOMAIN};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+#[cfg(windows)]
+pub const PIPE_NAME: &str = r"\\.\pipe\clusterbanned-helper";
+
+// Overrides the socket path, e.g. to run a helper on a local socket for testing
+#[cfg(unix)]
+pub const SOCKET_ENV: &str = "CLUSTERBANNED_HELPER_SOCKET";
+
+const MAX_MESSAGE_BYTES: u64 = 64 * 1024;
+const MAX_DOMAINS: usize = 256;
+const MAX_BACKUPS: u32 = 100;
+// How long the helper waits for a request and the client for a connection
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+// How long the client waits for the answer: firewall changes may need a catalog download first
+const REPLY_TIMEOUT: Duration = Duration::from_secs(120);
+
+// Which region's domains to block or unblock, and where
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Change {
+    pub region_id: String,
+    pub domains: Vec<String>,
+    pub hosts: bool,
+    pub firewall: bool,
+    // The user's backup settings; the handling process's own when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backups: Option<Backups>,
+    // IPs to block per domain from the user's catalog (overlay and merged IPs included); the
+    // handling process's catalog when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ips: Option<BTreeMap<String, Vec<String>>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "camelCase")]
+pub enum Request {
+    // Replace the region's hosts block with `domains` and add firewall rules for them
+    Apply(Change),
+    // Unblock `domains` of the region
+    Remove(Change),
+    // Remove every hosts block and firewall rule of the app
+    Clear {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        backups: Option<Backups>,
+    },
+    // Whether the helper is there, and which version
+    Status,
+}
+
+// The answer line; also how an elevated copy of the app reports back (see elevate.rs)
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Response {
+    ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<Error>,
+}
+
+impl From<Result<Value>> for Response {
+    fn from(outcome: Result<Value>) -> Self {
+        match outcome {
+            Ok(result) => Response {
+                ok: true,
+                result: Some(result),
+                error: None,
+            },
+            Err(e) => Response {
+                ok: false,
+                result: None,
+                error: Some(e),
+            },
+        }
+    }
+}
+
+impl Response {
+    pub(crate) fn parse(line: &str) -> Result<Value> {
+        let response: Response = serde_json::from_str(line)
+            .map_err(|e| Error::Parse(format!("invalid helper response: {}", e)))?;
+        match (response.ok, response.error) {
+            (true, _) => Ok(response.result.unwrap_or(Value::Null)),
+            (false, Some(e)) => Err(e),
+            (false, None) => Err(Error::Other("helper failed without an error".into())),
+        }
+    }
+}
+
+fn valid_region_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+// A DNS name with at least two labels: letters, digits and inner hyphens only
+fn valid_domain(domain: &str) -> bool {
+    let labels: Vec<&str> = domain.split('.').collect();
+    domain.len() <= 253
+        && labels.len() >= 2
+        && labels.iter().all(|l| {
+            !l.is_empty()
+                && l.len() <= 63
+                && !l.starts_with('-')
+                && !l.ends_with('-')
+                && l.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+fn validate_backups(backups: Option<Backups>) -> Result<()> {
+    match backups {
+        Some(b) if b.count > MAX_BACKUPS => Err(Error::InvalidInput(format!(
+            "too many backups to keep ({}, at most {})",
+            b.count, MAX_BACKUPS
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn validate_ips(change: &Change) -> Result<()> {
+    let ips = match &change.ips {
+        Some(ips) => ips,
+        None => return Ok(()),
+    };
+    for (domain, list) in ips {
+        if !change
+            .domains
+            .iter()
+            .any(|d| d.eq_ignore_ascii_case(domain))
+        {
+            return Err(Error::InvalidInput(format!(
+                "IPs given for {}, which is not in the change",
+                domain
+            )));
+        }
+        if list.len() > MAX_IPS_PER_DOMAIN {
+            return Err(Error::InvalidInput(format!(
+                "too many IPs for {} ({}, at most {})",
+                domain,
+                list.len(),
+                MAX_IPS_PER_DOMAIN
+            )));
+        }
+        if let Some(ip) = list
+            .iter()
+            .find(|ip| !ip.parse::<IpAddr>().map(public_unicast).unwrap_or(false))
+        {
+            return Err(Error::InvalidInput(format!(
+                "invalid IP for {}: {:?}",
+                domain, ip
+            )));
+        }
+    }
+    Ok(())
+}
+
+impl Request {
+    pub fn validate(&self) -> Result<()> {
+        let change = match self {
+            Request::Apply(c) | Request::Remove(c) => c,
+            Request::Clear { backups } => return validate_backups(*backups),
+            Request::Status => return Ok(()),
+        };
+        if !valid_region_id(&change.region_id) {
+            return Err(Error::InvalidInput(format!(
+                "invalid region id: {:?}",
+                change.region_id
+            )));
+        }
+        if change.domains.len() > MAX_DOMAINS {
+            return Err(Error::InvalidInput(format!(
+                "too many domains ({}, at most {})",
+                change.domains.len(),
+                MAX_DOMAINS
+            )));
+        }
+        if let Some(d) = change.domains.iter().find(|d| !valid_domain(d)) {
+            return Err(Error::InvalidInput(format!("invalid domain: {:?}", d)));
+        }
+        validate_backups(change.backups)?;
+        validate_ips(change)
+    }
+}
+
+// Only clusters of the region in `catalog`, and only their IPs, may be blocked or unblocked. The
+// helper runs as root for every local user: a hosts line for any other domain would hijack it
+// machine-wide, and a firewall rule for any other address would cut everyone off from it.
+pub fn authorize_in(catalog: &Game, request: &Request) -> Result<()> {
+    let change = match request {
+        Request::Apply(c) | Request::Remove(c) => c,
+        Request::Clear { .. } | Request::Status => return Ok(()),
+    };
+    let region = catalog.region(&change.region_id).ok_or_else(|| {
+        Error::NotAuthorized(format!("region {} is not in the catalog", change.region_id))
+    })?;
+    let cluster = |domain: &str| {
+        region
+            .clusters
+            .iter()
+            .find(|c| c.domain.eq_ignore_ascii_case(domain))
+    };
+    if let Some(d) = change.domains.iter().find(|d| cluster(d).is_none()) {
+        return Err(Error::NotAuthorized(format!(
+            "{} is not a cluster of region {}",
+            d, change.region_id
+        )));
+    }
+    for (domain, ips) in change.ips.iter().flatten() {
+        let known = cluster(domain).map(|c| c.ip_addrs()).unwrap_or_default();
+        if let Some(ip) = ips.iter().find(|ip| {
+            !ip.parse::<IpAddr>()
+                .map(|a| known.contains(&a))
+                .unwrap_or(false)
+        }) {
+            return Err(Error::NotAuthorized(format!(
+                "{} is not an IP of {} in the catalog",
+                ip, domain
+            )));
+        }
+    }
+    Ok(())
+}
+
+// `authorize_in` against this process's own catalog; for requests from other users
+pub async fn authorize(request: &Request) -> Result<()> {
+    match request {
+        Request::Apply(_) | Request::Remove(_) => {
+            authorize_in(&crate::catalog::load().await?, request)
+        }
+        Request::Clear { .. } | Request::Status => Ok(()),
+    }
+}
+
+// The IPs of `domains` in this process's catalog, as a Change carries them; firewall_targets
+// only yields addresses the helper accepts
+async fn user_ips(region_id: &str, domains: &[String]) -> Option<BTreeMap<String, Vec<String>>> {
+    let catalog = crate::catalog::load().await.ok()?;
+    let targets = blocking::firewall_targets(&catalog, region_id, domains).ok()?;
+    Some(targets.into_iter().collect())
+}
+
+// Fill in what the user's side knows and the helper doesn't: backup settings and catalog IPs.
+// Whatever the request already carries is kept.
+pub async fn with_user_settings(mut request: Request) -> Request {
+    match &mut request {
+        Request::Apply(c) | Request::Remove(c) => {
+            c.backups = c.backups.or_else(|| Some(Backups::configured()));
+            if c.firewall && c.ips.is_none() {
+                c.ips = user_ips(&c.region_id, &c.domains).await;
+            }
+        }
+        Request::Clear { backups } => {
+            *backups = backups.or_else(|| Some(Backups::configured()));
+        }
+        Request::Status => {}
+    }
+    request
+}
+
+async fn change(c: &Change, enable: bool) -> Value {
+    blocking::update(
+        &c.region_id,
+        &c.domains,
+        enable,
+        c.hosts,
+        c.firewall,
+        c.backups.unwrap_or_else(Backups::configured),
+        c.ips.as_ref(),
+    )
+    .await
+}
+
+// Carry out a validated request in this process
+pub async fn handle(request: Request) -> Result<Value> {
+    match request {
+        Request::Apply(c) => Ok(change(&c, true).await),
+        Request::Remove(c) => Ok(change(&c, false).await),
+        Request::Clear { backups } => blocking::clear(backups.unwrap_or_else(Backups::configured))
+            .await
+            .map(Value::String),
+        Request::Status => Ok(serde_json::json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "pid": std::process::id()
+        })),
+    }
+}
+
+async fn read_line<S: AsyncRead + Unpin>(stream: S, timeout: Duration) -> Result<String> {
+    let mut line = String::new();
+    let mut reader = BufReader::new(stream.take(MAX_MESSAGE_BYTES));
+    tokio::time::timeout(timeout, reader.read_line(&mut line))
+        .await
+        .map_err(|_| Error::Io("timed out waiting for the helper connection".into()))?
+        .map_err(|e| Error::io("failed to read from the helper connection", e))?;
+    if !line.ends_with('\n') {
+        return Err(Error::InvalidInput(
+            "incomplete or oversized helper message".into(),
+        ));
+    }
+    Ok(line)
+}
+
+async fn write_line<S: AsyncWrite + Unpin>(stream: &mut S, value: &impl Serialize) -> Result<()> {
+    let mut text = serde_json::to_string(value)?;
+    text.push('\n');
+    stream
+        .write_all(text.as_bytes())
+        .await
+        .map_err(|e| Error::io("failed to write to the helper connection", e))?;
+    stream
+        .flush()
+        .await
+        .map_err(|e| Error::io("failed to write to the helper connection", e))
+}
+
+// Serve one connection: read a request, validate it, carry it out and answer
+pub async fn serve<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S) {
+    let outcome = async {
+        let line = read_line(&mut stream, IO_TIMEOUT).await?;
+        let request: Request = serde_json::from_str(&line)
+            .map_err(|e| Error::InvalidInput(format!("invalid request: {}", e)))?;
+        request.validate()?;
+        authorize(&request).await?;
+        eprintln!("[HELPER] {:?}", request);
+        handle(request).await
+    }
+    .await;
+
+    if let Err(e) = &outcome {
+        eprintln!("[HELPER] request failed: {}", e);
+    }
+    if let Err(e) = write_line(&mut stream, &Response::from(outcome)).await {
+        eprintln!("[HELPER] {}", e);
+    }
+}
+
+// Where the helper listens
+#[cfg(unix)]
+pub fn socket_path() -> std::path::PathBuf {
+    if let Some(p) = std::env::var_os(SOCKET_ENV).filter(|p| !p.is_empty()) {
+        return p.into();
+    }
+    #[cfg(target_os = "macos")]
+    {
+        "/var/run/clusterbanned-helper.sock".into()
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        "/run/clusterbanned/helper.sock".into()
+    }
+}
+
+// A connection to a running helper
+pub struct Client {
+    #[cfg(unix)]
+    stream: tokio::net::UnixStream,
+    #[cfg(windows)]
+    stream: tokio::net::windows::named_pipe::NamedPipeClient,
+}
+
+impl Client {
+    // Connect to the helper; fails when none is running
+    pub async fn connect() -> Result<Client> {
+        #[cfg(unix)]
+        {
+            let path = socket_path();
+            let stream = tokio::time::timeout(IO_TIMEOUT, tokio::net::UnixStream::connect(&path))
+                .await
+                .map_err(|_| Error::Io("timed out connecting to the helper".into()))?
+                .map_err(|e| Error::io(format!("helper is not running ({})", path.display()), e))?;
+            Ok(Client { stream })
+        }
+
+        #[cfg(windows)]
+        {
+            // All pipe instances busy: the helper is serving someone else, wait for it
+            const ERROR_PIPE_BUSY: i32 = 231;
+            let started = std::time::Instant::now();
+            loop {
+                match tokio::net::windows::named_pipe::ClientOptions::new().open(PIPE_NAME) {
+                    Ok(stream) => return Ok(Client { stream }),
+                    Err(e)
+                        if e.raw_os_error() == Some(ERROR_PIPE_BUSY)
+                            && started.elapsed() < IO_TIMEOUT =>
+                    {
+                        tokio::time::sleep(Duration::from_millis(50)).await
+                    }
+                    Err(e) => {
+                        return Err(Error::io(
+                            format!("helper is not running ({})", PIPE_NAME),
+                            e,
+                        ))
+                    }
+                }
+            }
+        }
+
+        #[cfg(not(any(unix, windows)))]
+        {
+            Err(Error::Unsupported(
+                "the helper is not supported on this platform".into(),
+            ))
+        }
+    }
+
+    pub async fn call(mut self, request: &Request) -> Result<Value> {
+        write_line(&mut self.stream, request).await?;
+        let line = read_line(&mut self.stream, REPLY_TIMEOUT).await?;
+        Response::parse(&line)
+    }
+}
+
+// `request` through the helper when one is running, in this process otherwise. What the helper
+// refuses to vouch for is done here too when this process has the rights for it; otherwise the
+// "not-authorized" error goes back to the caller, which can retry elevated.
+pub async fn dispatch(request: Request) -> Result<Value> {
+    let request = with_user_settings(request).await;
+    match Client::connect().await {
+        Ok(client) => match client.call(&request).await {
+            Err(Error::NotAuthorized(_)) if crate::capabilities::privileged() == Some(true) => {
+                handle(request).await
+            }
+            other => other,
+        },
+        Err(_) => handle(request).await,
+    }
+}
+
+// blocking::update through the helper when one is running
+pub async fn update(
+    region_id: &str,
+    domains: &[String],
+    enable: bool,
+    use_hosts: bool,
+    use_firewall: bool,
+) -> Result<Value> {
+    let change = Change {
+        region_id: region_id.into(),
+        domains: domains.to_vec(),
+        hosts: use_hosts,
+        firewall: use_firewall,
+        backups: None,
+        ips: None,
+    };
+    let request = if enable {
+        Request::Apply(change)
+    } else {
+        Request::Remove(change)
+    };
+    // The helper refuses what it can't validate; do the same here so both paths agree
+    request.validate()?;
+    dispatch(request).await
+}
+
+// blocking::clear through the helper when one is running
+pub async fn clear() -> Result<String> {
+    match dispatch(Request::Clear { backups: None }).await? {
+        Value::String(s) => Ok(s),
+        other => Ok(other.to_string()),
+    }
+}
+
+// The "hosts" or "firewall" part of an update result, for callers that changed only one of them
+pub fn part(result: &Value, key: &str) -> Result<String> {
+    let text = result[key].as_str().unwrap_or("").to_string();
+    match text.strip_prefix("Error: ") {
+        Some(message) if result["success"] != true => Err(Error::from_code(
+            result["errorCode"].as_str().unwrap_or("other"),
+            message.to_string(),
+        )),
+        _ => Ok(text),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tokio::net::{UnixListener, UnixStream};
+
+    // A helper on a fresh socket, serving in the background
+    fn listen(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "clusterbanned-helper-test-{}-{}.sock",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                serve(stream).await;
+            }
+        });
+        path
+    }
+
+    async fn call(path: &std::path::Path, request: &Request) -> Result<Value> {
+        let stream = UnixStream::connect(path).await.unwrap();
+        Client { stream }.call(request).await
+    }
+
+    // Send `bytes` as they are, then read the answer
+    async fn send_raw(path: &std::path::Path, bytes: &[u8]) -> Result<Value> {
+        let mut stream = UnixStream::connect(path).await.unwrap();
+        // The helper may stop reading (and hang up) before all of it is written
+        let _ = stream.write_all(bytes).await;
+        let _ = stream.shutdown().await;
+        let line = read_line(&mut stream, IO_TIMEOUT).await?;
+        Response::parse(&line)
+    }
+
+    fn change(region_id: &str, domains: &[&str]) -> Change {
+        Change {
+            region_id: region_id.into(),
+            domains: domains.iter().map(|d| d.to_string()).collect(),
+            hosts: true,
+            firewall: false,
+            backups: None,
+            ips: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn answers_status() {
+        let path = listen("status");
+        let status = call(&path, &Request::Status).await.unwrap();
+        assert_eq!(status["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(status["pid"], std::process::id());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn rejects_a_domain_that_would_inject_hosts_lines() {
+        let path = listen("bad-domain");
+        let request = Request::Apply(change(
+            "wot_eu",
+            &["login.p1.worldoftanks.eu\n127.0.0.1 bank.example.com"],
+        ));
+        let err = call(&path, &request).await.unwrap_err();
+        assert!(matches!(err, Error::InvalidInput(_)), "{:?}", err);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_and_unterminated_messages() {
+        let path = listen("framing");
+        let oversized = vec![b'x'; MAX_MESSAGE_BYTES as usize + 4096];
+        let err = send_raw(&path, &oversized).await.unwrap_err();
+        assert!(matches!(err, Error::InvalidInput(_)), "{:?}", err);
+
+        let err = send_raw(&path, br#"{"op":"status"}"#).await.unwrap_err();
+        assert!(matches!(err, Error::InvalidInput(_)), "{:?}", err);
+
+        // The same request with its newline goes through
+        assert!(send_raw(&path, b"{\"op\":\"status\"}\n").await.is_ok());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn only_clusters_of_the_catalog_region_are_authorized() {
+        let catalog = crate::catalog::embedded().unwrap();
+        let (region, cluster) = catalog.clusters().next().unwrap();
+        let domain = cluster.domain.to_uppercase();
+
+        let ok = Request::Remove(change(&region.id, &[&domain]));
+        assert!(authorize_in(&catalog, &ok).is_ok());
+
+        let foreign = Request::Apply(change(&region.id, &[&domain, "security.debian.org"]));
+        assert!(matches!(
+            authorize_in(&catalog, &foreign),
+            Err(Error::NotAuthorized(_))
+        ));
+
+        let unknown = Request::Apply(change("no_such_region", &[&domain]));
+        assert!(matches!(
+            authorize_in(&catalog, &unknown),
+            Err(Error::NotAuthorized(_))
+        ));
+        assert!(authorize_in(&catalog, &Request::Clear { backups: None }).is_ok());
+    }
+
+    #[test]
+    fn only_catalog_ips_of_the_cluster_are_authorized() {
+        let catalog = crate::catalog::embedded().unwrap();
+        let (region, cluster) = catalog.clusters().find(|(_, c)| !c.ips.is_empty()).unwrap();
+        let with_ips = |ips: &[&str]| {
+            let mut c = change(&region.id, &[&cluster.domain]);
+            c.firewall = true;
+            c.ips = Some(BTreeMap::from([(
+                cluster.domain.clone(),
+                ips.iter().map(|ip| ip.to_string()).collect(),
+            )]));
+            Request::Apply(c)
+        };
+        assert!(authorize_in(&catalog, &with_ips(&[&cluster.ips[0]])).is_ok());
+        assert!(authorize_in(&catalog, &with_ips(&[])).is_ok());
+        // Public, well-formed and passes validate(), but not this cluster's
+        let other = with_ips(&[&cluster.ips[0], "1.1.1.1"]);
+        assert!(other.validate().is_ok());
+        assert!(matches!(
+            authorize_in(&catalog, &other),
+            Err(Error::NotAuthorized(_))
+        ));
+    }
+
+    #[test]
+    fn validates_the_user_settings_a_change_carries() {
+        let with_ips = |ips: &[(&str, &str)]| {
+            let mut c = change("wot_eu", &["login.p1.worldoftanks.eu"]);
+            let mut map = BTreeMap::new();
+            for (domain, ip) in ips {
+                map.entry(domain.to_string())
+                    .or_insert_with(Vec::new)
+                    .push(ip.to_string());
+            }
+            c.ips = Some(map);
+            Request::Apply(c)
+        };
+        assert!(with_ips(&[("login.p1.worldoftanks.eu", "92.223.6.76")])
+            .validate()
+            .is_ok());
+        for ip in [
+            "127.0.0.1",
+            "0.0.0.0",
+            "192.168.1.1",
+            "fe80::1",
+            "fd00::1",
+            "not-an-ip",
+        ] {
+            assert!(
+                with_ips(&[("login.p1.worldoftanks.eu", ip)])
+                    .validate()
+                    .is_err(),
+                "{}",
+                ip
+            );
+        }
+        // IPs only for the domains being changed
+        assert!(with_ips(&[("login.p2.worldoftanks.eu", "92.223.6.76")])
+            .validate()
+            .is_err());
+
+        let backups = |count| Request::Clear {
+            backups: Some(Backups {
+                enabled: true,
+                count,
+            }),
+        };
+        assert!(backups(5).validate().is_ok());
+        assert!(backups(MAX_BACKUPS + 1).validate().is_err());
+    }
+}
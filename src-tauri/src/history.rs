@@ -0,0 +1,557 @@
+// Latency history: the probe results of the monitor and ping_server (except those that measured
+// nothing, see `recordable`), appended to latency-history.jsonl in the app config dir (one JSON record per line).
+//
+// Retention is enforced by rewriting the file without records older than `max_age_days` and
+// beyond the newest `max_records`; that happens on startup and every PRUNE_EVERY appends.
+// Lines that don't parse (e.g. a half-written last line after a crash) are skipped.
+
+use crate::error::{Error, Result};
+use crate::monitor::Tick;
+use crate::probe::stats::{summarize, LatencyStats};
+use crate::probe::{Method, ProbeResult};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+const HISTORY_FILE: &str = "latency-history.jsonl";
+const PRUNE_EVERY: usize = 1_000;
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+const HOUR_MS: u64 = 60 * 60 * 1000;
+// Keeps one query from producing a series too long to draw
+const MAX_BUCKETS: u64 = 10_000;
+
+pub const DEFAULT_MAX_AGE_DAYS: u64 = 30;
+pub const DEFAULT_MAX_RECORDS: usize = 200_000;
+
+// Serializes writers: the monitor recorder and ping_server may append at the same time
+static FILE_LOCK: Mutex<()> = Mutex::new(());
+static APPENDED: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    Monitor,
+    Ping,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Record {
+    // Milliseconds since the Unix epoch
+    pub at: u64,
+    pub region_id: String,
+    pub cluster_id: String,
+    pub method: Method,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rtt_ms: Option<f64>,
+    pub status: String,
+    pub source: Source,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Retention {
+    pub max_age_days: u64,
+    pub max_records: usize,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention {
+            max_age_days: DEFAULT_MAX_AGE_DAYS,
+            max_records: DEFAULT_MAX_RECORDS,
+        }
+    }
+}
+
+pub fn history_path(dir: &Path) -> PathBuf {
+    dir.join(HISTORY_FILE)
+}
+
+pub fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+// Whether a probe result says anything about the server's latency. "blocked" means the hosts
+// file sends the domain nowhere and "unavailable" that the probe method can't run here; recording
+// either would count as loss the server never had.
+pub fn recordable(result: &ProbeResult) -> bool {
+    result.status != "blocked" && result.status != "unavailable"
+}
+
+pub fn records_from_tick(tick: &Tick) -> Vec<Record> {
+    tick.samples
+        .iter()
+        .filter(|s| recordable(&s.result))
+        .map(|s| Record {
+            at: tick.at,
+            region_id: tick.region_id.clone(),
+            cluster_id: s.cluster_id.clone(),
+            method: s.result.method,
+            rtt_ms: s.result.rtt_ms,
+            status: s.result.status.clone(),
+            source: Source::Monitor,
+        })
+        .collect()
+}
+
+pub fn append(dir: &Path, records: &[Record], retention: Retention) -> Result<()> {
+    if records.is_empty() {
+        return Ok(());
+    }
+    let mut text = String::new();
+    for record in records {
+        let line = serde_json::to_string(record)?;
+        text.push_str(&line);
+        text.push('\n');
+    }
+
+    {
+        let _guard = FILE_LOCK.lock().unwrap();
+        std::fs::create_dir_all(dir)
+            .map_err(|e| Error::io(format!("failed to create {}", dir.display()), e))?;
+        let path = history_path(dir);
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| Error::io(format!("failed to open {}", path.display()), e))?;
+        // Don't glue the first record onto a half-written line
+        if !ends_with_newline(&mut file) {
+            text.insert(0, '\n');
+        }
+        file.write_all(text.as_bytes())
+            .map_err(|e| Error::io(format!("failed to write {}", path.display()), e))?;
+    }
+
+    let before = APPENDED.fetch_add(records.len(), Ordering::Relaxed);
+    if before / PRUNE_EVERY != (before + records.len()) / PRUNE_EVERY {
+        prune(dir, retention)?;
+    }
+    Ok(())
+}
+
+fn ends_with_newline(file: &mut std::fs::File) -> bool {
+    use std::io::{Read, Seek, SeekFrom};
+    let mut last = [0u8; 1];
+    match file.seek(SeekFrom::End(-1)) {
+        Ok(_) => file
+            .read_exact(&mut last)
+            .map(|_| last[0] == b'\n')
+            .unwrap_or(true),
+        // Empty file
+        Err(_) => true,
+    }
+}
+
+fn read_all(path: &Path) -> Result<Vec<Record>> {
+    let file = match std::fs::File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(Error::io(format!("failed to open {}", path.display()), e)),
+    };
+    let mut records = Vec::new();
+    for line in std::io::BufReader::new(file).lines() {
+        let line = line.map_err(|e| Error::io(format!("failed to read {}", path.display()), e))?;
+        if let Ok(record) = serde_json::from_str::<Record>(&line) {
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+// Drop records outside the retention limits; returns how many were removed
+pub fn prune(dir: &Path, retention: Retention) -> Result<usize> {
+    let _guard = FILE_LOCK.lock().unwrap();
+    let path = history_path(dir);
+    let mut records = read_all(&path)?;
+    let before = records.len();
+
+    let cutoff = now_ms().saturating_sub(retention.max_age_days.saturating_mul(DAY_MS));
+    records.retain(|r| r.at >= cutoff);
+    records.sort_by_key(|r| r.at);
+    if records.len() > retention.max_records {
+        records.drain(..records.len() - retention.max_records);
+    }
+    let removed = before - records.len();
+    if removed == 0 {
+        return Ok(0);
+    }
+
+    let mut text = String::new();
+    for record in &records {
+        let line = serde_json::to_string(record)?;
+        text.push_str(&line);
+        text.push('\n');
+    }
+    crate::hosts::write_atomic(&path, &text)?;
+    Ok(removed)
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Query {
+    pub region_id: String,
+    #[serde(default)]
+    pub cluster_id: Option<String>,
+    // Window in milliseconds since the Unix epoch; the last 24 hours by default
+    #[serde(default)]
+    pub from: Option<u64>,
+    #[serde(default)]
+    pub to: Option<u64>,
+}
+
+impl Query {
+    fn window(&self) -> Result<(u64, u64)> {
+        let to = self.to.unwrap_or_else(now_ms);
+        let from = self.from.unwrap_or_else(|| to.saturating_sub(DAY_MS));
+        if from >= to {
+            return Err(Error::InvalidInput(
+                "history window is empty: `from` must be before `to`".into(),
+            ));
+        }
+        Ok((from, to))
+    }
+
+    fn matches(&self, record: &Record, from: u64, to: u64) -> bool {
+        record.at >= from
+            && record.at < to
+            && record.region_id == self.region_id
+            && self
+                .cluster_id
+                .as_ref()
+                .map(|id| &record.cluster_id == id)
+                .unwrap_or(true)
+    }
+}
+
+// Records matching `query`, oldest first; with `limit`, only the newest ones
+pub fn query(dir: &Path, query: &Query, limit: Option<usize>) -> Result<Vec<Record>> {
+    let (from, to) = query.window()?;
+    let mut records: Vec<Record> = {
+        let _guard = FILE_LOCK.lock().unwrap();
+        read_all(&history_path(dir))?
+    }
+    .into_iter()
+    .filter(|r| query.matches(r, from, to))
+    .collect();
+    records.sort_by_key(|r| r.at);
+    if let Some(limit) = limit {
+        if records.len() > limit {
+            records.drain(..records.len() - limit);
+        }
+    }
+    Ok(records)
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Bucket {
+    // Start of the bucket in milliseconds since the Unix epoch, or the hour of day (0-23) for
+    // hourly profiles
+    pub key: u64,
+    #[serde(flatten)]
+    pub stats: LatencyStats,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterSeries {
+    pub region_id: String,
+    pub cluster_id: String,
+    // Only buckets that have samples
+    pub buckets: Vec<Bucket>,
+}
+
+fn series(region_id: &str, records: &[Record], key: impl Fn(&Record) -> u64) -> Vec<ClusterSeries> {
+    let mut grouped: BTreeMap<&str, BTreeMap<u64, Vec<Option<f64>>>> = BTreeMap::new();
+    for record in records {
+        grouped
+            .entry(record.cluster_id.as_str())
+            .or_default()
+            .entry(key(record))
+            .or_default()
+            .push(record.rtt_ms);
+    }
+    grouped
+        .into_iter()
+        .map(|(cluster_id, buckets)| ClusterSeries {
+            region_id: region_id.to_string(),
+            cluster_id: cluster_id.to_string(),
+            buckets: buckets
+                .into_iter()
+                .map(|(key, samples)| Bucket {
+                    key,
+                    stats: summarize(&samples),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+// Per-cluster averages and loss over consecutive `bucket_secs` windows
+pub fn buckets(dir: &Path, q: &Query, bucket_secs: u64) -> Result<Vec<ClusterSeries>> {
+    let (from, to) = q.window()?;
+    if bucket_secs == 0 {
+        return Err(Error::InvalidInput("bucket size must be positive".into()));
+    }
+    let bucket_ms = bucket_secs
+        .checked_mul(1000)
+        .ok_or_else(|| Error::InvalidInput("bucket size is too large".into()))?;
+    if (to - from) / bucket_ms > MAX_BUCKETS {
+        return Err(Error::InvalidInput(format!(
+            "too many buckets for this window (at most {}); use larger buckets",
+            MAX_BUCKETS
+        )));
+    }
+    let records = query(dir, q, None)?;
+    Ok(series(&q.region_id, &records, |r| {
+        from + (r.at - from) / bucket_ms * bucket_ms
+    }))
+}
+
+// Per-cluster averages and loss by hour of day, in the time zone `utc_offset_minutes` from UTC
+pub fn hourly(dir: &Path, q: &Query, utc_offset_minutes: i32) -> Result<Vec<ClusterSeries>> {
+    if utc_offset_minutes.abs() > 14 * 60 {
+        return Err(Error::InvalidInput(
+            "UTC offset must be within ±14 hours".into(),
+        ));
+    }
+    let records = query(dir, q, None)?;
+    let offset_ms = utc_offset_minutes as i64 * 60 * 1000;
+    Ok(series(&q.region_id, &records, |r| {
+        ((r.at as i64 + offset_ms).rem_euclid(DAY_MS as i64) as u64) / HOUR_MS
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monitor::Sample;
+
+    const T0: u64 = 1_700_000_000_000;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "clusterbanned-history-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn record(at: u64, region_id: &str, cluster_id: &str, rtt_ms: Option<f64>) -> Record {
+        Record {
+            at,
+            region_id: region_id.into(),
+            cluster_id: cluster_id.into(),
+            method: Method::Tcp,
+            rtt_ms,
+            status: if rtt_ms.is_some() { "ok" } else { "timeout" }.into(),
+            source: Source::Monitor,
+        }
+    }
+
+    fn eu(cluster_id: Option<&str>, from: u64, to: u64) -> Query {
+        Query {
+            region_id: "eu".into(),
+            cluster_id: cluster_id.map(String::from),
+            from: Some(from),
+            to: Some(to),
+        }
+    }
+
+    fn times(records: &[Record]) -> Vec<u64> {
+        records.iter().map(|r| r.at).collect()
+    }
+
+    #[test]
+    fn appended_records_are_queried_by_region_cluster_and_window() {
+        let dir = temp_dir("query");
+        let records = [
+            record(T0 + 30, "eu", "c1", Some(20.0)),
+            record(T0 + 10, "eu", "c1", Some(10.0)),
+            record(T0 + 20, "eu", "c2", None),
+            record(T0 + 15, "na", "c1", Some(90.0)),
+        ];
+        append(&dir, &records[..2], Retention::default()).unwrap();
+        append(&dir, &records[2..], Retention::default()).unwrap();
+
+        let all = query(&dir, &eu(None, T0, T0 + 100), None).unwrap();
+        assert_eq!(times(&all), vec![T0 + 10, T0 + 20, T0 + 30]);
+        assert_eq!(all[1].rtt_ms, None);
+        let c1 = query(&dir, &eu(Some("c1"), T0, T0 + 100), None).unwrap();
+        assert_eq!(times(&c1), vec![T0 + 10, T0 + 30]);
+        // `to` is exclusive
+        let window = query(&dir, &eu(None, T0 + 10, T0 + 30), None).unwrap();
+        assert_eq!(times(&window), vec![T0 + 10, T0 + 20]);
+        let newest = query(&dir, &eu(None, T0, T0 + 100), Some(2)).unwrap();
+        assert_eq!(times(&newest), vec![T0 + 20, T0 + 30]);
+
+        assert!(matches!(
+            query(&dir, &eu(None, T0, T0), None),
+            Err(Error::InvalidInput(_))
+        ));
+        // Nothing recorded yet is an empty history, not an error
+        assert!(query(&temp_dir("missing"), &eu(None, T0, T0 + 1), None)
+            .unwrap()
+            .is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_half_written_line_is_skipped_and_not_glued_to() {
+        let dir = temp_dir("torn");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(history_path(&dir), r#"{"at": 1, "regionId": "eu""#).unwrap();
+        append(
+            &dir,
+            &[record(T0, "eu", "c1", Some(5.0))],
+            Retention::default(),
+        )
+        .unwrap();
+
+        let records = query(&dir, &eu(None, 0, T0 + 1), None).unwrap();
+        assert_eq!(times(&records), vec![T0]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn prune_drops_old_and_excess_records() {
+        let dir = temp_dir("prune");
+        let now = now_ms();
+        let records = [
+            record(now - 40 * DAY_MS, "eu", "c1", Some(1.0)),
+            record(now - 2 * DAY_MS, "eu", "c1", Some(2.0)),
+            record(now - DAY_MS, "eu", "c1", Some(3.0)),
+            record(now - 1000, "eu", "c1", Some(4.0)),
+        ];
+        append(&dir, &records, Retention::default()).unwrap();
+
+        let retention = Retention {
+            max_age_days: 30,
+            max_records: 2,
+        };
+        assert_eq!(prune(&dir, retention).unwrap(), 2);
+        assert_eq!(prune(&dir, retention).unwrap(), 0);
+        let left = query(&dir, &eu(None, 0, now + 1), None).unwrap();
+        assert_eq!(times(&left), vec![now - DAY_MS, now - 1000]);
+
+        // A retention too long to express in milliseconds keeps everything
+        let forever = Retention {
+            max_age_days: u64::MAX,
+            max_records: usize::MAX,
+        };
+        assert_eq!(prune(&dir, forever).unwrap(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn buckets_start_at_the_window_and_count_loss() {
+        let dir = temp_dir("buckets");
+        let from = T0 + 500;
+        let records = [
+            record(from, "eu", "c1", Some(10.0)),
+            record(from + 999, "eu", "c1", Some(30.0)),
+            record(from + 1000, "eu", "c1", None),
+            record(from + 1500, "eu", "c1", Some(40.0)),
+            record(from + 2500, "eu", "c2", Some(50.0)),
+        ];
+        append(&dir, &records, Retention::default()).unwrap();
+
+        let series = buckets(&dir, &eu(None, from, from + 3000), 1).unwrap();
+        assert_eq!(series.len(), 2);
+        let c1: Vec<(u64, u32, Option<f64>, f64)> = series[0]
+            .buckets
+            .iter()
+            .map(|b| (b.key, b.stats.sent, b.stats.avg_ms, b.stats.loss_percent))
+            .collect();
+        assert_eq!(
+            c1,
+            vec![
+                (from, 2, Some(20.0), 0.0),
+                (from + 1000, 2, Some(40.0), 50.0)
+            ]
+        );
+        assert_eq!(series[1].cluster_id, "c2");
+        assert_eq!(series[1].buckets[0].key, from + 2000);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bucket_sizes_are_validated() {
+        let dir = temp_dir("bucket-sizes");
+        let q = eu(None, T0, T0 + DAY_MS);
+        for bucket_secs in [0, 1, u64::MAX] {
+            assert!(
+                matches!(buckets(&dir, &q, bucket_secs), Err(Error::InvalidInput(_))),
+                "{}",
+                bucket_secs
+            );
+        }
+        assert!(buckets(&dir, &q, 3600).unwrap().is_empty());
+    }
+
+    #[test]
+    fn hourly_profiles_use_the_local_hour() {
+        let dir = temp_dir("hourly");
+        // 2023-11-14 22:30 UTC
+        let at = T0 - T0 % DAY_MS + 22 * HOUR_MS + 30 * 60 * 1000;
+        append(
+            &dir,
+            &[record(at, "eu", "c1", Some(10.0))],
+            Retention::default(),
+        )
+        .unwrap();
+        let q = eu(None, at, at + 1);
+        let hour = |offset: i32| hourly(&dir, &q, offset).unwrap()[0].buckets[0].key;
+        assert_eq!(hour(0), 22);
+        assert_eq!(hour(90), 0);
+        assert_eq!(hour(-12 * 60), 10);
+        assert!(matches!(
+            hourly(&dir, &q, 15 * 60),
+            Err(Error::InvalidInput(_))
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rounds_keep_only_results_that_measured_something() {
+        let sample = |cluster_id: &str, status: &str| Sample {
+            cluster_id: cluster_id.into(),
+            domain: format!("{}.example", cluster_id),
+            result: ProbeResult {
+                method: Method::Icmp,
+                ip: None,
+                port: None,
+                rtt_ms: None,
+                status: status.into(),
+                error: None,
+            },
+        };
+        let tick = Tick {
+            region_id: "eu".into(),
+            seq: 1,
+            at: T0,
+            hidden: false,
+            samples: vec![
+                sample("c1", "timeout"),
+                sample("c2", "blocked"),
+                sample("c3", "unavailable"),
+                sample("c4", "unreachable"),
+            ],
+        };
+        let kept: Vec<String> = records_from_tick(&tick)
+            .into_iter()
+            .map(|r| r.cluster_id)
+            .collect();
+        assert_eq!(kept, vec!["c1", "c4"]);
+    }
+}
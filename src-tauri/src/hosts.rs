@@ -0,0 +1,826 @@
+// Parsed model of the hosts file.
+//
+// The document keeps every line exactly as it was read (text and line ending), so
+// rendering an untouched document gives back the original bytes. Only the managed
+// "# clusterbanned start ... # clusterbanned end" blocks are ever rewritten.
+
+use crate::error::{Error, Result};
+
+pub const START_MARKER: &str = "# clusterbanned start";
+pub const END_MARKER: &str = "# clusterbanned end";
+pub const SINK_IP: &str = "0.0.0.0";
+
+// Where the hosts file lives on Windows and everywhere else
+pub const PATHS: [&str; 2] = ["C:\\Windows\\System32\\drivers\\etc\\hosts", "/etc/hosts"];
+
+const MARKER_PREFIX: &str = "# clusterbanned";
+const REGION_TAG: &str = "region:";
+// Start marker tag: the blank line in front of the block was inserted by us
+const SEPARATED_TAG: &str = "separated";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    // Last line of a file without a trailing newline
+    None,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::None => "",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineKind {
+    Blank,
+    Comment,
+    Entry { ip: String, names: Vec<String> },
+    // Anything we can't make sense of (a lone IP, garbage); kept verbatim
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostsLine {
+    pub text: String,
+    pub ending: LineEnding,
+    pub kind: LineKind,
+}
+
+impl HostsLine {
+    fn parse(text: &str, ending: LineEnding) -> Self {
+        let trimmed = text.trim();
+        let kind = if trimmed.is_empty() {
+            LineKind::Blank
+        } else if trimmed.starts_with('#') {
+            LineKind::Comment
+        } else {
+            // Inline comments ("1.2.3.4 host # note") are not part of the entry
+            let data = trimmed.split('#').next().unwrap_or("");
+            let mut parts = data.split_whitespace();
+            match parts.next() {
+                Some(ip) => {
+                    let names: Vec<String> = parts.map(|s| s.to_string()).collect();
+                    if names.is_empty() {
+                        LineKind::Other
+                    } else {
+                        LineKind::Entry {
+                            ip: ip.to_string(),
+                            names,
+                        }
+                    }
+                }
+                None => LineKind::Other,
+            }
+        };
+        HostsLine {
+            text: text.to_string(),
+            ending,
+            kind,
+        }
+    }
+
+    fn new(text: String, ending: LineEnding) -> Self {
+        HostsLine::parse(&text, ending)
+    }
+}
+
+// A "# clusterbanned start [region:<id>] [separated]" ... "# clusterbanned end" section we own
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManagedBlock {
+    pub region: Option<String>,
+    // The blank line right before the block is ours and goes away with it
+    pub separated: bool,
+    pub lines: Vec<HostsLine>,
+}
+
+impl ManagedBlock {
+    fn build(region: Option<&str>, domains: &[String], eol: LineEnding, separated: bool) -> Self {
+        let mut start = match region {
+            Some(r) => format!("{} {}{}", START_MARKER, REGION_TAG, r),
+            None => START_MARKER.to_string(),
+        };
+        if separated {
+            start.push(' ');
+            start.push_str(SEPARATED_TAG);
+        }
+        let mut lines = vec![HostsLine::new(start, eol)];
+        for d in domains {
+            lines.push(HostsLine::new(format!("{} {}", SINK_IP, d), eol));
+        }
+        lines.push(HostsLine::new(END_MARKER.to_string(), eol));
+        ManagedBlock {
+            region: region.map(|r| r.to_string()),
+            separated,
+            lines,
+        }
+    }
+
+    // Lower-cased domains listed inside the block, in file order
+    pub fn domains(&self) -> Vec<String> {
+        let mut out: Vec<String> = Vec::new();
+        for line in &self.lines {
+            if let LineKind::Entry { names, .. } = &line.kind {
+                for n in names {
+                    let d = n.to_lowercase();
+                    if !out.contains(&d) {
+                        out.push(d);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    Line(HostsLine),
+    Block(ManagedBlock),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct HostsDocument {
+    pub nodes: Vec<Node>,
+}
+
+fn parse_region_tag(marker_line: &str) -> Option<String> {
+    let pos = marker_line.find(REGION_TAG)?;
+    let tag = marker_line[pos + REGION_TAG.len()..]
+        .split_whitespace()
+        .next()
+        .unwrap_or("");
+    if tag.is_empty() {
+        None
+    } else {
+        Some(tag.to_string())
+    }
+}
+
+fn split_lines(text: &str) -> Vec<(&str, LineEnding)> {
+    let mut out = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        match rest.find('\n') {
+            Some(pos) => {
+                let line = &rest[..pos];
+                match line.strip_suffix('\r') {
+                    Some(l) => out.push((l, LineEnding::CrLf)),
+                    None => out.push((line, LineEnding::Lf)),
+                }
+                rest = &rest[pos + 1..];
+            }
+            None => {
+                out.push((rest, LineEnding::None));
+                rest = "";
+            }
+        }
+    }
+    out
+}
+
+impl HostsDocument {
+    pub fn parse(text: &str) -> Self {
+        let raw = split_lines(text);
+        let mut nodes = Vec::new();
+        let mut i = 0usize;
+
+        while i < raw.len() {
+            let (text, ending) = raw[i];
+            let trimmed = text.trim();
+            // Any "# clusterbanned ..." line other than the end marker opens a block
+            // (older builds wrote tags we don't know about, clear must still catch them)
+            if trimmed.starts_with(MARKER_PREFIX) && !trimmed.starts_with(END_MARKER) {
+                let close = raw[i + 1..]
+                    .iter()
+                    .position(|(t, _)| t.trim().starts_with(END_MARKER))
+                    .map(|p| i + 1 + p);
+                if let Some(end) = close {
+                    let lines = raw[i..=end]
+                        .iter()
+                        .map(|(t, e)| HostsLine::parse(t, *e))
+                        .collect();
+                    nodes.push(Node::Block(ManagedBlock {
+                        region: parse_region_tag(trimmed),
+                        separated: trimmed.split_whitespace().any(|t| t == SEPARATED_TAG),
+                        lines,
+                    }));
+                    i = end + 1;
+                    continue;
+                }
+                // Unterminated marker: not ours to touch, keep it as a plain comment
+            }
+            nodes.push(Node::Line(HostsLine::parse(text, ending)));
+            i += 1;
+        }
+
+        HostsDocument { nodes }
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for node in &self.nodes {
+            match node {
+                Node::Line(l) => {
+                    out.push_str(&l.text);
+                    out.push_str(l.ending.as_str());
+                }
+                Node::Block(b) => {
+                    for l in &b.lines {
+                        out.push_str(&l.text);
+                        out.push_str(l.ending.as_str());
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    // Line ending used by the majority of the file, LF for empty files
+    pub fn line_ending(&self) -> LineEnding {
+        let (mut lf, mut crlf) = (0usize, 0usize);
+        for l in self.all_lines() {
+            match l.ending {
+                LineEnding::Lf => lf += 1,
+                LineEnding::CrLf => crlf += 1,
+                LineEnding::None => {}
+            }
+        }
+        if crlf > lf {
+            LineEnding::CrLf
+        } else {
+            LineEnding::Lf
+        }
+    }
+
+    fn all_lines(&self) -> impl Iterator<Item = &HostsLine> {
+        self.nodes.iter().flat_map(|n| match n {
+            Node::Line(l) => std::slice::from_ref(l).iter(),
+            Node::Block(b) => b.lines.iter(),
+        })
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = &ManagedBlock> {
+        self.nodes.iter().filter_map(|n| match n {
+            Node::Block(b) => Some(b),
+            _ => None,
+        })
+    }
+
+    // (region, domains) of every managed block, used to check what actually landed on disk
+    pub fn block_summary(&self) -> Vec<(Option<String>, Vec<String>)> {
+        self.blocks()
+            .map(|b| (b.region.clone(), b.domains()))
+            .collect()
+    }
+
+    // Index (into `nodes`) of the block for `region`; `None` picks the first block
+    pub fn find_block(&self, region: Option<&str>) -> Option<usize> {
+        self.nodes.iter().position(|n| match n {
+            Node::Block(b) => match region {
+                Some(r) => b.region.as_deref() == Some(r),
+                None => true,
+            },
+            _ => false,
+        })
+    }
+
+    pub fn block(&self, region: Option<&str>) -> Option<&ManagedBlock> {
+        self.find_block(region).and_then(|i| match &self.nodes[i] {
+            Node::Block(b) => Some(b),
+            _ => None,
+        })
+    }
+
+    // Every hostname with a dot that the file maps to some address, lower-cased and sorted
+    pub fn blocked_domains(&self) -> Vec<String> {
+        let mut set = std::collections::BTreeSet::new();
+        for l in self.all_lines() {
+            if let LineKind::Entry { names, .. } = &l.kind {
+                for n in names {
+                    let d = n.to_lowercase();
+                    if d.contains('.') {
+                        set.insert(d);
+                    }
+                }
+            }
+        }
+        set.into_iter().collect()
+    }
+
+    // Replace the block for `region` in place, or append a new one at the end of the file.
+    // An empty domain list removes the block.
+    pub fn set_block(&mut self, region: Option<&str>, domains: &[String]) {
+        if domains.is_empty() {
+            self.remove_block(region);
+            return;
+        }
+        let eol = self.line_ending();
+
+        if let Some(idx) = self.find_block(region) {
+            // Keep whatever terminated the old block (it may be the last line of the file)
+            // and whether the blank line in front of it is ours
+            let (old_end, separated) = match &self.nodes[idx] {
+                Node::Block(b) => (b.lines.last().map(|l| l.ending).unwrap_or(eol), b.separated),
+                _ => (eol, false),
+            };
+            let mut block = ManagedBlock::build(region, domains, eol, separated);
+            if let Some(last) = block.lines.last_mut() {
+                last.ending = old_end;
+            }
+            self.nodes[idx] = Node::Block(block);
+            return;
+        }
+
+        // Appending: terminate the last line if needed and separate with one blank line
+        if let Some(last) = self.last_line_mut() {
+            if last.ending == LineEnding::None {
+                last.ending = eol;
+            }
+        }
+        let needs_separator = match self.nodes.last() {
+            None => false,
+            Some(Node::Line(l)) => l.kind != LineKind::Blank,
+            Some(Node::Block(_)) => true,
+        };
+        if needs_separator {
+            self.nodes
+                .push(Node::Line(HostsLine::new(String::new(), eol)));
+        }
+        self.nodes.push(Node::Block(ManagedBlock::build(
+            region,
+            domains,
+            eol,
+            needs_separator,
+        )));
+    }
+
+    // Remove the block for `region` (first block if `None`). Returns whether one was found.
+    pub fn remove_block(&mut self, region: Option<&str>) -> bool {
+        match self.find_block(region) {
+            Some(idx) => {
+                self.remove_node(idx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Remove every managed block, returns how many were removed
+    pub fn remove_all_blocks(&mut self) -> usize {
+        let mut removed = 0usize;
+        while let Some(idx) = self.find_block(None) {
+            self.remove_node(idx);
+            removed += 1;
+        }
+        removed
+    }
+
+    fn remove_node(&mut self, idx: usize) {
+        let (last_ending, separated) = match &self.nodes[idx] {
+            Node::Block(b) => (b.lines.last().map(|l| l.ending), b.separated),
+            Node::Line(l) => (Some(l.ending), false),
+        };
+        self.nodes.remove(idx);
+
+        // Only a separator `set_block` inserted goes; blank lines the user wrote stay
+        let prev_blank =
+            idx > 0 && matches!(&self.nodes[idx - 1], Node::Line(l) if l.kind == LineKind::Blank);
+        if separated && prev_blank {
+            self.nodes.remove(idx - 1);
+        }
+
+        // A block at the very end without a newline: the new last line inherits that
+        if idx >= self.nodes.len() && last_ending == Some(LineEnding::None) {
+            if let Some(last) = self.last_line_mut() {
+                last.ending = LineEnding::None;
+            }
+        }
+    }
+
+    fn last_line_mut(&mut self) -> Option<&mut HostsLine> {
+        match self.nodes.last_mut()? {
+            Node::Line(l) => Some(l),
+            Node::Block(b) => b.lines.last_mut(),
+        }
+    }
+}
+
+// Result of applying a block/unblock request to a document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegionUpdate {
+    // Nothing to write; the message says why
+    Unchanged(String),
+    // The document was changed; the message says how
+    Changed(String),
+}
+
+// Block `domains` for `region` (replacing its block), or with `remove` take them out of it.
+// Without a region the first block is used, as older builds wrote untagged blocks.
+pub fn update_region(
+    doc: &mut HostsDocument,
+    domains: &[String],
+    region: Option<&str>,
+    remove: bool,
+) -> RegionUpdate {
+    let existing_block = doc.block(region).cloned();
+
+    if remove {
+        let block = match existing_block {
+            Some(b) => b,
+            None => return RegionUpdate::Unchanged("No cluster entries to update".into()),
+        };
+        let existing: std::collections::BTreeSet<String> = block.domains().into_iter().collect();
+        let remove_set: std::collections::BTreeSet<String> =
+            domains.iter().map(|s| s.to_lowercase()).collect();
+        let remaining: Vec<String> = existing.difference(&remove_set).cloned().collect();
+
+        if remaining.len() == existing.len() {
+            return RegionUpdate::Unchanged("No matching entries to remove".into());
+        }
+
+        // Write back the block under the tag it was found with (empty => remove block)
+        doc.set_block(block.region.as_deref(), &remaining);
+
+        if remaining.is_empty() {
+            RegionUpdate::Changed("Removed clusterbanned block".into())
+        } else {
+            RegionUpdate::Changed(format!(
+                "Removed {} entries, left {} entries",
+                existing.len() - remaining.len(),
+                remaining.len()
+            ))
+        }
+    } else if !domains.is_empty() {
+        doc.set_block(region, domains);
+        RegionUpdate::Changed(format!("Successfully blocked {} domains", domains.len()))
+    } else if existing_block.is_some() {
+        // Empty list for a region that had a block: everything in it is unblocked
+        doc.remove_block(region);
+        RegionUpdate::Changed("Removed clusterbanned block".into())
+    } else {
+        RegionUpdate::Unchanged("No cluster entries to update".into())
+    }
+}
+
+pub fn unified_diff(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    similar::TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(3)
+        .header(old_name, new_name)
+        .to_string()
+}
+
+// The file write_atomic replaces (a symlink's target, not the link itself) and a file named
+// `.<name>.<suffix>` next to it
+fn sibling_path(
+    path: &std::path::Path,
+    suffix: &str,
+) -> Result<(std::path::PathBuf, std::path::PathBuf)> {
+    let target = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let dir = target
+        .parent()
+        .ok_or_else(|| Error::NotFound(format!("{} has no parent directory", target.display())))?;
+    let file_name = target
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "hosts".into());
+    let sibling = dir.join(format!(".{}.{}", file_name, suffix));
+    Ok((target, sibling))
+}
+
+// Write `contents` next to `path` in a temp file, fsync it and rename it over the original,
+// so a crash leaves either the old or the new file, never a truncated one.
+pub fn write_atomic(path: &std::path::Path, contents: &str) -> Result<()> {
+    use std::io::Write;
+
+    let (target, tmp) = sibling_path(path, "clusterbanned.tmp")?;
+    let dir = tmp.parent().unwrap_or(std::path::Path::new("."));
+
+    let original_meta = std::fs::metadata(&target).ok();
+
+    let result = (|| -> Result<()> {
+        let mut f = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp)
+            .map_err(|e| Error::io(format!("failed to create temp file {}", tmp.display()), e))?;
+        f.write_all(contents.as_bytes())
+            .map_err(|e| Error::io(format!("failed to write temp file {}", tmp.display()), e))?;
+        f.sync_all()
+            .map_err(|e| Error::io(format!("failed to sync temp file {}", tmp.display()), e))?;
+        drop(f);
+
+        if let Some(meta) = &original_meta {
+            std::fs::set_permissions(&tmp, meta.permissions()).map_err(|e| {
+                Error::io(
+                    format!("failed to copy permissions to {}", tmp.display()),
+                    e,
+                )
+            })?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::MetadataExt;
+                // Only root can chown; when we are not root the owner is already us
+                let _ = std::os::unix::fs::chown(&tmp, Some(meta.uid()), Some(meta.gid()));
+            }
+        }
+
+        std::fs::rename(&tmp, &target)
+            .map_err(|e| Error::io(format!("failed to replace {}", target.display()), e))?;
+
+        // Persist the rename itself
+        #[cfg(unix)]
+        {
+            if let Ok(d) = std::fs::File::open(dir) {
+                let _ = d.sync_all();
+            }
+        }
+        Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    result
+}
+
+// Atomically write `doc`, re-read the file and check that exactly the managed blocks of `doc`
+// ended up on disk. Anything else (another writer, antivirus rewriting hosts, a filter driver)
+// puts `original` back and fails.
+pub fn commit(path: &std::path::Path, original: &str, doc: &HostsDocument) -> Result<()> {
+    write_atomic(path, &doc.render())?;
+
+    let expected = doc.block_summary();
+    let actual = std::fs::read_to_string(path)
+        .map(|text| HostsDocument::parse(&text).block_summary())
+        .map_err(|e| format!("failed to re-read {}: {}", path.display(), e));
+
+    match actual {
+        Ok(actual) if actual == expected => Ok(()),
+        other => {
+            let reason = match other {
+                Ok(actual) => format!(
+                    "expected {} managed block(s), found {}",
+                    expected.len(),
+                    actual.len()
+                ),
+                Err(e) => e,
+            };
+            eprintln!(
+                "[TAURI] hosts verification failed ({}), rolling back",
+                reason
+            );
+            match write_atomic(path, original) {
+                Ok(_) => Err(Error::Io(format!(
+                    "hosts verification failed ({}); original file restored",
+                    reason
+                ))),
+                Err(e) => Err(e.context(format!(
+                    "hosts verification failed ({}) and rollback failed",
+                    reason
+                ))),
+            }
+        }
+    }
+}
+
+// First hosts path that exists, or the Windows path as a default
+pub fn active_path() -> &'static str {
+    PATHS
+        .iter()
+        .find(|p| std::path::Path::new(p).exists())
+        .cloned()
+        .unwrap_or(PATHS[0])
+}
+
+// Contents of the first readable hosts file
+pub fn read_text() -> Result<String> {
+    let mut unreadable = None;
+    for p in PATHS {
+        match std::fs::read_to_string(p) {
+            Ok(s) => return Ok(s),
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                unreadable = Some(Error::io(format!("failed to read hosts file ({})", p), e))
+            }
+            Err(_) => {}
+        }
+    }
+    Err(unreadable.unwrap_or_else(|| Error::NotFound("hosts file not found".into())))
+}
+
+// Contents of the hosts file at `path` (normally `active_path()`)
+pub fn read(path: &str) -> Result<String> {
+    std::fs::read_to_string(path)
+        .map_err(|e| Error::io(format!("failed to read hosts file ({})", path), e))
+}
+
+// Whether write_atomic can replace `path`: the file opens for writing and its directory takes
+// the temp file
+pub fn check_writable(path: &str) -> Result<()> {
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(path)
+        .map_err(|e| {
+            Error::io(
+                format!("failed to open hosts file ({}) for writing", path),
+                e,
+            )
+        })?;
+    // Not write_atomic's temp file: a write in progress must not lose it to this check
+    let (_, probe) = sibling_path(std::path::Path::new(path), "clusterbanned.probe")?;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&probe)
+        .map_err(|e| Error::io(format!("failed to create temp file {}", probe.display()), e))?;
+    let _ = std::fs::remove_file(&probe);
+    Ok(())
+}
+
+// Whether the frontend's selections (region -> { domain: enabled }) disagree with what is
+// blocked in hosts: a disabled cluster must be blocked and an enabled one must not
+pub fn selections_mismatch(blocked: &[String], selections: &serde_json::Value) -> bool {
+    let blocked: std::collections::BTreeSet<String> = blocked.iter().cloned().collect();
+    let regions = match selections.as_object() {
+        Some(map) => map,
+        None => return false,
+    };
+    regions
+        .values()
+        .filter_map(|v| v.as_object())
+        .flat_map(|domains| domains.iter())
+        .any(|(domain, enabled)| {
+            let enabled = enabled.as_bool().unwrap_or(true);
+            blocked.contains(&domain.to_lowercase()) == enabled
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn domains(list: &[&str]) -> Vec<String> {
+        list.iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_render_round_trip() {
+        let samples = [
+            "",
+            "127.0.0.1 localhost\n",
+            "127.0.0.1 localhost",
+            "# comment\r\n127.0.0.1 localhost\r\n\r\n::1 localhost",
+            "a\n\n# clusterbanned start region:eu\n0.0.0.0 x.example\n# clusterbanned end",
+            "# clusterbanned start\nunterminated\n\n",
+            "  \t\n1.2.3.4 host # note\r\ngarbage\n",
+        ];
+        for text in samples {
+            assert_eq!(HostsDocument::parse(text).render(), text, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn parse_finds_blocks() {
+        let doc = HostsDocument::parse(
+            "# clusterbanned start region:eu separated\n0.0.0.0 A.example b.example\n# clusterbanned end\n# clusterbanned start\n",
+        );
+        let block = doc.block(Some("eu")).unwrap();
+        assert!(block.separated);
+        assert_eq!(block.domains(), domains(&["a.example", "b.example"]));
+        // The second marker is never closed, so it stays a comment
+        assert_eq!(doc.blocks().count(), 1);
+    }
+
+    #[test]
+    fn set_then_remove_restores_the_file() {
+        let original = "127.0.0.1 localhost\n::1 localhost\n";
+        let mut doc = HostsDocument::parse(original);
+        doc.set_block(Some("eu"), &domains(&["a.example"]));
+        let written = doc.render();
+        assert_eq!(
+            written,
+            "127.0.0.1 localhost\n::1 localhost\n\n# clusterbanned start region:eu separated\n0.0.0.0 a.example\n# clusterbanned end\n"
+        );
+
+        // The separator is remembered across a re-read
+        let mut doc = HostsDocument::parse(&written);
+        assert!(doc.remove_block(Some("eu")));
+        assert_eq!(doc.render(), original);
+    }
+
+    #[test]
+    fn set_keeps_line_endings() {
+        let mut doc = HostsDocument::parse("127.0.0.1 localhost\r\n::1 localhost");
+        doc.set_block(None, &domains(&["a.example"]));
+        assert_eq!(
+            doc.render(),
+            "127.0.0.1 localhost\r\n::1 localhost\r\n\r\n# clusterbanned start separated\r\n0.0.0.0 a.example\r\n# clusterbanned end\r\n"
+        );
+    }
+
+    #[test]
+    fn no_separator_after_a_blank_line() {
+        let original = "127.0.0.1 localhost\n\n";
+        let mut doc = HostsDocument::parse(original);
+        doc.set_block(Some("eu"), &domains(&["a.example"]));
+        assert!(!doc.block(Some("eu")).unwrap().separated);
+        assert!(doc.remove_block(Some("eu")));
+        assert_eq!(doc.render(), original);
+    }
+
+    #[test]
+    fn remove_keeps_the_users_blank_lines() {
+        // Written by hand (or an older build): the blank line in front is not ours
+        let text = "127.0.0.1 localhost\n\n# clusterbanned start region:eu\n0.0.0.0 a.example\n# clusterbanned end\n\n::1 localhost\n";
+        let mut doc = HostsDocument::parse(text);
+        assert!(doc.remove_block(Some("eu")));
+        assert_eq!(doc.render(), "127.0.0.1 localhost\n\n\n::1 localhost\n");
+    }
+
+    #[test]
+    fn remove_drops_our_separator_before_user_lines() {
+        let mut doc = HostsDocument::parse("127.0.0.1 localhost\n");
+        doc.set_block(Some("eu"), &domains(&["a.example"]));
+        let text = format!("{}::1 localhost\n", doc.render());
+        let mut doc = HostsDocument::parse(&text);
+        assert!(doc.remove_block(Some("eu")));
+        assert_eq!(doc.render(), "127.0.0.1 localhost\n::1 localhost\n");
+    }
+
+    #[test]
+    fn replacing_a_block_keeps_its_place_and_separator() {
+        let mut doc = HostsDocument::parse("127.0.0.1 localhost\n");
+        doc.set_block(Some("eu"), &domains(&["a.example"]));
+        doc.set_block(Some("na"), &domains(&["n.example"]));
+        doc.set_block(Some("eu"), &domains(&["a.example", "b.example"]));
+        assert_eq!(
+            doc.block_summary(),
+            vec![
+                (Some("eu".to_string()), domains(&["a.example", "b.example"])),
+                (Some("na".to_string()), domains(&["n.example"])),
+            ]
+        );
+        assert!(doc.block(Some("eu")).unwrap().separated);
+
+        assert_eq!(doc.remove_all_blocks(), 2);
+        assert_eq!(doc.render(), "127.0.0.1 localhost\n");
+    }
+
+    #[test]
+    fn block_without_trailing_newline() {
+        let text =
+            "127.0.0.1 localhost\n# clusterbanned start\n0.0.0.0 a.example\n# clusterbanned end";
+        let mut doc = HostsDocument::parse(text);
+        doc.set_block(None, &domains(&["b.example"]));
+        assert!(doc
+            .render()
+            .ends_with("0.0.0.0 b.example\n# clusterbanned end"));
+        assert!(doc.remove_block(None));
+        assert_eq!(doc.render(), "127.0.0.1 localhost");
+    }
+
+    #[test]
+    fn writes_leave_no_files_behind() {
+        let dir = std::env::temp_dir().join(format!("clusterbanned-hosts-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("hosts");
+        std::fs::write(&path, "127.0.0.1 localhost\n").unwrap();
+
+        check_writable(path.to_str().unwrap()).unwrap();
+        write_atomic(&path, "::1 localhost\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "::1 localhost\n");
+        let names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names, vec!["hosts"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn update_region_removes_domains() {
+        let mut doc = HostsDocument::parse("");
+        doc.set_block(Some("eu"), &domains(&["a.example", "b.example"]));
+        assert_eq!(
+            update_region(&mut doc, &domains(&["A.example"]), Some("eu"), true),
+            RegionUpdate::Changed("Removed 1 entries, left 1 entries".into())
+        );
+        assert_eq!(
+            update_region(&mut doc, &domains(&["a.example"]), Some("eu"), true),
+            RegionUpdate::Unchanged("No matching entries to remove".into())
+        );
+        assert_eq!(
+            update_region(&mut doc, &domains(&["b.example"]), Some("eu"), true),
+            RegionUpdate::Changed("Removed clusterbanned block".into())
+        );
+        assert_eq!(doc.render(), "");
+    }
+}
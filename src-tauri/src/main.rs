@@ -2,84 +2,104 @@
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 fn main() {
+    // Повышенная копия приложения ради одной операции (elevate.rs): выполнить её и выйти
+    if let Some(code) = elevate::serve_from_args() {
+        std::process::exit(code);
+    }
+
     tauri::Builder::default()
         .plugin(tauri_plugin_updater::Builder::new().build())
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_fs::init())
+        .setup(|_app| {
+            tauri::async_runtime::spawn(record_latency_history());
+            tauri::async_runtime::spawn(run_auto_block());
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             ping_server,
+            measure_cluster_latency,
+            probe_cluster_ips,
+            start_latency_monitor,
+            reconfigure_latency_monitor,
+            stop_latency_monitor,
+            get_latency_monitor_status,
+            get_latency_history,
+            get_latency_buckets,
+            get_latency_hourly_profile,
+            prune_latency_history,
+            get_auto_block_policy,
+            save_auto_block_policy,
+            get_auto_block_status,
+            get_auto_block_audit,
             check_hosts_consistency,
             read_blocked_domains,
             clear_cluster_blocks,
             check_elevation,
+            get_capabilities,
+            relaunch_elevated,
+            run_elevated,
             get_app_info,
             test_tauri,
             launch_game,
             debug_network,
             is_process_running,
+            detect_connected_cluster,
+            verify_blocks,
             kill_process,
             clear_firewall_rules,
             get_firewall_rules,
+            get_firewall_inventory,
             update_hosts_block,
             update_firewall_rules,
             update_cluster_rules,
+            plan_cluster_rules,
+            apply_cluster_plan,
             get_settings,
             save_settings,
+            list_backups,
+            restore_backup,
+            diff_backup,
+            get_catalog_status,
+            get_catalog,
+            get_catalog_overlay,
+            save_catalog_overlay,
+            upsert_overlay_region,
+            delete_overlay_region,
+            upsert_overlay_cluster,
+            delete_overlay_cluster,
+            discover_cluster_ips,
+            merge_discovered_ips,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
 
-// Helpers for hosts management
-use sysinfo::{PidExt, ProcessExt, SystemExt};
-use serde_json::Value;
-use std::fs;
-
-const START_MARKER: &str = "# clusterbanned start";
-const END_MARKER: &str = "# clusterbanned end";
-
-
-fn hosts_paths() -> [&'static str; 2] {
-    ["C:\\Windows\\System32\\drivers\\etc\\hosts", "/etc/hosts"]
-}
-
-fn read_hosts_file_text() -> Result<String, String> {
-    for p in hosts_paths() {
-        if let Ok(s) = std::fs::read_to_string(p) {
-            return Ok(s);
-        }
-    }
-    Err("hosts file not found or unreadable".into())
-}
-
-fn parse_blocked_domains_from_text(text: &str) -> Vec<String> {
-    let mut set = std::collections::BTreeSet::new();
-    for line in text.lines() {
-        let line = line.trim();
-        if line.is_empty() || line.starts_with('#') {
-            continue;
-        }
-        let parts: Vec<_> = line.split_whitespace().collect();
-        if parts.len() >= 2 {
-            for p in &parts[1..] {
-                let d = p.to_lowercase();
-                if d.contains('.') {
-                    set.insert(d);
-                }
-            }
-        }
-    }
-    set.into_iter().collect()
-}
+use clusterbanned_lib::hosts::{self, HostsDocument};
+use clusterbanned_lib::{
+    backup, blocking, capabilities, catalog, discovery, elevate, firewall, helper, history,
+    monitor, plan, policy, probe, process, settings, sockets, verify, Error,
+};
 
 #[tauri::command]
-async fn debug_network(hostname: String) -> Result<serde_json::Value, String> {
+async fn debug_network(
+    hostname: String,
+    trace: Option<bool>,
+    max_hops: Option<u8>,
+    rounds: Option<u32>,
+) -> Result<serde_json::Value, Error> {
     use std::net::ToSocketAddrs;
     use std::time::Instant;
 
     println!("[DEBUG] Testing network for: {}", hostname);
 
+    let trace_opts = probe::trace::Options::new(
+        max_hops.unwrap_or(probe::trace::DEFAULT_MAX_HOPS),
+        rounds.unwrap_or(probe::trace::DEFAULT_ROUNDS),
+        probe::trace::DEFAULT_TIMEOUT_MS,
+    )?;
     let mut results = serde_json::json!({});
+    let mut resolved_ips: Vec<String> = Vec::new();
 
     // Тест 1: Простое DNS разрешение
     let dns_start = Instant::now();
@@ -98,6 +118,7 @@ async fn debug_network(hostname: String) -> Result<serde_json::Value, String> {
                 "time_ms": dns_time.as_millis()
             });
             println!("[DEBUG] DNS resolved: {:?}", ips);
+            resolved_ips = ips.clone();
 
             // Тест 2: Попробовать TCP на первый IP
             if let Some(first_ip) = ips.get(0) {
@@ -223,265 +244,343 @@ async fn debug_network(hostname: String) -> Result<serde_json::Value, String> {
         }
     }
 
+    // Тест 4: Трассировка до каждого IP кластера из каталога (или до IP из DNS), как MTR.
+    // Только по запросу: это до max_hops сырых сокетов на IP и rounds × таймаут ожидания
+    if !trace.unwrap_or(false) {
+        return Ok(results);
+    }
+    let catalog_ips = catalog::last().and_then(|c| {
+        c.clusters()
+            .find(|(_, cluster)| cluster.domain.eq_ignore_ascii_case(&hostname))
+            .map(|(_, cluster)| cluster.ips.clone())
+    });
+    let (source, trace_ips) = match catalog_ips {
+        Some(ips) if !ips.is_empty() => ("catalog", ips),
+        _ => ("dns", resolved_ips),
+    };
+    let trace_opts = std::sync::Arc::new(trace_opts);
+    let mut tasks = tokio::task::JoinSet::new();
+    for (i, ip) in trace_ips.iter().enumerate() {
+        // 0.0.0.0 из hosts трассировать бессмысленно
+        let ip = match ip.trim().parse::<std::net::IpAddr>() {
+            Ok(ip) if !ip.is_unspecified() => ip,
+            _ => continue,
+        };
+        let opts = trace_opts.clone();
+        tasks.spawn(async move { (i, probe::trace::trace(ip, &opts).await) });
+    }
+    let mut traces = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        traces.push(joined.map_err(|e| Error::Other(format!("trace task failed: {}", e)))?);
+    }
+    traces.sort_by_key(|(i, _)| *i);
+    let traces: Vec<probe::trace::Trace> = traces.into_iter().map(|(_, t)| t).collect();
+    println!(
+        "[DEBUG] Path traces: {} of {} reached the target",
+        traces.iter().filter(|t| t.reached).count(),
+        traces.len()
+    );
+    results["path"] = serde_json::json!({
+        "source": source,
+        "maxHops": trace_opts.max_hops,
+        "rounds": trace_opts.rounds,
+        "traces": traces
+    });
+
     Ok(results)
 }
 
-// Получаем кластеры из внешнего файла с fallback на локальный
-async fn get_clusters_with_fallback() -> Value {
-    // 1. Пробуем GitHub
-    let github_url = "https://raw.githubusercontent.com/SWIRCH/cluster-banned-manager/main/src/data/servers.json";
-    
-    match reqwest::get(github_url).await {
-        Ok(response) if response.status().is_success() => {
-            match response.json::<Value>().await {
-                Ok(data) => {
-                    println!("✅ Данные загружены с GitHub");
-                    return data;
-                }
-                Err(e) => {
-                    println!("⚠️ Ошибка парсинга GitHub: {}", e);
-                }
-            }
-        }
-        Ok(response) => {
-            println!("⚠️ GitHub статус: {}", response.status());
-        }
-        Err(e) => {
-            println!("⚠️ Не подключиться к GitHub: {}", e);
-        }
-    }
-    
-    // 2. Fallback: локальный файл
-    let local_path = "../../src/data/servers.json";
-    match fs::read_to_string(local_path) {
-        Ok(content) => {
-            match serde_json::from_str(&content) {
-                Ok(data) => {
-                    println!("✅ Используем локальный файл");
-                    return data;
-                }
-                Err(e) => {
-                    println!("⚠️ Ошибка парсинга локального файла: {}", e);
-                }
-            }
-        }
-        Err(e) => {
-            println!("⚠️ Не прочитать локальный файл: {}", e);
-        }
-    }
-    
-    // 3. Fallback: встроенный файл
-    println!("⚠️ Используем встроенный servers.json");
-    serde_json::from_str(
-        include_str!("../../src/data/servers.json")
-    ).unwrap_or_else(|_| {
-        println!("⛔ Все источники недоступны!");
-        serde_json::json!({})
-    })
+// Источник и возраст servers.json; если каталог ещё не загружался, загружает его
+#[tauri::command]
+async fn get_catalog_status() -> Result<catalog::cache::Status, Error> {
+    println!("[TAURI] get_catalog_status called");
+    catalog::status().await
 }
 
-// Проверка ping (не блокирующая, выполняется в blocking-пуле с таймаутом)
+// Проверка задержки внутри процесса (ICMP/TCP/UDP); метод берётся из кластера в каталоге,
+// если не передан явно
 #[tauri::command]
 async fn ping_server(
     hostname: String,
     timeout_ms: Option<u64>,
-    _port: Option<u16>,
-) -> Result<serde_json::Value, String> {
-    use std::process::Command;
-    use std::time::Instant;
-
+    port: Option<u16>,
+    method: Option<String>,
+) -> Result<serde_json::Value, Error> {
     println!("[TAURI] ping_server called for: {}", hostname);
 
-    let start = Instant::now();
-    let timeout_sec = (timeout_ms.unwrap_or(600) as f64 / 1000.0).ceil() as u64;
+    let timeout = std::time::Duration::from_millis(timeout_ms.unwrap_or(600));
+    probe::ping(&hostname, timeout, port, method).await
+}
 
-    #[cfg(windows)]
-    let output = {
-        use std::os::windows::process::CommandExt;
-        const CREATE_NO_WINDOW: u32 = 0x08000000;
-        Command::new("ping")
-            .args(&[
-                "-n",
-                "1",
-                "-w",
-                &(timeout_sec * 1000).to_string(),
-                &hostname,
-            ])
-            .creation_flags(CREATE_NO_WINDOW)
-            .output()
-    };
+// Серия замеров по каждому кластеру: min/avg/median/p95/max, джиттер, потери и общий score
+// для сортировки; результат отсортирован от лучшего кластера к худшему
+#[tauri::command]
+async fn measure_cluster_latency(
+    region_id: Option<String>,
+    count: Option<u32>,
+    interval_ms: Option<u64>,
+    timeout_ms: Option<u64>,
+) -> Result<Vec<probe::stats::ClusterLatency>, Error> {
+    println!(
+        "[TAURI] measure_cluster_latency called: region={:?}, count={:?}, interval_ms={:?}",
+        region_id, count, interval_ms
+    );
+    let opts = probe::stats::Options::new(
+        count.unwrap_or(probe::stats::DEFAULT_COUNT),
+        interval_ms.unwrap_or(probe::stats::DEFAULT_INTERVAL_MS),
+        timeout_ms.unwrap_or(probe::stats::DEFAULT_TIMEOUT_MS),
+    )?;
+    let catalog = catalog::load().await?;
+    let results = probe::stats::measure_clusters(&catalog, region_id.as_deref(), opts).await?;
+
+    let answered = results.iter().filter(|r| r.rank.is_some()).count();
+    println!(
+        "[TAURI] measure_cluster_latency: {} of {} clusters answered",
+        answered,
+        results.len()
+    );
+    Ok(results)
+}
 
-    #[cfg(not(windows))]
-    let output = Command::new("ping")
-        .args(&["-c", "1", "-W", &timeout_sec.to_string(), &hostname])
-        .output();
-
-    match output {
-        Ok(output) => {
-            let elapsed = start.elapsed().as_millis() as u64;
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            let stderr = String::from_utf8_lossy(&output.stderr);
-
-            if output.status.success() {
-                // Парсим время из вывода
-                if let Some(ping_time) = parse_ping_time(&stdout) {
-                    Ok(serde_json::json!({
-                        "ping": ping_time,
-                        "status": "ok",
-                        "method": "icmp",
-                        "elapsed_ms": elapsed
-                    }))
-                } else {
-                    // Если не удалось распарсить время
-                    Ok(serde_json::json!({
-                        "ping": elapsed,
-                        "status": "ok_no_time",
-                        "method": "icmp",
-                        "output": stdout.to_string(),
-                        "elapsed_ms": elapsed
-                    }))
-                }
-            } else {
-                // Проверяем тип ошибки
-                let status = if stdout.contains("timed out") || stdout.contains("Request timed out")
-                {
-                    "timeout"
-                } else if stdout.contains("Destination host unreachable") {
-                    "unreachable"
-                } else if stdout.contains("could not find host")
-                    || stderr.contains("could not find host")
-                {
-                    "dns_error"
-                } else {
-                    "error"
-                };
-
-                Ok(serde_json::json!({
-                    "ping": null,
-                    "status": status,
-                    "error": format!("{} - {}", stdout, stderr),
-                    "elapsed_ms": elapsed
-                }))
-            }
-        }
-        Err(e) => {
-            let elapsed = start.elapsed().as_millis() as u64;
-            Ok(serde_json::json!({
-                "ping": null,
-                "status": "command_error",
-                "error": e.to_string(),
-                "elapsed_ms": elapsed
-            }))
+// Замер по каждому IP кластера из каталога напрямую, без DNS и hosts; плюс сводка по всем IP
+#[tauri::command]
+async fn probe_cluster_ips(
+    region_id: String,
+    cluster_id: String,
+    count: Option<u32>,
+    interval_ms: Option<u64>,
+    timeout_ms: Option<u64>,
+) -> Result<probe::stats::ClusterIpsLatency, Error> {
+    println!(
+        "[TAURI] probe_cluster_ips called for: {} / {}",
+        region_id, cluster_id
+    );
+    let opts = probe::stats::Options::new(
+        count.unwrap_or(probe::stats::DEFAULT_COUNT),
+        interval_ms.unwrap_or(probe::stats::DEFAULT_INTERVAL_MS),
+        timeout_ms.unwrap_or(probe::stats::DEFAULT_TIMEOUT_MS),
+    )?;
+    let catalog = catalog::load().await?;
+    let cluster = catalog
+        .region(&region_id)
+        .ok_or_else(|| Error::NotFound(format!("region {} not found in catalog", region_id)))?
+        .clusters
+        .iter()
+        .find(|c| c.id == cluster_id)
+        .ok_or_else(|| {
+            Error::NotFound(format!("cluster {} not found in region {}", cluster_id, region_id))
+        })?;
+    let result = probe::stats::measure_cluster_ips(&region_id, cluster, opts).await?;
+
+    println!(
+        "[TAURI] probe_cluster_ips: {} of {} IPs reachable",
+        result.aggregate.reachable_ips, result.aggregate.total_ips
+    );
+    Ok(result)
+}
+
+// Окно приложения для фонового монитора: видимость и доставка событий в webview
+struct WebviewUi(tauri::AppHandle);
+
+impl monitor::Ui for WebviewUi {
+    fn is_visible(&self) -> bool {
+        use tauri::Manager;
+        self.0
+            .webview_windows()
+            .values()
+            .any(|w| w.is_visible().unwrap_or(true) && !w.is_minimized().unwrap_or(false))
+    }
+
+    fn emit(&self, tick: &monitor::Tick) {
+        use tauri::Emitter;
+        if let Err(e) = self.0.emit(monitor::EVENT, tick) {
+            println!("[TAURI] latency monitor: failed to emit event: {}", e);
         }
     }
 }
 
-fn parse_ping_time(output: &str) -> Option<u64> {
-    // Ищем строки типа "time=64ms" в выводе ping
-    for line in output.lines() {
-        let line = line.trim();
+// Фоновый монитор задержки по кластерам региона; результаты приходят событием
+// "latency-monitor://tick". Если монитор уже запущен, меняет его настройки
+#[tauri::command]
+async fn start_latency_monitor(
+    app: tauri::AppHandle,
+    config: monitor::Config,
+) -> Result<monitor::Status, Error> {
+    println!(
+        "[TAURI] start_latency_monitor called: region={}, interval_ms={}",
+        config.region_id, config.interval_ms
+    );
+    let catalog = catalog::load().await?;
+    let targets = monitor::targets(&catalog, &config.region_id)?;
+    monitor::start(std::sync::Arc::new(WebviewUi(app)), config, targets)
+}
 
-        // Формат Windows: "Reply from 92.223.31.134: bytes=32 time=64ms TTL=45"
-        if let Some(pos) = line.find("time=") {
-            let after_time = &line[pos + 5..];
-            if let Some(end) = after_time.find("ms") {
-                let time_str = &after_time[..end].trim();
-                if let Ok(time) = time_str.parse::<u64>() {
-                    return Some(time);
-                }
-            }
-        }
+#[tauri::command]
+async fn reconfigure_latency_monitor(config: monitor::Config) -> Result<monitor::Status, Error> {
+    println!(
+        "[TAURI] reconfigure_latency_monitor called: region={}, interval_ms={}",
+        config.region_id, config.interval_ms
+    );
+    let catalog = catalog::load().await?;
+    let targets = monitor::targets(&catalog, &config.region_id)?;
+    monitor::reconfigure(config, targets)
+}
 
-        // Формат Linux: "64 bytes from ...: icmp_seq=1 ttl=45 time=63.8 ms"
-        if let Some(pos) = line.find("time=") {
-            let after_time = &line[pos + 5..];
-            if let Some(end) = after_time.find(" ms") {
-                let time_str = &after_time[..end].trim();
-                if let Ok(time) = time_str.parse::<f64>() {
-                    return Some(time as u64);
-                }
+#[tauri::command]
+fn stop_latency_monitor() -> Result<bool, Error> {
+    println!("[TAURI] stop_latency_monitor called");
+    Ok(monitor::stop())
+}
+
+#[tauri::command]
+fn get_latency_monitor_status() -> Result<monitor::Status, Error> {
+    Ok(monitor::status())
+}
+
+// Пишет каждый раунд фонового монитора в историю задержек; при старте чистит устаревшие записи
+async fn record_latency_history() {
+    let mut ticks = monitor::subscribe();
+    if let Ok(dir) = settings::config_dir() {
+        match history::prune(&dir, settings::history_retention()) {
+            Ok(0) => {}
+            Ok(n) => println!("[TAURI] latency history: pruned {} old records", n),
+            Err(e) => println!("[TAURI] latency history: prune failed: {}", e),
+        }
+    }
+    loop {
+        let tick = match ticks.recv().await {
+            Ok(tick) => tick,
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                println!("[TAURI] latency history: skipped {} monitor rounds", n);
+                continue;
             }
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
+        };
+        let records = history::records_from_tick(&tick);
+        if let Err(e) = settings::config_dir()
+            .and_then(|dir| history::append(&dir, &records, settings::history_retention()))
+        {
+            println!("[TAURI] latency history: failed to record: {}", e);
         }
     }
+}
+
+// История замеров по кластерам региона (или одного кластера) за окно времени
+#[tauri::command]
+fn get_latency_history(
+    query: history::Query,
+    limit: Option<usize>,
+) -> Result<Vec<history::Record>, Error> {
+    println!(
+        "[TAURI] get_latency_history called for: {} / {:?}",
+        query.region_id, query.cluster_id
+    );
+    history::query(&settings::config_dir()?, &query, limit)
+}
+
+// Средние и потери по интервалам bucket_secs (по умолчанию час)
+#[tauri::command]
+fn get_latency_buckets(
+    query: history::Query,
+    bucket_secs: Option<u64>,
+) -> Result<Vec<history::ClusterSeries>, Error> {
+    println!(
+        "[TAURI] get_latency_buckets called for: {} / {:?}",
+        query.region_id, query.cluster_id
+    );
+    history::buckets(&settings::config_dir()?, &query, bucket_secs.unwrap_or(3600))
+}
 
-    None
+// Средние и потери по часам суток, чтобы видеть, в какое время кластер деградирует
+#[tauri::command]
+fn get_latency_hourly_profile(
+    query: history::Query,
+    utc_offset_minutes: Option<i32>,
+) -> Result<Vec<history::ClusterSeries>, Error> {
+    println!(
+        "[TAURI] get_latency_hourly_profile called for: {} / {:?}",
+        query.region_id, query.cluster_id
+    );
+    history::hourly(&settings::config_dir()?, &query, utc_offset_minutes.unwrap_or(0))
+}
+
+#[tauri::command]
+fn prune_latency_history() -> Result<usize, Error> {
+    println!("[TAURI] prune_latency_history called");
+    history::prune(&settings::config_dir()?, settings::history_retention())
 }
 
 // Команда 2: Проверка consistency hosts
 #[tauri::command]
-fn check_hosts_consistency(selections: serde_json::Value) -> Result<serde_json::Value, String> {
+fn check_hosts_consistency(selections: serde_json::Value) -> Result<serde_json::Value, Error> {
     println!("[TAURI] check_hosts_consistency called");
 
-    match read_hosts_file_text() {
+    match hosts::read_text() {
         Ok(text) => {
-            let blocked = parse_blocked_domains_from_text(&text);
-            let blocked_set: std::collections::BTreeSet<String> = blocked.iter().cloned().collect();
-
-            // selections is expected to be a map of region -> { domain: bool }
-            let mut mismatch = false;
-
-            if let serde_json::Value::Object(map) = selections {
-                'outer: for (_region, val) in map {
-                    if let serde_json::Value::Object(domain_map) = val {
-                        for (domain, enabled_val) in domain_map {
-                            let enabled = match enabled_val {
-                                serde_json::Value::Bool(b) => b,
-                                _ => true,
-                            };
-                            let hosts_blocked = blocked_set.contains(&domain.to_lowercase());
-                            if hosts_blocked != !enabled {
-                                mismatch = true;
-                                break 'outer;
-                            }
-                        }
-                    }
-                }
-            }
-
+            let blocked = HostsDocument::parse(&text).blocked_domains();
+            let mismatch = hosts::selections_mismatch(&blocked, &selections);
             Ok(serde_json::json!({ "blocked": blocked, "mismatch": mismatch, "message": "ok" }))
         }
-        Err(e) => Ok(serde_json::json!({ "blocked": [], "mismatch": false, "message": e })),
+        Err(e) => Ok(serde_json::json!({
+            "blocked": [],
+            "mismatch": false,
+            "message": e.to_string(),
+            "code": e.code()
+        })),
     }
 }
 
 // Команда: Проверить, запущен ли процесс с правами записи в hosts (проверка привилегий)
 #[tauri::command]
-fn check_elevation() -> Result<serde_json::Value, String> {
+fn check_elevation() -> Result<serde_json::Value, Error> {
     println!("[TAURI] check_elevation called");
-    let path = hosts_paths()
-        .iter()
-        .find(|p| std::path::Path::new(p).exists())
-        .cloned()
-        .unwrap_or(hosts_paths()[0]);
+    let path = hosts::active_path();
 
-    match std::fs::OpenOptions::new()
-        .write(true)
-        .append(true)
-        .open(path)
-    {
+    match hosts::check_writable(path) {
         Ok(_) => Ok(serde_json::json!({"isAdmin": true, "path": path})),
-        Err(e) => {
-            Ok(serde_json::json!({"isAdmin": false, "path": path, "error": format!("{}", e)}))
-        }
+        Err(e) => Ok(serde_json::json!({
+            "isAdmin": false,
+            "path": path,
+            "error": e.to_string(),
+            "code": e.code()
+        })),
     }
 }
 
+// Что доступно этому процессу: hosts и брандмауэр проверяются по отдельности, с причиной отказа
+#[tauri::command]
+async fn get_capabilities() -> Result<capabilities::Capabilities, Error> {
+    println!("[TAURI] get_capabilities called");
+    Ok(capabilities::check().await)
+}
+
+// Перезапуск приложения с повышенными правами (UAC); текущий экземпляр закрывается
+#[tauri::command]
+fn relaunch_elevated(app: tauri::AppHandle) -> Result<(), Error> {
+    println!("[TAURI] relaunch_elevated called");
+    elevate::relaunch()?;
+    app.exit(0);
+    Ok(())
+}
+
+// Одна операция (как у помощника) в повышенной копии приложения; результат возвращается сюда
+#[tauri::command]
+async fn run_elevated(request: helper::Request) -> Result<serde_json::Value, Error> {
+    println!("[TAURI] run_elevated called: {:?}", request);
+    elevate::run(request).await
+}
+
 // Команда 3: Обновление hosts (региональная поддержка и аккуратная работа с переводами строк)
 #[tauri::command]
-fn update_hosts_block(
+async fn update_hosts_block(
     blocked_domains: Option<Vec<String>>,
     blocked_domains_alt: Option<Vec<String>>,
     remove: Option<bool>,
     region: Option<String>,
     args: Option<serde_json::Value>,
-) -> Result<String, String> {
+) -> Result<String, Error> {
     // Prefer directly provided named params (matches Tauri's expected mapping)
     let mut blocked: Option<Vec<String>> = blocked_domains.or(blocked_domains_alt);
-    // Читаем настройки из файла, если не переданы явно
-    let backup_saved = {
-        let (_use_firewall, use_backup, _backup_count) = read_settings_from_file();
-        use_backup
-    };
 
     // If not provided, try to extract from the optional `args` wrapper
     if blocked.is_none() {
@@ -501,7 +600,9 @@ fn update_hosts_block(
                             .collect(),
                     );
                 } else {
-                    return Err("blocked_domains must be an array of strings".into());
+                    return Err(Error::InvalidInput(
+                        "blocked_domains must be an array of strings".into(),
+                    ));
                 }
             }
         }
@@ -510,7 +611,9 @@ fn update_hosts_block(
     let blocked_domains = match blocked {
         Some(v) => v,
         None => {
-            return Err("command update_hosts_block missing required key blocked_domains".into())
+            return Err(Error::InvalidInput(
+                "command update_hosts_block missing required key blocked_domains".into(),
+            ))
         }
     };
 
@@ -536,59 +639,6 @@ fn update_hosts_block(
         blocked_domains, region_str
     );
 
-    // Find a hosts file path we can read (prefer the first existing), or default to the Windows path
-    let path = hosts_paths()
-        .iter()
-        .find(|p| std::path::Path::new(p).exists())
-        .cloned()
-        .unwrap_or(hosts_paths()[0]);
-
-    // Read original
-    let original = std::fs::read_to_string(path)
-        .map_err(|e| format!("failed to read hosts file ({}): {}", path, e))?;
-    let mut content = original.clone();
-
-    // Find existing block indices for the target region (if any). We allow multiple blocks, each may include a region tag on the START_MARKER line.
-    let mut maybe_block_range: Option<(usize, usize)> = None;
-    let mut idx = 0usize;
-    while let Some(rel_start) = content[idx..].find(START_MARKER) {
-        let start_idx = idx + rel_start;
-        if let Some(rel_end) = content[start_idx..].find(END_MARKER) {
-            let end_idx = start_idx + rel_end + END_MARKER.len();
-            let first_line = content[start_idx..].lines().next().unwrap_or("");
-            // parse optional region tag like "# clusterbanned start region:my_region"
-            let mut block_region_tag: Option<String> = None;
-            if let Some(pos) = first_line.find("region:") {
-                let tag = first_line[pos + "region:".len()..]
-                    .trim()
-                    .split_whitespace()
-                    .next()
-                    .unwrap_or("")
-                    .to_string();
-                if !tag.is_empty() {
-                    block_region_tag = Some(tag);
-                }
-            }
-
-            if let Some(ref target) = region_str {
-                if let Some(br) = &block_region_tag {
-                    if br == target {
-                        maybe_block_range = Some((start_idx, end_idx));
-                        break;
-                    }
-                }
-            } else {
-                // No region specified: pick the first block found (back-compat)
-                maybe_block_range = Some((start_idx, end_idx));
-                break;
-            }
-
-            idx = end_idx; // continue searching
-        } else {
-            break;
-        }
-    }
-
     // Determine if this is a removal (unblock) operation: look at explicit 'remove' param or args wrapper
     let mut remove_flag = false;
     if let Some(b) = remove {
@@ -606,182 +656,27 @@ fn update_hosts_block(
         }
     }
 
-    // If remove mode is requested, remove the supplied domains from the existing block (if present)
-    if remove_flag {
-        if maybe_block_range.is_none() {
-            return Ok("No cluster entries to update".to_string());
-        }
-        let (start_idx, end_idx) = maybe_block_range.unwrap();
-        let block_str = &content[start_idx..end_idx];
-        let mut existing: std::collections::BTreeSet<String> = std::collections::BTreeSet::new();
-        for line in block_str.lines() {
-            let t = line.trim();
-            if t == START_MARKER || t == END_MARKER || t.is_empty() {
-                continue;
-            }
-            let parts: Vec<_> = t.split_whitespace().collect();
-            if parts.len() >= 2 {
-                existing.insert(parts[1].to_lowercase());
-            }
-        }
-
-        let remove_set: std::collections::BTreeSet<String> =
-            blocked_domains.iter().map(|s| s.to_lowercase()).collect();
-        let remaining: Vec<String> = existing.difference(&remove_set).cloned().collect();
-
-        if remaining.len() == existing.len() {
-            return Ok("No matching entries to remove".to_string());
-        }
-
-        // Build replacement (empty => remove block)
-        let replacement = if remaining.is_empty() {
-            String::new()
-        } else {
-            let mut b = String::new();
-            if let Some(ref r) = region_str {
-                b.push_str(&format!("{} region:{}", START_MARKER, r));
-            } else {
-                b.push_str(START_MARKER);
-            }
-            b.push('\n');
-            for d in &remaining {
-                b.push_str(&format!("0.0.0.0 {}\n", d));
-            }
-            b.push_str(END_MARKER);
-            b
-        };
-
-        // Replace the block in the content
-        content.replace_range(start_idx..end_idx, &replacement);
-
-        // Collapse excessive blank lines (prevent leftover double spacing)
-        while content.contains("\n\n\n") {
-            content = content.replace("\n\n\n", "\n\n");
-        }
-        
-        if backup_saved {
-            // Backup original
-            let ts = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .map_err(|e| format!("failed to compute timestamp for backup: {}", e))?
-                .as_secs();
-            let backup_path = format!("{}.clusterbanned.bak.{}", path, ts);
-            std::fs::write(&backup_path, original.as_bytes())
-                .map_err(|e| format!("failed to write backup {}: {}", backup_path, e))?;
-        }
-
-        // Try to write new hosts content
-        std::fs::write(path, content.as_bytes()).map_err(|e| {
-            format!(
-                "failed to write hosts file ({}): {}. Try running the app with elevated privileges",
-                path, e
-            )
-        })?;
-
-        if remaining.is_empty() {
-            return Ok(format!("Removed clusterbanned block (wrote to {})", path));
-        } else {
-            return Ok(format!(
-                "Removed {} entries, left {} entries (wrote to {})",
-                remove_set.len() - remaining.len(),
-                remaining.len(),
-                path
-            ));
-        }
-    }
-
-    // Normal (blocking) path: remove existing block for this region (if any) and append new block for the given region
-    let had_block = maybe_block_range.is_some();
-    if let Some((start_idx, end_idx)) = maybe_block_range {
-        content.replace_range(start_idx..end_idx, "");
-        // collapse leftover multiple blanklines
-        while content.contains("\n\n\n") {
-            content = content.replace("\n\n\n", "\n\n");
-        }
-    }
-
-    if !blocked_domains.is_empty() {
-        // Build a region-tagged block (if region specified)
-        let mut block = String::new();
-        if let Some(ref r) = region_str {
-            block.push_str(&format!("{} region:{}", START_MARKER, r));
-        } else {
-            block.push_str(START_MARKER);
-        }
-        block.push('\n');
-        for d in &blocked_domains {
-            block.push_str(&format!("0.0.0.0 {}\n", d));
-        }
-        block.push_str(END_MARKER);
-
-        // Normalize spacing: trim trailing newlines from content and ensure exactly one blank line before block
-        content = content.trim_end_matches('\n').to_string();
-        content.push('\n');
-        content.push('\n');
-        content.push_str(&block);
-        content.push('\n'); // ensure file ends with a single newline
-
-        if backup_saved {
-            // Backup original
-            let ts = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .map_err(|e| format!("failed to compute timestamp for backup: {}", e))?
-                .as_secs();
-            let backup_path = format!("{}.clusterbanned.bak.{}", path, ts);
-            std::fs::write(&backup_path, original.as_bytes())
-                .map_err(|e| format!("failed to write backup {}: {}", backup_path, e))?;
-        }
-
-        // Try to write new hosts content
-        std::fs::write(path, content.as_bytes()).map_err(|e| {
-            format!(
-                "failed to write hosts file ({}): {}. Try running the app with elevated privileges",
-                path, e
-            )
-        })?;
-
-        return Ok(format!(
-            "Successfully blocked {} domains (wrote to {})",
-            blocked_domains.len(),
-            path
-        ));
-    } else if had_block {
-        // We removed the existing block; this means we've unblocked everything for clusterbanned (for this region)
-        
-        if backup_saved {
-            // Backup original
-            let ts = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .map_err(|e| format!("failed to compute timestamp for backup: {}", e))?
-                .as_secs();
-            let backup_path = format!("{}.clusterbanned.bak.{}", path, ts);
-            std::fs::write(&backup_path, original.as_bytes())
-                .map_err(|e| format!("failed to write backup {}: {}", backup_path, e))?;
-        }
-
-        // Write content (block removed)
-        std::fs::write(path, content.as_bytes()).map_err(|e| {
-            format!(
-                "failed to write hosts file ({}): {}. Try running the app with elevated privileges",
-                path, e
-            )
-        })?;
-
-        return Ok(format!("Removed clusterbanned block (wrote to {})", path));
-    } else {
-        // Nothing to do
-        return Ok("No cluster entries to update".to_string());
+    // Региональный блок пишет помощник (если запущен); старый общий блок остаётся локальным
+    match region_str {
+        Some(region) => helper::part(
+            &helper::update(&region, &blocked_domains, !remove_flag, true, false).await?,
+            "hosts",
+        ),
+        None => blocking::update_hosts(
+            None,
+            &blocked_domains,
+            remove_flag,
+            blocking::Backups::configured(),
+        ),
     }
 }
 
-
-
 #[tauri::command]
 async fn update_firewall_rules(
     region_id: String,
     blocked_domains: Vec<String>,
     enable: bool, // true = блокировать, false = разблокировать
-) -> Result<String, String> {
+) -> Result<String, Error> {
     println!("[TAURI] update_firewall_rules CALLED");
     println!("  region_id: '{}'", region_id);
     println!("  blocked_domains: {:?}", blocked_domains);
@@ -790,101 +685,259 @@ async fn update_firewall_rules(
     // Если параметры пустые, вернем ошибку сразу
     if region_id.is_empty() {
         println!("[TAURI] ERROR: region_id is empty!");
-        return Err("region_id is empty".into());
+        return Err(Error::InvalidInput("region_id is empty".into()));
     }
 
-    #[cfg(windows)]
-    {
-        use serde_json::Value;
+    helper::part(
+        &helper::update(&region_id, &blocked_domains, enable, false, true).await?,
+        "firewall",
+    )
+}
 
-        println!(
-            "[TAURI] update_firewall_rules called for region: {}, enable: {}",
-            region_id, enable
-        );
+// Движок автоблокировки; None, пока политика не загружена
+static AUTO_BLOCK: std::sync::Mutex<Option<policy::Engine>> = std::sync::Mutex::new(None);
 
-        // Получаем данные о кластерах из файла
-        let clusters_data: Value = get_clusters_with_fallback().await;
-
-        let mut results = Vec::new();
-        let mut total_ips_blocked = 0;
-
-        if let Value::Array(regions) = &clusters_data["clusters"] {
-            for region_data in regions {
-                if region_data["id"].as_str() == Some(&region_id) {
-                    if let Value::Array(clusters) = &region_data["clusters"] {
-                        for cluster in clusters {
-                            if let Some(domain) = cluster["domain"].as_str() {
-                                let should_process = blocked_domains.contains(&domain.to_string());
-
-                                if should_process {
-                                    if let Value::Array(ips_array) = &cluster["ips"] {
-                                        let ips: Vec<String> = ips_array
-                                            .iter()
-                                            .filter_map(|v| v.as_str().map(|s| s.to_string()))
-                                            .filter(|s| !s.is_empty())
-                                            .collect();
-
-                                        if !ips.is_empty() {
-                                            match block_with_firewall(
-                                                domain.to_string(),
-                                                ips.clone(),
-                                                enable,
-                                            ) {
-                                                Ok(msg) => {
-                                                    results.push(format!("{}: {}", domain, msg));
-                                                    total_ips_blocked += ips.len();
-                                                }
-                                                Err(e) => results.push(format!(
-                                                    "Failed to {} {}: {}",
-                                                    if enable { "block" } else { "unblock" },
-                                                    domain,
-                                                    e
-                                                )),
-                                            }
-                                        } else {
-                                            results.push(format!("No IPs found for {}", domain));
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    break;
-                }
+// Домены, заблокированные в hosts для региона
+fn region_blocked_domains(region_id: &str) -> Result<Vec<String>, Error> {
+    let text = hosts::read_text()?;
+    Ok(HostsDocument::parse(&text)
+        .block(Some(region_id))
+        .map(|b| b.domains())
+        .unwrap_or_default())
+}
+
+fn reset_auto_block_engine(dir: &std::path::Path, policy: policy::Policy) {
+    let audit = policy::read_audit(dir, None).unwrap_or_else(|e| {
+        println!("[TAURI] auto-block: audit log unreadable: {}", e);
+        Vec::new()
+    });
+    *AUTO_BLOCK.lock().unwrap() = Some(policy::Engine::new(policy, &audit));
+}
+
+// Следит за раундами фонового монитора и применяет политику автоблокировки
+async fn run_auto_block() {
+    let mut ticks = monitor::subscribe();
+    match settings::config_dir().and_then(|dir| policy::load(&dir).map(|p| (dir, p))) {
+        Ok((dir, policy)) => reset_auto_block_engine(&dir, policy),
+        Err(e) => println!("[TAURI] auto-block: policy not loaded: {}", e),
+    }
+    loop {
+        let tick = match ticks.recv().await {
+            Ok(tick) => tick,
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                println!("[TAURI] auto-block: skipped {} monitor rounds", n);
+                continue;
             }
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
+        };
+        if let Err(e) = auto_block_round(&tick).await {
+            println!("[TAURI] auto-block: {}", e);
+        }
+    }
+}
+
+async fn auto_block_round(tick: &monitor::Tick) -> Result<(), Error> {
+    let (auto_blocked, min_open) = match AUTO_BLOCK.lock().unwrap().as_ref() {
+        Some(engine)
+            if engine.policy().enabled && engine.policy().region_id == tick.region_id =>
+        {
+            (engine.auto_blocked(), engine.policy().min_open)
+        }
+        _ => return Ok(()),
+    };
+    let region_id = tick.region_id.clone();
+    let catalog = catalog::cached().await?;
+    let region = catalog
+        .region(&region_id)
+        .ok_or_else(|| Error::NotFound(format!("region {} not found in catalog", region_id)))?;
+    let blocked = region_blocked_domains(&region_id)?;
+    let is_blocked = |domain: &str| blocked.iter().any(|d| d.eq_ignore_ascii_case(domain));
+
+    // Открытые кластеры меряем по данным монитора; заблокированные движком - напрямую по IP
+    // из каталога, потому что их домен ведёт в 0.0.0.0
+    let mut samples: Vec<(String, Option<f64>)> = tick
+        .samples
+        .iter()
+        .filter(|s| !is_blocked(&s.domain))
+        .map(|s| (s.cluster_id.clone(), s.result.rtt_ms))
+        .collect();
+    let timeout_ms = monitor::status()
+        .config
+        .map(|c| c.timeout_ms)
+        .unwrap_or(monitor::DEFAULT_TIMEOUT_MS);
+    for cluster in region
+        .clusters
+        .iter()
+        .filter(|c| auto_blocked.contains(&c.id) && is_blocked(&c.domain))
+    {
+        let opts = probe::stats::Options::new(1, 0, timeout_ms)?;
+        let rtt = probe::stats::measure_cluster_ips(&region_id, cluster, opts)
+            .await
+            .ok()
+            .and_then(|r| r.aggregate.stats.min_ms);
+        samples.push((cluster.id.clone(), rtt));
+    }
+
+    let clusters: Vec<policy::ClusterInfo> = region
+        .clusters
+        .iter()
+        .map(|c| policy::ClusterInfo {
+            cluster_id: c.id.clone(),
+            domain: c.domain.clone(),
+            blocked: is_blocked(&c.domain),
+        })
+        .collect();
+    let decisions = match AUTO_BLOCK.lock().unwrap().as_mut() {
+        Some(engine) => {
+            engine.observe(tick.at, &samples);
+            engine.decide(tick.at, &clusters)
         }
+        None => return Ok(()),
+    };
 
-        if results.is_empty() {
-            Ok(format!(
-                "No firewall rules {} for region {}",
-                if enable { "added" } else { "removed" },
-                region_id
-            ))
-        } else {
-            let summary = format!(
-                "{} {} IPs across {} domains",
-                if enable { "Blocked" } else { "Unblocked" },
-                total_ips_blocked,
-                results.len()
+    let (use_firewall, _, _) = settings::blocking();
+    let dir = settings::config_dir()?;
+    for mut decision in decisions {
+        let mut blocked_now = region_blocked_domains(&region_id)?;
+        // Движок рассчитывал на разблокировки этого раунда; если какая-то не удалась, открытых
+        // кластеров по hosts может оказаться меньше minOpen
+        let open = region
+            .clusters
+            .iter()
+            .filter(|c| !blocked_now.iter().any(|d| d.eq_ignore_ascii_case(&c.domain)))
+            .count();
+        if decision.action == policy::Action::Block && open <= min_open {
+            decision.action = policy::Action::Skip;
+            decision.reason = format!(
+                "{}; only {} cluster(s) open, at least {} must stay open",
+                decision.reason, open, min_open
             );
-            results.insert(0, summary);
-            Ok(results.join("\n"))
         }
-    }
+        let outcome = match decision.action {
+            policy::Action::Block => {
+                blocked_now.push(decision.domain.clone());
+                update_cluster_rules(region_id.clone(), blocked_now, true, true, use_firewall)
+                    .await
+            }
+            policy::Action::Unblock => {
+                update_cluster_rules(
+                    region_id.clone(),
+                    vec![decision.domain.clone()],
+                    false,
+                    true,
+                    use_firewall,
+                )
+                .await
+            }
+            policy::Action::Skip => Ok(serde_json::json!({ "success": true })),
+        };
+        let error = match outcome {
+            Ok(r) if r["success"].as_bool() == Some(true) => None,
+            Ok(r) => Some(format!("hosts: {}; firewall: {}", r["hosts"], r["firewall"])),
+            Err(e) => Some(e.to_string()),
+        };
 
-    #[cfg(not(windows))]
-    {
-        Ok("Firewall rules only supported on Windows".into())
+        // Даже при частичной ошибке (например, брандмауэр) смотрим, что стало с hosts
+        let now_blocked = region_blocked_domains(&region_id)?
+            .iter()
+            .any(|d| d.eq_ignore_ascii_case(&decision.domain));
+        let took_effect = match decision.action {
+            policy::Action::Block => now_blocked,
+            policy::Action::Unblock => !now_blocked,
+            policy::Action::Skip => false,
+        };
+        if took_effect {
+            if let Some(engine) = AUTO_BLOCK.lock().unwrap().as_mut() {
+                engine.applied(&decision, tick.at);
+            }
+        }
+
+        println!(
+            "[TAURI] auto-block: {:?} {} in {}: {}{}",
+            decision.action,
+            decision.cluster_id,
+            region_id,
+            decision.reason,
+            error
+                .as_ref()
+                .map(|e| format!(" (failed: {})", e))
+                .unwrap_or_default()
+        );
+        let entry = policy::AuditEntry {
+            at: history::now_ms(),
+            region_id: region_id.clone(),
+            cluster_id: decision.cluster_id.clone(),
+            domain: decision.domain.clone(),
+            action: decision.action,
+            reason: decision.reason.clone(),
+            avg_ms: decision.stats.avg_ms,
+            loss_percent: decision.stats.loss_percent,
+            success: error.is_none(),
+            error,
+        };
+        if let Err(e) = policy::append_audit(&dir, &entry) {
+            println!("[TAURI] auto-block: failed to write audit log: {}", e);
+        }
     }
+    Ok(())
 }
 
-// Команда 4: Чтение заблокированных доменов
 #[tauri::command]
-fn read_blocked_domains() -> Result<Vec<String>, String> {
+fn get_auto_block_policy() -> Result<policy::Policy, Error> {
+    println!("[TAURI] get_auto_block_policy called");
+    policy::load(&settings::config_dir()?)
+}
+
+// Сохраняет политику и перезапускает движок; заблокированные им кластеры восстанавливаются
+// из журнала
+#[tauri::command]
+fn save_auto_block_policy(policy: policy::Policy) -> Result<policy::Policy, Error> {
+    println!(
+        "[TAURI] save_auto_block_policy called: enabled={}, region={}",
+        policy.enabled, policy.region_id
+    );
+    let dir = settings::config_dir()?;
+    policy::save(&dir, &policy)?;
+    reset_auto_block_engine(&dir, policy.clone());
+    Ok(policy)
+}
+
+// Состояние движка по кластерам и то, идут ли по региону замеры монитора
+#[tauri::command]
+fn get_auto_block_status() -> Result<serde_json::Value, Error> {
+    println!("[TAURI] get_auto_block_status called");
+    let monitor = monitor::status();
+    let guard = AUTO_BLOCK.lock().unwrap();
+    let engine = match guard.as_ref() {
+        Some(e) => e,
+        None => return Err(Error::NotFound("auto-block policy is not loaded".into())),
+    };
+    let monitored = monitor.running
+        && monitor
+            .config
+            .as_ref()
+            .map(|c| c.region_id == engine.policy().region_id)
+            .unwrap_or(false);
+    Ok(serde_json::json!({
+        "policy": engine.policy(),
+        "monitorRunning": monitored,
+        "clusters": engine.clusters(),
+    }))
+}
+
+#[tauri::command]
+fn get_auto_block_audit(limit: Option<usize>) -> Result<Vec<policy::AuditEntry>, Error> {
+    println!("[TAURI] get_auto_block_audit called");
+    policy::read_audit(&settings::config_dir()?, limit)
+}
+
+// Команда 4: Чтение заблокированных доменов
+#[tauri::command]
+fn read_blocked_domains() -> Result<Vec<String>, Error> {
     println!("[TAURI] read_blocked_domains called");
 
-    match read_hosts_file_text() {
-        Ok(text) => Ok(parse_blocked_domains_from_text(&text)),
+    match hosts::read_text() {
+        Ok(text) => Ok(HostsDocument::parse(&text).blocked_domains()),
         Err(e) => Err(e),
     }
 }
@@ -896,206 +949,318 @@ async fn update_cluster_rules(
     enable: bool, // true = блокировать, false = разблокировать
     use_hosts: bool,
     use_firewall: bool,
-) -> Result<serde_json::Value, String> {
+) -> Result<serde_json::Value, Error> {
     println!(
         "[TAURI] update_cluster_rules called: region={}, enable={}, hosts={}, firewall={}",
         region_id, enable, use_hosts, use_firewall
     );
 
-    let mut results = serde_json::json!({
-        "success": true,
-        "hosts": "",
-        "firewall": ""
-    });
+    helper::update(
+        &region_id,
+        &blocked_domains,
+        enable,
+        use_hosts,
+        use_firewall,
+    )
+    .await
+}
+
+// Firewall part of a plan: the backend's plan, its current rules and the targets it was made for
+async fn plan_firewall(
+    region_id: &str,
+    blocked_domains: &[String],
+    enable: bool,
+) -> Result<(firewall::FirewallPlan, Vec<String>, Vec<(String, Vec<String>)>), Error> {
+    let backend = firewall::backend()?;
+    let catalog = catalog::load().await?;
+    let targets = blocking::firewall_targets(&catalog, region_id, blocked_domains)?;
+    // Hosts-only clusters are skipped by update_firewall_rules as well
+    let with_ips: Vec<(String, Vec<String>)> = targets
+        .iter()
+        .filter(|(_, ips)| !ips.is_empty())
+        .cloned()
+        .collect();
+    let firewall_plan = backend.plan(region_id, &with_ips, enable)?;
+    let rules = backend.rules()?;
+    Ok((firewall_plan, rules, targets))
+}
 
-    // 1. Обновляем hosts файл если нужно
+async fn build_cluster_plan(
+    region_id: String,
+    blocked_domains: Vec<String>,
+    enable: bool,
+    use_hosts: bool,
+    use_firewall: bool,
+) -> Result<plan::ChangePlan, Error> {
+    // Hosts: the same edit update_hosts_block would make, applied to an in-memory copy only
+    let mut hosts_text = None;
+    let mut hosts_plan = None;
     if use_hosts {
-        match if enable {
-            update_hosts_block(
-                Some(blocked_domains.clone()),
-                None,
-                Some(false),
-                Some(region_id.clone()),
-                None,
-            )
+        let path = hosts::active_path();
+        let original = std::fs::read_to_string(path)
+            .map_err(|e| Error::io(format!("failed to read hosts file ({})", path), e))?;
+        let mut doc = HostsDocument::parse(&original);
+        let (changed, message) =
+            match hosts::update_region(&mut doc, &blocked_domains, Some(&region_id), !enable) {
+                hosts::RegionUpdate::Unchanged(msg) => (false, msg),
+                hosts::RegionUpdate::Changed(msg) => (true, msg),
+            };
+        let diff = if changed {
+            hosts::unified_diff(&original, &doc.render(), path, &format!("{} (planned)", path))
         } else {
-            update_hosts_block(
-                Some(blocked_domains.clone()),
-                None,
-                Some(true),
-                Some(region_id.clone()),
-                None,
-            )
-        } {
-            Ok(msg) => results["hosts"] = serde_json::Value::String(msg),
-            Err(e) => {
-                results["success"] = serde_json::Value::Bool(false);
-                results["hosts"] = serde_json::Value::String(format!("Error: {}", e));
-            }
-        }
-    } else {
-        results["hosts"] = serde_json::Value::String("Skipped".into());
+            String::new()
+        };
+        hosts_plan = Some(plan::HostsPlan {
+            path: path.to_string(),
+            changed,
+            message,
+            diff,
+        });
+        hosts_text = Some(original);
     }
 
-    // 2. Обновляем правила брандмауэра если нужно
+    let mut firewall_plan = None;
+    let mut firewall_error = None;
+    let mut firewall_rules = None;
+    let mut targets = Vec::new();
     if use_firewall {
-        match update_firewall_rules(region_id.clone(), blocked_domains.clone(), enable).await {
-            Ok(msg) => results["firewall"] = serde_json::Value::String(msg),
-            Err(e) => {
-                results["success"] = serde_json::Value::Bool(false);
-                results["firewall"] = serde_json::Value::String(format!("Error: {}", e));
+        match plan_firewall(&region_id, &blocked_domains, enable).await {
+            Ok((p, rules, t)) => {
+                firewall_plan = Some(p);
+                firewall_rules = Some(rules);
+                targets = t;
             }
+            Err(e) => firewall_error = Some(e.to_string()),
         }
-    } else {
-        results["firewall"] = serde_json::Value::String("Skipped".into());
     }
 
-    Ok(results)
+    let fingerprint = plan::fingerprint(
+        hosts_text.as_deref(),
+        firewall_rules.as_deref(),
+        &targets,
+    );
+
+    Ok(plan::ChangePlan {
+        region_id,
+        blocked_domains,
+        enable,
+        use_hosts,
+        use_firewall,
+        hosts: hosts_plan,
+        firewall: firewall_plan,
+        firewall_error,
+        fingerprint,
+        created_at: plan::now_secs(),
+    })
 }
 
-// Команда 4.1: Очистить все блоки, созданные clusterbanned (не трогая остальное)
+// Dry run of update_cluster_rules: nothing is written, the result says what would be
 #[tauri::command]
-async fn clear_cluster_blocks() -> Result<String, String> {
-    println!("[TAURI] clear_cluster_blocks called");
-    // Читаем настройки из файла, если не переданы явно
-    let backup_saved = {
-        let (_use_firewall, use_backup, _backup_count) = read_settings_from_file();
-        use_backup
-    };
-
-    let mut messages = Vec::new();
-
-    // 1. Очищаем hosts файл
-    let path = hosts_paths()
-        .iter()
-        .find(|p| std::path::Path::new(p).exists())
-        .cloned()
-        .unwrap_or(hosts_paths()[0]);
-
-    let original = std::fs::read_to_string(&path)
-        .map_err(|e| format!("failed to read hosts file ({}): {}", path, e))?;
-    let mut content = original.clone();
-
-    println!("[TAURI] Hosts file size: {} bytes", content.len());
+async fn plan_cluster_rules(
+    region_id: String,
+    blocked_domains: Vec<String>,
+    enable: bool,
+    use_hosts: bool,
+    use_firewall: bool,
+) -> Result<plan::ChangePlan, Error> {
+    println!(
+        "[TAURI] plan_cluster_rules called: region={}, enable={}, hosts={}, firewall={}",
+        region_id, enable, use_hosts, use_firewall
+    );
+    build_cluster_plan(region_id, blocked_domains, enable, use_hosts, use_firewall).await
+}
 
-    // Ищем ВСЕ блоки, начинающиеся с "# clusterbanned"
-    let mut removed = 0usize;
-    let mut position = 0;
+// Run a plan from plan_cluster_rules, unless hosts, the firewall or the catalog changed since
+#[tauri::command]
+async fn apply_cluster_plan(plan: plan::ChangePlan) -> Result<serde_json::Value, Error> {
+    println!(
+        "[TAURI] apply_cluster_plan called: region={}, fingerprint={}",
+        plan.region_id, plan.fingerprint
+    );
 
-    loop {
-        // Ищем любое вхождение "# clusterbanned" (может быть с region tag)
-        if let Some(start_idx) = content[position..].find("# clusterbanned") {
-            let actual_start = position + start_idx;
-
-            // Найдем конец этой строки
-            let line_end = content[actual_start..]
-                .find('\n')
-                .map(|pos| actual_start + pos + 1)
-                .unwrap_or(content.len());
-
-            // Теперь ищем END_MARKER после этой строки
-            if let Some(rel_end) = content[line_end..].find("# clusterbanned end") {
-                let end_idx = line_end + rel_end + "# clusterbanned end".len();
-
-                // Удаляем блок
-                content.replace_range(actual_start..end_idx, "");
-                removed += 1;
-
-                println!(
-                    "[TAURI] Removed block {} at position {}-{}",
-                    removed, actual_start, end_idx
-                );
-
-                // Продолжаем поиск с той же позиции (так как content изменился)
-                position = actual_start;
-                continue;
-            } else {
-                // END_MARKER не найден, прерываем
-                break;
-            }
-        }
-        break;
+    let current = build_cluster_plan(
+        plan.region_id.clone(),
+        plan.blocked_domains.clone(),
+        plan.enable,
+        plan.use_hosts,
+        plan.use_firewall,
+    )
+    .await?;
+    if current.fingerprint != plan.fingerprint {
+        return Err(Error::InvalidInput(
+            "System state changed since the plan was made; create a new plan and review it again"
+                .into(),
+        ));
     }
 
-    // Убираем лишние пустые строки
-    while content.contains("\n\n\n") {
-        content = content.replace("\n\n\n", "\n\n");
-    }
+    update_cluster_rules(
+        plan.region_id,
+        plan.blocked_domains,
+        plan.enable,
+        plan.use_hosts,
+        plan.use_firewall,
+    )
+    .await
+}
 
-    // Убираем возможные пустые строки в начале/конце
-    content = content.trim().to_string();
-    if !content.ends_with('\n') {
-        content.push('\n');
-    }
+// Пользовательский overlay каталога: просмотр, изменение, итоговый каталог
+#[tauri::command]
+async fn get_catalog() -> Result<catalog::Game, Error> {
+    println!("[TAURI] get_catalog called");
+    catalog::load().await
+}
 
-    println!("[TAURI] Removed {} blocks total", removed);
+#[tauri::command]
+fn get_catalog_overlay() -> Result<catalog::overlay::Overlay, Error> {
+    println!("[TAURI] get_catalog_overlay called");
+    catalog::overlay::load(&settings::config_dir()?)
+}
 
-    if removed > 0 {
-        if backup_saved {
-            // Backup original
-            let ts = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .map_err(|e| format!("failed to compute timestamp for backup: {}", e))?
-                .as_secs();
-            let backup_path = format!("{}.clusterbanned.bak.{}", path, ts);
+// Save `overlay` if it merges cleanly over the current catalog; returns the merged catalog
+async fn store_overlay(overlay: catalog::overlay::Overlay) -> Result<catalog::Game, Error> {
+    overlay.validate()?;
+    let (base, _) = catalog::load_base().await?;
+    let merged = overlay.apply(&base)?;
+    catalog::overlay::save(&settings::config_dir()?, &overlay)?;
+    Ok(merged)
+}
 
-            println!("[TAURI] Creating backup: {}", backup_path);
+#[tauri::command]
+async fn save_catalog_overlay(
+    overlay: catalog::overlay::Overlay,
+) -> Result<catalog::Game, Error> {
+    println!("[TAURI] save_catalog_overlay called");
+    store_overlay(overlay).await
+}
 
-            if let Err(e) = std::fs::write(&backup_path, original.as_bytes()) {
-                messages.push(format!("Backup failed: {}", e));
-            } else {
-                messages.push(format!("Backup created: {}", backup_path));
-            }
-        }
+#[tauri::command]
+async fn upsert_overlay_region(
+    region: catalog::overlay::RegionOverride,
+) -> Result<catalog::Game, Error> {
+    println!("[TAURI] upsert_overlay_region called for: {}", region.id);
+    let mut overlay = catalog::overlay::load(&settings::config_dir()?)?;
+    overlay.upsert_region(region);
+    store_overlay(overlay).await
+}
 
-        // Записываем обновленный файл
-        println!("[TAURI] Writing updated hosts file to: {}", path);
-        match std::fs::write(&path, content.as_bytes()) {
-            Ok(_) => messages.push(format!(
-                "Successfully removed {} block(s) from hosts",
-                removed
-            )),
-            Err(e) => {
-                let error_msg = format!("Failed to write hosts file ({}): {}. Try running the app with elevated privileges", path, e);
-                println!("[TAURI] {}", error_msg);
-                messages.push(error_msg);
-            }
-        }
-    } else {
-        messages.push("No clusterbanned blocks found in hosts".into());
-        println!("[TAURI] No blocks found to remove");
+#[tauri::command]
+async fn delete_overlay_region(region_id: String) -> Result<catalog::Game, Error> {
+    println!("[TAURI] delete_overlay_region called for: {}", region_id);
+    let mut overlay = catalog::overlay::load(&settings::config_dir()?)?;
+    if !overlay.remove_region(&region_id) {
+        return Err(Error::NotFound(format!(
+            "region {} has no overrides",
+            region_id
+        )));
     }
+    store_overlay(overlay).await
+}
 
-    // 2. Очищаем все правила брандмауэра
-    #[cfg(windows)]
-    {
-        println!("[TAURI] Cleaning up firewall rules...");
-        match clear_firewall_rules().await {
-            Ok(fw_msg) => {
-                messages.push(fw_msg);
-                println!("[TAURI] Firewall cleanup successful");
-            }
-            Err(e) => {
-                let error_msg = format!("Firewall cleanup error: {}", e);
-                messages.push(error_msg.clone());
-                println!("[TAURI] {}", error_msg);
-            }
-        }
+#[tauri::command]
+async fn upsert_overlay_cluster(
+    region_id: String,
+    cluster: catalog::overlay::ClusterOverride,
+) -> Result<catalog::Game, Error> {
+    println!(
+        "[TAURI] upsert_overlay_cluster called for: {} / {}",
+        region_id, cluster.id
+    );
+    let mut overlay = catalog::overlay::load(&settings::config_dir()?)?;
+    overlay.upsert_cluster(&region_id, cluster);
+    store_overlay(overlay).await
+}
+
+#[tauri::command]
+async fn delete_overlay_cluster(
+    region_id: String,
+    cluster_id: String,
+) -> Result<catalog::Game, Error> {
+    println!(
+        "[TAURI] delete_overlay_cluster called for: {} / {}",
+        region_id, cluster_id
+    );
+    let mut overlay = catalog::overlay::load(&settings::config_dir()?)?;
+    if !overlay.remove_cluster(&region_id, &cluster_id) {
+        return Err(Error::NotFound(format!(
+            "cluster {} in region {} has no overrides",
+            cluster_id, region_id
+        )));
     }
+    store_overlay(overlay).await
+}
 
-    #[cfg(not(windows))]
-    {
-        messages.push("Firewall cleanup skipped (Windows only)".into());
+// Поиск IP кластеров через DNS: резолвим домены несколькими резолверами и сравниваем с каталогом
+#[tauri::command]
+async fn discover_cluster_ips(
+    region_id: Option<String>,
+    resolvers: Option<Vec<String>>,
+    rounds: Option<u32>,
+) -> Result<discovery::DiscoveryReport, Error> {
+    println!(
+        "[TAURI] discover_cluster_ips called: region={:?}, resolvers={:?}, rounds={:?}",
+        region_id, resolvers, rounds
+    );
+    let resolvers = resolvers.unwrap_or_else(settings::dns_resolvers);
+    let opts = discovery::Options::new(&resolvers, rounds.unwrap_or(discovery::DEFAULT_ROUNDS))?;
+    let catalog = catalog::load().await?;
+    let report = discovery::discover(&catalog, region_id.as_deref(), &opts).await?;
+
+    let new_ips: usize = report.clusters.iter().map(|c| c.new_ips.len()).sum();
+    println!(
+        "[TAURI] discover_cluster_ips: {} clusters, {} new IPs",
+        report.clusters.len(),
+        new_ips
+    );
+    Ok(report)
+}
+
+// Добавить найденные IP в пользовательский overlay
+#[tauri::command]
+async fn merge_discovered_ips(
+    additions: Vec<discovery::IpAddition>,
+) -> Result<catalog::Game, Error> {
+    println!(
+        "[TAURI] merge_discovered_ips called for {} cluster(s)",
+        additions.len()
+    );
+    let mut overlay = catalog::overlay::load(&settings::config_dir()?)?;
+    for addition in &additions {
+        overlay.add_ips(&addition.region_id, &addition.cluster_id, &addition.ips);
     }
+    store_overlay(overlay).await
+}
 
-    let result = messages.join("\n");
+// Команда 4.1: Очистить все блоки, созданные clusterbanned (не трогая остальное)
+#[tauri::command]
+async fn clear_cluster_blocks() -> Result<String, Error> {
+    println!("[TAURI] clear_cluster_blocks called");
+    let result = helper::clear().await?;
     println!("[TAURI] clear_cluster_blocks result: {}", result);
     Ok(result)
 }
 
+// Бэкапы hosts: список, восстановление, diff с текущим файлом
+#[tauri::command]
+fn list_backups() -> Result<Vec<backup::BackupInfo>, Error> {
+    println!("[TAURI] list_backups called");
+    backup::list(std::path::Path::new(hosts::active_path()))
+}
+
+#[tauri::command]
+fn restore_backup(id: String) -> Result<String, Error> {
+    println!("[TAURI] restore_backup called for: {}", id);
+    blocking::restore_backup(&id)
+}
+
+#[tauri::command]
+fn diff_backup(id: String) -> Result<String, Error> {
+    println!("[TAURI] diff_backup called for: {}", id);
+    blocking::diff_backup(&id)
+}
+
 // Команда 5: Получение информации о приложении
 #[tauri::command]
-fn get_app_info() -> Result<serde_json::Value, String> {
+fn get_app_info() -> Result<serde_json::Value, Error> {
     let info = serde_json::json!({
         "name": "WoT Blitz Cluster Banned Manager",
         "version": "0.1.0",
@@ -1109,576 +1274,150 @@ fn get_app_info() -> Result<serde_json::Value, String> {
 
 // Команда 6: Тестовая команда для проверки связи
 #[tauri::command]
-fn test_tauri() -> Result<String, String> {
+fn test_tauri() -> Result<String, Error> {
     println!("[TAURI] test_tauri called - connection is working!");
     Ok("Tauri backend is working correctly! ✅".to_string())
 }
 
 // Launch a game via protocol (steam://rungameid/<id>) or platform default opener
 #[tauri::command]
-fn launch_game(appid: String) -> Result<String, String> {
+fn launch_game(appid: String) -> Result<String, Error> {
     println!("[TAURI] launch_game called for appid: {}", appid);
-    let uri = format!("steam://rungameid/{}", appid);
-    // Platform-specific open
-    #[cfg(target_os = "windows")]
-    {
-        use std::os::windows::process::CommandExt;
-        const CREATE_NO_WINDOW: u32 = 0x08000000;
-        // Use cmd start to honor URL schemes
-        let res = std::process::Command::new("cmd")
-            .args(&["/C", "start", "", &uri])
-            .creation_flags(CREATE_NO_WINDOW)
-            .spawn();
-        match res {
-            Ok(_child) => Ok(uri),
-            Err(e) => Err(format!("failed to launch {}: {}", uri, e)),
-        }
-    }
-
-    #[cfg(target_os = "macos")]
-    {
-        let res = std::process::Command::new("open").arg(&uri).spawn();
-        match res {
-            Ok(_child) => Ok(uri),
-            Err(e) => Err(format!("failed to launch {}: {}", uri, e)),
-        }
-    }
-
-    #[cfg(all(not(target_os = "macos"), not(target_os = "windows")))]
-    {
-        let res = std::process::Command::new("xdg-open").arg(&uri).spawn();
-        match res {
-            Ok(_child) => Ok(uri),
-            Err(e) => Err(format!("failed to launch {}: {}", uri, e)),
-        }
-    }
+    process::launch(&appid)
 }
 
 // Check if a process with a name substring is running
 #[tauri::command]
-fn is_process_running(name: String) -> Result<bool, String> {
+fn is_process_running(name: String) -> Result<bool, Error> {
     println!("[TAURI] is_process_running called for: {}", name);
-    let mut sys = sysinfo::System::new_all();
-    sys.refresh_processes();
-    let needle = name.to_lowercase();
-    for (_pid, process) in sys.processes() {
-        if process.name().to_lowercase().contains(&needle) {
-            return Ok(true);
-        }
-        // Also check cmd line
-        if let Some(cmd) = process.cmd().get(0) {
-            if cmd.to_lowercase().contains(&needle) {
-                return Ok(true);
-            }
-        }
-    }
-    Ok(false)
+    Ok(process::is_running(&name))
 }
 
-// Kill process(es) matching name substring (best-effort)
+// Кластер, к которому реально подключена игра: соединения её процесса против IP каталога
 #[tauri::command]
-fn kill_process(name: String) -> Result<String, String> {
-    println!("[TAURI] kill_process called for: {}", name);
-    let mut sys = sysinfo::System::new_all();
-    sys.refresh_processes();
-    let needle = name.to_lowercase();
-    let mut killed = 0usize;
-
-    for (pid, process) in sys.processes() {
-        if process.name().to_lowercase().contains(&needle)
-            || process
-                .cmd()
-                .iter()
-                .any(|s| s.to_lowercase().contains(&needle))
-        {
-            let pid_u = pid.as_u32();
-            #[cfg(target_os = "windows")]
-            {
-                use std::os::windows::process::CommandExt;
-                const CREATE_NO_WINDOW: u32 = 0x08000000;
-                if let Ok(st) = std::process::Command::new("taskkill")
-                    .args(&["/PID", &pid_u.to_string(), "/F"])
-                    .creation_flags(CREATE_NO_WINDOW)
-                    .status()
-                {
-                    if st.success() {
-                        killed += 1;
-                    }
-                }
-            }
-
-            #[cfg(not(target_os = "windows"))]
-            {
-                if let Ok(st) = std::process::Command::new("kill")
-                    .arg("-TERM")
-                    .arg(pid_u.to_string())
-                    .status()
-                {
-                    if st.success() {
-                        killed += 1;
-                    }
-                }
-            }
-        }
+async fn detect_connected_cluster(
+    names: Option<Vec<String>>,
+) -> Result<sockets::ConnectedCluster, Error> {
+    let names = names.unwrap_or_else(process::game_process_names);
+    println!("[TAURI] detect_connected_cluster called for: {:?}", names);
+    let pids = process::matching_pids(&names);
+    if pids.is_empty() {
+        return Err(Error::NotFound(format!(
+            "game process not running (looked for {:?})",
+            names
+        )));
     }
-
-    Ok(format!("killed {} processes", killed))
+    let catalog = catalog::load().await?;
+    let report = sockets::detect(&catalog, &pids)?;
+    println!(
+        "[TAURI] detect_connected_cluster: {} connections, cluster {:?} / {:?}",
+        report.connections.len(),
+        report.region_id,
+        report.cluster_id
+    );
+    Ok(report)
 }
 
+// Проверка, что блокировка реально работает: домен резолвится в 0.0.0.0, а IP каталога не отвечают
 #[tauri::command]
-fn block_with_firewall(domain: String, ips: Vec<String>, enable: bool) -> Result<String, String> {
-    #[cfg(windows)]
-    {
-        use std::process::Command;
-
-        let mut results = Vec::new();
-        let rule_name = format!("WoT_Blitz_Block_{}", domain.replace(".", "_"));
-
-        use std::os::windows::process::CommandExt;
-        const CREATE_NO_WINDOW: u32 = 0x08000000;
-        
-        if enable {
-            // Сначала удаляем существующее правило, если есть
-            let _ = Command::new("netsh")
-                .args(&[
-                    "advfirewall",
-                    "firewall",
-                    "delete",
-                    "rule",
-                    &format!("name={}", rule_name),
-                ])
-                .creation_flags(CREATE_NO_WINDOW)
-                .output();
-
-            // Блокируем каждый IP отдельно или все вместе
-            let ips_str = ips.join(",");
-
-            let output = Command::new("netsh")
-                .args(&[
-                    "advfirewall",
-                    "firewall",
-                    "add",
-                    "rule",
-                    &format!("name={}", rule_name),
-                    "dir=out",
-                    "action=block",
-                    &format!("remoteip={}", ips_str),
-                    "protocol=any",
-                    "enable=yes",
-                    "profile=any",
-                ])
-                .creation_flags(CREATE_NO_WINDOW)
-                .output()
-                .map_err(|e| format!("Failed to create firewall rule: {}", e))?;
-
-            if output.status.success() {
-                results.push(format!(
-                    "Firewall rule created for {} ({} IPs)",
-                    domain,
-                    ips.len()
-                ));
-            } else {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                // Пробуем создать правила для каждого IP отдельно
-                if stderr.contains("remoteip") {
-                    for ip in ips {
-                        let ip_rule_name = format!("{}_{}", rule_name, ip.replace(".", "_"));
-                        let output = Command::new("netsh")
-                            .args(&[
-                                "advfirewall",
-                                "firewall",
-                                "add",
-                                "rule",
-                                &format!("name={}", ip_rule_name),
-                                "dir=out",
-                                "action=block",
-                                &format!("remoteip={}", ip),
-                                "protocol=any",
-                                "enable=yes",
-                                "profile=any",
-                            ])
-                            .creation_flags(CREATE_NO_WINDOW)
-                            .output();
-
-                        if let Ok(out) = output {
-                            if out.status.success() {
-                                results.push(format!("Blocked IP: {}", ip));
-                            }
-                        }
-                    }
-                } else {
-                    return Err(format!("Failed to create firewall rule: {}", stderr));
-                }
-            }
-        } else {
-            // Удаляем правило
-            let output = Command::new("netsh")
-                .args(&[
-                    "advfirewall",
-                    "firewall",
-                    "delete",
-                    "rule",
-                    &format!("name={}", rule_name),
-                ])
-                .creation_flags(CREATE_NO_WINDOW)
-                .output();
-
-            // Также удаляем отдельные правила IP
-            for ip in ips {
-                let ip_rule_name = format!("{}_{}", rule_name, ip.replace(".", "_"));
-                let _ = Command::new("netsh")
-                    .args(&[
-                        "advfirewall",
-                        "firewall",
-                        "delete",
-                        "rule",
-                        &format!("name={}", ip_rule_name),
-                    ])
-                    .creation_flags(CREATE_NO_WINDOW)
-                    .output();
-            }
-
-            if let Ok(out) = output {
-                if out.status.success() {
-                    results.push(format!("Firewall rule removed for {}", domain));
-                } else {
-                    results.push(format!(
-                        "Note: Firewall rule for {} may not have existed",
-                        domain
-                    ));
-                }
-            }
-        }
-
-        if results.is_empty() {
-            Ok("No firewall changes made".into())
-        } else {
-            Ok(results.join("\n"))
-        }
+async fn verify_blocks(
+    region_id: Option<String>,
+    timeout_ms: Option<u64>,
+) -> Result<verify::Report, Error> {
+    println!("[TAURI] verify_blocks called for: {:?}", region_id);
+    let timeout_ms = timeout_ms.unwrap_or(verify::DEFAULT_TIMEOUT_MS);
+    if timeout_ms == 0 {
+        return Err(Error::InvalidInput("timeout must be positive".into()));
     }
+    let text = hosts::read_text()?;
+    let blocked = HostsDocument::parse(&text).blocked_domains();
+    let (use_firewall, _, _) = settings::blocking();
+    let catalog = catalog::load().await?;
+    // Без списка правил молчащий IP не отличить от заблокированного
+    let rules = match firewall::backend().and_then(|b| b.inventory(Some(&catalog))) {
+        Ok(rules) => Some(rules),
+        Err(e) => {
+            println!("[TAURI] verify_blocks: firewall rules not listed: {}", e);
+            None
+        }
+    };
+    let report = verify::verify(
+        &catalog,
+        &blocked,
+        region_id.as_deref(),
+        use_firewall,
+        rules,
+        std::time::Duration::from_millis(timeout_ms),
+    )
+    .await;
 
-    #[cfg(not(windows))]
-    {
-        Ok("Firewall rules only supported on Windows".into())
-    }
+    println!(
+        "[TAURI] verify_blocks: {} clusters checked, {} leaky",
+        report.clusters.len(),
+        report.clusters.iter().filter(|c| !c.effective).count()
+    );
+    Ok(report)
 }
 
+// Kill process(es) matching name substring (best-effort)
 #[tauri::command]
-async fn clear_firewall_rules() -> Result<String, String> {
-    #[cfg(windows)]
-    {
-        use std::process::Command;
-        use std::os::windows::process::CommandExt;
-        const CREATE_NO_WINDOW: u32 = 0x08000000;
-
-        let mut results = Vec::new();
-        let mut deleted_rules = std::collections::HashSet::new();
-
-        // Удаляем все правила, созданные нашим приложением
-        let prefixes = ["WoT_Blitz_Block_", "WoT_Block_", "ClusterBanned_"];
-
-        // Получаем список всех правил
-        let output = Command::new("netsh")
-            .args(&["advfirewall", "firewall", "show", "rule", "name=all"])
-            .creation_flags(CREATE_NO_WINDOW)
-            .output()
-            .map_err(|e| format!("Failed to get firewall rules: {}", e))?;
-
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let mut current_rule_name: Option<String> = None;
-        
-        // Парсим вывод netsh построчно
-        // Формат: Rule Name: <name>
-        for line in stdout.lines() {
-            let line = line.trim();
-            
-            // Ищем строку с именем правила (может быть "Rule Name:" или "Rule Name :")
-            if line.contains("Rule Name") {
-                // Извлекаем имя правила после "Rule Name:" или "Rule Name :"
-                let parts: Vec<&str> = line.split(':').collect();
-                if parts.len() >= 2 {
-                    let rule_name = parts[1].trim();
-                    if !rule_name.is_empty() {
-                        current_rule_name = Some(rule_name.to_string());
-                    }
-                }
-            }
-            
-            // Если у нас есть имя правила и оно содержит нужный префикс
-            if let Some(ref rule_name) = current_rule_name {
-                for prefix in &prefixes {
-                    if rule_name.starts_with(prefix) && !deleted_rules.contains(rule_name) {
-                        // Удаляем правило
-                        let delete_output = Command::new("netsh")
-                            .args(&[
-                                "advfirewall",
-                                "firewall",
-                                "delete",
-                                "rule",
-                                &format!("name={}", rule_name),
-                            ])
-                            .creation_flags(CREATE_NO_WINDOW)
-                            .output();
-
-                        match delete_output {
-                            Ok(del_out) => {
-                                if del_out.status.success() {
-                                    deleted_rules.insert(rule_name.clone());
-                                    results.push(format!("Deleted rule: {}", rule_name));
-                                    println!("[TAURI] Successfully deleted firewall rule: {}", rule_name);
-                                } else {
-                                    let stderr = String::from_utf8_lossy(&del_out.stderr);
-                                    let stdout_msg = String::from_utf8_lossy(&del_out.stdout);
-                                    println!("[TAURI] Failed to delete rule {}: stderr={}, stdout={}", rule_name, stderr, stdout_msg);
-                                }
-                            }
-                            Err(e) => {
-                                println!("[TAURI] Error deleting rule {}: {}", rule_name, e);
-                            }
-                        }
-                        break; // Переходим к следующему правилу
-                    }
-                }
-            }
-            
-            // Сбрасываем имя правила при переходе к следующему блоку правил
-            // (пустая строка или разделитель)
-            if line.is_empty() || line.starts_with("---") || line.starts_with("=") {
-                current_rule_name = None;
-            }
-        }
-
-        // Также пробуем удалить правила напрямую по известным доменам из servers.json
-        // Это нужно на случай, если парсинг не сработал
-        let clusters_data: serde_json::Value = get_clusters_with_fallback().await;
-        
-        if let serde_json::Value::Array(regions) = &clusters_data["clusters"] {
-            for region_data in regions {
-                if let serde_json::Value::Array(clusters) = &region_data["clusters"] {
-                    for cluster in clusters {
-                        if let Some(domain) = cluster["domain"].as_str() {
-                            let rule_name = format!("WoT_Blitz_Block_{}", domain.replace(".", "_"));
-                            
-                            if !deleted_rules.contains(&rule_name) {
-                                let delete_output = Command::new("netsh")
-                                    .args(&[
-                                        "advfirewall",
-                                        "firewall",
-                                        "delete",
-                                        "rule",
-                                        &format!("name={}", rule_name),
-                                    ])
-                                    .creation_flags(CREATE_NO_WINDOW)
-                                    .output();
-
-                                if let Ok(del_out) = delete_output {
-                                    if del_out.status.success() {
-                                        deleted_rules.insert(rule_name.clone());
-                                        results.push(format!("Deleted rule: {}", rule_name));
-                                        println!("[TAURI] Successfully deleted firewall rule (direct): {}", rule_name);
-                                    }
-                                }
-                                
-                                // Также пробуем удалить правила для отдельных IP
-                                if let serde_json::Value::Array(ips_array) = &cluster["ips"] {
-                                    for ip_val in ips_array {
-                                        if let Some(ip) = ip_val.as_str() {
-                                            let ip_rule_name = format!("{}_{}", rule_name, ip.replace(".", "_"));
-                                            
-                                            if !deleted_rules.contains(&ip_rule_name) {
-                                                let _ = Command::new("netsh")
-                                                    .args(&[
-                                                        "advfirewall",
-                                                        "firewall",
-                                                        "delete",
-                                                        "rule",
-                                                        &format!("name={}", ip_rule_name),
-                                                    ])
-                                                    .creation_flags(CREATE_NO_WINDOW)
-                                                    .output();
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
-
-        if results.is_empty() {
-            Ok("No firewall rules found to delete".into())
-        } else {
-            Ok(format!("Deleted {} firewall rules", results.len()))
-        }
-    }
-
-    #[cfg(not(windows))]
-    {
-        Ok("Firewall rules only supported on Windows".into())
-    }
+fn kill_process(name: String) -> Result<String, Error> {
+    println!("[TAURI] kill_process called for: {}", name);
+    Ok(format!("killed {} processes", process::kill(&name)))
 }
 
 #[tauri::command]
-fn get_firewall_rules() -> Result<Vec<String>, String> {
-    #[cfg(windows)]
-    {
-        use std::process::Command;
-        use std::os::windows::process::CommandExt;
-        const CREATE_NO_WINDOW: u32 = 0x08000000;
-
-        let output = Command::new("netsh")
-            .args(&["advfirewall", "firewall", "show", "rule", "name=all"])
-            .creation_flags(CREATE_NO_WINDOW)
-            .output()
-            .map_err(|e| format!("Failed to get firewall rules: {}", e))?;
-
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let mut rules = Vec::new();
-
-        for line in stdout.lines() {
-            if line.contains("Rule Name:")
-                && (line.contains("WoT_Blitz")
-                    || line.contains("WoT_Block")
-                    || line.contains("ClusterBanned"))
-            {
-                rules.push(line.trim().to_string());
-            }
-        }
-
-        Ok(rules)
-    }
+fn block_with_firewall(
+    domain: String,
+    ips: Vec<String>,
+    enable: bool,
+    region_id: Option<String>,
+) -> Result<String, Error> {
+    let backend = firewall::backend()?;
+    // netsh names rules after the domain only; nftables/iptables also group them by region
+    let region = region_id.unwrap_or_else(|| "custom".into());
+    backend.set_blocked(&region, &domain, &ips, enable)
+}
 
-    #[cfg(not(windows))]
-    {
-        Ok(vec!["Firewall rules only supported on Windows".into()])
-    }
+#[tauri::command]
+async fn clear_firewall_rules() -> Result<String, Error> {
+    blocking::clear_firewall().await
 }
 
-// Settings management - храним настройки в файле конфигурации
-fn get_settings_path() -> Result<std::path::PathBuf, String> {
-    #[cfg(windows)]
-    {
-        let appdata = std::env::var("APPDATA")
-            .map_err(|_| "APPDATA environment variable not found".to_string())?;
-        Ok(std::path::PathBuf::from(appdata)
-            .join("clusterbanned")
-            .join("settings.json"))
-    }
-    
-    #[cfg(target_os = "macos")]
-    {
-        let home = std::env::var("HOME")
-            .map_err(|_| "HOME environment variable not found".to_string())?;
-        Ok(std::path::PathBuf::from(home)
-            .join("Library")
-            .join("Application Support")
-            .join("clusterbanned")
-            .join("settings.json"))
-    }
-    
-    #[cfg(all(unix, not(target_os = "macos")))]
-    {
-        let home = std::env::var("HOME")
-            .map_err(|_| "HOME environment variable not found".to_string())?;
-        Ok(std::path::PathBuf::from(home)
-            .join(".config")
-            .join("clusterbanned")
-            .join("settings.json"))
+#[tauri::command]
+fn get_firewall_rules() -> Result<Vec<String>, Error> {
+    match firewall::backend() {
+        Ok(backend) => backend.rules(),
+        Err(e) => Ok(vec![e.to_string()]),
     }
 }
 
+// Правила файрвола в структурированном виде, сопоставленные с кластерами каталога:
+// осиротевшие правила и расхождения IP с каталогом помечаются
 #[tauri::command]
-fn get_settings() -> Result<serde_json::Value, String> {
-    let settings_path = get_settings_path()?;
-    
-    // Если файл не существует, возвращаем дефолтные настройки
-    if !settings_path.exists() {
-        return Ok(serde_json::json!({
-            "useFirewall": true,
-            "useBackup": false,
-            "backupCount": 5
-        }));
-    }
-    
-    // Читаем файл
-    let content = std::fs::read_to_string(&settings_path)
-        .map_err(|e| format!("Failed to read settings file: {}", e))?;
-    
-    // Парсим JSON
-    let settings: serde_json::Value = serde_json::from_str(&content)
-        .map_err(|e| format!("Failed to parse settings JSON: {}", e))?;
-    
-    // Убеждаемся, что все поля присутствуют (слияние с дефолтами)
-    let mut result = serde_json::json!({
-        "useFirewall": true,
-        "useBackup": false,
-        "backupCount": 5
-    });
-    
-    if let serde_json::Value::Object(map) = settings {
-        for (key, value) in map {
-            result[key] = value;
-        }
-    }
-    
-    Ok(result)
+async fn get_firewall_inventory() -> Result<firewall::Inventory, Error> {
+    println!("[TAURI] get_firewall_inventory called");
+    let backend = firewall::backend()?;
+    let catalog = catalog::load().await?;
+    let rules = backend.inventory(Some(&catalog))?;
+    let inventory = firewall::audit(backend.name(), rules, &catalog);
+
+    println!(
+        "[TAURI] get_firewall_inventory: {} rules, {} orphaned, {} drifted",
+        inventory.rules.len(),
+        inventory.orphaned,
+        inventory.drifted
+    );
+    Ok(inventory)
 }
 
+// Settings management - храним настройки в файле конфигурации
 #[tauri::command]
-fn save_settings(settings: serde_json::Value) -> Result<(), String> {
-    let settings_path = get_settings_path()?;
-    
-    // Создаем директорию, если её нет
-    if let Some(parent) = settings_path.parent() {
-        std::fs::create_dir_all(parent)
-            .map_err(|e| format!("Failed to create settings directory: {}", e))?;
-    }
-    
-    // Записываем настройки в файл
-    let json_string = serde_json::to_string_pretty(&settings)
-        .map_err(|e| format!("Failed to serialize settings: {}", e))?;
-    
-    std::fs::write(&settings_path, json_string)
-        .map_err(|e| format!("Failed to write settings file: {}", e))?;
-    
-    Ok(())
+fn get_settings() -> Result<serde_json::Value, Error> {
+    settings::load()
 }
 
-// Вспомогательная функция для чтения настроек из Rust-кода
-fn read_settings_from_file() -> (bool, bool, u32) {
-    match get_settings_path() {
-        Ok(settings_path) => {
-            if !settings_path.exists() {
-                return (true, false, 5); // Дефолтные значения
-            }
-            
-            match std::fs::read_to_string(&settings_path) {
-                Ok(content) => {
-                    match serde_json::from_str::<serde_json::Value>(&content) {
-                        Ok(settings) => {
-                            let use_firewall = settings.get("useFirewall")
-                                .and_then(|v| v.as_bool())
-                                .unwrap_or(true);
-                            
-                            let use_backup = settings.get("useBackup")
-                                .and_then(|v| v.as_bool())
-                                .unwrap_or(false);
-                            
-                            let backup_count = settings.get("backupCount")
-                                .and_then(|v| v.as_u64())
-                                .map(|v| v as u32)
-                                .unwrap_or(5);
-                            
-                            (use_firewall, use_backup, backup_count)
-                        }
-                        Err(_) => (true, false, 5), // Дефолты при ошибке парсинга
-                    }
-                }
-                Err(_) => (true, false, 5), // Дефолты при ошибке чтения
-            }
-        }
-        Err(_) => (true, false, 5), // Дефолты при ошибке получения пути
-    }
+#[tauri::command]
+fn save_settings(settings: serde_json::Value) -> Result<(), Error> {
+    settings::save(&settings)
 }
@@ -0,0 +1,324 @@
+// Background latency monitor: probes every cluster of one region on a schedule and pushes each
+// round to the UI as an event, instead of the frontend polling ping_server host by host.
+//
+// Every round, hidden or not, is also published to in-process subscribers (see `subscribe`).
+//
+// Backpressure: while the window is hidden or minimized rounds run at `hiddenIntervalMs` (0
+// pauses them) and are not emitted; the latest one is still kept in the status. A round never
+// overlaps the previous one: the next wait only starts once every probe has finished.
+
+use crate::catalog::Game;
+use crate::error::{Error, Result};
+use crate::probe::{self, ProbeResult, ProbeSpec};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+pub const EVENT: &str = "latency-monitor://tick";
+
+pub const DEFAULT_INTERVAL_MS: u64 = 5_000;
+pub const DEFAULT_HIDDEN_INTERVAL_MS: u64 = 60_000;
+pub const DEFAULT_TIMEOUT_MS: u64 = 1_000;
+const MIN_INTERVAL_MS: u64 = 1_000;
+// How often visibility is re-checked while waiting for the next round
+const VISIBILITY_POLL: Duration = Duration::from_secs(1);
+
+// What the monitor needs from the window it reports to
+pub trait Ui: Send + Sync + 'static {
+    fn is_visible(&self) -> bool;
+    fn emit(&self, tick: &Tick);
+}
+
+fn default_interval() -> u64 {
+    DEFAULT_INTERVAL_MS
+}
+
+fn default_hidden_interval() -> u64 {
+    DEFAULT_HIDDEN_INTERVAL_MS
+}
+
+fn default_timeout() -> u64 {
+    DEFAULT_TIMEOUT_MS
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Config {
+    pub region_id: String,
+    #[serde(default = "default_interval")]
+    pub interval_ms: u64,
+    // 0 pauses the monitor while the window is hidden
+    #[serde(default = "default_hidden_interval")]
+    pub hidden_interval_ms: u64,
+    #[serde(default = "default_timeout")]
+    pub timeout_ms: u64,
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<()> {
+        if self.interval_ms < MIN_INTERVAL_MS {
+            return Err(Error::InvalidInput(format!(
+                "interval must be at least {} ms",
+                MIN_INTERVAL_MS
+            )));
+        }
+        if self.hidden_interval_ms != 0 && self.hidden_interval_ms < self.interval_ms {
+            return Err(Error::InvalidInput(
+                "hidden interval must be 0 or at least the visible interval".into(),
+            ));
+        }
+        if self.timeout_ms == 0 || self.timeout_ms >= self.interval_ms {
+            return Err(Error::InvalidInput(
+                "timeout must be positive and shorter than the interval".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// One cluster the monitor probes
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub cluster_id: String,
+    pub domain: String,
+    // First catalog IP; the domain is resolved when there is none
+    pub ip: Option<IpAddr>,
+    pub probe: Option<ProbeSpec>,
+}
+
+pub fn targets(catalog: &Game, region_id: &str) -> Result<Vec<Target>> {
+    let region = catalog
+        .region(region_id)
+        .ok_or_else(|| Error::NotFound(format!("region {} not found in catalog", region_id)))?;
+    Ok(region
+        .clusters
+        .iter()
+        .map(|c| Target {
+            cluster_id: c.id.clone(),
+            domain: c.domain.clone(),
+            ip: probe::stats::catalog_ip(c),
+            probe: c.probe,
+        })
+        .collect())
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sample {
+    pub cluster_id: String,
+    pub domain: String,
+    #[serde(flatten)]
+    pub result: ProbeResult,
+}
+
+// One round over every cluster of the region
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tick {
+    pub region_id: String,
+    pub seq: u64,
+    // Milliseconds since the Unix epoch
+    pub at: u64,
+    pub hidden: bool,
+    pub samples: Vec<Sample>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Status {
+    pub running: bool,
+    pub config: Option<Config>,
+    pub rounds: u64,
+    pub emitted: u64,
+    pub hidden: bool,
+    pub last_tick: Option<Tick>,
+}
+
+struct Monitor {
+    config: tokio::sync::watch::Sender<(Config, Vec<Target>)>,
+    status: Arc<Mutex<Status>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+static MONITOR: Mutex<Option<Monitor>> = Mutex::new(None);
+
+static TICKS: OnceLock<tokio::sync::broadcast::Sender<Tick>> = OnceLock::new();
+
+fn ticks() -> &'static tokio::sync::broadcast::Sender<Tick> {
+    TICKS.get_or_init(|| tokio::sync::broadcast::channel(64).0)
+}
+
+// Every round the monitor runs from now on; a subscriber that falls behind skips rounds
+pub fn subscribe() -> tokio::sync::broadcast::Receiver<Tick> {
+    ticks().subscribe()
+}
+
+async fn round(
+    region_id: &str,
+    targets: &[Target],
+    timeout: Duration,
+    seq: u64,
+    hidden: bool,
+) -> Tick {
+    let at = crate::history::now_ms();
+    let mut tasks = tokio::task::JoinSet::new();
+    for (i, target) in targets.iter().cloned().enumerate() {
+        tasks.spawn(async move {
+            let result =
+                probe::probe_cluster(&target.domain, target.ip, target.probe, timeout).await;
+            (
+                i,
+                Sample {
+                    cluster_id: target.cluster_id,
+                    domain: target.domain,
+                    result,
+                },
+            )
+        });
+    }
+    let mut samples = Vec::with_capacity(targets.len());
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok(sample) = joined {
+            samples.push(sample);
+        }
+    }
+    samples.sort_by_key(|(i, _)| *i);
+    Tick {
+        region_id: region_id.to_string(),
+        seq,
+        at,
+        hidden,
+        samples: samples.into_iter().map(|(_, s)| s).collect(),
+    }
+}
+
+async fn run(
+    ui: Arc<dyn Ui>,
+    mut config: tokio::sync::watch::Receiver<(Config, Vec<Target>)>,
+    status: Arc<Mutex<Status>>,
+) {
+    let mut seq = 0;
+    loop {
+        let (cfg, targets) = config.borrow_and_update().clone();
+        let hidden = !ui.is_visible();
+        let paused = hidden && cfg.hidden_interval_ms == 0;
+        status.lock().unwrap().hidden = hidden;
+
+        if !paused {
+            seq += 1;
+            let tick = round(
+                &cfg.region_id,
+                &targets,
+                Duration::from_millis(cfg.timeout_ms),
+                seq,
+                hidden,
+            )
+            .await;
+            if !hidden {
+                ui.emit(&tick);
+            }
+            // No subscribers is fine
+            let _ = ticks().send(tick.clone());
+            let mut st = status.lock().unwrap();
+            st.rounds += 1;
+            if !hidden {
+                st.emitted += 1;
+            }
+            st.last_tick = Some(tick);
+        }
+
+        // Wait for the next round; a config change or the window showing up cuts the wait short
+        let wait = if hidden {
+            cfg.hidden_interval_ms
+        } else {
+            cfg.interval_ms
+        };
+        let deadline = Instant::now() + Duration::from_millis(wait);
+        loop {
+            let now = Instant::now();
+            if !paused && now >= deadline {
+                break;
+            }
+            let step = if paused {
+                VISIBILITY_POLL
+            } else {
+                (deadline - now).min(VISIBILITY_POLL)
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(step) => {}
+                changed = config.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                    break;
+                }
+            }
+            if hidden && ui.is_visible() {
+                break;
+            }
+        }
+    }
+}
+
+// Start the monitor, or reconfigure it if it is already running
+pub fn start(ui: Arc<dyn Ui>, config: Config, targets: Vec<Target>) -> Result<Status> {
+    config.validate()?;
+    let mut guard = MONITOR.lock().unwrap();
+    if let Some(monitor) = guard.as_ref().filter(|m| !m.task.is_finished()) {
+        monitor.status.lock().unwrap().config = Some(config.clone());
+        monitor.config.send_replace((config, targets));
+        return Ok(monitor.status.lock().unwrap().clone());
+    }
+
+    let status = Arc::new(Mutex::new(Status {
+        running: true,
+        config: Some(config.clone()),
+        ..Default::default()
+    }));
+    let (tx, rx) = tokio::sync::watch::channel((config, targets));
+    let task = tokio::spawn(run(ui, rx, status.clone()));
+    let snapshot = status.lock().unwrap().clone();
+    *guard = Some(Monitor {
+        config: tx,
+        status,
+        task,
+    });
+    Ok(snapshot)
+}
+
+// Change the schedule or region of the running monitor
+pub fn reconfigure(config: Config, targets: Vec<Target>) -> Result<Status> {
+    config.validate()?;
+    let guard = MONITOR.lock().unwrap();
+    let monitor = guard
+        .as_ref()
+        .filter(|m| !m.task.is_finished())
+        .ok_or_else(|| Error::NotFound("latency monitor is not running".into()))?;
+    monitor.status.lock().unwrap().config = Some(config.clone());
+    monitor.config.send_replace((config, targets));
+    let status = monitor.status.lock().unwrap().clone();
+    Ok(status)
+}
+
+// Stop the monitor; false if it wasn't running
+pub fn stop() -> bool {
+    match MONITOR.lock().unwrap().take() {
+        Some(monitor) => {
+            monitor.task.abort();
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn status() -> Status {
+    match MONITOR.lock().unwrap().as_ref() {
+        Some(monitor) => {
+            let mut status = monitor.status.lock().unwrap().clone();
+            status.running = !monitor.task.is_finished();
+            status
+        }
+        None => Status::default(),
+    }
+}
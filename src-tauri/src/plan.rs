@@ -0,0 +1,55 @@
+// Dry runs of update_cluster_rules. A plan lists what would change in hosts and the firewall,
+// plus a fingerprint of the state it was computed from so apply can refuse a stale plan.
+
+use crate::firewall::FirewallPlan;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostsPlan {
+    pub path: String,
+    pub changed: bool,
+    pub message: String,
+    // Unified diff of the hosts file, empty when nothing changes
+    pub diff: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangePlan {
+    pub region_id: String,
+    pub blocked_domains: Vec<String>,
+    pub enable: bool,
+    pub use_hosts: bool,
+    pub use_firewall: bool,
+    pub hosts: Option<HostsPlan>,
+    pub firewall: Option<FirewallPlan>,
+    // Why the firewall part couldn't be planned (no backend, catalog unavailable, ...)
+    pub firewall_error: Option<String>,
+    pub fingerprint: String,
+    // Unix seconds
+    pub created_at: u64,
+}
+
+// Hash of everything a plan depends on: the hosts file, the rules the firewall backend
+// reports and the (domain, ips) targets taken from the catalog
+pub fn fingerprint(
+    hosts_text: Option<&str>,
+    firewall_rules: Option<&[String]>,
+    targets: &[(String, Vec<String>)],
+) -> String {
+    let mut hasher = DefaultHasher::new();
+    hosts_text.hash(&mut hasher);
+    firewall_rules.hash(&mut hasher);
+    targets.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+pub fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
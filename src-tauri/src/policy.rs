@@ -0,0 +1,688 @@
+// Auto-block policy: watches the latency monitor's rounds for one region and blocks clusters that
+// stay over the thresholds, unblocking them again once they recover.
+//
+// - A cluster is "over" when the average latency or the loss over the last `windowSecs` exceeds
+//   the block thresholds, and "under" when both are within the (lower) unblock thresholds; in
+//   between neither streak runs. That gap is the hysteresis.
+// - Blocking needs the cluster to stay over for `windowSecs`, unblocking to stay under for as
+//   long. Only clusters the engine blocked itself are ever unblocked by it.
+// - At least `minOpen` clusters of the region stay open; when several go bad at once the worst
+//   are blocked first.
+// - A blocked cluster can't be measured through its domain, so recovery is measured by probing
+//   its catalog IPs directly. If the firewall blocks those too, `retryAfterSecs` unblocks it on
+//   probation and the normal rules decide again.
+//
+// The policy lives in autoblock.json and every action in autoblock-audit.jsonl, both in the app
+// config dir.
+
+use crate::error::{Error, Result};
+use crate::probe::stats::{summarize, LatencyStats};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const POLICY_FILE: &str = "autoblock.json";
+const AUDIT_FILE: &str = "autoblock-audit.jsonl";
+// The audit log is trimmed back to this many entries once it holds twice as many
+const MAX_AUDIT_ENTRIES: usize = 2_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Policy {
+    pub enabled: bool,
+    pub region_id: String,
+    pub block_latency_ms: f64,
+    pub block_loss_percent: f64,
+    pub unblock_latency_ms: f64,
+    pub unblock_loss_percent: f64,
+    pub window_secs: u64,
+    // Fewer samples than this in the window: no decision either way
+    pub min_samples: u32,
+    pub min_open: usize,
+    // 0 never unblocks on probation
+    pub retry_after_secs: u64,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            enabled: false,
+            region_id: String::new(),
+            block_latency_ms: 150.0,
+            block_loss_percent: 20.0,
+            unblock_latency_ms: 100.0,
+            unblock_loss_percent: 5.0,
+            window_secs: 120,
+            min_samples: 5,
+            min_open: 1,
+            retry_after_secs: 1800,
+        }
+    }
+}
+
+impl Policy {
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |m: &str| Err(Error::InvalidInput(format!("auto-block policy: {}", m)));
+        if self.enabled && self.region_id.trim().is_empty() {
+            return invalid("`regionId` is empty");
+        }
+        if self.block_latency_ms <= 0.0 || self.unblock_latency_ms <= 0.0 {
+            return invalid("latency thresholds must be positive");
+        }
+        if self.unblock_latency_ms >= self.block_latency_ms {
+            return invalid("`unblockLatencyMs` must be below `blockLatencyMs`");
+        }
+        let loss_ok = |p: f64| (0.0..=100.0).contains(&p);
+        if !loss_ok(self.block_loss_percent) || !loss_ok(self.unblock_loss_percent) {
+            return invalid("loss thresholds must be between 0 and 100");
+        }
+        if self.unblock_loss_percent > self.block_loss_percent {
+            return invalid("`unblockLossPercent` must not exceed `blockLossPercent`");
+        }
+        if self.window_secs < 10 {
+            return invalid("`windowSecs` must be at least 10");
+        }
+        if self.min_samples == 0 {
+            return invalid("`minSamples` must be at least 1");
+        }
+        if self.min_open == 0 {
+            return invalid("`minOpen` must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+pub fn policy_path(dir: &Path) -> PathBuf {
+    dir.join(POLICY_FILE)
+}
+
+pub fn audit_path(dir: &Path) -> PathBuf {
+    dir.join(AUDIT_FILE)
+}
+
+// The saved policy; no file means the (disabled) default
+pub fn load(dir: &Path) -> Result<Policy> {
+    let path = policy_path(dir);
+    let text = match std::fs::read_to_string(&path) {
+        Ok(t) => t,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Policy::default()),
+        Err(e) => return Err(Error::io(format!("failed to read {}", path.display()), e)),
+    };
+    let policy: Policy = serde_json::from_str(&text)
+        .map_err(|e| Error::Parse(format!("{} is invalid: {}", path.display(), e)))?;
+    // A hand-edited file that fails validation is as unreadable as broken JSON
+    policy
+        .validate()
+        .map_err(|e| Error::Parse(format!("{} is invalid: {}", path.display(), e)))?;
+    Ok(policy)
+}
+
+pub fn save(dir: &Path, policy: &Policy) -> Result<()> {
+    policy.validate()?;
+    std::fs::create_dir_all(dir)
+        .map_err(|e| Error::io(format!("failed to create {}", dir.display()), e))?;
+    let text = serde_json::to_string_pretty(policy)?;
+    crate::hosts::write_atomic(&policy_path(dir), &text)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Block,
+    Unblock,
+    // Would have been blocked, but that would leave fewer than `minOpen` clusters open
+    Skip,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Decision {
+    pub cluster_id: String,
+    pub domain: String,
+    pub action: Action,
+    pub reason: String,
+    pub stats: LatencyStats,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    // Milliseconds since the Unix epoch
+    pub at: u64,
+    pub region_id: String,
+    pub cluster_id: String,
+    pub domain: String,
+    pub action: Action,
+    pub reason: String,
+    #[serde(default)]
+    pub avg_ms: Option<f64>,
+    #[serde(default)]
+    pub loss_percent: f64,
+    pub success: bool,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+pub fn append_audit(dir: &Path, entry: &AuditEntry) -> Result<()> {
+    let line = serde_json::to_string(entry)?;
+    std::fs::create_dir_all(dir)
+        .map_err(|e| Error::io(format!("failed to create {}", dir.display()), e))?;
+    let path = audit_path(dir);
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| Error::io(format!("failed to open {}", path.display()), e))?;
+    writeln!(file, "{}", line)
+        .map_err(|e| Error::io(format!("failed to write {}", path.display()), e))?;
+    drop(file);
+
+    let entries = read_audit(dir, None)?;
+    if entries.len() > 2 * MAX_AUDIT_ENTRIES {
+        let mut text = String::new();
+        for entry in &entries[entries.len() - MAX_AUDIT_ENTRIES..] {
+            let line = serde_json::to_string(entry)?;
+            text.push_str(&line);
+            text.push('\n');
+        }
+        crate::hosts::write_atomic(&path, &text)?;
+    }
+    Ok(())
+}
+
+// Audit entries, oldest first; with `limit`, only the newest ones
+pub fn read_audit(dir: &Path, limit: Option<usize>) -> Result<Vec<AuditEntry>> {
+    let path = audit_path(dir);
+    let text = match std::fs::read_to_string(&path) {
+        Ok(t) => t,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(Error::io(format!("failed to read {}", path.display()), e)),
+    };
+    let mut entries: Vec<AuditEntry> = text
+        .lines()
+        .filter_map(|l| serde_json::from_str(l).ok())
+        .collect();
+    if let Some(limit) = limit {
+        if entries.len() > limit {
+            entries.drain(..entries.len() - limit);
+        }
+    }
+    Ok(entries)
+}
+
+// A cluster of the policy's region as it is right now
+pub struct ClusterInfo {
+    pub cluster_id: String,
+    pub domain: String,
+    pub blocked: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterState {
+    // (at, rtt) inside the window
+    #[serde(skip)]
+    samples: VecDeque<(u64, Option<f64>)>,
+    pub over_since: Option<u64>,
+    pub under_since: Option<u64>,
+    // Set while the cluster is blocked because of the engine
+    pub auto_blocked_at: Option<u64>,
+    // A skip is audited once per streak, not every round
+    #[serde(skip)]
+    skip_reported: bool,
+    pub stats: Option<LatencyStats>,
+}
+
+pub struct Engine {
+    policy: Policy,
+    clusters: BTreeMap<String, ClusterState>,
+}
+
+impl Engine {
+    // `audit` restores which clusters the engine blocked before a restart
+    pub fn new(policy: Policy, audit: &[AuditEntry]) -> Engine {
+        let mut engine = Engine {
+            policy,
+            clusters: BTreeMap::new(),
+        };
+        let mut last: BTreeMap<&str, &AuditEntry> = BTreeMap::new();
+        for entry in audit.iter().filter(|e| {
+            e.region_id == engine.policy.region_id && e.success && e.action != Action::Skip
+        }) {
+            last.insert(&entry.cluster_id, entry);
+        }
+        for (cluster_id, entry) in last {
+            if entry.action == Action::Block {
+                engine
+                    .clusters
+                    .entry(cluster_id.to_string())
+                    .or_default()
+                    .auto_blocked_at = Some(entry.at);
+            }
+        }
+        engine
+    }
+
+    pub fn policy(&self) -> &Policy {
+        &self.policy
+    }
+
+    pub fn clusters(&self) -> &BTreeMap<String, ClusterState> {
+        &self.clusters
+    }
+
+    // Clusters the engine blocked and may unblock again
+    pub fn auto_blocked(&self) -> Vec<String> {
+        self.clusters
+            .iter()
+            .filter(|(_, s)| s.auto_blocked_at.is_some())
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    // Record one measurement per cluster taken at `at`
+    pub fn observe(&mut self, at: u64, samples: &[(String, Option<f64>)]) {
+        let window_start = at.saturating_sub(self.policy.window_secs * 1000);
+        for (cluster_id, rtt) in samples {
+            self.clusters
+                .entry(cluster_id.clone())
+                .or_default()
+                .samples
+                .push_back((at, *rtt));
+        }
+        for state in self.clusters.values_mut() {
+            while state
+                .samples
+                .front()
+                .map(|(t, _)| *t < window_start)
+                .unwrap_or(false)
+            {
+                state.samples.pop_front();
+            }
+        }
+    }
+
+    pub fn decide(&mut self, at: u64, clusters: &[ClusterInfo]) -> Vec<Decision> {
+        let policy = self.policy.clone();
+        let window_ms = policy.window_secs * 1000;
+        let mut open = clusters.iter().filter(|c| !c.blocked).count();
+        let mut to_block = Vec::new();
+        let mut decisions = Vec::new();
+
+        for info in clusters {
+            let state = self.clusters.entry(info.cluster_id.clone()).or_default();
+            // Unblocked by hand (or never blocked by us): not ours to manage
+            if !info.blocked {
+                state.auto_blocked_at = None;
+            }
+            if state.samples.len() < policy.min_samples as usize {
+                state.stats = None;
+                continue;
+            }
+            let samples: Vec<Option<f64>> = state.samples.iter().map(|(_, r)| *r).collect();
+            let stats = summarize(&samples);
+            let over = stats
+                .avg_ms
+                .map(|a| a > policy.block_latency_ms)
+                .unwrap_or(true)
+                || stats.loss_percent > policy.block_loss_percent;
+            let under = stats
+                .avg_ms
+                .map(|a| a <= policy.unblock_latency_ms)
+                .unwrap_or(false)
+                && stats.loss_percent <= policy.unblock_loss_percent;
+            state.over_since = if over {
+                state.over_since.or(Some(at))
+            } else {
+                None
+            };
+            state.under_since = if under {
+                state.under_since.or(Some(at))
+            } else {
+                None
+            };
+            if !over {
+                state.skip_reported = false;
+            }
+            state.stats = Some(stats.clone());
+
+            let held = |since: Option<u64>| {
+                since
+                    .map(|s| at.saturating_sub(s) >= window_ms)
+                    .unwrap_or(false)
+            };
+            if !info.blocked && held(state.over_since) {
+                to_block.push((info, stats));
+            } else if let Some(blocked_at) = state.auto_blocked_at {
+                let reason = if held(state.under_since) {
+                    Some(format!(
+                        "recovered: {} over the last {} s",
+                        describe(&stats),
+                        policy.window_secs
+                    ))
+                } else if policy.retry_after_secs > 0
+                    && at.saturating_sub(blocked_at) >= policy.retry_after_secs * 1000
+                {
+                    Some(format!(
+                        "probation after {} s blocked: {}",
+                        policy.retry_after_secs,
+                        describe(&stats)
+                    ))
+                } else {
+                    None
+                };
+                if let Some(reason) = reason {
+                    decisions.push(Decision {
+                        cluster_id: info.cluster_id.clone(),
+                        domain: info.domain.clone(),
+                        action: Action::Unblock,
+                        reason,
+                        stats,
+                    });
+                    open += 1;
+                }
+            }
+        }
+
+        // Worst first, so the best of the bad ones is what stays open
+        to_block.sort_by(|a, b| {
+            crate::probe::stats::compare_scores(b.1.score, a.1.score)
+                .then_with(|| a.0.cluster_id.cmp(&b.0.cluster_id))
+        });
+        for (info, stats) in to_block {
+            let state = self
+                .clusters
+                .get_mut(&info.cluster_id)
+                .expect("inserted above");
+            if open > policy.min_open {
+                open -= 1;
+                decisions.push(Decision {
+                    cluster_id: info.cluster_id.clone(),
+                    domain: info.domain.clone(),
+                    action: Action::Block,
+                    reason: format!(
+                        "{} for {} s (limits {} ms, {}% loss)",
+                        describe(&stats),
+                        policy.window_secs,
+                        policy.block_latency_ms,
+                        policy.block_loss_percent
+                    ),
+                    stats,
+                });
+            } else if !state.skip_reported {
+                state.skip_reported = true;
+                decisions.push(Decision {
+                    cluster_id: info.cluster_id.clone(),
+                    domain: info.domain.clone(),
+                    action: Action::Skip,
+                    reason: format!(
+                        "{}, but at least {} cluster(s) must stay open",
+                        describe(&stats),
+                        policy.min_open
+                    ),
+                    stats,
+                });
+            }
+        }
+        decisions
+    }
+
+    // Record that a decision was carried out
+    pub fn applied(&mut self, decision: &Decision, at: u64) {
+        let state = self
+            .clusters
+            .entry(decision.cluster_id.clone())
+            .or_default();
+        match decision.action {
+            Action::Block => {
+                state.auto_blocked_at = Some(at);
+                // Samples through the domain are meaningless once the hosts entry is in place
+                state.samples.clear();
+            }
+            Action::Unblock => {
+                state.auto_blocked_at = None;
+                state.samples.clear();
+            }
+            Action::Skip => return,
+        }
+        // Both streaks start over: the cluster has to earn the next action from scratch
+        state.over_since = None;
+        state.under_since = None;
+    }
+}
+
+fn describe(stats: &LatencyStats) -> String {
+    match stats.avg_ms {
+        Some(avg) => format!("avg {:.0} ms, {:.0}% loss", avg, stats.loss_percent),
+        None => "no answers".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> Policy {
+        Policy {
+            enabled: true,
+            region_id: "eu".into(),
+            window_secs: 10,
+            min_samples: 3,
+            retry_after_secs: 0,
+            ..Policy::default()
+        }
+    }
+
+    fn cluster(id: &str, blocked: bool) -> ClusterInfo {
+        ClusterInfo {
+            cluster_id: id.into(),
+            domain: format!("{}.example.com", id),
+            blocked,
+        }
+    }
+
+    // One sample per cluster every second in `from..to` (ms), carrying out every decision
+    fn run(
+        engine: &mut Engine,
+        clusters: &mut [ClusterInfo],
+        from: u64,
+        to: u64,
+        rtt: impl Fn(&str) -> Option<f64>,
+    ) -> Vec<(u64, Decision)> {
+        let mut log = Vec::new();
+        for at in (from..to).step_by(1000) {
+            let samples: Vec<(String, Option<f64>)> = clusters
+                .iter()
+                .map(|c| (c.cluster_id.clone(), rtt(&c.cluster_id)))
+                .collect();
+            engine.observe(at, &samples);
+            for decision in engine.decide(at, clusters) {
+                let info = clusters
+                    .iter_mut()
+                    .find(|c| c.cluster_id == decision.cluster_id)
+                    .unwrap();
+                match decision.action {
+                    Action::Block => info.blocked = true,
+                    Action::Unblock => info.blocked = false,
+                    Action::Skip => {}
+                }
+                engine.applied(&decision, at);
+                log.push((at, decision));
+            }
+        }
+        log
+    }
+
+    fn actions(log: &[(u64, Decision)]) -> Vec<(&str, Action)> {
+        log.iter()
+            .map(|(_, d)| (d.cluster_id.as_str(), d.action))
+            .collect()
+    }
+
+    fn entry(cluster_id: &str, action: Action, success: bool, at: u64) -> AuditEntry {
+        AuditEntry {
+            at,
+            region_id: "eu".into(),
+            cluster_id: cluster_id.into(),
+            domain: format!("{}.example.com", cluster_id),
+            action,
+            reason: String::new(),
+            avg_ms: None,
+            loss_percent: 0.0,
+            success,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn hysteresis_gap_neither_blocks_nor_unblocks() {
+        let mut engine = Engine::new(policy(), &[]);
+        let mut clusters = vec![cluster("a", false), cluster("b", false)];
+        let fast = |id: &str, ms: f64| if id == "a" { Some(ms) } else { Some(20.0) };
+
+        // Between the unblock and block thresholds
+        let log = run(&mut engine, &mut clusters, 0, 60_000, |id| fast(id, 120.0));
+        assert!(log.is_empty());
+
+        let log = run(&mut engine, &mut clusters, 60_000, 90_000, |id| {
+            fast(id, 200.0)
+        });
+        assert_eq!(actions(&log), vec![("a", Action::Block)]);
+        // Over for a whole window, not just once
+        assert!(log[0].0 >= 70_000);
+        assert!(clusters[0].blocked);
+
+        let log = run(&mut engine, &mut clusters, 90_000, 150_000, |id| {
+            fast(id, 120.0)
+        });
+        assert!(log.is_empty());
+        assert_eq!(engine.auto_blocked(), vec!["a".to_string()]);
+
+        let log = run(&mut engine, &mut clusters, 150_000, 180_000, |id| {
+            fast(id, 50.0)
+        });
+        assert_eq!(actions(&log), vec![("a", Action::Unblock)]);
+        assert!(log[0].1.reason.starts_with("recovered"));
+        assert!(engine.auto_blocked().is_empty());
+    }
+
+    #[test]
+    fn min_open_blocks_the_worst_cluster_first() {
+        let mut engine = Engine::new(
+            Policy {
+                min_open: 2,
+                ..policy()
+            },
+            &[],
+        );
+        let mut clusters = vec![
+            cluster("a", false),
+            cluster("b", false),
+            cluster("c", false),
+        ];
+        let log = run(&mut engine, &mut clusters, 0, 60_000, |id| match id {
+            "a" => Some(200.0),
+            "b" => Some(400.0),
+            _ => Some(20.0),
+        });
+
+        // Both go over in the same round; b is worse, and a skip is reported once per streak
+        assert_eq!(
+            actions(&log),
+            vec![("b", Action::Block), ("a", Action::Skip)]
+        );
+        assert_eq!(log[0].0, log[1].0);
+        assert!(!clusters[0].blocked);
+        assert!(clusters[1].blocked);
+    }
+
+    #[test]
+    fn probation_unblocks_after_retry_even_without_answers() {
+        let mut engine = Engine::new(
+            Policy {
+                retry_after_secs: 30,
+                ..policy()
+            },
+            &[],
+        );
+        let mut clusters = vec![cluster("a", false), cluster("b", false)];
+        let log = run(&mut engine, &mut clusters, 0, 20_000, |id| {
+            if id == "a" {
+                None
+            } else {
+                Some(20.0)
+            }
+        });
+        assert_eq!(actions(&log), vec![("a", Action::Block)]);
+        let blocked_at = log[0].0;
+
+        let log = run(&mut engine, &mut clusters, 20_000, 60_000, |id| {
+            if id == "a" {
+                None
+            } else {
+                Some(20.0)
+            }
+        });
+        // On probation the normal rules decide again, so it goes back once it has been over a
+        // whole window
+        assert_eq!(
+            actions(&log),
+            vec![("a", Action::Unblock), ("a", Action::Block)]
+        );
+        assert_eq!(log[0].0, blocked_at + 30_000);
+        assert!(log[0].1.reason.starts_with("probation"));
+    }
+
+    #[test]
+    fn restores_auto_blocked_clusters_from_the_audit_log() {
+        let audit = vec![
+            entry("a", Action::Block, true, 1_000),
+            entry("b", Action::Block, true, 1_000),
+            entry("b", Action::Unblock, true, 2_000),
+            entry("c", Action::Block, false, 1_000),
+            entry("a", Action::Skip, true, 3_000),
+            AuditEntry {
+                region_id: "us".into(),
+                ..entry("d", Action::Block, true, 1_000)
+            },
+        ];
+        let mut engine = Engine::new(policy(), &audit);
+        assert_eq!(engine.auto_blocked(), vec!["a".to_string()]);
+
+        // Only what the engine blocked itself is unblocked again, never d blocked by hand
+        let mut clusters = vec![cluster("a", true), cluster("d", true), cluster("e", false)];
+        let log = run(&mut engine, &mut clusters, 10_000, 40_000, |_| Some(20.0));
+        assert_eq!(actions(&log), vec![("a", Action::Unblock)]);
+        assert!(clusters[1].blocked);
+    }
+
+    #[test]
+    fn a_cluster_unblocked_by_hand_is_no_longer_ours() {
+        let audit = vec![entry("a", Action::Block, true, 1_000)];
+        let mut engine = Engine::new(policy(), &audit);
+        engine.decide(2_000, &[cluster("a", false)]);
+        assert!(engine.auto_blocked().is_empty());
+    }
+
+    #[test]
+    fn a_clock_going_back_does_not_panic() {
+        let audit = vec![entry("a", Action::Block, true, 100_000)];
+        let mut engine = Engine::new(
+            Policy {
+                retry_after_secs: 30,
+                ..policy()
+            },
+            &audit,
+        );
+        let mut clusters = vec![cluster("a", true), cluster("b", false)];
+        let log = run(&mut engine, &mut clusters, 0, 20_000, |id| {
+            if id == "a" {
+                None
+            } else {
+                Some(20.0)
+            }
+        });
+        assert!(log.is_empty());
+    }
+}
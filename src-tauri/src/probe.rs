@@ -0,0 +1,443 @@
+// In-process latency probes: ICMP echo, TCP connect and UDP echo round trips.
+//
+// The method comes from the cluster's `probe` entry in the catalog when there is one. Otherwise
+// ICMP is tried first and TCP on port 443 is used when this system doesn't allow ICMP sockets.
+
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+pub mod icmp;
+pub mod stats;
+pub mod trace;
+
+pub const DEFAULT_TCP_PORT: u16 = 443;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Method {
+    Icmp,
+    Tcp,
+    Udp,
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Icmp => "icmp",
+            Method::Tcp => "tcp",
+            Method::Udp => "udp",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Method> {
+        match s.trim().to_lowercase().as_str() {
+            "icmp" => Ok(Method::Icmp),
+            "tcp" => Ok(Method::Tcp),
+            "udp" => Ok(Method::Udp),
+            other => Err(Error::InvalidInput(format!(
+                "unknown probe method: {:?}",
+                other
+            ))),
+        }
+    }
+}
+
+// How to probe one cluster (the catalog's per-cluster `probe` entry)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProbeSpec {
+    pub method: Method,
+    // TCP defaults to 443; UDP has no sensible default and requires one
+    #[serde(default)]
+    pub port: Option<u16>,
+}
+
+impl ProbeSpec {
+    pub fn validate(&self) -> Result<()> {
+        if self.method == Method::Udp && self.port.is_none() {
+            return Err(Error::InvalidInput("udp probe needs a `port`".into()));
+        }
+        Ok(())
+    }
+}
+
+// Why a probe got no answer
+#[derive(Debug, Clone, PartialEq)]
+pub enum Failure {
+    Timeout,
+    Unreachable(String),
+    // The method can't be used here (e.g. no permission for ICMP sockets)
+    Unavailable(String),
+    // The domain resolves to a local address: the hosts file blocks it
+    Blocked(String),
+    Error(String),
+}
+
+impl Failure {
+    // Status string as reported to the UI by ping_server
+    pub fn status(&self) -> &'static str {
+        match self {
+            Failure::Timeout => "timeout",
+            Failure::Unreachable(_) => "unreachable",
+            Failure::Unavailable(_) => "unavailable",
+            Failure::Blocked(_) => "blocked",
+            Failure::Error(_) => "error",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Failure::Timeout => "timed out".into(),
+            Failure::Unreachable(m)
+            | Failure::Unavailable(m)
+            | Failure::Blocked(m)
+            | Failure::Error(m) => m.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProbeResult {
+    pub method: Method,
+    pub ip: Option<String>,
+    pub port: Option<u16>,
+    pub rtt_ms: Option<f64>,
+    pub status: String,
+    pub error: Option<String>,
+}
+
+impl ProbeResult {
+    fn new(method: Method, addr: Option<SocketAddr>, outcome: Result<Duration, Failure>) -> Self {
+        let port = addr.map(|a| a.port()).filter(|_| method != Method::Icmp);
+        let ip = addr.map(|a| a.ip().to_string());
+        match outcome {
+            Ok(rtt) => ProbeResult {
+                method,
+                ip,
+                port,
+                rtt_ms: Some(rtt.as_secs_f64() * 1000.0),
+                status: "ok".into(),
+                error: None,
+            },
+            Err(f) => ProbeResult {
+                method,
+                ip,
+                port,
+                rtt_ms: None,
+                status: f.status().into(),
+                error: Some(f.message()),
+            },
+        }
+    }
+}
+
+// A connection refused still means the SYN (or datagram) made the round trip
+fn connect_outcome(start: Instant, err: &std::io::Error) -> Result<Duration, Failure> {
+    match err.kind() {
+        std::io::ErrorKind::ConnectionRefused => Ok(start.elapsed()),
+        std::io::ErrorKind::TimedOut => Err(Failure::Timeout),
+        _ => Err(Failure::Unreachable(err.to_string())),
+    }
+}
+
+// Time from SYN to the connection being established (or refused)
+pub async fn tcp(addr: SocketAddr, timeout: Duration) -> Result<Duration, Failure> {
+    let start = Instant::now();
+    match tokio::time::timeout(timeout, tokio::net::TcpStream::connect(addr)).await {
+        Err(_) => Err(Failure::Timeout),
+        Ok(Ok(_stream)) => Ok(start.elapsed()),
+        Ok(Err(e)) => connect_outcome(start, &e),
+    }
+}
+
+// Time from sending a datagram to the first datagram coming back from `addr`
+pub async fn udp(addr: SocketAddr, timeout: Duration) -> Result<Duration, Failure> {
+    let bind: SocketAddr = if addr.is_ipv6() {
+        "[::]:0".parse().unwrap()
+    } else {
+        "0.0.0.0:0".parse().unwrap()
+    };
+    let socket = tokio::net::UdpSocket::bind(bind)
+        .await
+        .map_err(|e| Failure::Error(format!("failed to open UDP socket: {}", e)))?;
+    socket
+        .connect(addr)
+        .await
+        .map_err(|e| Failure::Unreachable(e.to_string()))?;
+
+    let start = Instant::now();
+    socket
+        .send(b"clusterbanned probe")
+        .await
+        .map_err(|e| Failure::Unreachable(e.to_string()))?;
+    let mut buf = [0u8; 512];
+    match tokio::time::timeout(timeout, socket.recv(&mut buf)).await {
+        Err(_) => Err(Failure::Timeout),
+        Ok(Ok(_)) => Ok(start.elapsed()),
+        Ok(Err(e)) => connect_outcome(start, &e),
+    }
+}
+
+// One probe of an address with a fixed method
+pub async fn probe_addr(method: Method, addr: SocketAddr, timeout: Duration) -> ProbeResult {
+    let outcome = match method {
+        Method::Icmp => icmp::ping(addr.ip(), timeout).await,
+        Method::Tcp => tcp(addr, timeout).await,
+        Method::Udp => udp(addr, timeout).await,
+    };
+    ProbeResult::new(method, Some(addr), outcome)
+}
+
+pub async fn resolve(host: &str) -> Result<IpAddr> {
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(ip);
+    }
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, 0))
+        .await
+        .map_err(|e| Error::Network(format!("could not resolve {}: {}", host, e)))?
+        .collect();
+    // IPv4 first: it's what the catalog and most networks use
+    addrs
+        .iter()
+        .find(|a| a.is_ipv4())
+        .or_else(|| addrs.first())
+        .map(|a| a.ip())
+        .ok_or_else(|| Error::Network(format!("could not resolve {}: no addresses", host)))
+}
+
+// A blocked domain is mapped to 0.0.0.0 in the hosts file, which the system answers itself (as
+// localhost on Linux). Probing that would measure this machine and rank the cluster first.
+pub fn blocked_answer(host: &str, ip: IpAddr) -> Option<Failure> {
+    if host.parse::<IpAddr>().is_ok() || !(ip.is_unspecified() || ip.is_loopback()) {
+        return None;
+    }
+    Some(Failure::Blocked(format!(
+        "{} resolves to {}, it is blocked in the hosts file",
+        host, ip
+    )))
+}
+
+// Probe `host` with `spec`, or with the default chain (ICMP, then TCP 443) when there is none
+pub async fn probe_host(host: &str, spec: Option<ProbeSpec>, timeout: Duration) -> ProbeResult {
+    let method = spec.map(|s| s.method).unwrap_or(Method::Icmp);
+    let ip = match resolve(host).await {
+        Ok(ip) => match blocked_answer(host, ip) {
+            Some(f) => return ProbeResult::new(method, None, Err(f)),
+            None => ip,
+        },
+        Err(e) => {
+            return ProbeResult {
+                method,
+                ip: None,
+                port: None,
+                rtt_ms: None,
+                status: "dns_error".into(),
+                error: Some(e.to_string()),
+            }
+        }
+    };
+    probe_ip(ip, spec, timeout).await
+}
+
+// Probe a catalog cluster at its first catalog IP, so hosts entries (our own blocks included)
+// don't change what is measured; through DNS when the catalog has none
+pub async fn probe_cluster(
+    domain: &str,
+    ip: Option<IpAddr>,
+    spec: Option<ProbeSpec>,
+    timeout: Duration,
+) -> ProbeResult {
+    match ip {
+        Some(ip) => probe_ip(ip, spec, timeout).await,
+        None => probe_host(domain, spec, timeout).await,
+    }
+}
+
+pub async fn probe_ip(ip: IpAddr, spec: Option<ProbeSpec>, timeout: Duration) -> ProbeResult {
+    match spec {
+        Some(spec) => {
+            let port = spec.port.unwrap_or(DEFAULT_TCP_PORT);
+            probe_addr(spec.method, SocketAddr::new(ip, port), timeout).await
+        }
+        None => {
+            let result = probe_addr(Method::Icmp, SocketAddr::new(ip, 0), timeout).await;
+            if result.status != "unavailable" {
+                return result;
+            }
+            probe_addr(Method::Tcp, SocketAddr::new(ip, DEFAULT_TCP_PORT), timeout).await
+        }
+    }
+}
+
+// Explicit method/port, else the probe of the catalog cluster with this domain
+pub async fn spec_for(
+    hostname: &str,
+    method: Option<String>,
+    port: Option<u16>,
+) -> Result<Option<ProbeSpec>> {
+    if let Some(method) = method {
+        let spec = ProbeSpec {
+            method: Method::parse(&method)?,
+            port,
+        };
+        spec.validate()?;
+        return Ok(Some(spec));
+    }
+    if port.is_some() {
+        return Ok(Some(ProbeSpec {
+            method: Method::Tcp,
+            port,
+        }));
+    }
+
+    Ok(crate::catalog::cluster_by_domain(hostname)
+        .await
+        .and_then(|(_, _, probe)| probe))
+}
+
+// One probe of `hostname` in the shape ping_server returns; probes of catalog clusters are
+// recorded in the latency history unless they measured nothing (see history::recordable)
+pub async fn ping(
+    hostname: &str,
+    timeout: Duration,
+    port: Option<u16>,
+    method: Option<String>,
+) -> Result<serde_json::Value> {
+    let spec = spec_for(hostname, method, port).await?;
+
+    let start = Instant::now();
+    let result = probe_host(hostname, spec, timeout).await;
+    let elapsed = start.elapsed().as_millis() as u64;
+
+    let cluster = if crate::history::recordable(&result) {
+        crate::catalog::cluster_by_domain(hostname).await
+    } else {
+        None
+    };
+    if let Some((region_id, cluster_id, _)) = cluster {
+        let record = crate::history::Record {
+            at: crate::history::now_ms(),
+            region_id,
+            cluster_id,
+            method: result.method,
+            rtt_ms: result.rtt_ms,
+            status: result.status.clone(),
+            source: crate::history::Source::Ping,
+        };
+        if let Err(e) = crate::settings::config_dir().and_then(|dir| {
+            crate::history::append(&dir, &[record], crate::settings::history_retention())
+        }) {
+            eprintln!("[TAURI] ping_server: failed to record history: {}", e);
+        }
+    }
+
+    Ok(serde_json::json!({
+        "ping": result.rtt_ms.map(|ms| ms.round() as u64),
+        "rtt_ms": result.rtt_ms,
+        "status": result.status,
+        "method": result.method.as_str(),
+        "ip": result.ip,
+        "port": result.port,
+        "error": result.error,
+        "elapsed_ms": elapsed
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    // A local port nothing listens on
+    fn closed_port() -> SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    #[tokio::test]
+    async fn tcp_connect_and_refused() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        assert!(tcp(addr, TIMEOUT).await.is_ok());
+        // A refusal is an answer too
+        assert!(tcp(closed_port(), TIMEOUT).await.is_ok());
+
+        let spec = ProbeSpec {
+            method: Method::Tcp,
+            port: Some(addr.port()),
+        };
+        let result = probe_ip(addr.ip(), Some(spec), TIMEOUT).await;
+        assert_eq!(result.status, "ok");
+        assert_eq!(result.port, Some(addr.port()));
+        assert!(result.rtt_ms.is_some());
+    }
+
+    #[tokio::test]
+    async fn udp_echo_and_silence() {
+        let echo = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = echo.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            while let Ok((n, from)) = echo.recv_from(&mut buf).await {
+                let _ = echo.send_to(&buf[..n], from).await;
+            }
+        });
+        let spec = ProbeSpec {
+            method: Method::Udp,
+            port: Some(addr.port()),
+        };
+        let result = probe_ip(addr.ip(), Some(spec), TIMEOUT).await;
+        assert_eq!(result.status, "ok");
+        assert_eq!(result.method, Method::Udp);
+        assert_eq!(result.ip.as_deref(), Some("127.0.0.1"));
+
+        // Bound but never answering
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert_eq!(
+            udp(silent.local_addr().unwrap(), Duration::from_millis(200)).await,
+            Err(Failure::Timeout)
+        );
+    }
+
+    #[test]
+    fn blocked_answers() {
+        let zero: IpAddr = "0.0.0.0".parse().unwrap();
+        let loopback: IpAddr = "127.0.0.1".parse().unwrap();
+        let v6_loopback: IpAddr = "::1".parse().unwrap();
+        let public: IpAddr = "92.223.1.1".parse().unwrap();
+
+        for ip in [zero, loopback, v6_loopback] {
+            let failure = blocked_answer("login.example.com", ip).unwrap();
+            assert_eq!(failure.status(), "blocked");
+        }
+        assert_eq!(blocked_answer("login.example.com", public), None);
+        // Asking for a local address by IP is a deliberate probe of it
+        assert_eq!(blocked_answer("127.0.0.1", loopback), None);
+    }
+
+    #[tokio::test]
+    async fn probe_host_reports_blocked_domains() {
+        let spec = ProbeSpec {
+            method: Method::Tcp,
+            port: Some(closed_port().port()),
+        };
+        let result = probe_host("localhost", Some(spec), TIMEOUT).await;
+        assert_eq!(result.status, "blocked");
+        assert_eq!(result.ip, None);
+        assert_eq!(result.rtt_ms, None);
+    }
+
+    #[test]
+    fn specs() {
+        assert_eq!(Method::parse(" TCP ").unwrap(), Method::Tcp);
+        assert!(Method::parse("http").is_err());
+        let udp = ProbeSpec {
+            method: Method::Udp,
+            port: None,
+        };
+        assert!(udp.validate().is_err());
+    }
+}
@@ -0,0 +1,509 @@
+// ICMP echo without spawning `ping`.
+//
+// Unix: an unprivileged datagram ICMP socket (Linux with net.ipv4.ping_group_range covering our
+// group, macOS always), or a raw socket when running as root. Windows: IcmpSendEcho via winping,
+// which needs no privileges.
+//
+// `probe_ttl` sends a TTL-limited echo for path traces. On Unix that needs a raw socket (root or
+// CAP_NET_RAW), since Linux datagram ICMP sockets don't see the routers' Time Exceeded replies;
+// macOS datagram sockets do.
+
+use super::Failure;
+use std::net::IpAddr;
+use std::time::Duration;
+
+#[cfg(unix)]
+pub async fn ping(ip: IpAddr, timeout: Duration) -> Result<Duration, Failure> {
+    unix::ping(ip, timeout).await
+}
+
+#[cfg(windows)]
+pub async fn ping(ip: IpAddr, timeout: Duration) -> Result<Duration, Failure> {
+    let timeout_ms = timeout.as_millis().min(u32::MAX as u128) as u32;
+    tokio::task::spawn_blocking(move || {
+        let mut pinger = winping::Pinger::new()
+            .map_err(|e| Failure::Unavailable(format!("ICMP handle: {}", e)))?;
+        pinger.set_timeout(timeout_ms);
+        let mut buffer = winping::Buffer::new();
+        match pinger.send(ip, &mut buffer) {
+            Ok(rtt) => Ok(Duration::from_millis(rtt as u64)),
+            Err(e) => {
+                let msg = e.to_string();
+                if msg.to_lowercase().contains("timed out") {
+                    Err(Failure::Timeout)
+                } else {
+                    Err(Failure::Unreachable(msg))
+                }
+            }
+        }
+    })
+    .await
+    .map_err(|e| Failure::Error(format!("ICMP task failed: {}", e)))?
+}
+
+#[cfg(not(any(unix, windows)))]
+pub async fn ping(_ip: IpAddr, _timeout: Duration) -> Result<Duration, Failure> {
+    Err(Failure::Unavailable(
+        "ICMP is not supported on this platform".into(),
+    ))
+}
+
+// Who answered a TTL-limited echo request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HopReply {
+    // A router on the way: the TTL ran out there
+    Router(IpAddr),
+    // The target itself
+    Target,
+    // Someone reported the target unreachable
+    Unreachable(IpAddr),
+}
+
+// Whether `probe_ttl` can work here at all, checked by opening (and closing) its socket
+#[cfg(unix)]
+pub fn trace_available(v6: bool) -> Result<(), Failure> {
+    unix::open_for_trace(v6).map(drop)
+}
+
+#[cfg(windows)]
+pub fn trace_available(_v6: bool) -> Result<(), Failure> {
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn trace_available(_v6: bool) -> Result<(), Failure> {
+    Err(Failure::Unavailable(
+        "ICMP is not supported on this platform".into(),
+    ))
+}
+
+#[cfg(unix)]
+pub async fn probe_ttl(
+    ip: IpAddr,
+    ttl: u8,
+    timeout: Duration,
+) -> Result<(HopReply, Duration), Failure> {
+    unix::probe_ttl(ip, ttl, timeout).await
+}
+
+#[cfg(windows)]
+pub async fn probe_ttl(
+    ip: IpAddr,
+    ttl: u8,
+    timeout: Duration,
+) -> Result<(HopReply, Duration), Failure> {
+    let timeout_ms = timeout.as_millis().min(u32::MAX as u128) as u32;
+    tokio::task::spawn_blocking(move || {
+        let mut pinger = winping::Pinger::new()
+            .map_err(|e| Failure::Unavailable(format!("ICMP handle: {}", e)))?;
+        pinger.set_timeout(timeout_ms);
+        pinger.set_ttl(ttl);
+        let mut buffer = winping::Buffer::new();
+        let start = std::time::Instant::now();
+        let responder = |buffer: &winping::Buffer| -> Option<IpAddr> {
+            match ip {
+                IpAddr::V4(_) => buffer.responding_ipv4().map(IpAddr::V4),
+                IpAddr::V6(_) => buffer.responding_ipv6().map(IpAddr::V6),
+            }
+        };
+        match pinger.send(ip, &mut buffer) {
+            Ok(rtt) => Ok((HopReply::Target, Duration::from_millis(rtt as u64))),
+            Err(winping::Error::TtlExpired) => match responder(&buffer) {
+                Some(from) => Ok((HopReply::Router(from), start.elapsed())),
+                None => Err(Failure::Error(
+                    "TTL expired, but no responder address".into(),
+                )),
+            },
+            Err(e) => {
+                let msg = e.to_string();
+                if msg.to_lowercase().contains("timed out") {
+                    Err(Failure::Timeout)
+                } else {
+                    match responder(&buffer) {
+                        Some(from) => Ok((HopReply::Unreachable(from), start.elapsed())),
+                        None => Err(Failure::Unreachable(msg)),
+                    }
+                }
+            }
+        }
+    })
+    .await
+    .map_err(|e| Failure::Error(format!("ICMP task failed: {}", e)))?
+}
+
+#[cfg(not(any(unix, windows)))]
+pub async fn probe_ttl(
+    _ip: IpAddr,
+    _ttl: u8,
+    _timeout: Duration,
+) -> Result<(HopReply, Duration), Failure> {
+    Err(Failure::Unavailable(
+        "ICMP is not supported on this platform".into(),
+    ))
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::{Failure, HopReply};
+    use socket2::{Domain, Protocol, Socket, Type};
+    use std::net::{IpAddr, SocketAddr};
+    use std::sync::atomic::{AtomicU16, Ordering};
+    use std::time::{Duration, Instant};
+
+    const ECHO_REQUEST_V4: u8 = 8;
+    const ECHO_REPLY_V4: u8 = 0;
+    const ECHO_REQUEST_V6: u8 = 128;
+    const ECHO_REPLY_V6: u8 = 129;
+    const TIME_EXCEEDED_V4: u8 = 11;
+    const UNREACHABLE_V4: u8 = 3;
+    const TIME_EXCEEDED_V6: u8 = 3;
+    const UNREACHABLE_V6: u8 = 1;
+
+    fn next_sequence() -> u16 {
+        static SEQ: AtomicU16 = AtomicU16::new(1);
+        SEQ.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn checksum(data: &[u8]) -> u16 {
+        let mut sum: u32 = data
+            .chunks(2)
+            .map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]) as u32)
+            .sum();
+        while sum >> 16 != 0 {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        !(sum as u16)
+    }
+
+    // Echo request; identifier and sequence both carry `seq`, the payload carries a token
+    // (datagram sockets on Linux replace the identifier with their own)
+    fn echo_request(v6: bool, seq: u16, token: &[u8; 8]) -> Vec<u8> {
+        let mut packet = vec![if v6 { ECHO_REQUEST_V6 } else { ECHO_REQUEST_V4 }, 0, 0, 0];
+        packet.extend_from_slice(&seq.to_be_bytes());
+        packet.extend_from_slice(&seq.to_be_bytes());
+        packet.extend_from_slice(token);
+        packet.extend_from_slice(&[0x42; 24]);
+        if !v6 {
+            // ICMPv6 checksums cover a pseudo-header; the kernel fills those in
+            let sum = checksum(&packet);
+            packet[2..4].copy_from_slice(&sum.to_be_bytes());
+        }
+        packet
+    }
+
+    // The ICMP message in `buf`: raw IPv4 sockets (and datagram ones on macOS) deliver the IP
+    // header too
+    fn icmp_message(v6: bool, buf: &[u8]) -> &[u8] {
+        if !v6 && buf.len() >= 20 && buf[0] >> 4 == 4 {
+            &buf[((buf[0] & 0x0f) as usize) * 4..]
+        } else {
+            buf
+        }
+    }
+
+    // Whether `buf` is the reply to our request
+    fn is_reply(v6: bool, buf: &[u8], seq: u16, token: &[u8; 8]) -> bool {
+        let icmp = icmp_message(v6, buf);
+        icmp.len() >= 16
+            && icmp[0] == if v6 { ECHO_REPLY_V6 } else { ECHO_REPLY_V4 }
+            && icmp[6..8] == seq.to_be_bytes()
+            && &icmp[8..16] == token
+    }
+
+    // Whether `buf` is a Time Exceeded or Unreachable error about our request to `target`; the
+    // error quotes the IP header and at least the first 8 bytes of the request
+    fn hop_error(v6: bool, target: IpAddr, from: IpAddr, buf: &[u8], seq: u16) -> Option<HopReply> {
+        let icmp = icmp_message(v6, buf);
+        if icmp.len() < 8 {
+            return None;
+        }
+        let (time_exceeded, unreachable, echo_request) = if v6 {
+            (TIME_EXCEEDED_V6, UNREACHABLE_V6, ECHO_REQUEST_V6)
+        } else {
+            (TIME_EXCEEDED_V4, UNREACHABLE_V4, ECHO_REQUEST_V4)
+        };
+        if icmp[0] != time_exceeded && icmp[0] != unreachable {
+            return None;
+        }
+        let quoted = &icmp[8..];
+        let (quoted_dst, request): (IpAddr, &[u8]) = match target {
+            IpAddr::V4(_) => {
+                if quoted.len() < 20 || quoted[0] >> 4 != 4 {
+                    return None;
+                }
+                let ihl = ((quoted[0] & 0x0f) as usize) * 4;
+                let dst: [u8; 4] = quoted[16..20].try_into().ok()?;
+                (IpAddr::from(dst), quoted.get(ihl..)?)
+            }
+            IpAddr::V6(_) => {
+                if quoted.len() < 40 {
+                    return None;
+                }
+                let dst: [u8; 16] = quoted[24..40].try_into().ok()?;
+                (IpAddr::from(dst), &quoted[40..])
+            }
+        };
+        if quoted_dst != target
+            || request.len() < 8
+            || request[0] != echo_request
+            || request[6..8] != seq.to_be_bytes()
+        {
+            return None;
+        }
+        Some(if icmp[0] == time_exceeded {
+            HopReply::Router(from)
+        } else {
+            HopReply::Unreachable(from)
+        })
+    }
+
+    fn open(v6: bool) -> Result<Socket, Failure> {
+        let (domain, protocol) = if v6 {
+            (Domain::IPV6, Protocol::ICMPV6)
+        } else {
+            (Domain::IPV4, Protocol::ICMPV4)
+        };
+        Socket::new(domain, Type::DGRAM, Some(protocol))
+            .or_else(|dgram_err| {
+                Socket::new(domain, Type::RAW, Some(protocol)).map_err(|_| dgram_err)
+            })
+            .map_err(|e| Failure::Unavailable(format!("ICMP socket not permitted: {}", e)))
+    }
+
+    // Socket for path traces: raw, or datagram on macOS where those see ICMP errors too
+    pub(super) fn open_for_trace(v6: bool) -> Result<Socket, Failure> {
+        let (domain, protocol) = if v6 {
+            (Domain::IPV6, Protocol::ICMPV6)
+        } else {
+            (Domain::IPV4, Protocol::ICMPV4)
+        };
+        Socket::new(domain, Type::RAW, Some(protocol))
+            .or_else(|raw_err| {
+                if cfg!(target_os = "macos") {
+                    Socket::new(domain, Type::DGRAM, Some(protocol)).map_err(|_| raw_err)
+                } else {
+                    Err(raw_err)
+                }
+            })
+            .map_err(|e| {
+                Failure::Unavailable(format!(
+                    "path trace needs a raw ICMP socket (run elevated): {}",
+                    e
+                ))
+            })
+    }
+
+    // Datagram-style send/recv is all we need, so reuse tokio's UDP socket plumbing
+    fn into_tokio(socket: Socket) -> Result<tokio::net::UdpSocket, Failure> {
+        socket
+            .set_nonblocking(true)
+            .map_err(|e| Failure::Error(e.to_string()))?;
+        let std_socket: std::net::UdpSocket = socket.into();
+        tokio::net::UdpSocket::from_std(std_socket).map_err(|e| Failure::Error(e.to_string()))
+    }
+
+    fn new_token(seq: u16) -> [u8; 8] {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        (nanos ^ ((seq as u64) << 48)).to_be_bytes()
+    }
+
+    pub async fn probe_ttl(
+        ip: IpAddr,
+        ttl: u8,
+        timeout: Duration,
+    ) -> Result<(HopReply, Duration), Failure> {
+        let v6 = ip.is_ipv6();
+        let socket = open_for_trace(v6)?;
+        if v6 {
+            socket.set_unicast_hops_v6(ttl as u32)
+        } else {
+            socket.set_ttl(ttl as u32)
+        }
+        .map_err(|e| Failure::Error(format!("failed to set TTL: {}", e)))?;
+        let socket = into_tokio(socket)?;
+
+        let seq = next_sequence();
+        let token = new_token(seq);
+        let packet = echo_request(v6, seq, &token);
+
+        let start = Instant::now();
+        socket
+            .send_to(&packet, SocketAddr::new(ip, 0))
+            .await
+            .map_err(|e| Failure::Unreachable(e.to_string()))?;
+
+        // A raw socket sees every ICMP message for this host; pick out the ones about our request
+        let mut buf = [0u8; 1500];
+        let wait = async {
+            loop {
+                let (n, from) = socket
+                    .recv_from(&mut buf)
+                    .await
+                    .map_err(|e| Failure::Unreachable(e.to_string()))?;
+                if from.ip() == ip && is_reply(v6, &buf[..n], seq, &token) {
+                    return Ok((HopReply::Target, start.elapsed()));
+                }
+                if let Some(reply) = hop_error(v6, ip, from.ip(), &buf[..n], seq) {
+                    return Ok((reply, start.elapsed()));
+                }
+            }
+        };
+        tokio::time::timeout(timeout, wait)
+            .await
+            .map_err(|_| Failure::Timeout)?
+    }
+
+    pub async fn ping(ip: IpAddr, timeout: Duration) -> Result<Duration, Failure> {
+        let v6 = ip.is_ipv6();
+        let socket = into_tokio(open(v6)?)?;
+
+        let seq = next_sequence();
+        let token = new_token(seq);
+        let packet = echo_request(v6, seq, &token);
+
+        let start = Instant::now();
+        socket
+            .send_to(&packet, SocketAddr::new(ip, 0))
+            .await
+            .map_err(|e| Failure::Unreachable(e.to_string()))?;
+
+        let mut buf = [0u8; 1500];
+        let wait = async {
+            loop {
+                let (n, from) = socket
+                    .recv_from(&mut buf)
+                    .await
+                    .map_err(|e| Failure::Unreachable(e.to_string()))?;
+                if from.ip() == ip && is_reply(v6, &buf[..n], seq, &token) {
+                    return Ok(start.elapsed());
+                }
+            }
+        };
+        tokio::time::timeout(timeout, wait)
+            .await
+            .map_err(|_| Failure::Timeout)?
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const SEQ: u16 = 0x1234;
+
+        fn v4(s: &str) -> IpAddr {
+            s.parse().unwrap()
+        }
+
+        // IPv4 header (no options) from 10.0.0.2 to `dst`
+        fn ipv4_header(dst: [u8; 4]) -> Vec<u8> {
+            let mut header = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 1, 1, 0, 0, 10, 0, 0, 2];
+            header.extend_from_slice(&dst);
+            header
+        }
+
+        // ICMPv4 error of `kind` quoting our echo request to `dst`
+        fn error_v4(kind: u8, dst: [u8; 4], seq: u16) -> Vec<u8> {
+            let mut icmp = vec![kind, 0, 0, 0, 0, 0, 0, 0];
+            icmp.extend_from_slice(&ipv4_header(dst));
+            icmp.extend_from_slice(&echo_request(false, seq, &[7; 8])[..8]);
+            icmp
+        }
+
+        #[test]
+        fn time_exceeded_v4() {
+            let router = v4("192.0.2.1");
+            let target = v4("203.0.113.5");
+            let icmp = error_v4(TIME_EXCEEDED_V4, [203, 0, 113, 5], SEQ);
+            // Datagram sockets deliver the bare ICMP message
+            assert_eq!(
+                hop_error(false, target, router, &icmp, SEQ),
+                Some(HopReply::Router(router))
+            );
+            // Raw sockets prepend the IP header the error came in
+            let mut raw = ipv4_header([10, 0, 0, 2]);
+            raw.extend_from_slice(&icmp);
+            assert_eq!(
+                hop_error(false, target, router, &raw, SEQ),
+                Some(HopReply::Router(router))
+            );
+        }
+
+        #[test]
+        fn unreachable_v4() {
+            let router = v4("192.0.2.1");
+            let icmp = error_v4(UNREACHABLE_V4, [203, 0, 113, 5], SEQ);
+            assert_eq!(
+                hop_error(false, v4("203.0.113.5"), router, &icmp, SEQ),
+                Some(HopReply::Unreachable(router))
+            );
+        }
+
+        #[test]
+        fn foreign_errors_are_ignored() {
+            let router = v4("192.0.2.1");
+            let target = v4("203.0.113.5");
+            let icmp = error_v4(TIME_EXCEEDED_V4, [203, 0, 113, 5], SEQ);
+            // Someone else's request: other sequence or other destination
+            assert_eq!(hop_error(false, target, router, &icmp, SEQ + 1), None);
+            assert_eq!(
+                hop_error(false, v4("203.0.113.6"), router, &icmp, SEQ),
+                None
+            );
+            // Cut before the quoted request ends
+            assert_eq!(
+                hop_error(false, target, router, &icmp[..icmp.len() - 1], SEQ),
+                None
+            );
+            // Not an error message at all
+            let mut redirect = icmp.clone();
+            redirect[0] = 5;
+            assert_eq!(hop_error(false, target, router, &redirect, SEQ), None);
+        }
+
+        #[test]
+        fn errors_v6() {
+            let router: IpAddr = "2001:db8::1".parse().unwrap();
+            let target: IpAddr = "2001:db8:ffff::5".parse().unwrap();
+            let dst = match target {
+                IpAddr::V6(a) => a.octets(),
+                _ => unreachable!(),
+            };
+            for (kind, expected) in [
+                (TIME_EXCEEDED_V6, HopReply::Router(router)),
+                (UNREACHABLE_V6, HopReply::Unreachable(router)),
+            ] {
+                let mut icmp = vec![kind, 0, 0, 0, 0, 0, 0, 0];
+                // IPv6 header: version 6, next header ICMPv6, source unset, then destination
+                let mut header = vec![0x60, 0, 0, 0, 0, 8, 58, 64];
+                header.extend_from_slice(&[0; 16]);
+                header.extend_from_slice(&dst);
+                icmp.extend_from_slice(&header);
+                icmp.extend_from_slice(&echo_request(true, SEQ, &[7; 8])[..8]);
+                assert_eq!(hop_error(true, target, router, &icmp, SEQ), Some(expected));
+                assert_eq!(hop_error(true, target, router, &icmp[..40], SEQ), None);
+            }
+        }
+
+        #[test]
+        fn echo_reply_matching() {
+            let token = [1, 2, 3, 4, 5, 6, 7, 8];
+            let request = echo_request(false, SEQ, &token);
+            // A valid checksum sums to zero over the whole message
+            assert_eq!(checksum(&request), 0);
+
+            let mut reply = request.clone();
+            reply[0] = ECHO_REPLY_V4;
+            assert!(is_reply(false, &reply, SEQ, &token));
+            assert!(!is_reply(false, &reply, SEQ + 1, &token));
+            assert!(!is_reply(false, &reply, SEQ, &[0; 8]));
+            assert!(!is_reply(false, &request, SEQ, &token));
+
+            let mut raw = ipv4_header([10, 0, 0, 2]);
+            raw.extend_from_slice(&reply);
+            assert!(is_reply(false, &raw, SEQ, &token));
+        }
+    }
+}
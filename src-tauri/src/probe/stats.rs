@@ -0,0 +1,510 @@
+// Repeated probes of a cluster: summary statistics over the series, and the score clusters are
+// ranked by.
+
+use super::{Method, ProbeSpec};
+use crate::catalog::{Cluster, Game};
+use crate::error::{Error, Result};
+use serde::Serialize;
+use std::net::IpAddr;
+use std::time::Duration;
+
+pub const DEFAULT_COUNT: u32 = 10;
+pub const DEFAULT_INTERVAL_MS: u64 = 200;
+pub const DEFAULT_TIMEOUT_MS: u64 = 1000;
+// Keeps one call from running for minutes
+pub const MAX_COUNT: u32 = 100;
+
+// Every percentage point of loss costs as much as this many milliseconds of extra latency in the
+// score: one probe lost out of ten adds 100 ms
+const LOSS_PENALTY_MS: f64 = 10.0;
+// Jitter counts double: a stable 60 ms beats a 50 ms that swings by 20
+const JITTER_WEIGHT: f64 = 2.0;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencyStats {
+    pub sent: u32,
+    pub received: u32,
+    pub loss_percent: f64,
+    pub min_ms: Option<f64>,
+    pub avg_ms: Option<f64>,
+    pub median_ms: Option<f64>,
+    pub p95_ms: Option<f64>,
+    pub max_ms: Option<f64>,
+    // Mean absolute deviation from the average
+    pub jitter_ms: Option<f64>,
+    // Lower is better; None when nothing answered
+    pub score: Option<f64>,
+}
+
+// Nearest-rank percentile of sorted values
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn median(sorted: &[f64]) -> f64 {
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+        sorted[mid]
+    }
+}
+
+// `samples` holds one entry per probe sent, None for the lost ones
+pub fn summarize(samples: &[Option<f64>]) -> LatencyStats {
+    let mut rtts: Vec<f64> = samples.iter().flatten().copied().collect();
+    let sent = samples.len() as u32;
+    let received = rtts.len() as u32;
+    let loss_percent = if sent == 0 {
+        0.0
+    } else {
+        (sent - received) as f64 * 100.0 / sent as f64
+    };
+    let mut stats = LatencyStats {
+        sent,
+        received,
+        loss_percent,
+        ..Default::default()
+    };
+    if rtts.is_empty() {
+        return stats;
+    }
+
+    rtts.sort_by(|a, b| a.total_cmp(b));
+    let avg = rtts.iter().sum::<f64>() / rtts.len() as f64;
+    let jitter = rtts.iter().map(|r| (r - avg).abs()).sum::<f64>() / rtts.len() as f64;
+
+    stats.min_ms = rtts.first().copied();
+    stats.max_ms = rtts.last().copied();
+    stats.avg_ms = Some(avg);
+    stats.median_ms = Some(median(&rtts));
+    stats.p95_ms = Some(percentile(&rtts, 95.0));
+    stats.jitter_ms = Some(jitter);
+    stats.score = Some(avg + JITTER_WEIGHT * jitter + LOSS_PENALTY_MS * loss_percent);
+    stats
+}
+
+// Order for ranking: best score first, clusters that never answered last
+pub fn compare_scores(a: Option<f64>, b: Option<f64>) -> std::cmp::Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    }
+}
+
+pub struct Options {
+    pub count: u32,
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Options {
+    pub fn new(count: u32, interval_ms: u64, timeout_ms: u64) -> Result<Options> {
+        if count == 0 || count > MAX_COUNT {
+            return Err(Error::InvalidInput(format!(
+                "probe count must be between 1 and {}",
+                MAX_COUNT
+            )));
+        }
+        if timeout_ms == 0 {
+            return Err(Error::InvalidInput("probe timeout must be positive".into()));
+        }
+        Ok(Options {
+            count,
+            interval: Duration::from_millis(interval_ms),
+            timeout: Duration::from_millis(timeout_ms),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Measurement {
+    pub method: Method,
+    pub ip: Option<String>,
+    pub port: Option<u16>,
+    // Round trip of every probe in order, None for the lost ones
+    pub samples: Vec<Option<f64>>,
+    pub stats: LatencyStats,
+    // Distinct reasons for the lost probes
+    pub errors: Vec<String>,
+}
+
+fn push_error(errors: &mut Vec<String>, error: Option<String>) {
+    if let Some(e) = error {
+        if !errors.contains(&e) {
+            errors.push(e);
+        }
+    }
+}
+
+// `opts.count` probes of `ip`, `opts.interval` apart
+pub async fn measure_ip(ip: IpAddr, spec: Option<ProbeSpec>, opts: &Options) -> Measurement {
+    let mut spec = spec;
+    let mut samples = Vec::with_capacity(opts.count as usize);
+    let mut errors = Vec::new();
+    let mut last = None;
+    for i in 0..opts.count {
+        if i > 0 {
+            tokio::time::sleep(opts.interval).await;
+        }
+        let result = super::probe_ip(ip, spec, opts.timeout).await;
+        // Without a spec the first probe settles the method; don't retry ICMP every time
+        if spec.is_none() && result.method != Method::Icmp {
+            spec = Some(ProbeSpec {
+                method: result.method,
+                port: result.port,
+            });
+        }
+        samples.push(result.rtt_ms);
+        push_error(&mut errors, result.error.clone());
+        last = Some(result);
+    }
+    let (method, port) = last
+        .map(|r| (r.method, r.port))
+        .unwrap_or((Method::Icmp, None));
+    Measurement {
+        method,
+        ip: Some(ip.to_string()),
+        port,
+        stats: summarize(&samples),
+        samples,
+        errors,
+    }
+}
+
+// Every probe lost for `error`, without sending any
+fn unmeasured(spec: Option<ProbeSpec>, error: String, opts: &Options) -> Measurement {
+    let samples = vec![None; opts.count as usize];
+    Measurement {
+        method: spec.map(|s| s.method).unwrap_or(Method::Icmp),
+        ip: None,
+        port: None,
+        stats: summarize(&samples),
+        samples,
+        errors: vec![error],
+    }
+}
+
+// Resolve `host` once, then measure the address it resolved to. A domain blocked in the hosts
+// file is not measured at all.
+pub async fn measure_host(host: &str, spec: Option<ProbeSpec>, opts: &Options) -> Measurement {
+    match super::resolve(host).await {
+        Ok(ip) => match super::blocked_answer(host, ip) {
+            Some(f) => unmeasured(spec, f.message(), opts),
+            None => measure_ip(ip, spec, opts).await,
+        },
+        Err(e) => unmeasured(spec, e.to_string(), opts),
+    }
+}
+
+// The one address a cluster is measured at: its first catalog IP, or None to go through DNS.
+// measure_clusters and the monitor both use it, so a ranking, the monitor rounds and auto-block
+// all talk about the same server; measure_cluster_ips compares every IP of a cluster.
+pub fn catalog_ip(cluster: &Cluster) -> Option<IpAddr> {
+    cluster
+        .ips
+        .iter()
+        .find_map(|ip| ip.trim().parse::<IpAddr>().ok())
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterLatency {
+    pub region_id: String,
+    pub cluster_id: String,
+    pub domain: String,
+    // 1 is the best cluster of its region; None when it never answered
+    pub rank: Option<u32>,
+    // `ip` is the address measured, None when there was none in the catalog
+    #[serde(flatten)]
+    pub measurement: Measurement,
+}
+
+// Measure every cluster of `catalog` (or of one region) at once; best score first, ranked within
+// each region. Each cluster is measured at a single address (see catalog_ip), so its stats are
+// those of that IP, not of the cluster's other IPs.
+pub async fn measure_clusters(
+    catalog: &Game,
+    region_id: Option<&str>,
+    opts: Options,
+) -> Result<Vec<ClusterLatency>> {
+    let clusters: Vec<_> = catalog
+        .clusters()
+        .filter(|(r, _)| region_id.map(|id| r.id == id).unwrap_or(true))
+        .map(|(r, c)| {
+            let ip = catalog_ip(c);
+            (r.id.clone(), c.id.clone(), c.domain.clone(), ip, c.probe)
+        })
+        .collect();
+    if clusters.is_empty() {
+        return Err(Error::NotFound(match region_id {
+            Some(id) => format!("region {} not found in catalog", id),
+            None => "catalog has no clusters".into(),
+        }));
+    }
+
+    let opts = std::sync::Arc::new(opts);
+    let mut tasks = tokio::task::JoinSet::new();
+    for (region_id, cluster_id, domain, ip, spec) in clusters {
+        let opts = opts.clone();
+        tasks.spawn(async move {
+            let measurement = match ip {
+                Some(ip) => measure_ip(ip, spec, &opts).await,
+                None => measure_host(&domain, spec, &opts).await,
+            };
+            ClusterLatency {
+                region_id,
+                cluster_id,
+                domain,
+                rank: None,
+                measurement,
+            }
+        });
+    }
+    let mut results = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        results.push(joined.map_err(|e| Error::Other(format!("probe task failed: {}", e)))?);
+    }
+
+    rank(&mut results);
+    Ok(results)
+}
+
+fn rank(results: &mut [ClusterLatency]) {
+    results.sort_by(|a, b| {
+        compare_scores(a.measurement.stats.score, b.measurement.stats.score)
+            .then_with(|| a.cluster_id.cmp(&b.cluster_id))
+    });
+    let mut next: std::collections::HashMap<String, u32> = std::collections::HashMap::new();
+    for r in results.iter_mut() {
+        if r.measurement.stats.score.is_some() {
+            let n = next.entry(r.region_id.clone()).or_insert(0);
+            *n += 1;
+            r.rank = Some(*n);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterIpsLatency {
+    pub region_id: String,
+    pub cluster_id: String,
+    pub domain: String,
+    // One entry per catalog IP, in catalog order
+    pub ips: Vec<Measurement>,
+    pub aggregate: IpsAggregate,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IpsAggregate {
+    pub total_ips: u32,
+    // IPs that answered at least one probe
+    pub reachable_ips: u32,
+    // Reachable IP with the best score
+    pub best_ip: Option<String>,
+    // Over every probe of every IP
+    pub stats: LatencyStats,
+}
+
+fn aggregate(ips: &[Measurement]) -> IpsAggregate {
+    let all: Vec<Option<f64>> = ips.iter().flat_map(|m| m.samples.iter().copied()).collect();
+    let best_ip = ips
+        .iter()
+        .filter(|m| m.stats.score.is_some())
+        .min_by(|a, b| compare_scores(a.stats.score, b.stats.score))
+        .and_then(|m| m.ip.clone());
+    IpsAggregate {
+        total_ips: ips.len() as u32,
+        reachable_ips: ips.iter().filter(|m| m.stats.received > 0).count() as u32,
+        best_ip,
+        stats: summarize(&all),
+    }
+}
+
+// Probe every address in the cluster's catalog `ips` directly, bypassing DNS (and hosts
+// entries that point the domain elsewhere), all IPs at once
+pub async fn measure_cluster_ips(
+    region_id: &str,
+    cluster: &Cluster,
+    opts: Options,
+) -> Result<ClusterIpsLatency> {
+    if cluster.ips.is_empty() {
+        return Err(Error::NotFound(format!(
+            "cluster {} in region {} has no IPs in the catalog",
+            cluster.id, region_id
+        )));
+    }
+    let ips = cluster
+        .ips
+        .iter()
+        .map(|ip| {
+            ip.trim().parse::<IpAddr>().map_err(|_| {
+                Error::Parse(format!(
+                    "cluster {}: {:?} is not an IP address",
+                    cluster.id, ip
+                ))
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let opts = std::sync::Arc::new(opts);
+    let mut tasks = tokio::task::JoinSet::new();
+    for (i, ip) in ips.into_iter().enumerate() {
+        let (opts, spec) = (opts.clone(), cluster.probe);
+        tasks.spawn(async move { (i, measure_ip(ip, spec, &opts).await) });
+    }
+    let mut measured = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        measured.push(joined.map_err(|e| Error::Other(format!("probe task failed: {}", e)))?);
+    }
+    measured.sort_by_key(|(i, _)| *i);
+    let ips: Vec<Measurement> = measured.into_iter().map(|(_, m)| m).collect();
+
+    Ok(ClusterIpsLatency {
+        region_id: region_id.to_string(),
+        cluster_id: cluster.id.clone(),
+        domain: cluster.domain.clone(),
+        aggregate: aggregate(&ips),
+        ips,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cluster(region_id: &str, cluster_id: &str, samples: &[Option<f64>]) -> ClusterLatency {
+        ClusterLatency {
+            region_id: region_id.into(),
+            cluster_id: cluster_id.into(),
+            domain: format!("{}.example", cluster_id),
+            rank: None,
+            measurement: Measurement {
+                method: Method::Icmp,
+                ip: None,
+                port: None,
+                samples: samples.to_vec(),
+                stats: summarize(samples),
+                errors: Vec::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn clusters_are_measured_at_their_first_usable_ip() {
+        let with_ips = |ips: &[&str]| Cluster {
+            id: "c1".into(),
+            domain: "login.eu.example".into(),
+            location: None,
+            ips: ips.iter().map(|s| s.to_string()).collect(),
+            probe: None,
+        };
+        assert_eq!(
+            catalog_ip(&with_ips(&["bogus", " 10.0.0.2 ", "10.0.0.1"])),
+            Some(IpAddr::from([10, 0, 0, 2]))
+        );
+        assert_eq!(catalog_ip(&with_ips(&[])), None);
+    }
+
+    #[test]
+    fn percentile_is_nearest_rank() {
+        let sorted: Vec<f64> = (1..=20).map(f64::from).collect();
+        assert_eq!(percentile(&sorted, 95.0), 19.0);
+        assert_eq!(percentile(&sorted, 50.0), 10.0);
+        assert_eq!(percentile(&sorted, 100.0), 20.0);
+        // Rank 0 is clamped to the first value
+        assert_eq!(percentile(&sorted, 0.0), 1.0);
+        assert_eq!(percentile(&[7.0], 95.0), 7.0);
+    }
+
+    #[test]
+    fn median_of_odd_and_even_counts() {
+        assert_eq!(median(&[1.0, 2.0, 9.0]), 2.0);
+        assert_eq!(median(&[1.0, 2.0, 4.0, 9.0]), 3.0);
+        assert_eq!(median(&[5.0]), 5.0);
+    }
+
+    #[test]
+    fn summarizes_a_series_with_losses() {
+        let stats = summarize(&[Some(30.0), None, Some(10.0), Some(20.0)]);
+        assert_eq!(stats.sent, 4);
+        assert_eq!(stats.received, 3);
+        assert_eq!(stats.loss_percent, 25.0);
+        assert_eq!(stats.min_ms, Some(10.0));
+        assert_eq!(stats.max_ms, Some(30.0));
+        assert_eq!(stats.avg_ms, Some(20.0));
+        assert_eq!(stats.median_ms, Some(20.0));
+        assert_eq!(stats.p95_ms, Some(30.0));
+        let jitter = 20.0 / 3.0;
+        assert_eq!(stats.jitter_ms, Some(jitter));
+        // 25 % loss costs 250 ms
+        assert_eq!(stats.score, Some(20.0 + 2.0 * jitter + 250.0));
+    }
+
+    #[test]
+    fn summarizes_a_single_sample() {
+        let stats = summarize(&[Some(42.0)]);
+        assert_eq!(
+            (stats.sent, stats.received, stats.loss_percent),
+            (1, 1, 0.0)
+        );
+        assert_eq!(stats.min_ms, Some(42.0));
+        assert_eq!(stats.median_ms, Some(42.0));
+        assert_eq!(stats.p95_ms, Some(42.0));
+        assert_eq!(stats.jitter_ms, Some(0.0));
+        assert_eq!(stats.score, Some(42.0));
+    }
+
+    #[test]
+    fn summarizes_all_lost_and_nothing_sent() {
+        let lost = summarize(&[None, None, None]);
+        assert_eq!((lost.sent, lost.received, lost.loss_percent), (3, 0, 100.0));
+        assert_eq!(lost.avg_ms, None);
+        assert_eq!(lost.score, None);
+        assert_eq!(summarize(&[]), LatencyStats::default());
+    }
+
+    #[test]
+    fn compare_scores_puts_unanswered_last() {
+        use std::cmp::Ordering::*;
+        assert_eq!(compare_scores(Some(1.0), Some(2.0)), Less);
+        assert_eq!(compare_scores(Some(2.0), Some(1.0)), Greater);
+        assert_eq!(compare_scores(Some(500.0), None), Less);
+        assert_eq!(compare_scores(None, Some(500.0)), Greater);
+        assert_eq!(compare_scores(None, None), Equal);
+    }
+
+    #[test]
+    fn ranks_within_each_region_and_leaves_silent_clusters_unranked() {
+        let mut results = vec![
+            cluster("eu", "slow", &[Some(80.0)]),
+            cluster("eu", "down", &[None, None]),
+            cluster("na", "only", &[Some(120.0)]),
+            cluster("eu", "fast", &[Some(20.0)]),
+            // Faster when it answers, but half the probes are lost
+            cluster("eu", "lossy", &[Some(10.0), None]),
+        ];
+        rank(&mut results);
+        let order: Vec<(&str, Option<u32>)> = results
+            .iter()
+            .map(|r| (r.cluster_id.as_str(), r.rank))
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                ("fast", Some(1)),
+                ("slow", Some(2)),
+                ("only", Some(1)),
+                ("lossy", Some(3)),
+                ("down", None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,166 @@
+// Hop-by-hop path trace with TTL-limited ICMP echoes, reported like MTR: every round probes all
+// TTLs at once, and each hop gets the addresses that answered plus latency and loss over the
+// rounds.
+
+use super::icmp::{self, HopReply};
+use super::stats::{summarize, LatencyStats};
+use super::Failure;
+use crate::error::{Error, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::time::Duration;
+
+pub const DEFAULT_MAX_HOPS: u8 = 30;
+pub const DEFAULT_ROUNDS: u32 = 5;
+pub const DEFAULT_TIMEOUT_MS: u64 = 1500;
+const MAX_ROUNDS: u32 = 20;
+
+pub struct Options {
+    pub max_hops: u8,
+    pub rounds: u32,
+    pub timeout: Duration,
+}
+
+impl Options {
+    pub fn new(max_hops: u8, rounds: u32, timeout_ms: u64) -> Result<Options> {
+        if max_hops == 0 || max_hops > 64 {
+            return Err(Error::InvalidInput(
+                "max hops must be between 1 and 64".into(),
+            ));
+        }
+        if rounds == 0 || rounds > MAX_ROUNDS {
+            return Err(Error::InvalidInput(format!(
+                "rounds must be between 1 and {}",
+                MAX_ROUNDS
+            )));
+        }
+        if timeout_ms == 0 {
+            return Err(Error::InvalidInput("probe timeout must be positive".into()));
+        }
+        Ok(Options {
+            max_hops,
+            rounds,
+            timeout: Duration::from_millis(timeout_ms),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Hop {
+    pub ttl: u8,
+    // Addresses that answered at this TTL; more than one means load-balanced paths
+    pub hosts: Vec<String>,
+    // Someone reported the target unreachable at this hop
+    pub unreachable: bool,
+    #[serde(flatten)]
+    pub stats: LatencyStats,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Trace {
+    pub ip: String,
+    pub reached: bool,
+    pub hops: Vec<Hop>,
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+struct HopSamples {
+    samples: Vec<Option<f64>>,
+    hosts: Vec<String>,
+    reached: bool,
+    unreachable: bool,
+}
+
+pub async fn trace(ip: IpAddr, opts: &Options) -> Trace {
+    // Without a raw ICMP socket every probe would fail the same way
+    if let Err(f) = icmp::trace_available(ip.is_ipv6()) {
+        return failed(ip, f.message());
+    }
+    let mut by_ttl: BTreeMap<u8, HopSamples> = BTreeMap::new();
+    // Lowest TTL the target (or an unreachable report) came back from; no need to go further
+    let mut last_ttl = opts.max_hops;
+
+    for _ in 0..opts.rounds {
+        let mut tasks = tokio::task::JoinSet::new();
+        for ttl in 1..=last_ttl {
+            let timeout = opts.timeout;
+            tasks.spawn(async move { (ttl, icmp::probe_ttl(ip, ttl, timeout).await) });
+        }
+        while let Some(joined) = tasks.join_next().await {
+            let (ttl, outcome) = match joined {
+                Ok(r) => r,
+                Err(e) => return failed(ip, format!("probe task failed: {}", e)),
+            };
+            let hop = by_ttl.entry(ttl).or_default();
+            match outcome {
+                Ok((reply, rtt)) => {
+                    hop.samples.push(Some(rtt.as_secs_f64() * 1000.0));
+                    let host = match reply {
+                        HopReply::Router(from) => from,
+                        HopReply::Target => {
+                            hop.reached = true;
+                            ip
+                        }
+                        HopReply::Unreachable(from) => {
+                            hop.unreachable = true;
+                            from
+                        }
+                    }
+                    .to_string();
+                    if !hop.hosts.contains(&host) {
+                        hop.hosts.push(host);
+                    }
+                }
+                // ICMP sockets aren't available here: no point in the remaining probes
+                Err(f @ Failure::Unavailable(_)) => return failed(ip, f.message()),
+                Err(_) => hop.samples.push(None),
+            }
+        }
+        if let Some((&ttl, _)) = by_ttl.iter().find(|(_, h)| h.reached || h.unreachable) {
+            last_ttl = last_ttl.min(ttl);
+        }
+    }
+
+    let reached = by_ttl.values().any(|h| h.reached);
+    // Without the target, stop after the last hop that answered and one silent hop after it
+    let answered_up_to = by_ttl
+        .iter()
+        .filter(|(_, h)| !h.hosts.is_empty())
+        .map(|(&ttl, _)| ttl)
+        .max()
+        .unwrap_or(0);
+    let shown_up_to = if reached {
+        last_ttl
+    } else {
+        answered_up_to.saturating_add(1).min(last_ttl)
+    };
+    let hops = by_ttl
+        .into_iter()
+        .filter(|(ttl, _)| *ttl <= shown_up_to)
+        .map(|(ttl, h)| Hop {
+            ttl,
+            hosts: h.hosts,
+            unreachable: h.unreachable,
+            stats: summarize(&h.samples),
+        })
+        .collect();
+    Trace {
+        ip: ip.to_string(),
+        reached,
+        hops,
+        error: None,
+    }
+}
+
+fn failed(ip: IpAddr, error: String) -> Trace {
+    Trace {
+        ip: ip.to_string(),
+        reached: false,
+        hops: Vec::new(),
+        error: Some(error),
+    }
+}
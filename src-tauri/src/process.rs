@@ -0,0 +1,125 @@
+// The game's process: finding it, stopping it and starting it through Steam.
+
+use crate::error::{Error, Result};
+use sysinfo::{PidExt, ProcessExt, SystemExt};
+
+#[cfg(windows)]
+use std::os::windows::process::CommandExt;
+#[cfg(windows)]
+const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+// Names of the game's process, as the frontend looks for them
+pub const GAME_PROCESS_NAMES: [&str; 3] = ["wotblitz", "blitz", "worldoftanksblitz"];
+
+pub fn game_process_names() -> Vec<String> {
+    GAME_PROCESS_NAMES.iter().map(|n| n.to_string()).collect()
+}
+
+// PIDs of processes whose name (or executable in the cmd line) contains one of `names`. Never
+// this process: its own path may well contain "blitz".
+pub fn matching_pids(names: &[String]) -> Vec<u32> {
+    let mut sys = sysinfo::System::new_all();
+    sys.refresh_processes();
+    let needles: Vec<String> = names.iter().map(|n| n.to_lowercase()).collect();
+    let own = std::process::id();
+    let mut pids = Vec::new();
+    for (pid, process) in sys.processes() {
+        if pid.as_u32() == own {
+            continue;
+        }
+        let process_name = process.name().to_lowercase();
+        // Also check cmd line
+        let cmd = process
+            .cmd()
+            .first()
+            .map(|c| c.to_lowercase())
+            .unwrap_or_default();
+        if needles
+            .iter()
+            .any(|n| process_name.contains(n) || cmd.contains(n))
+        {
+            pids.push(pid.as_u32());
+        }
+    }
+    pids.sort_unstable();
+    pids
+}
+
+pub fn is_running(name: &str) -> bool {
+    !matching_pids(&[name.to_string()]).is_empty()
+}
+
+// Kill processes whose name or cmd line contains `name` (best-effort), never this one; how many
+// were killed
+pub fn kill(name: &str) -> usize {
+    let mut sys = sysinfo::System::new_all();
+    sys.refresh_processes();
+    let needle = name.to_lowercase();
+    let own = std::process::id();
+    let mut killed = 0usize;
+
+    for (pid, process) in sys.processes() {
+        if pid.as_u32() == own {
+            continue;
+        }
+        if process.name().to_lowercase().contains(&needle)
+            || process
+                .cmd()
+                .iter()
+                .any(|s| s.to_lowercase().contains(&needle))
+        {
+            let pid_u = pid.as_u32();
+            #[cfg(target_os = "windows")]
+            let status = std::process::Command::new("taskkill")
+                .args(["/PID", &pid_u.to_string(), "/F"])
+                .creation_flags(CREATE_NO_WINDOW)
+                .status();
+
+            #[cfg(not(target_os = "windows"))]
+            let status = std::process::Command::new("kill")
+                .arg("-TERM")
+                .arg(pid_u.to_string())
+                .status();
+
+            if status.map(|st| st.success()).unwrap_or(false) {
+                killed += 1;
+            }
+        }
+    }
+    killed
+}
+
+// Launch a game via protocol (steam://rungameid/<id>) with the platform's URL opener
+pub fn launch(appid: &str) -> Result<String> {
+    let uri = format!("steam://rungameid/{}", appid);
+
+    // cmd start honors URL schemes
+    #[cfg(target_os = "windows")]
+    let res = std::process::Command::new("cmd")
+        .args(["/C", "start", "", &uri])
+        .creation_flags(CREATE_NO_WINDOW)
+        .spawn();
+
+    #[cfg(target_os = "macos")]
+    let res = std::process::Command::new("open").arg(&uri).spawn();
+
+    #[cfg(all(not(target_os = "macos"), not(target_os = "windows")))]
+    let res = std::process::Command::new("xdg-open").arg(&uri).spawn();
+
+    match res {
+        Ok(_child) => Ok(uri),
+        Err(e) => Err(Error::io(format!("failed to launch {}", uri), e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn never_matches_this_process() {
+        let exe = std::env::current_exe().unwrap();
+        let name = exe.file_stem().unwrap().to_string_lossy().to_string();
+        assert!(!matching_pids(&[name]).contains(&std::process::id()));
+    }
+}
@@ -0,0 +1,162 @@
+// App settings: settings.json in the config directory, merged over the defaults below. The
+// frontend keeps its own copy of the defaults (src/utils/settingsStorage.ts).
+
+use crate::error::{Error, Result};
+use serde_json::Value;
+use std::path::PathBuf;
+
+pub fn defaults() -> Value {
+    serde_json::json!({
+        "useFirewall": true,
+        "useBackup": false,
+        "backupCount": 5,
+        "offlineMode": false,
+        "catalogTtlMinutes": 360,
+        "historyRetentionDays": 30,
+        "historyMaxRecords": 200000
+    })
+}
+
+pub fn path() -> Result<PathBuf> {
+    #[cfg(windows)]
+    {
+        let appdata = std::env::var("APPDATA")
+            .map_err(|_| Error::NotFound("APPDATA environment variable not found".into()))?;
+        Ok(PathBuf::from(appdata)
+            .join("clusterbanned")
+            .join("settings.json"))
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        let home = std::env::var("HOME")
+            .map_err(|_| Error::NotFound("HOME environment variable not found".into()))?;
+        Ok(PathBuf::from(home)
+            .join("Library")
+            .join("Application Support")
+            .join("clusterbanned")
+            .join("settings.json"))
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    {
+        let home = std::env::var("HOME")
+            .map_err(|_| Error::NotFound("HOME environment variable not found".into()))?;
+        Ok(PathBuf::from(home)
+            .join(".config")
+            .join("clusterbanned")
+            .join("settings.json"))
+    }
+}
+
+// The settings directory; the servers.json cache, the user overlay and history live there too
+pub fn config_dir() -> Result<PathBuf> {
+    path()?
+        .parent()
+        .map(|p| p.to_path_buf())
+        .ok_or_else(|| Error::NotFound("settings path has no parent directory".into()))
+}
+
+// Saved settings over the defaults; just the defaults when nothing was saved yet
+pub fn load() -> Result<Value> {
+    let settings_path = path()?;
+    let mut result = defaults();
+    if !settings_path.exists() {
+        return Ok(result);
+    }
+
+    let content = std::fs::read_to_string(&settings_path)
+        .map_err(|e| Error::io("Failed to read settings file", e))?;
+    let settings: Value = serde_json::from_str(&content)
+        .map_err(|e| Error::Parse(format!("Failed to parse settings JSON: {}", e)))?;
+
+    if let Value::Object(map) = settings {
+        for (key, value) in map {
+            result[key] = value;
+        }
+    }
+    Ok(result)
+}
+
+pub fn save(settings: &Value) -> Result<()> {
+    let settings_path = path()?;
+    if let Some(parent) = settings_path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| Error::io("Failed to create settings directory", e))?;
+    }
+    let json_string = serde_json::to_string_pretty(settings)
+        .map_err(|e| Error::Parse(format!("Failed to serialize settings: {}", e)))?;
+    std::fs::write(&settings_path, json_string)
+        .map_err(|e| Error::io("Failed to write settings file", e))
+}
+
+// Settings that can't be read count as defaults: blocking must keep working without them
+fn load_or_defaults() -> Value {
+    load().unwrap_or_else(|_| defaults())
+}
+
+// (useFirewall, useBackup, backupCount)
+pub fn blocking() -> (bool, bool, u32) {
+    let settings = load_or_defaults();
+    let use_firewall = settings
+        .get("useFirewall")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(true);
+    let use_backup = settings
+        .get("useBackup")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    let backup_count = settings
+        .get("backupCount")
+        .and_then(|v| v.as_u64())
+        .map(|v| v as u32)
+        .unwrap_or(5);
+    (use_firewall, use_backup, backup_count)
+}
+
+// offlineMode and catalogTtlMinutes (in seconds) for the servers.json cache
+pub fn catalog() -> (bool, u64) {
+    let settings = load_or_defaults();
+    let offline = settings
+        .get("offlineMode")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    let ttl_secs = settings
+        .get("catalogTtlMinutes")
+        .and_then(|v| v.as_u64())
+        .map(|m| m * 60)
+        .unwrap_or(crate::catalog::cache::DEFAULT_TTL_SECS);
+    (offline, ttl_secs)
+}
+
+// Latency history limits
+pub fn history_retention() -> crate::history::Retention {
+    let settings = load_or_defaults();
+    let defaults = crate::history::Retention::default();
+    crate::history::Retention {
+        max_age_days: settings
+            .get("historyRetentionDays")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(defaults.max_age_days),
+        max_records: settings
+            .get("historyMaxRecords")
+            .and_then(|v| v.as_u64())
+            .map(|n| n as usize)
+            .unwrap_or(defaults.max_records),
+    }
+}
+
+// dnsResolvers, or the public resolvers discovery uses by default
+pub fn dns_resolvers() -> Vec<String> {
+    load_or_defaults()
+        .get("dnsResolvers")
+        .cloned()
+        .and_then(|v| serde_json::from_value::<Vec<String>>(v).ok())
+        .filter(|r| !r.is_empty())
+        .unwrap_or_else(|| {
+            crate::discovery::DEFAULT_RESOLVERS
+                .iter()
+                .map(|r| r.to_string())
+                .collect()
+        })
+}
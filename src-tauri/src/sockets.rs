@@ -0,0 +1,485 @@
+// Live TCP/UDP connections of a set of processes, matched against catalog IPs to tell which
+// cluster the game is actually connected to.
+//
+// Linux reads /proc/<pid>/fd for the process's socket inodes and /proc/net/{tcp,udp}{,6} for the
+// sockets themselves. Windows reads the TCP tables from GetExtendedTcpTable (netstat's output is
+// localized), macOS parses `lsof -i`. Unconnected UDP sockets have no remote address and can't
+// be matched; Windows doesn't list a remote address for any UDP socket.
+
+use crate::catalog::Game;
+use crate::error::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Connection {
+    pub pid: u32,
+    pub protocol: String,
+    pub local: String,
+    pub remote: String,
+    pub state: String,
+}
+
+impl Connection {
+    fn remote_ip(&self) -> Option<IpAddr> {
+        self.remote.parse::<SocketAddr>().ok().map(|a| a.ip())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterMatch {
+    pub region_id: String,
+    pub cluster_id: String,
+    pub domain: String,
+    #[serde(flatten)]
+    pub connection: Connection,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectedCluster {
+    pub pids: Vec<u32>,
+    pub connections: Vec<Connection>,
+    pub matches: Vec<ClusterMatch>,
+    // The cluster in use: the one with the most matching connections, established TCP first
+    pub region_id: Option<String>,
+    pub cluster_id: Option<String>,
+    pub domain: Option<String>,
+}
+
+// Connections of `pids` whose remote address is one of the catalog IPs
+pub fn match_catalog(catalog: &Game, connections: &[Connection]) -> Vec<ClusterMatch> {
+    let mut by_ip: BTreeMap<IpAddr, (&str, &str, &str)> = BTreeMap::new();
+    for (region, cluster) in catalog.clusters() {
+        for ip in &cluster.ips {
+            if let Ok(ip) = ip.trim().parse::<IpAddr>() {
+                by_ip
+                    .entry(ip)
+                    .or_insert((&region.id, &cluster.id, &cluster.domain));
+            }
+        }
+    }
+    connections
+        .iter()
+        .filter_map(|c| {
+            let ip = canonical(c.remote_ip()?);
+            by_ip
+                .get(&ip)
+                .map(|(region_id, cluster_id, domain)| ClusterMatch {
+                    region_id: region_id.to_string(),
+                    cluster_id: cluster_id.to_string(),
+                    domain: domain.to_string(),
+                    connection: c.clone(),
+                })
+        })
+        .collect()
+}
+
+// IPv4 peers of dual-stack sockets show up as ::ffff:a.b.c.d
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        v4 => v4,
+    }
+}
+
+pub fn detect(catalog: &Game, pids: &[u32]) -> Result<ConnectedCluster> {
+    let connections = connections(pids)?;
+    let matches = match_catalog(catalog, &connections);
+
+    let mut votes: BTreeMap<(&str, &str), (usize, usize, &str)> = BTreeMap::new();
+    for m in &matches {
+        let vote = votes
+            .entry((&m.region_id, &m.cluster_id))
+            .or_insert((0, 0, &m.domain));
+        if m.connection.protocol == "tcp" && m.connection.state == "ESTABLISHED" {
+            vote.0 += 1;
+        }
+        vote.1 += 1;
+    }
+    let best = votes
+        .into_iter()
+        .max_by_key(|(_, (established, total, _))| (*established, *total));
+
+    Ok(ConnectedCluster {
+        pids: pids.to_vec(),
+        region_id: best.as_ref().map(|((r, _), _)| r.to_string()),
+        cluster_id: best.as_ref().map(|((_, c), _)| c.to_string()),
+        domain: best.as_ref().map(|(_, (_, _, d))| d.to_string()),
+        connections,
+        matches,
+    })
+}
+
+#[cfg(target_os = "linux")]
+pub fn connections(pids: &[u32]) -> Result<Vec<Connection>> {
+    linux::connections(pids)
+}
+
+#[cfg(windows)]
+pub fn connections(pids: &[u32]) -> Result<Vec<Connection>> {
+    windows::connections(pids)
+}
+
+#[cfg(target_os = "macos")]
+pub fn connections(pids: &[u32]) -> Result<Vec<Connection>> {
+    let mut all = Vec::new();
+    for pid in pids {
+        let output = std::process::Command::new("lsof")
+            .args(["-nP", "-a", "-i", "-p", &pid.to_string()])
+            .output()
+            .map_err(|e| crate::error::Error::io("failed to run lsof", e))?;
+        // lsof exits with 1 when the process has no sockets
+        all.extend(parse_lsof(&String::from_utf8_lossy(&output.stdout), *pid));
+    }
+    Ok(all)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn connections(_pids: &[u32]) -> Result<Vec<Connection>> {
+    Err(crate::error::Error::Unsupported(
+        "listing process connections is not supported on this platform".into(),
+    ))
+}
+
+// `lsof -nP -i` lines end with NAME, e.g. "10.0.0.2:51000->1.2.3.4:443 (ESTABLISHED)"
+#[cfg(target_os = "macos")]
+fn parse_lsof(text: &str, pid: u32) -> Vec<Connection> {
+    text.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let cols: Vec<&str> = line.split_whitespace().collect();
+            let node = cols.iter().position(|c| *c == "TCP" || *c == "UDP")?;
+            let name = cols.get(node + 1)?;
+            let (local, remote) = name.split_once("->")?;
+            let state = cols
+                .get(node + 2)
+                .map(|s| s.trim_matches(|c| c == '(' || c == ')').to_string())
+                .unwrap_or_default();
+            Some(Connection {
+                pid,
+                protocol: cols[node].to_lowercase(),
+                local: local.to_string(),
+                remote: remote.to_string(),
+                state,
+            })
+        })
+        .collect()
+}
+
+#[cfg(windows)]
+mod windows {
+    use super::Connection;
+    use crate::error::{Error, Result};
+    use std::ffi::c_void;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+    const AF_INET: u32 = 2;
+    const AF_INET6: u32 = 23;
+    const TCP_TABLE_OWNER_PID_ALL: i32 = 5;
+    const NO_ERROR: u32 = 0;
+    const ERROR_INSUFFICIENT_BUFFER: u32 = 122;
+    // MIB_TCPROW_OWNER_PID and MIB_TCP6ROW_OWNER_PID
+    const ROW_V4: usize = 24;
+    const ROW_V6: usize = 56;
+
+    // MIB_TCP_STATE, 1-based; named like netstat's English output
+    const TCP_STATES: [&str; 13] = [
+        "",
+        "CLOSED",
+        "LISTENING",
+        "SYN_SENT",
+        "SYN_RECEIVED",
+        "ESTABLISHED",
+        "FIN_WAIT_1",
+        "FIN_WAIT_2",
+        "CLOSE_WAIT",
+        "CLOSING",
+        "LAST_ACK",
+        "TIME_WAIT",
+        "DELETE_TCB",
+    ];
+
+    #[link(name = "iphlpapi")]
+    extern "system" {
+        fn GetExtendedTcpTable(
+            table: *mut c_void,
+            size: *mut u32,
+            order: i32,
+            family: u32,
+            class: i32,
+            reserved: u32,
+        ) -> u32;
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_ne_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    // Ports are in network byte order in the low 16 bits
+    fn port_at(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_be_bytes([bytes[offset], bytes[offset + 1]])
+    }
+
+    fn v6_at(bytes: &[u8], offset: usize) -> IpAddr {
+        let octets: [u8; 16] = bytes[offset..offset + 16].try_into().unwrap();
+        IpAddr::V6(Ipv6Addr::from(octets))
+    }
+
+    // The whole table for one address family, retried while it grows between the two calls
+    fn table(family: u32) -> Result<Vec<u8>> {
+        let mut size = 0u32;
+        for _ in 0..5 {
+            // u32 elements keep the buffer aligned for the rows
+            let mut buf = vec![0u32; (size as usize).div_ceil(4)];
+            let ptr = if buf.is_empty() {
+                std::ptr::null_mut()
+            } else {
+                buf.as_mut_ptr() as *mut c_void
+            };
+            let rc = unsafe {
+                GetExtendedTcpTable(ptr, &mut size, 0, family, TCP_TABLE_OWNER_PID_ALL, 0)
+            };
+            match rc {
+                NO_ERROR => {
+                    let bytes: Vec<u8> = buf.iter().flat_map(|w| w.to_ne_bytes()).collect();
+                    return Ok(bytes);
+                }
+                ERROR_INSUFFICIENT_BUFFER => continue,
+                code => {
+                    return Err(Error::Io(format!(
+                        "GetExtendedTcpTable failed with error {}",
+                        code
+                    )))
+                }
+            }
+        }
+        Err(Error::Io(
+            "GetExtendedTcpTable: the connection table kept growing".into(),
+        ))
+    }
+
+    // dwNumEntries, then the rows
+    fn parse_table(bytes: &[u8], family: u32, pids: &[u32]) -> Vec<Connection> {
+        if bytes.len() < 4 {
+            return Vec::new();
+        }
+        let row = if family == AF_INET6 { ROW_V6 } else { ROW_V4 };
+        let count = u32_at(bytes, 0) as usize;
+        let rows = bytes[4..].chunks_exact(row).take(count);
+        rows.filter_map(|r| {
+            let (state, local, remote, pid) = if family == AF_INET6 {
+                (
+                    u32_at(r, 48),
+                    SocketAddr::new(v6_at(r, 0), port_at(r, 20)),
+                    SocketAddr::new(v6_at(r, 24), port_at(r, 44)),
+                    u32_at(r, 52),
+                )
+            } else {
+                let v4 = |offset| IpAddr::V4(Ipv4Addr::from(u32_at(r, offset).to_ne_bytes()));
+                (
+                    u32_at(r, 0),
+                    SocketAddr::new(v4(4), port_at(r, 8)),
+                    SocketAddr::new(v4(12), port_at(r, 16)),
+                    u32_at(r, 20),
+                )
+            };
+            // Listening sockets have no peer
+            if !pids.contains(&pid) || remote.ip().is_unspecified() {
+                return None;
+            }
+            Some(Connection {
+                pid,
+                protocol: "tcp".into(),
+                local: local.to_string(),
+                remote: remote.to_string(),
+                state: TCP_STATES
+                    .get(state as usize)
+                    .map(|s| s.to_string())
+                    .unwrap_or_default(),
+            })
+        })
+        .collect()
+    }
+
+    pub fn connections(pids: &[u32]) -> Result<Vec<Connection>> {
+        let mut all = parse_table(&table(AF_INET)?, AF_INET, pids);
+        // No IPv6 stack: just the IPv4 table
+        if let Ok(v6) = table(AF_INET6) {
+            all.extend(parse_table(&v6, AF_INET6, pids));
+        }
+        Ok(all)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::Connection;
+    use crate::error::{Error, Result};
+    use std::collections::BTreeMap;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+    // Names of the `st` column of /proc/net/tcp
+    const TCP_STATES: [&str; 12] = [
+        "",
+        "ESTABLISHED",
+        "SYN_SENT",
+        "SYN_RECV",
+        "FIN_WAIT1",
+        "FIN_WAIT2",
+        "TIME_WAIT",
+        "CLOSE",
+        "CLOSE_WAIT",
+        "LAST_ACK",
+        "LISTEN",
+        "CLOSING",
+    ];
+
+    // Socket inode -> pid, from the processes' open file descriptors
+    fn socket_inodes(pids: &[u32]) -> BTreeMap<u64, u32> {
+        let mut inodes = BTreeMap::new();
+        for &pid in pids {
+            let entries = match std::fs::read_dir(format!("/proc/{}/fd", pid)) {
+                Ok(e) => e,
+                // Gone, or not ours to look at
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let target = match std::fs::read_link(entry.path()) {
+                    Ok(t) => t,
+                    Err(_) => continue,
+                };
+                let target = target.to_string_lossy();
+                if let Some(inode) = target
+                    .strip_prefix("socket:[")
+                    .and_then(|s| s.strip_suffix(']'))
+                    .and_then(|s| s.parse().ok())
+                {
+                    inodes.insert(inode, pid);
+                }
+            }
+        }
+        inodes
+    }
+
+    // "0100007F:1F90" (IPv4) or 32 hex digits (IPv6), each 32-bit word in host byte order
+    fn parse_addr(field: &str) -> Option<SocketAddr> {
+        let (ip, port) = field.split_once(':')?;
+        let port = u16::from_str_radix(port, 16).ok()?;
+        let words: Vec<u32> = (0..ip.len() / 8)
+            .map(|i| u32::from_str_radix(&ip[i * 8..i * 8 + 8], 16))
+            .collect::<Result<_, _>>()
+            .ok()?;
+        let ip = match words.as_slice() {
+            [a] => IpAddr::V4(Ipv4Addr::from(a.to_ne_bytes())),
+            [a, b, c, d] => {
+                let mut bytes = [0u8; 16];
+                for (i, w) in [a, b, c, d].iter().enumerate() {
+                    bytes[i * 4..i * 4 + 4].copy_from_slice(&w.to_ne_bytes());
+                }
+                IpAddr::V6(Ipv6Addr::from(bytes))
+            }
+            _ => return None,
+        };
+        Some(SocketAddr::new(ip, port))
+    }
+
+    fn parse_table(text: &str, protocol: &str, inodes: &BTreeMap<u64, u32>) -> Vec<Connection> {
+        text.lines()
+            .skip(1)
+            .filter_map(|line| {
+                let cols: Vec<&str> = line.split_whitespace().collect();
+                let inode: u64 = cols.get(9)?.parse().ok()?;
+                let pid = *inodes.get(&inode)?;
+                let local = parse_addr(cols.get(1)?)?;
+                let remote = parse_addr(cols.get(2)?)?;
+                let state = match protocol {
+                    "tcp" => u8::from_str_radix(cols.get(3)?, 16)
+                        .ok()
+                        .and_then(|s| TCP_STATES.get(s as usize))
+                        .map(|s| s.to_string())
+                        .unwrap_or_default(),
+                    _ => String::new(),
+                };
+                // Listening and unconnected sockets have no peer
+                if remote.ip().is_unspecified() {
+                    return None;
+                }
+                Some(Connection {
+                    pid,
+                    protocol: protocol.to_string(),
+                    local: local.to_string(),
+                    remote: remote.to_string(),
+                    state,
+                })
+            })
+            .collect()
+    }
+
+    pub fn connections(pids: &[u32]) -> Result<Vec<Connection>> {
+        let inodes = socket_inodes(pids);
+        if inodes.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut all = Vec::new();
+        for (file, protocol) in [
+            ("tcp", "tcp"),
+            ("tcp6", "tcp"),
+            ("udp", "udp"),
+            ("udp6", "udp"),
+        ] {
+            let path = format!("/proc/net/{}", file);
+            match std::fs::read_to_string(&path) {
+                Ok(text) => all.extend(parse_table(&text, protocol, &inodes)),
+                // No IPv6 on this system
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(Error::io(format!("failed to read {}", path), e)),
+            }
+        }
+        Ok(all)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn parses_proc_net_addresses() {
+            assert_eq!(
+                parse_addr("0100007F:1F90"),
+                Some("127.0.0.1:8080".parse().unwrap())
+            );
+            assert_eq!(
+                parse_addr("00000000000000000000000001000000:01BB"),
+                Some("[::1]:443".parse().unwrap())
+            );
+            assert_eq!(parse_addr("0100007F"), None);
+            assert_eq!(parse_addr("zz00007F:1F90"), None);
+        }
+
+        #[test]
+        fn reports_an_established_local_connection() {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let server = listener.local_addr().unwrap();
+            let client = std::net::TcpStream::connect(server).unwrap();
+            let (_accepted, _) = listener.accept().unwrap();
+            let local = client.local_addr().unwrap();
+
+            let found = connections(&[std::process::id()]).unwrap();
+            let connection = found
+                .iter()
+                .find(|c| c.local == local.to_string() && c.remote == server.to_string())
+                .unwrap_or_else(|| panic!("{} -> {} not in {:?}", local, server, found));
+            assert_eq!(connection.pid, std::process::id());
+            assert_eq!(connection.protocol, "tcp");
+            assert_eq!(connection.state, "ESTABLISHED");
+            // The listening socket has no peer and is left out
+            assert!(!found
+                .iter()
+                .any(|c| c.local == server.to_string() && c.remote.ends_with(":0")));
+        }
+    }
+}
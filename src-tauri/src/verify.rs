@@ -0,0 +1,296 @@
+// End-to-end check that blocked clusters are really unreachable: the hosts entry has to win in
+// the system resolver (a DNS cache or DNS-over-HTTPS can bypass it), and with the firewall in use
+// every catalog IP has to be unreachable (the firewall may be off, or a rule may only have been
+// partly created by the per-IP fallback).
+//
+// An IP that doesn't answer only counts as blocked when one of our firewall rules covers it; a
+// server that is down or drops port 443 is silent too, so without a rule the check is
+// inconclusive.
+
+use crate::catalog::Game;
+use crate::firewall::{self, FirewallRule};
+use crate::probe::{self, Method};
+use serde::Serialize;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+pub const DEFAULT_TIMEOUT_MS: u64 = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    // No answer, and a firewall rule of the app covers the IP
+    Blocked,
+    // Connected or refused: packets made it to the server and back
+    Open,
+    // No answer, but no rule of the app covers it (or the rules couldn't be listed)
+    Inconclusive,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IpCheck {
+    pub ip: String,
+    pub port: u16,
+    pub verdict: Verdict,
+    // A rule of the app blocks the IP; None when the rules couldn't be listed
+    pub covered: Option<bool>,
+    pub status: String,
+    pub rtt_ms: Option<f64>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterCheck {
+    pub region_id: String,
+    pub cluster_id: String,
+    pub domain: String,
+    // What the system resolver returns for the domain
+    pub resolved: Vec<String>,
+    pub dns_blocked: bool,
+    pub dns_error: Option<String>,
+    pub ips: Vec<IpCheck>,
+    pub effective: bool,
+    // Why the block is leaky, in plain words
+    pub leaks: Vec<String>,
+    // What couldn't be told either way, in plain words
+    pub inconclusive: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Report {
+    // IP checks only count when the firewall is meant to block
+    pub firewall_expected: bool,
+    // Whether the app's firewall rules could be listed to tell blocked IPs from silent ones
+    pub rules_listed: bool,
+    pub effective: bool,
+    // Some IP check of some cluster was inconclusive
+    pub inconclusive: bool,
+    pub clusters: Vec<ClusterCheck>,
+    // Blocked in hosts, but not in the catalog
+    pub unknown_domains: Vec<String>,
+    pub checked_at: u64,
+}
+
+fn verdict(rtt_ms: Option<f64>, covered: Option<bool>) -> Verdict {
+    match (rtt_ms, covered) {
+        (Some(_), _) => Verdict::Open,
+        (None, Some(true)) => Verdict::Blocked,
+        (None, _) => Verdict::Inconclusive,
+    }
+}
+
+async fn check_ip(ip: IpAddr, port: u16, covered: Option<bool>, timeout: Duration) -> IpCheck {
+    let result = probe::probe_addr(Method::Tcp, SocketAddr::new(ip, port), timeout).await;
+    IpCheck {
+        ip: ip.to_string(),
+        port,
+        verdict: verdict(result.rtt_ms, covered),
+        covered,
+        status: result.status,
+        rtt_ms: result.rtt_ms,
+        error: result.error,
+    }
+}
+
+async fn check_cluster(
+    region_id: String,
+    cluster: crate::catalog::Cluster,
+    firewall_expected: bool,
+    rules: Option<std::sync::Arc<Vec<FirewallRule>>>,
+    timeout: Duration,
+) -> ClusterCheck {
+    let mut leaks = Vec::new();
+    let mut inconclusive = Vec::new();
+
+    let (resolved, dns_error) = match tokio::net::lookup_host((cluster.domain.as_str(), 0)).await {
+        Ok(addrs) => {
+            let mut ips: Vec<IpAddr> = Vec::new();
+            for ip in addrs.map(|a| a.ip()) {
+                if !ips.contains(&ip) {
+                    ips.push(ip);
+                }
+            }
+            (ips, None)
+        }
+        Err(e) => (Vec::new(), Some(e.to_string())),
+    };
+    // Failing to resolve at all keeps the game away too
+    let dns_blocked = resolved.iter().all(|ip| ip.is_unspecified());
+    if !dns_blocked {
+        let real: Vec<String> = resolved
+            .iter()
+            .filter(|ip| !ip.is_unspecified())
+            .map(|ip| ip.to_string())
+            .collect();
+        leaks.push(format!(
+            "{} resolves to {} instead of {}: the hosts entry is bypassed (DNS cache, DNS-over-HTTPS or a resolver ignoring hosts)",
+            cluster.domain,
+            real.join(", "),
+            crate::hosts::SINK_IP
+        ));
+    }
+
+    let port = cluster
+        .probe
+        .filter(|p| p.method == Method::Tcp)
+        .and_then(|p| p.port)
+        .unwrap_or(probe::DEFAULT_TCP_PORT);
+    let mut tasks = tokio::task::JoinSet::new();
+    for (i, ip) in cluster.ip_addrs().into_iter().enumerate() {
+        let covered = rules.as_ref().map(|r| firewall::blocks(r, ip));
+        tasks.spawn(async move { (i, check_ip(ip, port, covered, timeout).await) });
+    }
+    let mut ips = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok(check) = joined {
+            ips.push(check);
+        }
+    }
+    ips.sort_by_key(|(i, _)| *i);
+    let ips: Vec<IpCheck> = ips.into_iter().map(|(_, c)| c).collect();
+
+    if firewall_expected {
+        let with = |verdict| -> Vec<&str> {
+            ips.iter()
+                .filter(|c| c.verdict == verdict)
+                .map(|c| c.ip.as_str())
+                .collect()
+        };
+        let open = with(Verdict::Open);
+        if !open.is_empty() {
+            leaks.push(format!(
+                "{} of {} IPs still answer on port {} ({}): the firewall is off or the rule is incomplete",
+                open.len(),
+                ips.len(),
+                port,
+                open.join(", ")
+            ));
+        }
+        let silent = with(Verdict::Inconclusive);
+        if !silent.is_empty() {
+            inconclusive.push(format!(
+                "{} of {} IPs don't answer on port {} ({}), but {}: the server may just be down",
+                silent.len(),
+                ips.len(),
+                port,
+                silent.join(", "),
+                if rules.is_some() {
+                    "no firewall rule of the app covers them"
+                } else {
+                    "the app's firewall rules could not be listed"
+                }
+            ));
+        }
+    }
+
+    ClusterCheck {
+        region_id,
+        cluster_id: cluster.id,
+        domain: cluster.domain,
+        resolved: resolved.iter().map(|ip| ip.to_string()).collect(),
+        dns_blocked,
+        dns_error,
+        ips,
+        effective: leaks.is_empty(),
+        leaks,
+        inconclusive,
+    }
+}
+
+// Check every cluster whose domain is in `blocked_domains` (or only those of one region).
+// `rules` are the app's firewall rules (FirewallBackend::inventory), None when unavailable.
+pub async fn verify(
+    catalog: &Game,
+    blocked_domains: &[String],
+    region_id: Option<&str>,
+    firewall_expected: bool,
+    rules: Option<Vec<FirewallRule>>,
+    timeout: Duration,
+) -> Report {
+    let rules_listed = rules.is_some();
+    let rules = rules.map(std::sync::Arc::new);
+    let blocked: Vec<String> = blocked_domains.iter().map(|d| d.to_lowercase()).collect();
+    let mut tasks = tokio::task::JoinSet::new();
+    let mut index = 0;
+    for (region, cluster) in catalog.clusters() {
+        if region_id.map(|id| region.id != id).unwrap_or(false)
+            || !blocked.contains(&cluster.domain.to_lowercase())
+        {
+            continue;
+        }
+        let (i, region_id, cluster) = (index, region.id.clone(), cluster.clone());
+        let rules = rules.clone();
+        index += 1;
+        tasks.spawn(async move {
+            (
+                i,
+                check_cluster(region_id, cluster, firewall_expected, rules, timeout).await,
+            )
+        });
+    }
+    let mut clusters = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok(check) = joined {
+            clusters.push(check);
+        }
+    }
+    clusters.sort_by_key(|(i, _)| *i);
+    let clusters: Vec<ClusterCheck> = clusters.into_iter().map(|(_, c)| c).collect();
+
+    let known: Vec<String> = catalog
+        .clusters()
+        .map(|(_, c)| c.domain.to_lowercase())
+        .collect();
+    let unknown_domains = blocked.into_iter().filter(|d| !known.contains(d)).collect();
+
+    Report {
+        firewall_expected,
+        rules_listed,
+        effective: clusters.iter().all(|c| c.effective),
+        inconclusive: clusters.iter().any(|c| !c.inconclusive.is_empty()),
+        clusters,
+        unknown_domains,
+        checked_at: crate::history::now_ms(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    #[test]
+    fn silence_only_counts_as_blocked_under_a_rule() {
+        for covered in [Some(true), Some(false), None] {
+            assert_eq!(verdict(Some(12.0), covered), Verdict::Open);
+        }
+        assert_eq!(verdict(None, Some(true)), Verdict::Blocked);
+        assert_eq!(verdict(None, Some(false)), Verdict::Inconclusive);
+        assert_eq!(verdict(None, None), Verdict::Inconclusive);
+    }
+
+    #[tokio::test]
+    async fn an_answer_is_open_even_under_a_rule() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let ip = IpAddr::from([127, 0, 0, 1]);
+
+        let check = check_ip(ip, port, Some(true), TIMEOUT).await;
+        assert_eq!(
+            (check.verdict, check.status.as_str()),
+            (Verdict::Open, "ok")
+        );
+        assert_eq!((check.ip.as_str(), check.port), ("127.0.0.1", port));
+        assert_eq!(check.covered, Some(true));
+
+        // Refused is an answer too
+        drop(listener);
+        let check = check_ip(ip, port, None, TIMEOUT).await;
+        assert_eq!(check.verdict, Verdict::Open);
+        assert!(check.rtt_ms.is_some());
+    }
+}
@@ -1,6 +1,5 @@
 import { useState, useEffect } from "react";
 import { AnimatePresence } from "framer-motion";
-import clustersDataLocal from "../data/servers.json";
 import ClusterMenu from "./components/ClusterMenu";
 import Navbar from "./components/Navbar";
 import GamePoster from "./components/GamePoster";
@@ -18,33 +17,19 @@ import { useHosts } from "../hooks/useHosts";
 import { usePing } from "../hooks/usePing";
 import { useGameStatus } from "../hooks/useGameStatus";
 import { useHostsActions } from "../hooks/useHostsActions";
-import { safeInvoke, launchGame, diagnoseTauri } from "../utils/tauriInvoke";
+import { useCapabilities } from "../hooks/useCapabilities";
+import { useCatalog } from "../hooks/useCatalog";
+import {
+  launchGame,
+  diagnoseTauri,
+  relaunchElevated,
+} from "../utils/tauriInvoke";
 import { getSavedRegionId, saveRegionId } from "../utils/regionStorage";
-import type { Game } from "../types/cluster";
+import { errorMessage } from "../utils/errors";
 
 export default function App() {
-  const [clustersData, setClustersData] = useState(clustersDataLocal);
-
-  useEffect(() => {
-    async function loadClusters() {
-      try {
-        const response = await fetch(
-          "https://raw.githubusercontent.com/SWIRCH/cluster-banned-manager/main/src/data/servers.json"
-        );
-        const data = await response.json();
-        setClustersData(data);
-      } catch (error) {
-        console.error("Ошибка загрузки кластеров:", error);
-        import("../data/servers.json").then((module) => {
-          setClustersData(module.default);
-        });
-      }
-    }
-
-    loadClusters();
-  }, []);
-
-  const game = clustersData as Game;
+  // Каталог берём у бэкенда: кэш, проверка подписи и пользовательские правки
+  const { game, status: catalogStatus } = useCatalog();
   const [isLoading, setIsLoading] = useState(true);
 
   // Получаем сохраненный регион или используем EU по умолчанию
@@ -93,11 +78,16 @@ export default function App() {
   } = useHosts(selectedRegionId, selections, clusters);
   const { pings, pingClusters } = usePing(selectedRegion);
   const { gameRunning, checkGameRunning, killGame } = useGameStatus();
+  const { capabilities, firewallUnavailable } = useCapabilities();
+  // Брандмауэр отключаем, если он здесь недоступен (причина видна в настройках)
+  const effectiveSettings = firewallUnavailable
+    ? { ...settings, useFirewall: false }
+    : settings;
   const { applyHostsUpdate, clearCluster, loading } = useHostsActions(
     selectedRegionId,
     selections,
     clusters,
-    settings
+    effectiveSettings
   );
 
   // Poster selection
@@ -132,19 +122,16 @@ export default function App() {
     setIsLoading(false);
   };
 
-  // Check elevation on mount
+  // Права администратора нужны, только если hosts нельзя изменить из-за прав
   useEffect(() => {
-    (async () => {
-      try {
-        const ev: any = await safeInvoke("check_elevation");
-        if (ev && ev.isAdmin === false) {
-          setAdminModalOpen(true);
-        }
-      } catch (e) {
-        console.debug("check_elevation failed", e);
-      }
-    })();
-  }, []);
+    if (
+      capabilities &&
+      !capabilities.hosts.ok &&
+      capabilities.hosts.code === "permission-denied"
+    ) {
+      setAdminModalOpen(true);
+    }
+  }, [capabilities]);
 
   const regionMap = selections[selectedRegionId] ?? {};
   const selectedDomain =
@@ -217,14 +204,14 @@ export default function App() {
           setTimeout(() => checkGameRunning(), 10000);
         } catch (e) {
           setInfoTitle("Не удалось запустить игру");
-          setInfoMessage(String(e));
+          setInfoMessage(errorMessage(e));
           setInfoIsError(true);
           setInfoOpen(true);
         }
       }
     } catch (err) {
       setInfoTitle("Ошибка");
-      setInfoMessage(String(err));
+      setInfoMessage(errorMessage(err));
       setInfoIsError(true);
       setInfoOpen(true);
     }
@@ -248,7 +235,7 @@ export default function App() {
       setDiagnosticInfo(JSON.stringify(res, null, 2));
     } catch (err) {
       console.debug("diagnoseTauri failed", err);
-      setDiagnosticInfo(String(err));
+      setDiagnosticInfo(errorMessage(err));
     }
   };
 
@@ -344,7 +331,7 @@ export default function App() {
               open={clearConfirmOpen}
               onClose={() => setClearConfirmOpen(false)}
               onConfirm={handleClearCluster}
-              useFirewall={settings.useFirewall}
+              useFirewall={effectiveSettings.useFirewall}
               useBackup={settings.useBackup}
               loading={loading}
             />
@@ -361,6 +348,8 @@ export default function App() {
               open={settingsModalOpen}
               onClose={() => setSettingsModalOpen(false)}
               settings={settings}
+              firewallUnavailable={firewallUnavailable}
+              catalogStatus={catalogStatus}
               onUpdateSetting={updateSetting}
               onDiagnose={handleDiagnose}
               diagnosticInfo={diagnosticInfo}
@@ -377,6 +366,18 @@ export default function App() {
                 setInfoIsError(false);
                 setInfoOpen(true);
               }}
+              onRelaunch={async () => {
+                try {
+                  // При успехе этот экземпляр закрывается
+                  await relaunchElevated();
+                } catch (e) {
+                  setAdminModalOpen(false);
+                  setInfoTitle("Не удалось перезапустить приложение");
+                  setInfoMessage(errorMessage(e));
+                  setInfoIsError(true);
+                  setInfoOpen(true);
+                }
+              }}
             />
           </main>
         )}
@@ -3,11 +3,13 @@ import { motion, AnimatePresence } from "framer-motion";
 type AdminModalProps = {
   open: boolean;
   onShowInstructions: () => void;
+  onRelaunch: () => void;
 };
 
 export default function AdminModal({
   open,
   onShowInstructions,
+  onRelaunch,
 }: AdminModalProps) {
   return (
     <AnimatePresence>
@@ -42,11 +44,17 @@ export default function AdminModal({
 
             <div className="flex justify-end gap-2">
               <button
-                className="steam-btn bg-yellow-400 text-black px-4 py-2 rounded"
+                className="steam-btn px-4 py-2 rounded"
                 onClick={onShowInstructions}
               >
                 Инструкция
               </button>
+              <button
+                className="steam-btn bg-yellow-400 text-black px-4 py-2 rounded"
+                onClick={onRelaunch}
+              >
+                Перезапустить с правами администратора
+              </button>
             </div>
           </motion.div>
         </motion.div>
@@ -1,10 +1,23 @@
 import { motion, AnimatePresence } from "framer-motion";
 import type { AppSettings } from "../../../utils/settingsStorage";
+import type { CatalogSource, CatalogStatus } from "../../../types/catalog";
+
+const CATALOG_SOURCES: Record<CatalogSource, string> = {
+  network: "загружен с GitHub",
+  revalidated: "кэш, подтверждён сервером",
+  cache: "кэш",
+  staleCache: "устаревший кэш (сервер недоступен)",
+  embedded: "встроенная копия",
+};
 
 type SettingsModalProps = {
   open: boolean;
   onClose: () => void;
   settings: AppSettings;
+  // Why the firewall mode can't be used here, if it can't
+  firewallUnavailable?: string | null;
+  // Where the server catalog came from; null until the backend answered
+  catalogStatus?: CatalogStatus | null;
   onUpdateSetting: <K extends keyof AppSettings>(
     key: K,
     value: AppSettings[K]
@@ -17,6 +30,8 @@ export default function SettingsModal({
   open,
   onClose,
   settings,
+  firewallUnavailable,
+  catalogStatus,
   onUpdateSetting,
   onDiagnose,
   diagnosticInfo,
@@ -54,7 +69,8 @@ export default function SettingsModal({
               <label className="flex items-center gap-2 text-sm">
                 <input
                   type="checkbox"
-                  checked={settings.useFirewall}
+                  checked={settings.useFirewall && !firewallUnavailable}
+                  disabled={!!firewallUnavailable}
                   onChange={(e) =>
                     onUpdateSetting("useFirewall", e.target.checked)
                   }
@@ -68,6 +84,12 @@ export default function SettingsModal({
                 Блокирует подключения на уровне сети. Работает даже если игра
                 использует IP напрямую. Требует прав администратора.
               </p>
+
+              {firewallUnavailable && (
+                <p className="text-xs text-red-400 mt-1 pl-6">
+                  Недоступно: {firewallUnavailable}
+                </p>
+              )}
             </div>
 
             <div className="mt-3 p-3 rounded bg-white/5">
@@ -171,6 +193,47 @@ export default function SettingsModal({
               </div>
             </div>
 
+            <div className="mt-3 p-3 rounded bg-white/5">
+              <div className="text-sm font-medium mb-2">Каталог серверов:</div>
+
+              {catalogStatus ? (
+                <>
+                  <p className="text-xs text-white/60">
+                    Источник: {CATALOG_SOURCES[catalogStatus.source]}
+                    {catalogStatus.overlayApplied && ", с вашими правками"}
+                  </p>
+                  {catalogStatus.signatureError ? (
+                    <p className="text-xs text-red-400 mt-1">
+                      Загруженный каталог отклонён, подпись не прошла
+                      проверку: {catalogStatus.signatureError}
+                    </p>
+                  ) : catalogStatus.source === "embedded" ? (
+                    <p className="text-xs text-white/60 mt-1">
+                      Подпись: не требуется (копия из сборки приложения)
+                    </p>
+                  ) : (
+                    <p className="text-xs text-green-400 mt-1">
+                      Подпись: проверена
+                    </p>
+                  )}
+                  {catalogStatus.error && (
+                    <p className="text-xs text-white/60 mt-1">
+                      {catalogStatus.error}
+                    </p>
+                  )}
+                  {catalogStatus.overlayError && (
+                    <p className="text-xs text-red-400 mt-1">
+                      Правки не применены: {catalogStatus.overlayError}
+                    </p>
+                  )}
+                </>
+              ) : (
+                <p className="text-xs text-white/60">
+                  Встроенная копия (бэкенд недоступен)
+                </p>
+              )}
+            </div>
+
             <div className="mt-3 p-3 rounded bg-white/5">
               <div className="text-sm font-medium mb-2">
                 Отладка и диагностика:
@@ -4,3 +4,5 @@ export { useHosts } from "./useHosts";
 export { usePing } from "./usePing";
 export { useGameStatus } from "./useGameStatus";
 export { useHostsActions } from "./useHostsActions";
+export { useCapabilities } from "./useCapabilities";
+export { useCatalog } from "./useCatalog";
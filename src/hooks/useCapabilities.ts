@@ -0,0 +1,32 @@
+import { useState, useEffect } from "react";
+import { getCapabilities } from "../utils/tauriInvoke";
+import type { Capabilities } from "../types/capabilities";
+
+export function useCapabilities() {
+  const [capabilities, setCapabilities] = useState<Capabilities | null>(null);
+
+  const refreshCapabilities = async () => {
+    try {
+      const caps = await getCapabilities();
+      setCapabilities(caps);
+      return caps;
+    } catch (e) {
+      console.debug("get_capabilities failed", e);
+      return null;
+    }
+  };
+
+  useEffect(() => {
+    refreshCapabilities();
+  }, []);
+
+  // Why the firewall mode can't be used here; missing rights don't count, elevation fixes those
+  const firewallUnavailable =
+    capabilities &&
+    !capabilities.firewall.ok &&
+    capabilities.firewall.code !== "permission-denied"
+      ? capabilities.firewall.reason ?? "Брандмауэр недоступен"
+      : null;
+
+  return { capabilities, refreshCapabilities, firewallUnavailable };
+}
@@ -0,0 +1,27 @@
+import { useState, useEffect } from "react";
+import clustersDataLocal from "../data/servers.json";
+import { getCatalog, getCatalogStatus } from "../utils/tauriInvoke";
+import type { Game } from "../types/cluster";
+import type { CatalogStatus } from "../types/catalog";
+
+// The catalog as the backend uses it: cached, signature-checked and with the user overlay. The
+// copy bundled with the UI stands in until it answers, or when Tauri isn't available.
+export function useCatalog() {
+  const [game, setGame] = useState<Game>(clustersDataLocal as Game);
+  const [status, setStatus] = useState<CatalogStatus | null>(null);
+
+  const refreshCatalog = async () => {
+    try {
+      setGame(await getCatalog());
+      setStatus(await getCatalogStatus());
+    } catch (e) {
+      console.error("Ошибка загрузки каталога:", e);
+    }
+  };
+
+  useEffect(() => {
+    refreshCatalog();
+  }, []);
+
+  return { game, status, refreshCatalog };
+}
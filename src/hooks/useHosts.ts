@@ -1,5 +1,6 @@
 import { useState, useEffect } from "react";
 import { safeInvoke } from "../utils/tauriInvoke";
+import { errorMessage } from "../utils/errors";
 import type { Selections } from "../types/selections";
 import type { Cluster } from "../types/cluster";
 
@@ -45,7 +46,7 @@ export function useHosts(
       setTauriAvailable(false);
       setMismatchDomains([]);
       setHostsMismatch(false);
-      setLastTauriError(String(e));
+      setLastTauriError(errorMessage(e));
     }
   };
 
@@ -1,6 +1,11 @@
 import { useState } from "react";
 import { safeInvoke } from "../utils/tauriInvoke";
-import { updateFirewallRules, clearFirewallRules } from "../utils/tauriInvoke";
+import { errorCode, errorMessage } from "../utils/errors";
+import {
+  updateFirewallRules,
+  clearFirewallRules,
+  runElevated,
+} from "../utils/tauriInvoke";
 import type { AppSettings } from "../utils/settingsStorage";
 import type { Cluster } from "../types/cluster";
 import type { Selections } from "../types/selections";
@@ -48,7 +53,7 @@ export function useHostsActions(
         return {
           success: false,
           title: "Не удалось проверить hosts",
-          message: String(e),
+          message: errorMessage(e),
         };
       }
     }
@@ -79,7 +84,7 @@ export function useHostsActions(
           );
         } catch (firewallError) {
           console.error("Firewall update failed:", firewallError);
-          firewallRes = `Предупреждение: не удалось обновить брандмауэр: ${firewallError}`;
+          firewallRes = `Предупреждение: не удалось обновить брандмауэр: ${errorMessage(firewallError)}`;
         }
       }
 
@@ -114,25 +119,56 @@ ${
         message: successMessage.trim(),
       };
     } catch (e) {
-      let errorMessage = String(e);
+      let message = errorMessage(e);
       let errorTitle = "Ошибка обновления";
 
       if (settings.useFirewall) {
         errorTitle = "Ошибка обновления правил";
-        errorMessage = `
+      }
+      const code = errorCode(e);
+      if (code === "permission-denied" || code === "not-authorized") {
+        // Повторяем ту же операцию в копии приложения с правами администратора (UAC / pkexec)
+        try {
+          const elevated: any = await runElevated({
+            op: isRemoval ? "remove" : "apply",
+            regionId: selectedRegionId,
+            domains,
+            hosts: true,
+            firewall: settings.useFirewall,
+          });
+          if (elevated?.success) {
+            return {
+              success: true,
+              title: isRemoval ? "Разблокировано" : "Заблокировано",
+              message: `
+✅ Изменения применены с правами администратора.
+
+Hosts: ${elevated.hosts}
+Брандмауэр: ${elevated.firewall}
+              `.trim(),
+            };
+          }
+          if (elevated) {
+            message = `${elevated.hosts}\n${elevated.firewall}`;
+          }
+        } catch (elevationError) {
+          console.debug("Elevated retry failed:", elevationError);
+        }
+
+        message = `
         ❌ Не удалось применить изменения:
         
-        ${errorMessage}
+        ${message}
         
-        Возможно, требуются права администратора для изменения правил брандмауэра.
-        Попробуйте запустить приложение от имени администратора.
+        Требуются права администратора для изменения hosts и правил брандмауэра.
+        Запустите службу clusterbanned-helper или приложение от имени администратора.
       `;
       }
 
       return {
         success: false,
         title: errorTitle,
-        message: errorMessage.trim(),
+        message: message.trim(),
       };
     } finally {
       setLoading(false);
@@ -152,7 +188,7 @@ ${
           const fwRes = await clearFirewallRules();
           messages.push(`Брандмауэр: ${fwRes}`);
         } catch (fwError) {
-          messages.push(`Ошибка очистки брандмауэра: ${fwError}`);
+          messages.push(`Ошибка очистки брандмауэра: ${errorMessage(fwError)}`);
         }
       }
 
@@ -165,7 +201,7 @@ ${
       return {
         success: false,
         title: "Ошибка очистки",
-        message: String(e),
+        message: errorMessage(e),
       };
     } finally {
       setLoading(false);
@@ -1,18 +1,33 @@
 import { useState, useEffect, useRef } from "react";
-import { safeInvoke } from "../utils/tauriInvoke";
-import type { PingInfo, PingMap } from "../types/ping";
+import {
+  measureClusterLatency,
+  startLatencyMonitor,
+  stopLatencyMonitor,
+} from "../utils/tauriInvoke";
+import { errorMessage } from "../utils/errors";
+import { LATENCY_MONITOR_EVENT } from "../types/ping";
+import type {
+  ClusterLatency,
+  MonitorSample,
+  MonitorTick,
+  PingInfo,
+  PingMap,
+} from "../types/ping";
 import type { Region } from "../types/cluster";
-// import type { Cluster, Region } from "../types/cluster";
 
 const PING_TIMEOUT_MS = 2000;
 const PING_ATTEMPTS = 3;
-const PING_ATTEMPT_DELAY_MS = 120;
-const PING_CONCURRENCY = 4;
-const PING_REFRESH_INTERVAL_MS = 15 * 60 * 1000; // 15 minutes
+// Monitor rounds the average and loss are taken over
+const PING_WINDOW = 12;
 
+// Latency of the selected region's clusters. The backend monitor probes them every few seconds
+// at their first catalog IP (the same rounds auto-block decides on); pingClusters measures them
+// once right away, at the same IP.
 export function usePing(selectedRegion: Region | null) {
   const [pings, setPings] = useState<PingMap>({});
   const pingRunIdRef = useRef(0);
+  // domain -> recent round trips, null for lost probes
+  const windowsRef = useRef<Record<string, (number | null)[]>>({});
 
   const updateStats = (domain: string, infoPartial: Partial<PingInfo>) => {
     setPings((prev) => {
@@ -24,125 +39,118 @@ export function usePing(selectedRegion: Region | null) {
         lossPercent: 0,
         status: "idle",
       };
-      const next: PingInfo = {
-        last: infoPartial.last !== undefined ? infoPartial.last : prevInfo.last,
-        avg: infoPartial.avg !== undefined ? infoPartial.avg : prevInfo.avg,
-        attempts:
-          infoPartial.attempts !== undefined
-            ? infoPartial.attempts
-            : prevInfo.attempts,
-        successes:
-          infoPartial.successes !== undefined
-            ? infoPartial.successes
-            : prevInfo.successes,
-        lossPercent:
-          infoPartial.lossPercent !== undefined
-            ? infoPartial.lossPercent
-            : prevInfo.lossPercent,
-        status: infoPartial.status ?? prevInfo.status,
-      };
-      return { ...prev, [domain]: next };
+      return { ...prev, [domain]: { ...prevInfo, ...infoPartial } };
+    });
+  };
+
+  // Replace the window of `domain` and show what it adds up to
+  const showWindow = (
+    domain: string,
+    samples: (number | null)[],
+    status: string,
+    lastError?: string
+  ) => {
+    windowsRef.current[domain] = samples;
+    const answered = samples.filter((ms): ms is number => ms !== null);
+    const last = samples.length ? samples[samples.length - 1] : null;
+    updateStats(domain, {
+      last: last === null ? null : Math.round(last),
+      avg: answered.length
+        ? Math.round(answered.reduce((a, b) => a + b, 0) / answered.length)
+        : null,
+      attempts: samples.length,
+      successes: answered.length,
+      lossPercent: samples.length
+        ? Math.round(((samples.length - answered.length) / samples.length) * 100)
+        : 0,
+      status,
+      lastError,
     });
   };
 
+  const applySample = (sample: MonitorSample) => {
+    const prev = windowsRef.current[sample.domain] ?? [];
+    const samples = [...prev, sample.rttMs].slice(-PING_WINDOW);
+    // One lost probe doesn't make a cluster down; a window without answers does
+    const status = samples.some((ms) => ms !== null) ? "ok" : sample.status;
+    showWindow(sample.domain, samples, status, sample.error ?? undefined);
+  };
+
+  const applyMeasurement = (result: ClusterLatency) => {
+    showWindow(
+      result.domain,
+      result.samples.slice(-PING_WINDOW),
+      result.stats.received ? "ok" : "failed",
+      result.errors[0]
+    );
+  };
+
   const pingClusters = async (_regionId?: string) => {
     const runId = ++pingRunIdRef.current;
     const region = selectedRegion;
     if (!region) return;
-    const targets = region.clusters ?? [];
-
-    const tasks = targets.map((c) => async () => {
-      const domain = c.domain;
-      updateStats(domain, {
-        last: null,
-        avg: null,
-        attempts: 0,
-        successes: 0,
-        lossPercent: 0,
-        status: "running",
-      });
-
-      let successes = 0;
-      let totalMs = 0;
-      for (let i = 0; i < PING_ATTEMPTS; i++) {
-        if (runId !== pingRunIdRef.current) {
-          updateStats(domain, { status: "cancelled" });
-          return;
-        }
-        try {
-          const res: any = await safeInvoke("ping_server", {
-            hostname: domain,
-            timeout_ms: PING_TIMEOUT_MS,
-          });
-          const ms = typeof res?.ping === "number" ? res.ping : null;
-          const errMsg = typeof res?.error === "string" ? res.error : undefined;
-          if (ms !== null) {
-            successes++;
-            totalMs += ms;
-          }
-          const avg = successes ? Math.round(totalMs / successes) : null;
-          const lossPercent = Math.round(((i + 1 - successes) / (i + 1)) * 100);
-          updateStats(domain, {
-            last: ms,
-            avg,
-            attempts: i + 1,
-            successes,
-            lossPercent,
-            status: res?.status ?? "ok",
-            lastError: errMsg,
-          });
-        } catch (e) {
-          const lossPercent = Math.round(((i + 1 - successes) / (i + 1)) * 100);
-          updateStats(domain, {
-            last: null,
-            avg: successes ? Math.round(totalMs / successes) : null,
-            attempts: i + 1,
-            successes,
-            lossPercent,
-            status: "error",
-            lastError: String(e),
-          });
-        }
-        await new Promise((r) => setTimeout(r, PING_ATTEMPT_DELAY_MS));
-      }
 
-      const finalAvg = successes ? Math.round(totalMs / successes) : null;
-      const finalLoss = Math.round(
-        ((PING_ATTEMPTS - successes) / PING_ATTEMPTS) * 100
+    for (const c of region.clusters ?? []) {
+      updateStats(c.domain, { status: "running" });
+    }
+    try {
+      const results = await measureClusterLatency(
+        region.id,
+        PING_ATTEMPTS,
+        PING_TIMEOUT_MS
       );
-      updateStats(domain, {
-        avg: finalAvg,
-        lossPercent: finalLoss,
-        status: successes ? "ok" : "failed",
-      });
-    });
-
-    let idx = 0;
-    const workers: Promise<void>[] = new Array(
-      Math.min(PING_CONCURRENCY, tasks.length)
-    )
-      .fill(null)
-      .map(async () => {
-        while (idx < tasks.length) {
-          const i = idx++;
-          await tasks[i]();
-          if (runId !== pingRunIdRef.current) return;
-        }
-      });
-
-    await Promise.all(workers);
+      if (runId !== pingRunIdRef.current) return;
+      results.forEach(applyMeasurement);
+    } catch (e) {
+      if (runId !== pingRunIdRef.current) return;
+      for (const c of region.clusters ?? []) {
+        updateStats(c.domain, { status: "error", lastError: errorMessage(e) });
+      }
+    }
   };
 
   useEffect(() => {
-    if (!selectedRegion) return;
+    const regionId = selectedRegion?.id;
+    if (!regionId) return;
+    windowsRef.current = {};
+    setPings({});
+
+    let active = true;
+    let unlisten: (() => void) | undefined;
+    (async () => {
+      const { listen } = await import("@tauri-apps/api/event");
+      const stop = await listen<MonitorTick>(LATENCY_MONITOR_EVENT, (event) => {
+        if (event.payload.regionId !== regionId) return;
+        event.payload.samples.forEach(applySample);
+      });
+      if (!active) {
+        stop();
+        return;
+      }
+      unlisten = stop;
+      // Already running for another region: this moves it here
+      await startLatencyMonitor({ regionId });
+    })().catch((e) =>
+      console.error("[usePing] latency monitor not started:", errorMessage(e))
+    );
     pingClusters();
-    const id = setInterval(() => pingClusters(), PING_REFRESH_INTERVAL_MS);
+
     return () => {
+      active = false;
       pingRunIdRef.current++;
-      clearInterval(id);
+      unlisten?.();
     };
     // eslint-disable-next-line react-hooks/exhaustive-deps
   }, [selectedRegion?.id]);
 
+  // Switching regions reconfigures the monitor; it only stops with the app
+  useEffect(() => {
+    return () => {
+      stopLatencyMonitor().catch((e) =>
+        console.error("[usePing] latency monitor not stopped:", errorMessage(e))
+      );
+    };
+  }, []);
+
   return { pings, pingClusters };
 }
@@ -10,6 +10,10 @@ export function useSettings() {
     useFirewall: true,
     useBackup: false,
     backupCount: 5,
+    offlineMode: false,
+    catalogTtlMinutes: 360,
+    historyRetentionDays: 30,
+    historyMaxRecords: 200000,
   });
   const [loading, setLoading] = useState(true);
 
@@ -0,0 +1,30 @@
+import type { BackendErrorCode } from "../utils/errors";
+
+// One action the backend checked (src-tauri/src/capabilities.rs)
+export type CapabilityCheck = {
+  ok: boolean;
+  code?: BackendErrorCode;
+  reason?: string;
+};
+
+export type FirewallProfile = {
+  name: string;
+  enabled: boolean;
+  localRulesAllowed: boolean;
+  policyManaged: boolean;
+};
+
+export type Capabilities = {
+  hostsPath: string;
+  helper: boolean;
+  hostsWritable: CapabilityCheck;
+  firewallBackend: string | null;
+  firewallBackendAvailable: CapabilityCheck;
+  firewallPrivileges: CapabilityCheck;
+  firewallProfiles: FirewallProfile[];
+  firewallEnabled: CapabilityCheck;
+  policyManaged: boolean;
+  // The two blocking modes, everything above considered
+  hosts: CapabilityCheck;
+  firewall: CapabilityCheck;
+};
@@ -0,0 +1,24 @@
+// Where the catalog came from and how it was checked (src-tauri/src/catalog/cache.rs)
+export type CatalogSource =
+  | "network"
+  | "revalidated"
+  | "cache"
+  | "staleCache"
+  | "embedded";
+
+export type CatalogStatus = {
+  source: CatalogSource;
+  url: string;
+  fetchedAt: number | null;
+  ageSecs: number | null;
+  ttlSecs: number;
+  offline: boolean;
+  etag: string | null;
+  lastModified: string | null;
+  // Why a fresher source couldn't be used
+  error: string | null;
+  // Set when the downloaded catalog was thrown away because of its signature
+  signatureError: string | null;
+  overlayApplied: boolean;
+  overlayError: string | null;
+};
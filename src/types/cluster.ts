@@ -2,6 +2,8 @@ export type Cluster = {
   id: string;
   domain: string;
   location?: string;
+  // Addresses the cluster's domain should resolve to
+  ips?: string[];
   latency?: string;
 };
 
@@ -1,3 +1,9 @@
 export type { Cluster, Region, Game } from "./cluster";
 export type { PingInfo, PingMap } from "./ping";
 export type { Selections } from "./selections";
+export type {
+  Capabilities,
+  CapabilityCheck,
+  FirewallProfile,
+} from "./capabilities";
+export type { CatalogSource, CatalogStatus } from "./catalog";
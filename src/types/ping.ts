@@ -9,3 +9,50 @@ export type PingInfo = {
 };
 
 export type PingMap = Record<string, PingInfo>;
+
+// Event the backend latency monitor sends every round (src-tauri/src/monitor.rs)
+export const LATENCY_MONITOR_EVENT = "latency-monitor://tick";
+
+export type LatencyMonitorConfig = {
+  regionId: string;
+  intervalMs?: number;
+  // 0 pauses the monitor while the window is hidden
+  hiddenIntervalMs?: number;
+  timeoutMs?: number;
+};
+
+// One probe of one cluster, at its first catalog IP
+export type MonitorSample = {
+  clusterId: string;
+  domain: string;
+  method: string;
+  ip: string | null;
+  port: number | null;
+  rttMs: number | null;
+  status: string;
+  error: string | null;
+};
+
+export type MonitorTick = {
+  regionId: string;
+  seq: number;
+  at: number;
+  hidden: boolean;
+  samples: MonitorSample[];
+};
+
+// A measure_cluster_latency result (src-tauri/src/probe/stats.rs), the fields the UI reads
+export type ClusterLatency = {
+  regionId: string;
+  clusterId: string;
+  domain: string;
+  rank: number | null;
+  samples: (number | null)[];
+  stats: {
+    sent: number;
+    received: number;
+    lossPercent: number;
+    avgMs: number | null;
+  };
+  errors: string[];
+};
@@ -0,0 +1,38 @@
+// Tauri commands reject with { code, message } (src-tauri/src/error.rs)
+export type BackendErrorCode =
+  | "permission-denied"
+  // The helper won't vouch for a cluster or IP (overlay, discovery); elevating still works
+  | "not-authorized"
+  | "not-found"
+  | "parse"
+  | "invalid-input"
+  | "unsupported"
+  | "network"
+  | "command-failed"
+  | "io"
+  | "other";
+
+export interface BackendError {
+  code: BackendErrorCode;
+  message: string;
+}
+
+export function isBackendError(e: unknown): e is BackendError {
+  return (
+    typeof e === "object" &&
+    e !== null &&
+    typeof (e as any).code === "string" &&
+    typeof (e as any).message === "string"
+  );
+}
+
+export function errorCode(e: unknown): BackendErrorCode | undefined {
+  return isBackendError(e) ? e.code : undefined;
+}
+
+// Text for any rejection: backend errors, Error objects and plain strings
+export function errorMessage(e: unknown): string {
+  if (isBackendError(e)) return e.message;
+  if (e instanceof Error) return e.message;
+  return String(e);
+}
@@ -1,4 +1,5 @@
 import { safeInvoke } from "./tauriInvoke";
+import { errorMessage } from "./errors";
 
 export async function readHostsFile(): Promise<string | null> {
   try {
@@ -51,6 +52,6 @@ export async function updateHostsBlock(
     });
     return { success: true };
   } catch (e: any) {
-    return { success: false, message: String(e) };
+    return { success: false, message: errorMessage(e) };
   }
 }
@@ -4,12 +4,20 @@ export interface AppSettings {
   useFirewall: boolean;
   useBackup: boolean;
   backupCount: number;
+  offlineMode: boolean;
+  catalogTtlMinutes: number;
+  historyRetentionDays: number;
+  historyMaxRecords: number;
 }
 
 const defaultSettings: AppSettings = {
   useFirewall: true,
   useBackup: false,
   backupCount: 5,
+  offlineMode: false,
+  catalogTtlMinutes: 360,
+  historyRetentionDays: 30,
+  historyMaxRecords: 200000,
 };
 
 export async function loadSettings(): Promise<AppSettings> {
@@ -1,3 +1,9 @@
+import { errorMessage } from "./errors";
+import type { Capabilities } from "../types/capabilities";
+import type { CatalogStatus } from "../types/catalog";
+import type { Game } from "../types/cluster";
+import type { ClusterLatency, LatencyMonitorConfig } from "../types/ping";
+
 export async function safeInvoke<T = any>(
   cmd: string,
   args?: Record<string, any>
@@ -78,7 +84,7 @@ export async function diagnoseTauri() {
   } catch (error) {
     result.testInvoke = {
       success: false,
-      error: String(error),
+      error: errorMessage(error),
     };
   }
 
@@ -150,3 +156,62 @@ export async function getFirewallRules() {
 export async function clearFirewallRules() {
   return await directInvoke("clear_firewall_rules");
 }
+
+// Hosts and firewall checked separately, with the reason when one can't be used
+export async function getCapabilities(): Promise<Capabilities> {
+  return await safeInvoke("get_capabilities");
+}
+
+// The catalog the backend blocks and measures with (cache, signature check, overlay)
+export async function getCatalog(): Promise<Game> {
+  return await safeInvoke("get_catalog");
+}
+
+export async function getCatalogStatus(): Promise<CatalogStatus> {
+  return await safeInvoke("get_catalog_status");
+}
+
+// A few probes of every cluster of the region at its first catalog IP, so hosts entries don't
+// change what is measured
+export async function measureClusterLatency(
+  regionId: string,
+  count: number,
+  timeoutMs: number
+): Promise<ClusterLatency[]> {
+  return await safeInvoke("measure_cluster_latency", {
+    regionId,
+    count,
+    timeoutMs,
+  });
+}
+
+// Probes run in the backend and arrive as LATENCY_MONITOR_EVENT; starting it again while it
+// runs switches it to the new region
+export async function startLatencyMonitor(config: LatencyMonitorConfig) {
+  return await safeInvoke("start_latency_monitor", { config });
+}
+
+export async function stopLatencyMonitor(): Promise<boolean> {
+  return await safeInvoke("stop_latency_monitor");
+}
+
+// Same shape as the helper's requests (src-tauri/src/helper.rs)
+export type ElevatedRequest =
+  | {
+      op: "apply" | "remove";
+      regionId: string;
+      domains: string[];
+      hosts: boolean;
+      firewall: boolean;
+    }
+  | { op: "clear" };
+
+// Restart the app elevated (UAC); this instance closes on success
+export async function relaunchElevated() {
+  return await directInvoke("relaunch_elevated");
+}
+
+// Run one operation in an elevated copy of the app (UAC / pkexec) and get its result back
+export async function runElevated(request: ElevatedRequest) {
+  return await directInvoke("run_elevated", { request });
+}